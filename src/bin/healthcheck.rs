@@ -0,0 +1,101 @@
+//! Exits 0 when the server answers `/health` with a 200, for use as a container `HEALTHCHECK CMD`
+//! Only uses std so it builds as a fully static binary for minimal images
+
+use std::{
+    env,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    process::ExitCode,
+    thread,
+    time::Duration,
+};
+
+const DEFAULT_PORT: u16 = 4560; // Matches PORT in the server
+
+struct Args {
+    interval: Duration, // Wait between retries
+    timeout: Duration,  // For each attempt
+    retries: u32,       // Attempts before reporting unhealthy
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            eprintln!("Usage: healthcheck [--health-interval SECS] [--health-timeout SECS] [--health-retries N]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for attempt in 1..=args.retries {
+        match check(args.timeout) {
+            Ok(true) => return ExitCode::SUCCESS,
+            Ok(false) => eprintln!("Attempt {attempt}: unhealthy response"),
+            Err(e) => eprintln!("Attempt {attempt}: {e}"),
+        }
+        if attempt < args.retries {
+            thread::sleep(args.interval);
+        }
+    }
+    ExitCode::FAILURE
+}
+
+/// Accepts `--flag value` and `--flag=value`
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        interval: Duration::from_secs(2),
+        timeout: Duration::from_secs(5),
+        retries: 1,
+    };
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {arg}"))?;
+                (arg, value)
+            }
+        };
+        let number = value
+            .parse::<u32>()
+            .map_err(|_| format!("Invalid value '{value}' for {flag}"))?;
+        match flag.as_str() {
+            "--health-interval" => parsed.interval = Duration::from_secs(number.into()),
+            "--health-timeout" => parsed.timeout = Duration::from_secs(number.max(1).into()),
+            "--health-retries" => parsed.retries = number.max(1),
+            _ => return Err(format!("Unknown argument {flag}")),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Request `/health` over the servers Unix socket if `SOCKET_PATH` is set, otherwise over loopback
+fn check(timeout: Duration) -> io::Result<bool> {
+    #[cfg(unix)]
+    if let Some(path) = env::var_os("SOCKET_PATH") {
+        let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        return request_health(&mut stream);
+    }
+
+    let port = env::var("PORT")
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(DEFAULT_PORT);
+    let mut stream =
+        TcpStream::connect_timeout(&SocketAddr::from(([127, 0, 0, 1], port)), timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    request_health(&mut stream)
+}
+
+fn request_health(stream: &mut (impl Read + Write)) -> io::Result<bool> {
+    stream.write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+    // Only the status line matters, "HTTP/1.1 200"
+    let mut status_line = [0; 12];
+    stream.read_exact(&mut status_line)?;
+    Ok(&status_line[9..] == b"200")
+}
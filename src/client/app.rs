@@ -1,39 +1,122 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::client::networking::set_desktop_wallpaper;
 use crate::{
     client::networking::{
-        add_comment, edit_styles, generate_wallpaper, get_database, like_image, login,
-        query_prompt, recreate_image, remove_comment, remove_image,
+        add_comment, add_template, change_password, clear_moodboard, create_share_link, crop_image,
+        darken_image, delete_account, download_wallpaper, edit_history_config, edit_season,
+        edit_styles, generate_from_image, generate_from_template, generate_prompt,
+        generate_variation, generate_wallpaper, get_activity, get_audit_log, get_clusters,
+        get_cost_stats, get_database, get_gallery_page, get_image_context, get_image_stats,
+        get_models, get_profile_previews, get_retention, get_server_logs, get_status,
+        import_from_url, like_image, like_images, login, logout, pin_image, query_prompt,
+        rate_image, react_comment, recreate_image, recreate_image_edited, remove_comment,
+        remove_image, remove_images, remove_profile, remove_template, review_prompt, save_profile,
+        set_image_note, set_moodboard, style_preset, transform_image, upload_image,
+        verify_maintenance, PresetAction, PromptAction,
+    },
+    client::{
+        gallery::{aspect, justified_rows},
+        palette::{self, Command},
+        widgets::{pending_outline, TagCloud, MIN_TAG_COUNT},
+    },
+    common::{
+        image_stats::{ImageStats, HUE_BINS},
+        utils::{format_duration, hue_distance, mean_hue, word_frequencies},
+        AuditEntry, Cluster, ColorData, ColorTemperature, CommentData, CommentSentiment,
+        ContentTheme, CostStats, Database, FetchProfile, GalleryPage, GallerySort, GenerationMode,
+        ImageFile, ImageModelInfo, LikedState, LogLevel, LogLine, MaintenanceReport, Orientation,
+        ProfilePreview, PromptLength, RetentionPolicy, Season, ServerStatus, Source, StyleVariant,
+        WallpaperData,
     },
-    common::{CommentData, Database, LikedState, StyleVariant, WallpaperData},
     PORT,
 };
 use anyhow::Result;
 use bitflags::bitflags;
-use chrono::Local;
+use chrono::{DateTime, Datelike, Local, Utc};
 use egui::{
-    vec2, Align2, CentralPanel, Color32, Context, CursorIcon, FontId, Frame, Image, Key,
-    PointerButton, Rect, RichText, ScrollArea, Sense, Shape, TextEdit, Vec2, Widget, Window,
+    load::TexturePoll, pos2, vec2, Align2, CentralPanel, Color32, Context, CursorIcon, FontId,
+    Frame, Image, Key, KeyboardShortcut, Modifiers, PointerButton, Pos2, Rect, RichText,
+    ScrollArea, Sense, Shape, Stroke, TextEdit, Vec2, Widget, Window,
 };
+use egui_extras::{Column, TableBuilder};
 use egui_notify::Toasts;
 use egui_pull_to_refresh::PullToRefresh;
 use egui_thumbhash::ThumbhashImage;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
 use uuid::Uuid;
 
+const CLUSTER_COUNT: usize = 5;
+const AUDIT_PAGE_SIZE: usize = 50;
+const ACTIVITY_LIMIT: usize = 100;
+const LOG_REFRESH_SECS: f64 = 5.0; // How often the server logs window fetches new lines while open
+const OFFLINE_RETRY_SECS: f64 = 30.0; // How often the database is fetched again while offline
+const GALLERY_PAGE_SIZE: usize = 60;
+const GALLERY_ROW_HEIGHT: f32 = 240.0;
+const GRID_PAGE_SIZE: usize = 20;
+const NARROW_WIDTH: f32 = 600.0; // Below this the grid is a single full width column for phones
+const SWIPE_DISTANCE: f32 = 80.0;
+const STATUS_POLL: std::time::Duration = std::time::Duration::from_secs(60);
+const PREFETCH_DEFAULT: u32 = 1;
+const SLOW_LOAD_SECS: f64 = 3.0; // A first fullscreen load slower than this stops prefetching on the web
+const FULLSCREEN_FADE_SECS: f32 = 0.25;
+const FULLSCREEN_SPINNER_SIZE: f32 = 32.0; // Shown over the thumbnail until the full image is ready
+const DARK_VARIANT_BRIGHTNESS: f32 = 0.3; // Brightest a night copy gets
+const DATABASE_CACHE_KEY: &str = "database_cache";
+const CACHED_ENTRIES: usize = 300; // Most recent wallpapers and comments kept, to fit in localStorage
+
 nestify::nest! {
     pub struct Wallpapy {
         host: String,
         toasts: Arc<Mutex<Toasts>>,
 
         database: Option<Database>,
+        database_fetched: Option<DateTime<Utc>>, // When the shown database came from the server, maybe in an earlier session
+        offline: bool, // The last fetch failed so the cached database is shown read only
+        last_failed_fetch: Option<f64>, // Input time the database last failed to load, for the offline retry
+        snapshot_pending: bool, // A fresh database to cache on the next save
+        retention_policy: Option<RetentionPolicy>,
+        image_models: Vec<ImageModelInfo>, // Models the server can diffuse with, the first is its default
+        server_status: Option<ServerStatus>,
+        last_status_poll: Option<DateTime<Utc>>,
         fullscreen_image: Option<Uuid>,
         state_filter: StateFilter,
+        search: String, // Filters the gallery by prompt and note
+        focus_search: bool, // Move keyboard focus to the search box on the next frame
+        hue_filter: Option<(u16, u16)>, // Accent hue and tolerance in degrees, like smartget's hue query
+        tag_filter: Option<String>, // Only show wallpapers with this tag, picked from the tag cloud
+        last_auto_apply: Option<DateTime<Utc>>,
+        cluster_view: bool,
+        clusters: Option<Vec<Cluster>>,
+        cost_stats: Option<CostStats>,
+        generation_context: Option<(Uuid, String)>, // LLM context of a wallpaper, shown in its own window
+        remove_confirmation: Option<(Uuid, String)>, // Pinned wallpaper awaiting a typed delete confirmation
+        recreate_edit: Option<(Uuid, String)>, // Wallpaper being recreated and its edited prompt
+        swipe: Vec2, // Drag so far across the fullscreen image
+
+        #>[derive(Default, Clone, Copy)]
+        load_timing: struct LoadTiming {
+            pending: Option<(Uuid, f64)>, // Fullscreen wallpaper still loading and when it started
+            first_load_secs: Option<f64>, // How long the first fullscreen load took
+        },
 
         #>[derive(Deserialize, Serialize, Default)]
         #>[serde(default)]
         stored: pub struct StoredData {
             auth_token: String,
+            username: String, // Of the logged in account, typed back to confirm deleting it
+            auto_apply: bool,
+            auto_apply_hours: u32,
+            review_prompts: bool, // Hold generated prompts for review before spending image credits
+            filters_open: bool,
+            sort_order: SortOrder,
+            generation_mode: GenerationMode,
+            image_model: Option<String>, // None for the servers default
+            prefetch_count: Option<u32>, // Neighbours of the fullscreen wallpaper to load ahead, None for the default
         },
 
         login_form: struct LoginForm {
@@ -41,6 +124,129 @@ nestify::nest! {
             password: String,
         },
         comment_submission: String,
+        style_preset: String, // Preset picked to apply or delete, or the name to save the style under
+        inspire_drops: bool, // Dropped images inspire a new wallpaper instead of being uploaded
+        awaiting_paste: bool, // "Paste URL" was clicked, image links in the next paste are imported
+
+        #>[derive(Default)]
+        note_draft: struct NoteDraft {
+            id: Option<Uuid>, // Wallpaper the text belongs to
+            text: String,
+        },
+
+        #>[derive(Default)]
+        grid: struct GridPaging {
+            shown: usize, // Grows a page at a time as the end of the grid scrolls into view
+            filter_key: Option<(u32, SortOrder, String, Option<(u16, u16)>, Option<String>)>, // Filters the shown count was built for
+        },
+
+        #>[derive(Default)]
+        stats_overlay: struct StatsOverlay {
+            open: bool,
+            stats: Option<(Uuid, ImageStats)>, // Measured from the thumbnail of the fullscreen wallpaper
+            loading: Option<Uuid>,
+        },
+
+        #>[derive(Default)]
+        selection: struct Selection {
+            active: bool,
+            selected: HashSet<Uuid>,
+            anchor: Option<Uuid>, // Last clicked wallpaper, where shift-click ranges start
+            order: Vec<Uuid>, // Wallpapers in the order the grid last drew them
+        },
+
+        templates: struct TemplateWindow {
+            open: bool,
+            selected: Option<Uuid>,
+            slot_values: HashMap<String, String>,
+            new_name: String,
+            new_template: String,
+        },
+
+        history: struct HistoryWindow {
+            open: bool,
+            #>[derive(Clone, Copy, PartialEq, Eq)]
+            sort: enum HistorySort {
+                Date,
+                Prompt,
+                Liked,
+            },
+            ascending: bool,
+            filter: StateFilter,
+        },
+
+        audit: struct AuditWindow {
+            open: bool,
+            page: usize,
+            entries: Option<Vec<AuditEntry>>,
+        },
+
+        activity: struct ActivityWindow {
+            open: bool,
+            entries: Option<Vec<AuditEntry>>, // Newest first
+        },
+
+        server_logs: struct LogWindow {
+            open: bool,
+            level: LogLevel, // Least severe level shown
+            lines: Option<Vec<LogLine>>, // Oldest first
+            last_fetch: Option<f64>, // Input time of the last request, for the auto refresh
+        },
+
+        maintenance: struct MaintenanceWindow {
+            open: bool,
+            report: Option<MaintenanceReport>,
+        },
+
+        #>[derive(Default)]
+        account: struct AccountWindow {
+            open: bool,
+            old_password: String,
+            new_password: String,
+            confirm_password: String,
+            delete_open: bool, // Confirmation for deleting the account is showing
+            delete_username: String,
+            delete_password: String,
+        },
+
+        #>[derive(Default)]
+        palette: struct CommandPalette {
+            open: bool,
+            query: String,
+            selected: usize, // Highlighted row of the matching commands
+            expanded: Option<Command>, // Chosen command waiting on its inline text
+            argument: String,
+        },
+
+        #>[derive(Default)]
+        profiles: struct ProfileWindow {
+            open: bool,
+            draft: FetchProfile, // Loaded from a saved profile or written from scratch
+            previews: Option<Vec<ProfilePreview>>,
+        },
+
+        #>[derive(Default)]
+        gallery: struct GalleryView {
+            open: bool,
+            sort: GallerySort,
+            wallpapers: Vec<WallpaperData>, // Pages loaded so far, in sort order
+            total: Option<usize>, // Unknown until the first page arrives
+            loading: bool,
+        },
+
+        #>[derive(Clone, Copy)]
+        crop: struct CropTool {
+            active: bool,
+            aspect: CropAspect,
+            rect: Rect, // Normalized to the image size
+            drag: Option<CropDrag>,
+        },
+
+        #>[derive(Clone, Copy)]
+        fullscreen: struct FullscreenState {
+            zoom: f32, // 1 fits the whole image in view
+            pan: Vec2, // Offset of the image center from the view center
+        },
 
         #>[derive(Default)]*
         network_data: Arc<Mutex<struct DownloadData {
@@ -57,6 +263,22 @@ nestify::nest! {
                 InProgress,
                 Done(Result<Database>),
             },
+            retention_policy: Option<RetentionPolicy>,
+            image_models: Option<Vec<ImageModelInfo>>,
+            account_deleted: bool,
+            moodboard_changed: bool, // Poll the status again for the new thumbnail
+            server_status: Option<ServerStatus>,
+            clusters: Option<Vec<Cluster>>,
+            cost_stats: Option<CostStats>,
+            audit_log: Option<Vec<AuditEntry>>,
+            activity: Option<Vec<AuditEntry>>,
+            server_logs: Option<Result<Vec<LogLine>>>,
+            maintenance_report: Option<MaintenanceReport>,
+            profile_previews: Option<Vec<ProfilePreview>>,
+            generation_context: Option<(Uuid, String)>,
+            image_stats: Option<(Uuid, Result<ImageStats>)>,
+            gallery_page: Option<((GallerySort, usize), Result<GalleryPage>)>, // Keyed by the sort and offset requested
+            pending_requests: HashSet<&'static str>, // Endpoints with a request in flight, a second click waits for it
         }>>,
     }
 }
@@ -64,19 +286,210 @@ nestify::nest! {
 bitflags! {
     #[derive(Clone)]
     pub struct StateFilter: u32 {
-        const LIKED    = 0b00001;
-        const LOVED    = 0b00010;
-        const COMMENT  = 0b00100;
-        const NEUTRAL  = 0b01000;
-        const DISLIKED = 0b10000;
+        const LIKED               = 0b00000001;
+        const LOVED               = 0b00000010;
+        const COMMENT             = 0b00000100;
+        const NEUTRAL             = 0b00001000;
+        const DISLIKED            = 0b00010000;
+        const WARM                = 0b00100000;
+        const COOL                = 0b01000000;
+        const NEUTRAL_TEMPERATURE = 0b10000000;
+        const PINNED              = 0b1_00000000; // Only show pinned wallpapers
+    }
+}
+
+impl DownloadData {
+    /// Whether a request to the endpoint is still waiting on the server
+    fn request_in_progress(&self, endpoint: &str) -> bool {
+        self.pending_requests.contains(endpoint)
+    }
+}
+
+impl FullscreenState {
+    const FIT: Self = Self {
+        zoom: 1.0,
+        pan: Vec2::ZERO,
+    };
+    const MAX_ZOOM: f32 = 8.0;
+
+    /// Where the image is drawn when it fits `view` at zoom 1
+    fn image_rect(self, view: Rect) -> Rect {
+        Rect::from_center_size(view.center() + self.pan, view.size() * self.zoom)
+    }
+
+    /// Zoom by `factor` keeping the point under `anchor` in place
+    fn zoom_at(&mut self, view: Rect, anchor: Pos2, factor: f32) {
+        let zoom = (self.zoom * factor).clamp(1.0, Self::MAX_ZOOM);
+        let offset = anchor - view.center() - self.pan;
+        self.pan += offset - offset * (zoom / self.zoom);
+        self.zoom = zoom;
+        self.clamp_pan(view);
+    }
+
+    /// Keep the image covering the view so it can't be dragged off screen
+    fn clamp_pan(&mut self, view: Rect) {
+        let limit = view.size() * (self.zoom - 1.0) / 2.0;
+        self.pan = self.pan.clamp(-limit, limit);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CropAspect {
+    Ultrawide,
+    Widescreen,
+    Portrait,
+}
+
+impl CropAspect {
+    const ALL: [Self; 3] = [Self::Ultrawide, Self::Widescreen, Self::Portrait];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Ultrawide => "21:9",
+            Self::Widescreen => "16:9",
+            Self::Portrait => "9:16",
+        }
+    }
+
+    const fn ratio(self) -> f32 {
+        match self {
+            Self::Ultrawide => 21.0 / 9.0,
+            Self::Widescreen => 16.0 / 9.0,
+            Self::Portrait => 9.0 / 16.0,
+        }
+    }
+
+    /// The largest centered rectangle of this aspect that fits the image, normalized
+    fn initial_rect(self, image_aspect: f32) -> Rect {
+        let height_per_width = image_aspect / self.ratio();
+        let size = if height_per_width <= 1.0 {
+            vec2(1.0, height_per_width)
+        } else {
+            vec2(1.0 / height_per_width, 1.0)
+        };
+        Rect::from_center_size(pos2(0.5, 0.5), size)
+    }
+}
+
+/// How the main grid is ordered, pinned wallpapers always come first
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+enum SortOrder {
+    #[default]
+    DateDesc,
+    DateAsc,
+    HueAsc,
+    BrightnessDesc,
+    BrightnessAsc,
+    SaturationDesc,
+    LikedScore,
+    ReactionDesc,
+}
+
+impl SortOrder {
+    const ALL: [Self; 8] = [
+        Self::DateDesc,
+        Self::DateAsc,
+        Self::HueAsc,
+        Self::BrightnessDesc,
+        Self::BrightnessAsc,
+        Self::SaturationDesc,
+        Self::LikedScore,
+        Self::ReactionDesc,
+    ];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::DateDesc => "Newest",
+            Self::DateAsc => "Oldest",
+            Self::HueAsc => "Hue",
+            Self::BrightnessDesc => "Brightest",
+            Self::BrightnessAsc => "Darkest",
+            Self::SaturationDesc => "Most saturated",
+            Self::LikedScore => "Most liked",
+            Self::ReactionDesc => "Most reacted",
+        }
+    }
+
+    /// Order two grid entries, each a datetime with either the wallpaper or the comment
+    fn compare(
+        self,
+        viewer: Option<Uuid>,
+        (a_datetime, a, a_comment): (DateTime<Utc>, Option<&WallpaperData>, Option<&CommentData>),
+        (b_datetime, b, b_comment): (DateTime<Utc>, Option<&WallpaperData>, Option<&CommentData>),
+    ) -> std::cmp::Ordering {
+        let newest_first = b_datetime.cmp(&a_datetime);
+        if self == Self::ReactionDesc {
+            // Wallpapers have no reactions, so they sit among the comments nobody reacted to
+            let reactions =
+                |comment: Option<&CommentData>| comment.map_or(0, CommentData::reaction_count);
+            return reactions(b_comment)
+                .cmp(&reactions(a_comment))
+                .then(newest_first);
+        }
+        let (Some(a), Some(b)) = (a, b) else {
+            return match self {
+                Self::DateDesc => newest_first,
+                Self::DateAsc => a_datetime.cmp(&b_datetime),
+                // Comments have no colours or votes, so they go after the wallpapers
+                _ => b.is_some().cmp(&a.is_some()).then(newest_first),
+            };
+        };
+        match self {
+            Self::DateDesc => newest_first,
+            Self::DateAsc => a_datetime.cmp(&b_datetime),
+            Self::HueAsc => a.color_data.hue.total_cmp(&b.color_data.hue),
+            Self::BrightnessDesc => b.color_data.lightness.total_cmp(&a.color_data.lightness),
+            Self::BrightnessAsc => a.color_data.lightness.total_cmp(&b.color_data.lightness),
+            Self::SaturationDesc => b.color_data.saturation.total_cmp(&a.color_data.saturation),
+            Self::LikedScore => liked_state_rank(b.liked_state_for(viewer))
+                .cmp(&liked_state_rank(a.liked_state_for(viewer))),
+            Self::ReactionDesc => std::cmp::Ordering::Equal,
+        }
+        .then(newest_first)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Swipe {
+    Previous,
+    Next,
+    Close,
+}
+
+impl Swipe {
+    /// Which way a finished drag went, if it went far enough to count
+    fn from_drag(drag: Vec2) -> Option<Self> {
+        if drag.x.abs() > drag.y.abs() {
+            if drag.x.abs() < SWIPE_DISTANCE {
+                None
+            } else if drag.x > 0.0 {
+                Some(Self::Previous)
+            } else {
+                Some(Self::Next)
+            }
+        } else if drag.y > SWIPE_DISTANCE {
+            Some(Self::Close)
+        } else {
+            None
+        }
     }
 }
 
+#[derive(Clone, Copy)]
+enum CropDrag {
+    Move,
+    Resize(Pos2), // The normalized corner that stays fixed
+}
+
 impl Wallpapy {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         let stored = cc.storage.map_or_else(StoredData::default, |storage| {
             eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default()
         });
+        // Show the last database straight away, the fresh one replaces it once it loads
+        let cached = cc
+            .storage
+            .and_then(|storage| eframe::get_value::<DatabaseSnapshot>(storage, DATABASE_CACHE_KEY));
 
         egui_extras::install_image_loaders(&cc.egui_ctx);
         egui_thumbhash::register(&cc.egui_ctx);
@@ -93,15 +506,86 @@ impl Wallpapy {
         Self {
             host: format!("localhost:{PORT}"),
             toasts: Arc::new(Mutex::new(Toasts::default())),
-            database: None,
+            database_fetched: cached.as_ref().map(|cached| cached.fetched),
+            database: cached.map(|cached| cached.database),
+            offline: false,
+            last_failed_fetch: None,
+            snapshot_pending: false,
+            retention_policy: None,
+            image_models: Vec::new(),
+            server_status: None,
+            last_status_poll: None,
             fullscreen_image: None,
-            state_filter: StateFilter::all(),
+            state_filter: StateFilter::all().difference(StateFilter::PINNED),
+            search: String::new(),
+            focus_search: false,
+            hue_filter: None,
+            tag_filter: None,
+            last_auto_apply: None,
+            cluster_view: false,
+            clusters: None,
+            cost_stats: None,
+            generation_context: None,
+            remove_confirmation: None,
+            recreate_edit: None,
+            swipe: Vec2::ZERO,
+            load_timing: LoadTiming::default(),
             stored,
             login_form: LoginForm {
                 username: String::new(),
                 password: String::new(),
             },
             comment_submission: String::new(),
+            style_preset: String::new(),
+            inspire_drops: false,
+            awaiting_paste: false,
+            note_draft: NoteDraft::default(),
+            grid: GridPaging::default(),
+            stats_overlay: StatsOverlay::default(),
+            selection: Selection::default(),
+            templates: TemplateWindow {
+                open: false,
+                selected: None,
+                slot_values: HashMap::new(),
+                new_name: String::new(),
+                new_template: String::new(),
+            },
+            history: HistoryWindow {
+                open: false,
+                sort: HistorySort::Date,
+                ascending: false,
+                filter: StateFilter::all(),
+            },
+            audit: AuditWindow {
+                open: false,
+                page: 0,
+                entries: None,
+            },
+            activity: ActivityWindow {
+                open: false,
+                entries: None,
+            },
+            server_logs: LogWindow {
+                open: false,
+                level: LogLevel::Info,
+                lines: None,
+                last_fetch: None,
+            },
+            maintenance: MaintenanceWindow {
+                open: false,
+                report: None,
+            },
+            account: AccountWindow::default(),
+            palette: CommandPalette::default(),
+            profiles: ProfileWindow::default(),
+            gallery: GalleryView::default(),
+            crop: CropTool {
+                active: false,
+                aspect: CropAspect::Widescreen,
+                rect: Rect::from_min_max(Pos2::ZERO, pos2(1.0, 1.0)),
+                drag: None,
+            },
+            fullscreen: FullscreenState::FIT,
             network_data: Arc::new(Mutex::new(DownloadData::default())),
         }
     }
@@ -110,6 +594,18 @@ impl Wallpapy {
 impl eframe::App for Wallpapy {
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
         eframe::set_value(storage, eframe::APP_KEY, &self.stored);
+        if std::mem::take(&mut self.snapshot_pending) {
+            if let (Some(database), Some(fetched)) = (&self.database, self.database_fetched) {
+                eframe::set_value(
+                    storage,
+                    DATABASE_CACHE_KEY,
+                    &DatabaseSnapshot {
+                        database: cache_snapshot(database),
+                        fetched,
+                    },
+                );
+            }
+        }
     }
 
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
@@ -120,6 +616,10 @@ impl eframe::App for Wallpapy {
         }
 
         self.get_database(ctx);
+        self.retry_offline(ctx);
+        self.poll_status(ctx);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.auto_apply_wallpaper(ctx);
         if self.stored.auth_token.is_empty() {
             self.show_login_panel(ctx);
         } else {
@@ -127,38 +627,89 @@ impl eframe::App for Wallpapy {
         }
 
         self.toasts.lock().show(ctx);
+        self.show_generation_progress(ctx);
     }
 }
 
 impl Wallpapy {
+    fn prefetch_count(&self) -> u32 {
+        self.stored.prefetch_count.unwrap_or(PREFETCH_DEFAULT)
+    }
+
     fn show_main_panel(&mut self, ctx: &Context) {
+        // Drawn first so it takes the arrow, enter and escape keys before anything else sees them
+        self.show_command_palette(ctx);
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                if ui.button("Generate Wallpaper").clicked() {
-                    let toasts_store = self.toasts.clone();
-                    let network_store = self.network_data.clone();
-                    toasts_store.lock().info("Generating Wallpaper");
-                    let ctx = ctx.clone();
-                    generate_wallpaper(
-                        &self.host,
-                        &self.stored.auth_token,
-                        self.comment_submission.trim(),
-                        move |result| {
-                            ctx.request_repaint();
-                            button_pressed_result(
-                                result,
-                                &network_store,
-                                &toasts_store,
-                                "Generated wallpaper",
-                            );
+            if self.offline {
+                ui.horizontal(|ui| {
+                    let text = self.database_fetched.map_or_else(
+                        || "Offline, the server can't be reached".to_string(),
+                        |fetched| {
+                            format!(
+                                "Offline, showing cached data from {}",
+                                fetched.with_timezone(&Local).format("%d/%m/%Y %H:%M")
+                            )
                         },
                     );
-                    self.comment_submission = String::new();
+                    ui.weak(format!("{} {text}", egui_phosphor::regular::WIFI_SLASH));
+                    if ui.small_button("Retry").clicked() {
+                        self.network_data.lock().get_database = GetDatabaseState::Wanted;
+                    }
+                });
+            }
+            ui.horizontal_wrapped(|ui| {
+                let generating = {
+                    let network_data = self.network_data.lock();
+                    network_data.request_in_progress("/generate")
+                        || network_data.request_in_progress("/promptgenerate")
+                };
+                let response =
+                    ui.add_enabled(!self.offline, egui::Button::new("Generate Wallpaper"));
+                if generating {
+                    pending_outline(ui, &response);
+                }
+                if response.clicked() {
+                    let message = std::mem::take(&mut self.comment_submission);
+                    self.generate(ctx, message.trim());
+                }
+                for mode in GenerationMode::ALL {
+                    ui.selectable_value(&mut self.stored.generation_mode, mode, mode.name());
+                }
+                // The first model is the servers default, only worth a choice when there are others
+                if self.image_models.len() > 1 {
+                    let selected = self
+                        .stored
+                        .image_model
+                        .as_deref()
+                        .and_then(|id| self.image_models.iter().find(|model| model.id == id))
+                        .unwrap_or(&self.image_models[0]);
+                    egui::ComboBox::from_id_salt("image_model")
+                        .selected_text(&selected.name)
+                        .show_ui(ui, |ui| {
+                            for model in &self.image_models {
+                                ui.selectable_value(
+                                    &mut self.stored.image_model,
+                                    Some(model.id.clone()),
+                                    &model.name,
+                                );
+                            }
+                        });
+                }
+                ui.checkbox(&mut self.stored.review_prompts, "Review prompts");
+                if ui
+                    .add_enabled(!self.offline, egui::Button::new("From Template"))
+                    .clicked()
+                {
+                    self.templates.open = !self.templates.open;
                 }
 
                 // Text input for submitting a comment
                 ui.text_edit_singleline(&mut self.comment_submission);
-                if ui.button("Submit Comment").clicked() {
+                if ui
+                    .add_enabled(!self.offline, egui::Button::new("Submit Comment"))
+                    .clicked()
+                {
                     let toasts_store = self.toasts.clone();
                     let network_store = self.network_data.clone();
                     let ctx = ctx.clone();
@@ -174,6 +725,110 @@ impl Wallpapy {
                     self.comment_submission = String::new();
                 }
 
+                // Native file picker for uploading images, wasm uses drag and drop onto the canvas
+                #[cfg(not(target_arch = "wasm32"))]
+                if ui
+                    .add_enabled(!self.offline, egui::Button::new("Upload"))
+                    .clicked()
+                {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("Image", &["png", "jpg", "jpeg", "webp"])
+                        .pick_file()
+                    {
+                        match std::fs::read(&path) {
+                            Ok(data) => self.upload_image(ctx, data),
+                            Err(e) => {
+                                self.toasts
+                                    .lock()
+                                    .error(format!("Failed to read image: {e}"));
+                            }
+                        }
+                    }
+                }
+
+                #[cfg(not(target_arch = "wasm32"))]
+                if ui
+                    .add_enabled(!self.offline, egui::Button::new("Inspire from image"))
+                    .clicked()
+                {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("Image", &["png", "jpg", "jpeg", "webp"])
+                        .pick_file()
+                    {
+                        match std::fs::read(&path) {
+                            Ok(data) => self.generate_from_image(ctx, data),
+                            Err(e) => {
+                                self.toasts
+                                    .lock()
+                                    .error(format!("Failed to read image: {e}"));
+                            }
+                        }
+                    }
+                }
+                let paste_label = if self.awaiting_paste {
+                    "Press Ctrl+V"
+                } else {
+                    "Paste URL"
+                };
+                if ui
+                    .add_enabled(
+                        !self.offline,
+                        egui::Button::new(paste_label).selected(self.awaiting_paste),
+                    )
+                    .on_hover_text("Import an image from a link or image data in the clipboard")
+                    .clicked()
+                {
+                    self.awaiting_paste = !self.awaiting_paste;
+                }
+                ui.checkbox(&mut self.inspire_drops, "Drop to inspire")
+                    .on_hover_text("Dropped images inspire a new wallpaper instead of being uploaded");
+
+                // Reference image whose palette and texture new prompts lean towards
+                ui.label("Moodboard");
+                if let Some(updated) = self
+                    .server_status
+                    .as_ref()
+                    .and_then(|status| status.moodboard_updated)
+                {
+                    // Versioned so a new upload isn't hidden behind the cached image
+                    ui.add(
+                        egui::Image::new(format!(
+                            "http://{}/moodboard?v={}",
+                            self.host,
+                            updated.timestamp()
+                        ))
+                        .max_height(24.0)
+                        .rounding(4.0),
+                    )
+                    .on_hover_text("New prompts lean towards this image's aesthetic");
+                    if ui
+                        .add_enabled(!self.offline, egui::Button::new("Clear"))
+                        .clicked()
+                    {
+                        self.clear_moodboard(ctx);
+                    }
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                if ui
+                    .add_enabled(!self.offline, egui::Button::new("Upload"))
+                    .on_hover_text("Set the reference image for the look of new wallpapers")
+                    .clicked()
+                {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("Image", &["png", "jpg", "jpeg", "webp"])
+                        .pick_file()
+                    {
+                        match std::fs::read(&path) {
+                            Ok(data) => self.set_moodboard(ctx, data),
+                            Err(e) => {
+                                self.toasts
+                                    .lock()
+                                    .error(format!("Failed to read image: {e}"));
+                            }
+                        }
+                    }
+                }
+
                 // Debug button that prints the prompt to console
                 if ui.button("Query Prompt").clicked() {
                     query_prompt(&self.host, &self.stored.auth_token, move |result| {
@@ -183,71 +838,365 @@ impl Wallpapy {
                     });
                 }
 
+                if ui.button("History").clicked() {
+                    self.history.open = !self.history.open;
+                    if self.history.open {
+                        let network_store = self.network_data.clone();
+                        let toasts_store = self.toasts.clone();
+                        let ctx = ctx.clone();
+                        get_cost_stats(
+                            &self.host,
+                            &self.stored.auth_token,
+                            move |result| match result {
+                                Ok(cost_stats) => {
+                                    network_store.lock().cost_stats = Some(cost_stats);
+                                    ctx.request_repaint();
+                                }
+                                Err(e) => {
+                                    toasts_store.lock().error(e.to_string());
+                                }
+                            },
+                        );
+                    }
+                }
+
+                if ui.button("Activity").clicked() {
+                    self.activity.open = !self.activity.open;
+                    if self.activity.open {
+                        self.request_activity(ctx);
+                    }
+                }
+
+                if ui.button("Audit Log").clicked() {
+                    self.audit.open = !self.audit.open;
+                    if self.audit.open {
+                        self.audit.page = 0;
+                        self.request_audit_log(ctx);
+                    }
+                }
+
+                if ui.button("Server Logs").clicked() {
+                    self.server_logs.open = !self.server_logs.open;
+                    self.server_logs.lines = None;
+                    self.server_logs.last_fetch = None;
+                }
+
+                if ui.button("Maintenance").clicked() {
+                    self.maintenance.open = !self.maintenance.open;
+                    if self.maintenance.open {
+                        self.request_maintenance(ctx, None);
+                    }
+                }
+
+                if ui.button("Profiles").clicked() {
+                    self.profiles.open = !self.profiles.open;
+                }
+
+                // Click wallpapers to select them for bulk actions
+                if ui
+                    .selectable_label(
+                        self.selection.active,
+                        format!("{} Select", egui_phosphor::regular::CHECK_SQUARE),
+                    )
+                    .clicked()
+                {
+                    self.selection.active = !self.selection.active;
+                    if !self.selection.active {
+                        self.selection = Selection::default();
+                    }
+                }
+
+                // Group wallpapers by how similar they look
+                if ui
+                    .selectable_label(self.cluster_view, "Cluster View")
+                    .clicked()
+                {
+                    self.cluster_view = !self.cluster_view;
+                    if self.cluster_view {
+                        self.gallery.open = false;
+                        self.request_clusters(ctx);
+                    }
+                }
+
+                // Justified rows loaded a page at a time while scrolling
+                if ui.selectable_label(self.gallery.open, "Gallery").clicked() {
+                    self.gallery.open = !self.gallery.open;
+                    if self.gallery.open {
+                        self.cluster_view = false;
+                    }
+                }
+
+                // Periodically set the desktop wallpaper to the server's pick
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    if ui
+                        .checkbox(&mut self.stored.auto_apply, "Auto-apply latest wallpaper")
+                        .changed()
+                    {
+                        self.last_auto_apply = None;
+                    }
+                    egui::DragValue::new(&mut self.stored.auto_apply_hours)
+                        .range(1..=168)
+                        .suffix("h")
+                        .ui(ui);
+                }
+
+                let mut prefetch_count = self.prefetch_count();
+                if egui::DragValue::new(&mut prefetch_count)
+                    .range(0..=5)
+                    .prefix("Prefetch ")
+                    .ui(ui)
+                    .on_hover_text("Wallpapers each side of the fullscreen one to load ahead")
+                    .changed()
+                {
+                    self.stored.prefetch_count = Some(prefetch_count);
+                }
+
+                if let Some(status) = &self.server_status {
+                    let text = if status.generating {
+                        "Generating now…".to_string()
+                    } else if !status.api_keys_configured {
+                        "API keys not configured".to_string()
+                    } else if let Some(next) = status.next_generation {
+                        let until = next - Utc::now();
+                        if until > chrono::Duration::zero() {
+                            format!("Next wallpaper in {}", format_duration(until))
+                        } else {
+                            "Next wallpaper any minute".to_string()
+                        }
+                    } else {
+                        String::new()
+                    };
+                    let text = if status.queue_length > 0 {
+                        format!("{text} ({} prompts to review)", status.queue_length)
+                    } else {
+                        text
+                    };
+                    ui.weak(text.trim());
+                }
+
+                if ui.button("Account").clicked() {
+                    self.account.open = true;
+                }
                 if ui.button("Logout").clicked() {
-                    self.stored.auth_token.clear();
+                    self.log_out();
                 }
 
-                // Filter buttons
-                render_statefilter_button(
-                    ui,
-                    &mut self.state_filter,
-                    StateFilter::LOVED,
-                    egui_phosphor::regular::HEART,
-                );
-                render_statefilter_button(
-                    ui,
-                    &mut self.state_filter,
-                    StateFilter::LIKED,
-                    egui_phosphor::regular::THUMBS_UP,
-                );
-                render_statefilter_button(
-                    ui,
-                    &mut self.state_filter,
-                    StateFilter::NEUTRAL,
-                    egui_phosphor::regular::ALIGN_CENTER_HORIZONTAL_SIMPLE,
-                );
-                render_statefilter_button(
-                    ui,
-                    &mut self.state_filter,
-                    StateFilter::DISLIKED,
-                    egui_phosphor::regular::THUMBS_DOWN,
-                );
-                render_statefilter_button(
-                    ui,
-                    &mut self.state_filter,
-                    StateFilter::COMMENT,
-                    egui_phosphor::regular::CHAT_TEXT,
-                );
+                // Filters live in their own row so the panel fits on narrow screens
+                if ui
+                    .selectable_label(
+                        self.stored.filters_open,
+                        format!("{} Filters", egui_phosphor::regular::FUNNEL),
+                    )
+                    .clicked()
+                {
+                    self.stored.filters_open = !self.stored.filters_open;
+                }
+                egui::ComboBox::from_id_salt("sort_order")
+                    .selected_text(format!(
+                        "{} {}",
+                        egui_phosphor::regular::SORT_ASCENDING,
+                        self.stored.sort_order.name()
+                    ))
+                    .show_ui(ui, |ui| {
+                        for order in SortOrder::ALL {
+                            ui.selectable_value(&mut self.stored.sort_order, order, order.name());
+                        }
+                    });
+                ui.separator();
+                let search = TextEdit::singleline(&mut self.search)
+                    .hint_text(format!("{} Search", egui_phosphor::regular::MAGNIFYING_GLASS))
+                    .desired_width(160.0)
+                    .ui(ui);
+                if std::mem::take(&mut self.focus_search) {
+                    search.request_focus();
+                }
             });
+            if self.stored.filters_open {
+                ui.horizontal_wrapped(|ui| {
+                    render_statefilter_button(
+                        ui,
+                        &mut self.state_filter,
+                        StateFilter::LOVED,
+                        egui_phosphor::regular::HEART,
+                    );
+                    render_statefilter_button(
+                        ui,
+                        &mut self.state_filter,
+                        StateFilter::LIKED,
+                        egui_phosphor::regular::THUMBS_UP,
+                    );
+                    render_statefilter_button(
+                        ui,
+                        &mut self.state_filter,
+                        StateFilter::NEUTRAL,
+                        egui_phosphor::regular::ALIGN_CENTER_HORIZONTAL_SIMPLE,
+                    );
+                    render_statefilter_button(
+                        ui,
+                        &mut self.state_filter,
+                        StateFilter::DISLIKED,
+                        egui_phosphor::regular::THUMBS_DOWN,
+                    );
+                    render_statefilter_button(
+                        ui,
+                        &mut self.state_filter,
+                        StateFilter::COMMENT,
+                        egui_phosphor::regular::CHAT_TEXT,
+                    );
+                    render_statefilter_button(
+                        ui,
+                        &mut self.state_filter,
+                        StateFilter::PINNED,
+                        egui_phosphor::regular::PUSH_PIN,
+                    );
+
+                    // Temperature filter buttons
+                    ui.separator();
+                    render_statefilter_button(
+                        ui,
+                        &mut self.state_filter,
+                        StateFilter::WARM,
+                        egui_phosphor::regular::THERMOMETER_HOT,
+                    );
+                    render_statefilter_button(
+                        ui,
+                        &mut self.state_filter,
+                        StateFilter::COOL,
+                        egui_phosphor::regular::THERMOMETER_COLD,
+                    );
+                    render_statefilter_button(
+                        ui,
+                        &mut self.state_filter,
+                        StateFilter::NEUTRAL_TEMPERATURE,
+                        egui_phosphor::regular::THERMOMETER_SIMPLE,
+                    );
+
+                    // Accent hue filter, previews what smartget's hue query would pick from
+                    ui.separator();
+                    let mut hue_enabled = self.hue_filter.is_some();
+                    if ui
+                        .checkbox(&mut hue_enabled, "Accent hue")
+                        .on_hover_text("Only show wallpapers with a hue near an accent colour")
+                        .changed()
+                    {
+                        self.hue_filter = hue_enabled.then_some((210, 30));
+                    }
+                    if let Some((hue, tolerance)) = &mut self.hue_filter {
+                        let (rect, _) = ui.allocate_exact_size(vec2(16.0, 16.0), Sense::hover());
+                        ui.painter().circle_filled(
+                            rect.center(),
+                            7.0,
+                            egui::ecolor::Hsva::new(f32::from(*hue) / 360.0, 0.8, 0.9, 1.0),
+                        );
+                        egui::DragValue::new(hue).range(0..=359).suffix("°").ui(ui);
+                        egui::DragValue::new(tolerance)
+                            .range(0..=180)
+                            .prefix("±")
+                            .suffix("°")
+                            .ui(ui);
+                        ui.weak(format!("/smartget?hue={hue}&hue_tolerance={tolerance}"));
+                    }
+                });
+
+                // Tag cloud of the whole collection, click a tag to filter by it
+                if let Some(database) = &self.database {
+                    let mut tag_hues: HashMap<&str, Vec<f32>> = HashMap::new();
+                    for wallpaper in database.wallpapers.values() {
+                        for tag in &wallpaper.tags {
+                            tag_hues
+                                .entry(tag)
+                                .or_default()
+                                .push(wallpaper.color_data.hue * 360.0);
+                        }
+                    }
+                    if tag_hues.values().any(|hues| hues.len() >= MIN_TAG_COUNT) {
+                        let counts = tag_hues
+                            .iter()
+                            .map(|(tag, hues)| ((*tag).to_string(), hues.len()))
+                            .collect::<HashMap<_, _>>();
+                        let hues = tag_hues
+                            .into_iter()
+                            .filter_map(|(tag, hues)| Some((tag.to_string(), mean_hue(hues)?)))
+                            .collect::<HashMap<_, _>>();
+                        ui.collapsing(format!("{} Tags", egui_phosphor::regular::TAG), |ui| {
+                            let clicked = TagCloud::new(&counts, &hues)
+                                .selected(self.tag_filter.as_deref())
+                                .show(ui);
+                            if let Some(tag) = clicked {
+                                // Clicking the selected tag again clears the filter
+                                self.tag_filter =
+                                    (self.tag_filter.as_ref() != Some(&tag)).then_some(tag);
+                            }
+                        });
+                    }
+                }
+            }
             if let Some(database) = &mut self.database {
+                if self.offline {
+                    ui.disable();
+                }
+
+                // Named snapshots of every style field, to swap between whole looks at once
                 ui.horizontal(|ui| {
-                    if TextEdit::multiline(&mut database.style.style)
-                        .desired_width(f32::INFINITY)
-                        .hint_text("What styles of wallpapers should it aim for (painted, realistic, etc.)?")
-                        .ui(ui)
-                        .changed()
+                    let mut names = database.presets.keys().collect::<Vec<_>>();
+                    names.sort();
+                    egui::ComboBox::from_id_salt("style_preset")
+                        .selected_text("Presets")
+                        .show_ui(ui, |ui| {
+                            for name in names {
+                                ui.selectable_value(&mut self.style_preset, name.clone(), name);
+                            }
+                        });
+                    TextEdit::singleline(&mut self.style_preset)
+                        .hint_text("Preset name")
+                        .desired_width(160.0)
+                        .ui(ui);
+                    let name = self.style_preset.trim().to_string();
+                    let exists = database.presets.contains_key(&name);
+                    let mut action = None;
+                    if ui
+                        .add_enabled(!name.is_empty(), egui::Button::new("Save"))
+                        .on_hover_text("Save the current style under this name")
+                        .clicked()
+                    {
+                        action = Some((PresetAction::Save, "Saved style preset"));
+                    }
+                    if ui.add_enabled(exists, egui::Button::new("Apply")).clicked() {
+                        action = Some((PresetAction::Apply, "Applied style preset"));
+                    }
+                    if ui
+                        .add_enabled(exists, egui::Button::new(egui_phosphor::regular::TRASH))
+                        .clicked()
                     {
+                        action = Some((PresetAction::Delete, "Deleted style preset"));
+                    }
+                    if let Some((action, message)) = action {
                         let toasts_store = self.toasts.clone();
-                        edit_styles(
+                        let network_store = self.network_data.clone();
+                        let ctx = ui.ctx().clone();
+                        style_preset(
                             &self.host,
                             &self.stored.auth_token,
-                            StyleVariant::Style,
-                            database.style.style.trim(),
-                            move |result| match result {
-                                Ok(()) => {}
-                                Err(e) => {
-                                    toasts_store
-                                        .lock()
-                                        .error(format!("Failed to update style: {e}"));
-                                }
+                            action,
+                            &name,
+                            move |result| {
+                                ctx.request_repaint();
+                                button_pressed_result(
+                                    result,
+                                    &network_store,
+                                    &toasts_store,
+                                    message,
+                                );
                             },
                         );
                     }
                 });
+
                 ui.horizontal(|ui| {
-                    if TextEdit::multiline(&mut database.style.contents)
+                    if TextEdit::multiline(&mut database.style.style)
                         .desired_width(f32::INFINITY)
-                        .hint_text("What contents of wallpapers should it aim for (epic fantasy, surreal, abstract, etc.)?")
+                        .hint_text("What styles of wallpapers should it aim for (painted, realistic, etc.)?")
                         .ui(ui)
                         .changed()
                     {
@@ -255,23 +1204,88 @@ impl Wallpapy {
                         edit_styles(
                             &self.host,
                             &self.stored.auth_token,
-                            StyleVariant::Contents,
-                            database.style.contents.trim(),
+                            StyleVariant::Style(database.style.style.trim().to_string()),
                             move |result| match result {
                                 Ok(()) => {}
                                 Err(e) => {
                                     toasts_store
                                         .lock()
-                                        .error(format!("Failed to update contents: {e}"));
+                                        .error(format!("Failed to update style: {e}"));
                                 }
                             },
                         );
                     }
                 });
-                ui.horizontal(|ui| {
-                    if TextEdit::multiline(&mut database.style.negative_contents)
-                        .desired_width(f32::INFINITY)
-                        .hint_text("What should never be included in wallpapers?")
+                // Content themes in priority order, drag the handle to reorder
+                let themes = &mut database.style.contents;
+                let mut changed = false;
+                let mut moved = None;
+                let mut removed = None;
+                for (index, theme) in themes.iter_mut().enumerate() {
+                    let row = ui.horizontal(|ui| {
+                        ui.dnd_drag_source(ui.id().with(("content_theme", index)), index, |ui| {
+                            ui.label(egui_phosphor::regular::DOTS_SIX_VERTICAL);
+                        });
+                        changed |= TextEdit::singleline(&mut theme.text)
+                            .hint_text("Epic fantasy, surreal, abstract...")
+                            .ui(ui)
+                            .changed();
+                        changed |= ui
+                            .add(
+                                egui::Slider::new(
+                                    &mut theme.weight,
+                                    ContentTheme::MIN_WEIGHT..=ContentTheme::MAX_WEIGHT,
+                                )
+                                .step_by(0.5)
+                                .text(theme.emphasis().unwrap_or("include")),
+                            )
+                            .drag_stopped();
+                        if ui.button(egui_phosphor::regular::TRASH).clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                    if let Some(from) = row.response.dnd_release_payload::<usize>() {
+                        moved = Some((*from, index));
+                    }
+                }
+                if let Some((from, to)) = moved.filter(|(from, to)| from != to) {
+                    let theme = themes.remove(from);
+                    themes.insert(to, theme);
+                    changed = true;
+                }
+                if let Some(index) = removed {
+                    themes.remove(index);
+                    changed = true;
+                }
+                if ui
+                    .button(format!("{} Add Theme", egui_phosphor::regular::PLUS))
+                    .clicked()
+                {
+                    themes.push(ContentTheme {
+                        text: String::new(),
+                        weight: 1.0,
+                    });
+                }
+                if changed {
+                    let toasts_store = self.toasts.clone();
+                    edit_styles(
+                        &self.host,
+                        &self.stored.auth_token,
+                        StyleVariant::ContentThemes(themes.clone()),
+                        move |result| match result {
+                            Ok(()) => {}
+                            Err(e) => {
+                                toasts_store
+                                    .lock()
+                                    .error(format!("Failed to update contents: {e}"));
+                            }
+                        },
+                    );
+                }
+                ui.horizontal(|ui| {
+                    if TextEdit::multiline(&mut database.style.negative_contents)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("What should never be included in wallpapers?")
                         .ui(ui)
                         .changed()
                     {
@@ -279,8 +1293,9 @@ impl Wallpapy {
                         edit_styles(
                             &self.host,
                             &self.stored.auth_token,
-                            StyleVariant::NegativeContents,
-                            database.style.negative_contents.trim(),
+                            StyleVariant::NegativeContents(
+                                database.style.negative_contents.trim().to_string(),
+                            ),
                             move |result| match result {
                                 Ok(()) => {}
                                 Err(e) => {
@@ -292,14 +1307,207 @@ impl Wallpapy {
                         );
                     }
                 });
+
+                // How long the written prompts are, and anything else the prompt writer should know
+                ui.horizontal(|ui| {
+                    ui.label("Prompt length");
+                    let prompt_length = &mut database.style.prompt_length;
+                    let previous = *prompt_length;
+                    for length in PromptLength::ALL {
+                        ui.radio_value(prompt_length, length, length.name());
+                    }
+                    if *prompt_length != previous {
+                        let toasts_store = self.toasts.clone();
+                        edit_styles(
+                            &self.host,
+                            &self.stored.auth_token,
+                            StyleVariant::PromptLength(*prompt_length),
+                            move |result| match result {
+                                Ok(()) => {}
+                                Err(e) => {
+                                    toasts_store
+                                        .lock()
+                                        .error(format!("Failed to update prompt length: {e}"));
+                                }
+                            },
+                        );
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if TextEdit::multiline(&mut database.style.extra_instructions)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("Anything else the prompt writer should follow?")
+                        .ui(ui)
+                        .changed()
+                    {
+                        let toasts_store = self.toasts.clone();
+                        edit_styles(
+                            &self.host,
+                            &self.stored.auth_token,
+                            StyleVariant::ExtraInstructions(
+                                database.style.extra_instructions.trim().to_string(),
+                            ),
+                            move |result| match result {
+                                Ok(()) => {}
+                                Err(e) => {
+                                    toasts_store.lock().error(format!(
+                                        "Failed to update extra instructions: {e}"
+                                    ));
+                                }
+                            },
+                        );
+                    }
+                });
+
+                // How much history is sent to the LLM
+                ui.horizontal(|ui| {
+                    let config = &mut database.history_config;
+                    let mut changed = false;
+                    for (label, value) in [
+                        ("Loved", &mut config.max_loved),
+                        ("Recent", &mut config.recent_count),
+                        ("Disliked", &mut config.max_disliked),
+                        ("Comments", &mut config.max_comments),
+                        ("Summarised", &mut config.max_summarised),
+                    ] {
+                        ui.label(label);
+                        changed |= egui::DragValue::new(value).range(0..=200).ui(ui).changed();
+                    }
+                    if changed {
+                        let toasts_store = self.toasts.clone();
+                        edit_history_config(
+                            &self.host,
+                            &self.stored.auth_token,
+                            *config,
+                            move |result| match result {
+                                Ok(()) => {}
+                                Err(e) => {
+                                    toasts_store
+                                        .lock()
+                                        .error(format!("Failed to update history config: {e}"));
+                                }
+                            },
+                        );
+                    }
+
+                    // Force a seasonal theme regardless of the calendar
+                    let season_override = &mut database.style.season_override;
+                    let previous = *season_override;
+                    egui::ComboBox::from_label("Season override")
+                        .selected_text(season_override.map_or("Calendar", Season::name))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(season_override, None, "Calendar");
+                            for season in
+                                [Season::Winter, Season::Spring, Season::Summer, Season::Autumn]
+                            {
+                                ui.selectable_value(season_override, Some(season), season.name());
+                            }
+                        });
+                    if *season_override != previous {
+                        let toasts_store = self.toasts.clone();
+                        edit_season(
+                            &self.host,
+                            &self.stored.auth_token,
+                            *season_override,
+                            move |result| match result {
+                                Ok(()) => {}
+                                Err(e) => {
+                                    toasts_store
+                                        .lock()
+                                        .error(format!("Failed to update season: {e}"));
+                                }
+                            },
+                        );
+                    }
+                });
+
+                // Auto-delete policies are set by the server environment
+                if let Some(policy) = self.retention_policy {
+                    let describe = |days: u32| {
+                        if days == 0 {
+                            "never".to_string()
+                        } else {
+                            format!("after {days} days")
+                        }
+                    };
+                    ui.label(format!(
+                        "{} Auto-delete disliked {}, neutral {}",
+                        egui_phosphor::regular::TRASH,
+                        describe(policy.disliked_days),
+                        describe(policy.neutral_days)
+                    ));
+                }
             }
         });
 
+        self.show_history_window(ctx);
+        self.show_audit_window(ctx);
+        self.show_activity_window(ctx);
+        self.show_server_logs_window(ctx);
+        self.show_maintenance_window(ctx);
+        self.show_account_window(ctx);
+        self.show_template_window(ctx);
+        self.show_profile_window(ctx);
+        self.show_generation_context(ctx);
+        self.show_remove_confirmation(ctx);
+        self.show_recreate_edit_window(ctx);
+        self.show_selection_bar(ctx);
+
+        // Upload any images dropped onto the window, or generate from them when inspiring
+        for file in ctx.input_mut(|i| std::mem::take(&mut i.raw.dropped_files)) {
+            // Web builds receive the bytes directly, native builds receive a path
+            #[cfg(target_arch = "wasm32")]
+            let data = file.bytes.map(|bytes| bytes.to_vec());
+            #[cfg(not(target_arch = "wasm32"))]
+            let data = file
+                .bytes
+                .map(|bytes| bytes.to_vec())
+                .or_else(|| file.path.and_then(|path| std::fs::read(path).ok()));
+
+            match data {
+                Some(_) if self.offline => {
+                    self.toasts
+                        .lock()
+                        .error(format!("Offline, can't upload {}", file.name));
+                }
+                Some(data) if self.inspire_drops => self.generate_from_image(ctx, data),
+                Some(data) => self.upload_image(ctx, data),
+                None => {
+                    self.toasts
+                        .lock()
+                        .error(format!("Failed to read dropped file {}", file.name));
+                }
+            }
+        }
+
+        // After "Paste URL" the next paste is checked for image links to import
+        if self.awaiting_paste {
+            let pasted = ctx.input(|i| {
+                i.events.iter().find_map(|event| match event {
+                    egui::Event::Paste(text) => Some(text.clone()),
+                    _ => None,
+                })
+            });
+            if let Some(text) = pasted {
+                self.awaiting_paste = false;
+                let urls = pasted_image_urls(&text);
+                if urls.is_empty() {
+                    self.toasts.lock().error("No image link in the clipboard");
+                }
+                for url in urls {
+                    self.import_from_url(ctx, url);
+                }
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             let mut new_fullscreen = None;
+            let mut close_fullscreen = false;
             // If escape pressed, close the fullscreen image
             if ui.input(|i| i.key_pressed(Key::Escape)) {
                 self.fullscreen_image = None;
+                self.crop.active = false;
+                self.fullscreen = FullscreenState::FIT;
             }
 
             let refresh_response = PullToRefresh::new(false).scroll_area_ui(ui, |ui| {
@@ -314,18 +1522,174 @@ impl Wallpapy {
                         })
                     });
                     if let Some(wallpaper) = &wallpaper {
-                        let file = wallpaper
-                            .upscaled_file
-                            .as_ref()
-                            .map_or(&wallpaper.original_file, |upscaled_file| upscaled_file);
+                        let file = full_size_file(wallpaper);
+                        let image_aspect = file.width as f32 / file.height.max(1) as f32;
+                        let mut crop = self.crop;
+                        let mut view = self.fullscreen;
+                        let mut swipe_drag = self.swipe;
+                        let mut swipe = None;
+                        let mut overlay = std::mem::take(&mut self.stats_overlay);
+                        let mut note = std::mem::take(&mut self.note_draft);
+                        let mut load_timing = self.load_timing;
+                        let prefetch_count = self.prefetch_count();
+                        if note.id != Some(wallpaper.id) {
+                            note = NoteDraft {
+                                id: Some(wallpaper.id),
+                                text: wallpaper.note.clone().unwrap_or_default(),
+                            };
+                        }
                         ui.vertical(|ui| {
-                            Image::new(format!(
+                            // Fit the image in view, then draw it zoomed and panned inside that space
+                            let available = vec2(
+                                ui.available_width(),
+                                ui.available_height().min(ui.ctx().screen_rect().height()),
+                            );
+                            let fit_size = if available.x / image_aspect <= available.y {
+                                vec2(available.x, available.x / image_aspect)
+                            } else {
+                                vec2(available.y * image_aspect, available.y)
+                            };
+                            let (image_rect, _) = ui.allocate_exact_size(fit_size, Sense::hover());
+                            let painter = ui.painter_at(image_rect);
+                            let uv = Rect::from_min_max(Pos2::ZERO, pos2(1.0, 1.0));
+                            let full_poll = Image::new(format!(
                                 "http://{}/wallpapers/{}",
                                 self.host, file.file_name
                             ))
-                            .show_loading_spinner(false)
-                            .rounding(16.0)
-                            .ui(ui);
+                            .load_for_size(ui.ctx(), image_rect.size());
+
+                            // The thumbnail is usually cached from the grid, so stretch it over the
+                            // whole view until the full image has faded in on top
+                            let ready = matches!(full_poll, Ok(TexturePoll::Ready { .. }));
+                            let fade = ui.ctx().animate_bool_with_time(
+                                ui.id().with(("fullscreen_fade", wallpaper.id)),
+                                ready,
+                                FULLSCREEN_FADE_SECS,
+                            );
+                            if fade < 1.0 {
+                                if let Ok(TexturePoll::Ready { texture }) = Image::new(format!(
+                                    "http://{}/wallpapers/{}",
+                                    self.host, wallpaper.thumbnail_file.file_name
+                                ))
+                                .load_for_size(ui.ctx(), image_rect.size())
+                                {
+                                    painter.image(
+                                        texture.id,
+                                        view.image_rect(image_rect),
+                                        uv,
+                                        Color32::WHITE,
+                                    );
+                                }
+                            }
+
+                            let now = ui.input(|i| i.time);
+                            match (&full_poll, load_timing.pending) {
+                                (Ok(TexturePoll::Pending { .. }), pending)
+                                    if pending.map(|(id, _)| id) != Some(wallpaper.id) =>
+                                {
+                                    load_timing.pending = Some((wallpaper.id, now));
+                                }
+                                (Ok(TexturePoll::Ready { .. }), Some((id, started)))
+                                    if id == wallpaper.id =>
+                                {
+                                    load_timing.first_load_secs.get_or_insert(now - started);
+                                    load_timing.pending = None;
+                                }
+                                _ => {}
+                            }
+
+                            match full_poll {
+                                Ok(TexturePoll::Ready { texture }) => painter.image(
+                                    texture.id,
+                                    view.image_rect(image_rect),
+                                    uv,
+                                    Color32::WHITE.gamma_multiply(fade),
+                                ),
+                                // Second stage, the full image is loading over the stretched thumbnail
+                                Ok(TexturePoll::Pending { .. }) => {
+                                    egui::Spinner::new().paint_at(
+                                        ui,
+                                        Rect::from_center_size(
+                                            image_rect.center(),
+                                            Vec2::splat(FULLSCREEN_SPINNER_SIZE),
+                                        ),
+                                    );
+                                }
+                                Err(e) => {
+                                    painter.text(
+                                        image_rect.center(),
+                                        Align2::CENTER_CENTER,
+                                        e.to_string(),
+                                        FontId::proportional(16.0),
+                                        ui.visuals().error_fg_color,
+                                    );
+                                }
+                            }
+
+                            if crop.active {
+                                draw_crop_overlay(ui, image_rect, &mut crop, image_aspect);
+                            } else {
+                                let response = ui.interact(
+                                    image_rect,
+                                    ui.id().with("view"),
+                                    Sense::click_and_drag(),
+                                );
+                                if response.double_clicked() {
+                                    view = FullscreenState::FIT;
+                                }
+
+                                // Pinch or scroll over the image to zoom around the pointer
+                                if let Some(anchor) = response.hover_pos() {
+                                    let factor = ui.input(|i| {
+                                        i.zoom_delta() * (i.smooth_scroll_delta.y / 200.0).exp()
+                                    });
+                                    if (factor - 1.0).abs() > f32::EPSILON {
+                                        view.zoom_at(image_rect, anchor, factor);
+                                        ui.input_mut(|i| i.smooth_scroll_delta = Vec2::ZERO);
+                                    }
+                                }
+
+                                if view.zoom > 1.0 {
+                                    // Dragging pans while zoomed in
+                                    if response.dragged_by(PointerButton::Primary) {
+                                        view.pan += ui.input(|i| i.pointer.delta());
+                                        view.clamp_pan(image_rect);
+                                    }
+                                    swipe_drag = Vec2::ZERO;
+                                } else {
+                                    // Swipe sideways to step through wallpapers, or down to close
+                                    if response.dragged() {
+                                        swipe_drag += response.drag_delta();
+                                    }
+                                    if response.drag_stopped() {
+                                        swipe = Swipe::from_drag(swipe_drag);
+                                        swipe_drag = Vec2::ZERO;
+                                    }
+                                }
+                            }
+                            if overlay.open {
+                                match overlay.stats {
+                                    Some((id, stats)) if id == wallpaper.id => {
+                                        draw_stats_overlay(ui, image_rect, &stats);
+                                    }
+                                    _ if overlay.loading != Some(wallpaper.id) => {
+                                        overlay.loading = Some(wallpaper.id);
+                                        let network_store = self.network_data.clone();
+                                        let ctx = ui.ctx().clone();
+                                        let id = wallpaper.id;
+                                        get_image_stats(
+                                            &self.host,
+                                            &wallpaper.thumbnail_file.file_name,
+                                            move |result| {
+                                                network_store.lock().image_stats =
+                                                    Some((id, result));
+                                                ctx.request_repaint();
+                                            },
+                                        );
+                                    }
+                                    _ => {}
+                                }
+                            }
 
                             let font_id = FontId::proportional(20.0);
                             if ui
@@ -353,6 +1717,91 @@ impl Wallpapy {
                                     self.toasts.lock().info("Prompt copied to clipboard");
                                 });
                             }
+                            if let Some(negative_prompt) = &wallpaper.prompt_data.negative_prompt {
+                                ui.label(
+                                    RichText::new(format!(
+                                        "{} Avoiding: {negative_prompt}",
+                                        egui_phosphor::regular::PROHIBIT
+                                    ))
+                                    .weak(),
+                                );
+                            }
+                            if let Some(weather) = &wallpaper.weather_context {
+                                ui.label(
+                                    RichText::new(format!(
+                                        "{} Weather: {weather}",
+                                        egui_phosphor::regular::CLOUD_SUN
+                                    ))
+                                    .weak(),
+                                );
+                            }
+                            if wallpaper.prompt_data.was_refined {
+                                ui.label(
+                                    RichText::new(format!(
+                                        "{} Refined by a critique pass",
+                                        egui_phosphor::regular::MAGIC_WAND
+                                    ))
+                                    .weak(),
+                                );
+                            }
+                            if wallpaper.moodboard_influence {
+                                ui.label(
+                                    RichText::new(format!(
+                                        "{} Influenced by the moodboard",
+                                        egui_phosphor::regular::PALETTE
+                                    ))
+                                    .weak(),
+                                );
+                            }
+                            // Most controls below change the wallpaper, so they're read only offline
+                            if self.offline {
+                                ui.disable();
+                            }
+
+                            // Star rating, clicking the current rating clears it
+                            ui.horizontal(|ui| {
+                                for stars in 1..=5 {
+                                    let filled =
+                                        wallpaper.rating.is_some_and(|rating| rating >= stars);
+                                    if ui
+                                        .add(
+                                            egui::Button::new(
+                                                RichText::new(egui_phosphor::regular::STAR)
+                                                    .font(font_id.clone())
+                                                    .color(if filled {
+                                                        Color32::GOLD
+                                                    } else {
+                                                        Color32::GRAY
+                                                    }),
+                                            )
+                                            .frame(false),
+                                        )
+                                        .on_hover_text(format!("{stars} stars"))
+                                        .clicked()
+                                    {
+                                        let rating = Some(stars)
+                                            .filter(|stars| wallpaper.rating != Some(*stars));
+                                        let toasts_store = self.toasts.clone();
+                                        let network_store = self.network_data.clone();
+                                        let ctx = ui.ctx().clone();
+                                        rate_image(
+                                            &self.host,
+                                            &self.stored.auth_token,
+                                            &wallpaper.id,
+                                            rating,
+                                            move |result| {
+                                                ctx.request_repaint();
+                                                button_pressed_result(
+                                                    result,
+                                                    &network_store,
+                                                    &toasts_store,
+                                                    "",
+                                                );
+                                            },
+                                        );
+                                    }
+                                }
+                            });
                             ui.horizontal(|ui| {
                                 ui.label(
                                     RichText::new(format!(
@@ -371,109 +1820,2297 @@ impl Wallpapy {
                                     .strong(),
                                 );
                                 ui.label(
-                                    RichText::new(format!(
-                                        "Top20 {}%  Bot20 {}%  Contrast {:.1}",
-                                        (wallpaper.color_data.top_20_percent_brightness * 100.0)
-                                            as i32,
-                                        (wallpaper.color_data.bottom_20_percent_brightness * 100.0)
-                                            as i32,
-                                        wallpaper.color_data.contrast_ratio
-                                    ))
-                                    .font(font_id.clone())
-                                    .background_color(Color32::DARK_GRAY)
-                                    .color(Color32::WHITE)
-                                    .strong(),
+                                    RichText::new(format!(
+                                        "Top20 {}%  Bot20 {}%  Contrast {:.1}  Sharpness {:.0}",
+                                        (wallpaper.color_data.top_20_percent_brightness * 100.0)
+                                            as i32,
+                                        (wallpaper.color_data.bottom_20_percent_brightness * 100.0)
+                                            as i32,
+                                        wallpaper.color_data.contrast_ratio,
+                                        wallpaper.color_data.sharpness_score
+                                    ))
+                                    .font(font_id.clone())
+                                    .background_color(Color32::DARK_GRAY)
+                                    .color(Color32::WHITE)
+                                    .strong(),
+                                );
+                                if let Some(usage) = wallpaper.llm_usage {
+                                    ui.label(
+                                        RichText::new(format!(
+                                            "Cost ${:.4}  {} tokens",
+                                            usage.estimated_cost_usd, usage.total_tokens
+                                        ))
+                                        .font(font_id.clone())
+                                        .background_color(Color32::DARK_GRAY)
+                                        .color(Color32::WHITE)
+                                        .strong(),
+                                    );
+                                }
+                                if wallpaper.candidates_evaluated > 1 {
+                                    ui.label(
+                                        RichText::new(format!(
+                                            "Best of {}",
+                                            wallpaper.candidates_evaluated
+                                        ))
+                                        .font(font_id.clone())
+                                        .background_color(Color32::DARK_GRAY)
+                                        .color(Color32::WHITE)
+                                        .strong(),
+                                    );
+                                }
+                                if wallpaper.source == Source::Generated {
+                                    let generated_by = match wallpaper.generated_by {
+                                        Some(uuid) => format!(
+                                            "Generated by: {}",
+                                            self.database
+                                                .as_ref()
+                                                .and_then(|database| database.users.get(&uuid))
+                                                .map_or("Deleted account", String::as_str)
+                                        ),
+                                        None => "Auto-generated".to_string(),
+                                    };
+                                    ui.label(
+                                        RichText::new(generated_by)
+                                            .font(font_id.clone())
+                                            .background_color(Color32::DARK_GRAY)
+                                            .color(Color32::WHITE)
+                                            .strong(),
+                                    );
+                                }
+                            });
+                            // Tags the LLM pulled from the prompt, lighter than the stats above
+                            if !wallpaper.tags.is_empty() {
+                                ui.horizontal_wrapped(|ui| {
+                                    for tag in &wallpaper.tags {
+                                        ui.label(
+                                            RichText::new(format!(
+                                                "{} {tag}",
+                                                egui_phosphor::regular::TAG
+                                            ))
+                                            .font(font_id.clone())
+                                            .background_color(Color32::from_black_alpha(120))
+                                            .color(Color32::LIGHT_GRAY),
+                                        )
+                                        .on_hover_text("Extracted from the prompt");
+                                    }
+                                });
+                            }
+                            if !wallpaper.color_data.brightness_histogram.is_empty() {
+                                draw_brightness_histogram(ui, &wallpaper.color_data);
+                            }
+
+                            // Personal note, saved when the text box loses focus
+                            let note_response = TextEdit::multiline(&mut note.text)
+                                .desired_width(f32::INFINITY)
+                                .desired_rows(2)
+                                .font(font_id.clone())
+                                .hint_text(
+                                    "Note on this wallpaper, like great for the left monitor",
+                                )
+                                .ui(ui);
+                            if note_response.lost_focus()
+                                && note.text.trim() != wallpaper.note.as_deref().unwrap_or_default()
+                            {
+                                let toasts_store = self.toasts.clone();
+                                let network_store = self.network_data.clone();
+                                let ctx = ui.ctx().clone();
+                                set_image_note(
+                                    &self.host,
+                                    &self.stored.auth_token,
+                                    &wallpaper.id,
+                                    note.text.trim(),
+                                    move |result| {
+                                        ctx.request_repaint();
+                                        button_pressed_result(
+                                            result,
+                                            &network_store,
+                                            &toasts_store,
+                                            "Saved note",
+                                        );
+                                    },
+                                );
+                            }
+
+                            // Rotation and flip buttons
+                            ui.horizontal(|ui| {
+                                for (icon, rotation, flip_horizontal, flip_vertical) in [
+                                    (
+                                        egui_phosphor::regular::ARROW_CLOCKWISE,
+                                        Some(90),
+                                        false,
+                                        false,
+                                    ),
+                                    (egui_phosphor::regular::FLIP_HORIZONTAL, None, true, false),
+                                    (egui_phosphor::regular::FLIP_VERTICAL, None, false, true),
+                                ] {
+                                    if ui
+                                        .button(RichText::new(icon).font(font_id.clone()))
+                                        .clicked()
+                                    {
+                                        let toasts_store = self.toasts.clone();
+                                        let network_store = self.network_data.clone();
+                                        let ctx = ui.ctx().clone();
+                                        transform_image(
+                                            &self.host,
+                                            &self.stored.auth_token,
+                                            &wallpaper.id,
+                                            rotation,
+                                            flip_horizontal,
+                                            flip_vertical,
+                                            move |result| {
+                                                ctx.request_repaint();
+                                                button_pressed_result(
+                                                    result,
+                                                    &network_store,
+                                                    &toasts_store,
+                                                    "",
+                                                );
+                                            },
+                                        );
+                                    }
+                                }
+
+                                if ui
+                                    .button(
+                                        RichText::new(format!(
+                                            "{} Download",
+                                            egui_phosphor::regular::DOWNLOAD_SIMPLE
+                                        ))
+                                        .font(font_id.clone()),
+                                    )
+                                    .on_hover_text(format!(
+                                        "{}x{}, about {}",
+                                        file.width,
+                                        file.height,
+                                        format_bytes(file.estimated_size_bytes())
+                                    ))
+                                    .clicked()
+                                {
+                                    let toasts_store = self.toasts.clone();
+                                    download_wallpaper(
+                                        &self.host,
+                                        &file.file_name,
+                                        move |result| match result {
+                                            Ok(()) => {
+                                                toasts_store.lock().info("Downloaded wallpaper");
+                                            }
+                                            Err(e) => {
+                                                toasts_store.lock().error(e.to_string());
+                                            }
+                                        },
+                                    );
+                                }
+
+                                if ui
+                                    .button(
+                                        RichText::new(format!(
+                                            "{} Share",
+                                            egui_phosphor::regular::SHARE_NETWORK
+                                        ))
+                                        .font(font_id.clone()),
+                                    )
+                                    .on_hover_text("Copy a link that works without logging in")
+                                    .clicked()
+                                {
+                                    let toasts_store = self.toasts.clone();
+                                    let ctx = ui.ctx().clone();
+                                    let host = self.host.clone();
+                                    create_share_link(
+                                        &self.host,
+                                        &self.stored.auth_token,
+                                        &wallpaper.id,
+                                        move |result| match result {
+                                            Ok(token) => {
+                                                ctx.output_mut(|o| {
+                                                    o.copied_text =
+                                                        format!("http://{host}/share/{token}");
+                                                });
+                                                toasts_store
+                                                    .lock()
+                                                    .info("Share link copied to clipboard");
+                                                ctx.request_repaint();
+                                            }
+                                            Err(e) => {
+                                                toasts_store.lock().error(e.to_string());
+                                            }
+                                        },
+                                    );
+                                }
+
+                                if ui
+                                    .button(
+                                        RichText::new(format!(
+                                            "{} Variation",
+                                            egui_phosphor::regular::SHUFFLE
+                                        ))
+                                        .font(font_id.clone()),
+                                    )
+                                    .on_hover_text(
+                                        "Generate a new wallpaper on the same theme that looks different",
+                                    )
+                                    .clicked()
+                                {
+                                    self.generate_variation(ui.ctx(), wallpaper.id);
+                                }
+
+                                if ui
+                                    .button(
+                                        RichText::new(format!(
+                                            "{} Edit & recreate",
+                                            egui_phosphor::regular::PENCIL_SIMPLE
+                                        ))
+                                        .font(font_id.clone()),
+                                    )
+                                    .on_hover_text("Tweak the prompt before generating it again")
+                                    .clicked()
+                                {
+                                    self.recreate_edit =
+                                        Some((wallpaper.id, wallpaper.prompt_data.prompt.clone()));
+                                }
+
+                                if !wallpaper.dark_variant
+                                    && ui
+                                        .button(
+                                            RichText::new(format!(
+                                                "{} Dark variant",
+                                                egui_phosphor::regular::MOON
+                                            ))
+                                            .font(font_id.clone()),
+                                        )
+                                        .on_hover_text(
+                                            "Save a darkened copy, served at night instead when loved",
+                                        )
+                                        .clicked()
+                                {
+                                    let toasts_store = self.toasts.clone();
+                                    let network_store = self.network_data.clone();
+                                    let ctx = ui.ctx().clone();
+                                    darken_image(
+                                        &self.host,
+                                        &self.stored.auth_token,
+                                        &wallpaper.id,
+                                        DARK_VARIANT_BRIGHTNESS,
+                                        move |result| {
+                                            ctx.request_repaint();
+                                            button_pressed_result(
+                                                result,
+                                                &network_store,
+                                                &toasts_store,
+                                                "Saved dark variant",
+                                            );
+                                        },
+                                    );
+                                }
+
+                                // Links between recreations and the wallpaper they came from
+                                if let Some(database) = &self.database {
+                                    if let Some(original_id) = wallpaper
+                                        .original_id
+                                        .filter(|id| database.wallpapers.contains_key(id))
+                                    {
+                                        if ui
+                                            .button(
+                                                RichText::new(format!(
+                                                    "{} Original",
+                                                    egui_phosphor::regular::ARROW_BEND_UP_LEFT
+                                                ))
+                                                .font(font_id.clone()),
+                                            )
+                                            .on_hover_text("Open the wallpaper this was recreated from")
+                                            .clicked()
+                                        {
+                                            new_fullscreen = Some(original_id);
+                                        }
+                                    }
+                                    let variations = wallpaper
+                                        .variations
+                                        .iter()
+                                        .filter_map(|id| database.wallpapers.get(id))
+                                        .collect::<Vec<_>>();
+                                    if !variations.is_empty() {
+                                        ui.menu_button(
+                                            RichText::new(format!(
+                                                "{} Variations ({})",
+                                                egui_phosphor::regular::TREE_STRUCTURE,
+                                                variations.len()
+                                            ))
+                                            .font(font_id.clone()),
+                                            |ui| {
+                                                for variation in variations {
+                                                    if ui
+                                                        .button(
+                                                            &variation.prompt_data.shortened_prompt,
+                                                        )
+                                                        .clicked()
+                                                    {
+                                                        new_fullscreen = Some(variation.id);
+                                                        ui.close_menu();
+                                                    }
+                                                }
+                                            },
+                                        );
+                                    }
+                                }
+
+                                if wallpaper.context_file.is_some()
+                                    && ui
+                                        .button(
+                                            RichText::new(egui_phosphor::regular::SCROLL)
+                                                .font(font_id.clone()),
+                                        )
+                                        .on_hover_text("View generation context")
+                                        .clicked()
+                                {
+                                    let network_store = self.network_data.clone();
+                                    let toasts_store = self.toasts.clone();
+                                    let ctx = ui.ctx().clone();
+                                    let id = wallpaper.id;
+                                    get_image_context(
+                                        &self.host,
+                                        &self.stored.auth_token,
+                                        &id,
+                                        move |result| match result {
+                                            Ok(context) => {
+                                                network_store.lock().generation_context =
+                                                    Some((id, context));
+                                                ctx.request_repaint();
+                                            }
+                                            Err(e) => {
+                                                toasts_store.lock().error(e.to_string());
+                                            }
+                                        },
+                                    );
+                                }
+
+                                #[cfg(not(target_arch = "wasm32"))]
+                                if ui
+                                    .button(
+                                        RichText::new(format!(
+                                            "{} Set as wallpaper",
+                                            egui_phosphor::regular::MONITOR
+                                        ))
+                                        .font(font_id.clone()),
+                                    )
+                                    .clicked()
+                                {
+                                    self.set_desktop_wallpaper(format!(
+                                        "http://{}/wallpapers/{}",
+                                        self.host, file.file_name
+                                    ));
+                                }
+
+                                // Brightness per region and hue histogram, also toggled with I
+                                if ui
+                                    .selectable_label(
+                                        overlay.open,
+                                        RichText::new(egui_phosphor::regular::GRID_NINE)
+                                            .font(font_id.clone()),
+                                    )
+                                    .on_hover_text("Brightness overlay (I)")
+                                    .clicked()
+                                {
+                                    overlay.open = !overlay.open;
+                                }
+
+                                // Crop to a fixed aspect ratio, saved as a new wallpaper
+                                if ui
+                                    .selectable_label(
+                                        crop.active,
+                                        RichText::new(egui_phosphor::regular::CROP)
+                                            .font(font_id.clone()),
+                                    )
+                                    .clicked()
+                                {
+                                    crop.active = !crop.active;
+                                    crop.rect = crop.aspect.initial_rect(image_aspect);
+                                    view = FullscreenState::FIT;
+                                }
+                                if crop.active {
+                                    let previous = crop.aspect;
+                                    egui::ComboBox::from_id_salt("crop_aspect")
+                                        .selected_text(crop.aspect.name())
+                                        .show_ui(ui, |ui| {
+                                            for aspect in CropAspect::ALL {
+                                                ui.selectable_value(
+                                                    &mut crop.aspect,
+                                                    aspect,
+                                                    aspect.name(),
+                                                );
+                                            }
+                                        });
+                                    if crop.aspect != previous {
+                                        crop.rect = crop.aspect.initial_rect(image_aspect);
+                                    }
+                                    if ui
+                                        .add_enabled(
+                                            crop.rect.width() > 0.0 && crop.rect.height() > 0.0,
+                                            egui::Button::new(
+                                                RichText::new("Save crop").font(font_id.clone()),
+                                            ),
+                                        )
+                                        .clicked()
+                                    {
+                                        let toasts_store = self.toasts.clone();
+                                        let network_store = self.network_data.clone();
+                                        let ctx = ui.ctx().clone();
+                                        crop_image(
+                                            &self.host,
+                                            &self.stored.auth_token,
+                                            &wallpaper.id,
+                                            crop.rect,
+                                            move |result| {
+                                                ctx.request_repaint();
+                                                button_pressed_result(
+                                                    result,
+                                                    &network_store,
+                                                    &toasts_store,
+                                                    "Saved cropped wallpaper",
+                                                );
+                                            },
+                                        );
+                                        crop.active = false;
+                                    }
+                                }
+                            });
+                        });
+                        self.crop = crop;
+                        self.fullscreen = view;
+                        self.swipe = swipe_drag;
+                        self.stats_overlay = overlay;
+                        self.note_draft = note;
+                        self.load_timing = load_timing;
+                        close_fullscreen = swipe == Some(Swipe::Close);
+
+                        // Handle left and right arrow key press or swipe, unless typing in the note
+                        let typing = ui.ctx().memory(|m| m.focused().is_some());
+                        if !typing && ui.input(|i| i.key_pressed(Key::I)) {
+                            self.stats_overlay.open = !self.stats_overlay.open;
+                        }
+                        let left_pressed = swipe == Some(Swipe::Previous)
+                            || (!typing
+                                && ui.input(|i| {
+                                    i.key_pressed(Key::ArrowLeft) || i.key_pressed(Key::A)
+                                }));
+                        let right_pressed = swipe == Some(Swipe::Next)
+                            || (!typing
+                                && ui.input(|i| {
+                                    i.key_pressed(Key::ArrowRight) || i.key_pressed(Key::D)
+                                }));
+                        let order = self
+                            .database
+                            .as_ref()
+                            .map(navigation_order)
+                            .unwrap_or_default();
+                        let index = order.iter().position(|paper| paper.id == wallpaper.id);
+                        if let Some(index) = index {
+                            let target = if left_pressed {
+                                index.checked_sub(1)
+                            } else if right_pressed {
+                                Some(index + 1)
+                            } else {
+                                None
+                            };
+                            if let Some(target) = target.and_then(|target| order.get(target)) {
+                                new_fullscreen = Some(target.id);
+                            }
+
+                            // Load the neighbours ahead so stepping to them is instant, unless the
+                            // first load showed a slow connection on the web
+                            let slow = cfg!(target_arch = "wasm32")
+                                && self
+                                    .load_timing
+                                    .first_load_secs
+                                    .is_some_and(|secs| secs > SLOW_LOAD_SECS);
+                            if !slow {
+                                let count = prefetch_count as usize;
+                                let neighbours = order[index.saturating_sub(count)..index]
+                                    .iter()
+                                    .chain(order.iter().skip(index + 1).take(count));
+                                let size = ui.ctx().screen_rect().size();
+                                for neighbour in neighbours {
+                                    let _ = Image::new(format!(
+                                        "http://{}/wallpapers/{}",
+                                        self.host,
+                                        full_size_file(neighbour).file_name
+                                    ))
+                                    .load_for_size(ui.ctx(), size);
+                                }
+                            }
+                        }
+                    } else if self.gallery.open {
+                        self.draw_gallery(ui);
+                    } else if let Some(database) = self.database.clone() {
+                        let available_width = ui.available_width();
+                        let spacing = ui.spacing().item_spacing;
+                        let narrow = ui.ctx().screen_rect().width() < NARROW_WIDTH;
+                        let cell_width = if narrow { available_width } else { 400.0 };
+                        let columns = (available_width / (cell_width + spacing.x))
+                            .floor()
+                            .max(1.0) as usize;
+                        let cell_width = (columns as f32 - 1.0)
+                            .mul_add(-spacing.x, available_width / columns as f32);
+                        let cell_height = cell_width * 0.5625;
+
+                        if self.cluster_view {
+                            self.draw_clusters(ui, &database, cell_width, cell_height);
+                            return;
+                        }
+
+                        // Collect the wallpapers and comments into a single list, in the chosen order
+                        let mut combined_list = database
+                            .wallpapers
+                            .values()
+                            .filter(|wallpaper| self.passes_filter(wallpaper))
+                            .map(|wallpaper| (wallpaper.datetime, Some(wallpaper), None))
+                            .chain(
+                                database
+                                    .comments
+                                    .values()
+                                    .filter(|_| self.state_filter.contains(StateFilter::COMMENT))
+                                    .map(|comment| (comment.datetime, None, Some(comment))),
+                            )
+                            .collect::<Vec<_>>();
+                        let sort_order = self.stored.sort_order;
+                        let viewer = database.viewer;
+                        combined_list.sort_by(|a, b| {
+                            let pinned = |wallpaper: &Option<&WallpaperData>| {
+                                wallpaper.is_some_and(|wallpaper| wallpaper.pinned)
+                            };
+                            pinned(&b.1)
+                                .cmp(&pinned(&a.1))
+                                .then_with(|| sort_order.compare(viewer, *a, *b))
+                        });
+                        let combined_list = combined_list;
+                        self.selection.order = combined_list
+                            .iter()
+                            .filter_map(|(_, wallpaper, _)| wallpaper.map(|wallpaper| wallpaper.id))
+                            .collect();
+
+                        // Start again from the first page whenever the filters or order change
+                        let filter_key = (
+                            self.state_filter.bits(),
+                            self.stored.sort_order,
+                            self.search.clone(),
+                            self.hue_filter,
+                            self.tag_filter.clone(),
+                        );
+                        if self.grid.filter_key.as_ref() != Some(&filter_key) {
+                            self.grid = GridPaging {
+                                shown: GRID_PAGE_SIZE,
+                                filter_key: Some(filter_key),
+                            };
+                        }
+
+                        self.draw_pending_prompts(ui, &database);
+                        let shown = self.grid.shown.min(combined_list.len());
+                        let row_size = vec2(available_width, cell_height);
+                        for row in combined_list[..shown].chunks(columns) {
+                            // Rows outside the viewport only reserve their space
+                            if !ui.is_rect_visible(Rect::from_min_size(ui.cursor().min, row_size)) {
+                                ui.allocate_space(row_size);
+                                continue;
+                            }
+                            ui.horizontal(|ui| {
+                                for (_, wallpaper, comment) in row {
+                                    if let Some(wallpaper) = wallpaper {
+                                        self.draw_wallpaper_box(
+                                            ui,
+                                            wallpaper,
+                                            cell_width,
+                                            cell_height,
+                                        );
+                                    }
+                                    if let Some(comment) = comment {
+                                        let spawned = database
+                                            .wallpapers
+                                            .values()
+                                            .find(|wallpaper| {
+                                                wallpaper.source_comment == Some(comment.id)
+                                            })
+                                            .map(|wallpaper| wallpaper.id);
+                                        let author = comment
+                                            .author_uuid
+                                            .and_then(|uuid| database.users.get(&uuid));
+                                        let own_uuid = database
+                                            .users
+                                            .iter()
+                                            .find(|(_, username)| **username == self.stored.username)
+                                            .map(|(uuid, _)| *uuid);
+                                        self.draw_comment_box(
+                                            ui,
+                                            comment,
+                                            author.map(String::as_str),
+                                            own_uuid,
+                                            spawned,
+                                            cell_width,
+                                            cell_height,
+                                        );
+                                    }
+                                }
+                            });
+                        }
+
+                        // Add the next page once the end of the grid scrolls into view
+                        if shown < combined_list.len()
+                            && ui.is_rect_visible(Rect::from_min_size(ui.cursor().min, row_size))
+                        {
+                            self.grid.shown += GRID_PAGE_SIZE;
+                            ui.ctx().request_repaint();
+                        }
+                    }
+                })
+            });
+            if refresh_response.should_refresh() {
+                self.network_data.lock().get_database = GetDatabaseState::Wanted;
+                self.reset_gallery();
+                self.grid.filter_key = None;
+                if self.cluster_view {
+                    self.request_clusters(ui.ctx());
+                }
+                ui.ctx().forget_all_images();
+                ui.ctx().clear_animations();
+            }
+
+            if new_fullscreen.is_some() || close_fullscreen {
+                self.fullscreen_image = new_fullscreen;
+                self.crop.active = false;
+                self.fullscreen = FullscreenState::FIT;
+            }
+        });
+    }
+
+    /// Generate a wallpaper, or a prompt to review first, guided by the message
+    fn generate(&self, ctx: &Context, message: &str) {
+        let toasts_store = self.toasts.clone();
+        let network_store = self.network_data.clone();
+        let ctx = ctx.clone();
+        if self.stored.review_prompts {
+            if !begin_request(&network_store, &toasts_store, "/promptgenerate") {
+                return;
+            }
+            toasts_store.lock().info("Writing prompt");
+            generate_prompt(
+                &self.host,
+                &self.stored.auth_token,
+                message,
+                self.stored.generation_mode,
+                self.stored.image_model.as_deref(),
+                move |result| {
+                    ctx.request_repaint();
+                    network_store
+                        .lock()
+                        .pending_requests
+                        .remove("/promptgenerate");
+                    button_pressed_result(
+                        result,
+                        &network_store,
+                        &toasts_store,
+                        "Prompt ready for review",
+                    );
+                },
+            );
+        } else {
+            if !begin_request(&network_store, &toasts_store, "/generate") {
+                return;
+            }
+            toasts_store.lock().info("Generating Wallpaper");
+            generate_wallpaper(
+                &self.host,
+                &self.stored.auth_token,
+                message,
+                self.stored.generation_mode,
+                self.stored.image_model.as_deref(),
+                None,
+                move |result| {
+                    ctx.request_repaint();
+                    network_store.lock().pending_requests.remove("/generate");
+                    button_pressed_result(
+                        result,
+                        &network_store,
+                        &toasts_store,
+                        "Generated wallpaper",
+                    );
+                },
+            );
+        }
+    }
+
+    fn log_out(&mut self) {
+        // Clear locally whatever happens, the server revoking the token is best effort
+        logout(&self.host, &self.stored.auth_token, |result| {
+            if let Err(e) = result {
+                log::warn!("Failed to revoke token on logout: {:?}", e);
+            }
+        });
+        self.stored.auth_token.clear();
+        // Likes are per account, so the ones shown were only for the account just logged out
+        self.network_data.lock().get_database = GetDatabaseState::Wanted;
+        self.reset_gallery();
+    }
+
+    /// Ctrl+K palette of fuzzy matched commands, driven by arrows and enter
+    fn show_command_palette(&mut self, ctx: &Context) {
+        if ctx.input_mut(|i| i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::K)))
+        {
+            self.palette = CommandPalette {
+                open: !self.palette.open,
+                ..CommandPalette::default()
+            };
+        }
+        if !self.palette.open {
+            return;
+        }
+
+        let (up, down, enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(Modifiers::NONE, Key::ArrowUp),
+                i.consume_key(Modifiers::NONE, Key::ArrowDown),
+                i.consume_key(Modifiers::NONE, Key::Enter),
+                i.consume_key(Modifiers::NONE, Key::Escape),
+            )
+        });
+        if escape {
+            // Back out of the inline text first, then close
+            if self.palette.expanded.take().is_none() {
+                self.palette.open = false;
+                return;
+            }
+        }
+
+        let mut chosen = None;
+        let mut run = None;
+        Window::new("Command Palette")
+            .title_bar(false)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_TOP, vec2(0.0, 80.0))
+            .min_width(360.0)
+            .max_width(360.0)
+            .show(ctx, |ui| {
+                if let Some(command) = self.palette.expanded {
+                    ui.label(format!("{} {}", command.icon(), command.name()));
+                    TextEdit::singleline(&mut self.palette.argument)
+                        .hint_text(command.argument().unwrap_or_default())
+                        .desired_width(f32::INFINITY)
+                        .ui(ui)
+                        .request_focus();
+                    if enter {
+                        run = Some(command);
+                    }
+                    return;
+                }
+
+                let query = TextEdit::singleline(&mut self.palette.query)
+                    .hint_text("Type a command")
+                    .desired_width(f32::INFINITY)
+                    .ui(ui);
+                query.request_focus();
+                if query.changed() {
+                    self.palette.selected = 0;
+                }
+
+                let commands = palette::matching(&self.palette.query, self.offline);
+                if commands.is_empty() {
+                    ui.weak("No matching commands");
+                    return;
+                }
+                if up {
+                    self.palette.selected = self.palette.selected.saturating_sub(1);
+                }
+                if down {
+                    self.palette.selected += 1;
+                }
+                self.palette.selected = self.palette.selected.min(commands.len() - 1);
+                for (i, command) in commands.iter().enumerate() {
+                    if ui
+                        .selectable_label(
+                            i == self.palette.selected,
+                            format!("{} {}", command.icon(), command.name()),
+                        )
+                        .clicked()
+                    {
+                        chosen = Some(*command);
+                    }
+                }
+                if enter {
+                    chosen = Some(commands[self.palette.selected]);
+                }
+            });
+
+        // Commands that take text expand inline, the rest run straight away
+        if let Some(command) = chosen {
+            if command.argument().is_some() {
+                self.palette.expanded = Some(command);
+                self.palette.argument.clear();
+            } else {
+                run = Some(command);
+            }
+        }
+        if let Some(command) = run {
+            self.palette.open = false;
+            let argument = std::mem::take(&mut self.palette.argument);
+            self.run_command(ctx, command, argument.trim());
+        }
+    }
+
+    fn run_command(&mut self, ctx: &Context, command: Command, argument: &str) {
+        match command {
+            Command::Generate => self.generate(ctx, ""),
+            Command::GenerateWithMessage => self.generate(ctx, argument),
+            Command::ToggleFilters => self.stored.filters_open = !self.stored.filters_open,
+            Command::Search => self.focus_search = true,
+            Command::Account => self.account.open = true,
+            Command::Logout => self.log_out(),
+        }
+    }
+
+    /// The account the database's likes were filtered for
+    fn viewer(&self) -> Option<Uuid> {
+        self.database.as_ref().and_then(|database| database.viewer)
+    }
+
+    fn passes_filter(&self, wallpaper: &WallpaperData) -> bool {
+        let liked_flag = match wallpaper.liked_state_for(self.viewer()) {
+            LikedState::Liked => StateFilter::LIKED,
+            LikedState::Loved => StateFilter::LOVED,
+            LikedState::Disliked => StateFilter::DISLIKED,
+            LikedState::Neutral => StateFilter::NEUTRAL,
+        };
+        let temperature_flag = match wallpaper.color_data.color_temperature {
+            ColorTemperature::Warm => StateFilter::WARM,
+            ColorTemperature::Cool => StateFilter::COOL,
+            ColorTemperature::Neutral => StateFilter::NEUTRAL_TEMPERATURE,
+        };
+        let matches_hue = self.hue_filter.is_none_or(|(hue, tolerance)| {
+            hue_distance(wallpaper.color_data.hue * 360.0, f32::from(hue)) <= f32::from(tolerance)
+        });
+        let matches_tag = self
+            .tag_filter
+            .as_ref()
+            .is_none_or(|tag| wallpaper.tags.contains(tag));
+        let search = self.search.trim().to_lowercase();
+        let matches_search = search.is_empty()
+            || [
+                Some(&wallpaper.prompt_data.prompt),
+                Some(&wallpaper.prompt_data.shortened_prompt),
+                wallpaper.note.as_ref(),
+            ]
+            .into_iter()
+            .flatten()
+            .any(|text| text.to_lowercase().contains(&search));
+        // Pinned wallpapers are never hidden by the state filters, only by the search
+        matches_search
+            && (wallpaper.pinned
+                || (self.state_filter.contains(liked_flag)
+                    && self.state_filter.contains(temperature_flag)
+                    && matches_hue
+                    && matches_tag
+                    && !self.state_filter.contains(StateFilter::PINNED)))
+    }
+
+    /// Fill in one of the users prompt templates, or write a new one
+    fn show_template_window(&mut self, ctx: &Context) {
+        let templates = self
+            .database
+            .as_ref()
+            .map(|database| database.prompt_templates.clone())
+            .unwrap_or_default();
+        let dialog = &mut self.templates;
+
+        let mut open = dialog.open;
+        Window::new("Templates")
+            .open(&mut open)
+            .default_width(500.0)
+            .show(ctx, |ui| {
+                let selected = dialog
+                    .selected
+                    .and_then(|id| templates.iter().find(|template| template.id == id));
+                egui::ComboBox::from_label("Template")
+                    .selected_text(
+                        selected.map_or("Select a template", |template| template.name.as_str()),
+                    )
+                    .show_ui(ui, |ui| {
+                        for template in &templates {
+                            ui.selectable_value(
+                                &mut dialog.selected,
+                                Some(template.id),
+                                &template.name,
+                            );
+                        }
+                    });
+
+                if let Some(template) = selected {
+                    ui.label(RichText::new(&template.template).weak());
+                    for slot in &template.slots {
+                        ui.horizontal(|ui| {
+                            ui.label(slot);
+                            ui.text_edit_singleline(
+                                dialog.slot_values.entry(slot.clone()).or_default(),
+                            );
+                        });
+                    }
+
+                    ui.horizontal(|ui| {
+                        let filled = template.fill(&dialog.slot_values);
+                        let response = ui
+                            .add_enabled(filled.is_ok(), egui::Button::new("Generate"))
+                            .on_disabled_hover_text(filled.err().unwrap_or_default());
+                        if self
+                            .network_data
+                            .lock()
+                            .request_in_progress("/generatefromtemplate")
+                        {
+                            pending_outline(ui, &response);
+                        }
+                        if response.clicked()
+                            && begin_request(
+                                &self.network_data,
+                                &self.toasts,
+                                "/generatefromtemplate",
+                            )
+                        {
+                            let toasts_store = self.toasts.clone();
+                            let network_store = self.network_data.clone();
+                            toasts_store.lock().info("Generating Wallpaper");
+                            let ctx = ctx.clone();
+                            generate_from_template(
+                                &self.host,
+                                &self.stored.auth_token,
+                                &template.id,
+                                dialog.slot_values.clone(),
+                                move |result| {
+                                    ctx.request_repaint();
+                                    network_store
+                                        .lock()
+                                        .pending_requests
+                                        .remove("/generatefromtemplate");
+                                    button_pressed_result(
+                                        result,
+                                        &network_store,
+                                        &toasts_store,
+                                        "Generated wallpaper",
+                                    );
+                                },
+                            );
+                        }
+                        if ui.button(egui_phosphor::regular::TRASH).clicked() {
+                            let toasts_store = self.toasts.clone();
+                            let network_store = self.network_data.clone();
+                            let ctx = ctx.clone();
+                            remove_template(
+                                &self.host,
+                                &self.stored.auth_token,
+                                &template.id,
+                                move |result| {
+                                    ctx.request_repaint();
+                                    button_pressed_result(
+                                        result,
+                                        &network_store,
+                                        &toasts_store,
+                                        "",
+                                    );
+                                },
+                            );
+                            dialog.selected = None;
+                        }
+                    });
+                }
+
+                ui.separator();
+                ui.text_edit_singleline(&mut dialog.new_name)
+                    .on_hover_text("Template name");
+                TextEdit::multiline(&mut dialog.new_template)
+                    .desired_width(f32::INFINITY)
+                    .hint_text("A {subject} in the style of {artist}")
+                    .ui(ui);
+                if ui
+                    .add_enabled(
+                        !dialog.new_name.trim().is_empty()
+                            && !dialog.new_template.trim().is_empty(),
+                        egui::Button::new("Add Template"),
+                    )
+                    .clicked()
+                {
+                    let toasts_store = self.toasts.clone();
+                    let network_store = self.network_data.clone();
+                    let ctx = ctx.clone();
+                    add_template(
+                        &self.host,
+                        &self.stored.auth_token,
+                        dialog.new_name.trim(),
+                        dialog.new_template.trim(),
+                        move |result| {
+                            ctx.request_repaint();
+                            button_pressed_result(result, &network_store, &toasts_store, "");
+                        },
+                    );
+                    dialog.new_name.clear();
+                    dialog.new_template.clear();
+                }
+            });
+        self.templates.open = open;
+    }
+
+    /// The system messages and history a wallpaper's prompt was written from
+    fn show_generation_context(&mut self, ctx: &Context) {
+        let Some((id, context)) = &self.generation_context else {
+            return;
+        };
+        let title = self
+            .database
+            .as_ref()
+            .and_then(|database| database.wallpapers.get(id))
+            .map_or_else(
+                || id.to_string(),
+                |wallpaper| wallpaper.prompt_data.shortened_prompt.clone(),
+            );
+        let mut open = true;
+        Window::new("Generation Context")
+            .open(&mut open)
+            .default_size([600.0, 500.0])
+            .show(ctx, |ui| {
+                ui.label(RichText::new(title).strong());
+                ScrollArea::vertical().show(ui, |ui| {
+                    ui.label(context);
+                });
+            });
+        if !open {
+            self.generation_context = None;
+        }
+    }
+
+    fn request_profile_previews(&self, ctx: &Context) {
+        let network_store = self.network_data.clone();
+        let toasts_store = self.toasts.clone();
+        let ctx = ctx.clone();
+        get_profile_previews(&self.host, move |result| match result {
+            Ok(previews) => {
+                network_store.lock().profile_previews = Some(previews);
+                ctx.request_repaint();
+            }
+            Err(e) => {
+                toasts_store.lock().error(e.to_string());
+            }
+        });
+    }
+
+    /// Edit the saved smartget constraints each device fetches with
+    fn show_profile_window(&mut self, ctx: &Context) {
+        let profiles = self
+            .database
+            .as_ref()
+            .map(|database| database.fetch_profiles.clone())
+            .unwrap_or_default();
+        let mut open = self.profiles.open;
+        let mut preview = false;
+        let dialog = &mut self.profiles;
+        Window::new("Fetch Profiles")
+            .open(&mut open)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                egui::ComboBox::from_label("Profile")
+                    .selected_text(if dialog.draft.name.is_empty() {
+                        "New profile"
+                    } else {
+                        dialog.draft.name.as_str()
+                    })
+                    .show_ui(ui, |ui| {
+                        if ui.selectable_label(false, "New profile").clicked() {
+                            dialog.draft = FetchProfile::default();
+                        }
+                        for profile in &profiles {
+                            if ui
+                                .selectable_label(dialog.draft == *profile, &profile.name)
+                                .clicked()
+                            {
+                                dialog.draft = profile.clone();
+                            }
+                        }
+                    });
+
+                let draft = &mut dialog.draft;
+                ui.horizontal(|ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(&mut draft.name);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Orientation");
+                    ui.selectable_value(&mut draft.orientation, None, "Any");
+                    ui.selectable_value(
+                        &mut draft.orientation,
+                        Some(Orientation::Portrait),
+                        "Portrait",
+                    );
+                    ui.selectable_value(
+                        &mut draft.orientation,
+                        Some(Orientation::Landscape),
+                        "Landscape",
+                    );
+                });
+
+                let mut brightness = draft.brightness_range.is_some();
+                ui.checkbox(&mut brightness, "Brightness range")
+                    .on_hover_text("Otherwise brightness follows the time of day");
+                draft.brightness_range = brightness
+                    .then(|| draft.brightness_range.unwrap_or((0.0, 1.0)))
+                    .map(|(mut min, mut max)| {
+                        ui.add(egui::Slider::new(&mut min, 0.0..=1.0).text("Min"));
+                        ui.add(egui::Slider::new(&mut max, 0.0..=1.0).text("Max"));
+                        (min, max.max(min))
+                    });
+
+                ui.horizontal(|ui| {
+                    let mut rated = draft.min_rating.is_some();
+                    ui.checkbox(&mut rated, "Minimum rating")
+                        .on_hover_text("Unrated wallpapers count as three stars");
+                    draft.min_rating = rated.then(|| {
+                        let mut rating = draft.min_rating.unwrap_or(3);
+                        ui.add(egui::Slider::new(&mut rating, 1..=5).suffix(" stars"));
+                        rating
+                    });
+                });
+                ui.checkbox(&mut draft.loved_only, "Loved only");
+                ui.horizontal(|ui| {
+                    let mut rest = draft.rest_hours.is_some();
+                    ui.checkbox(&mut rest, "Skip served within");
+                    draft.rest_hours = rest.then(|| {
+                        let mut hours = draft.rest_hours.unwrap_or(24);
+                        ui.add(egui::DragValue::new(&mut hours).suffix(" hours"));
+                        hours
+                    });
+                });
+
+                ui.horizontal(|ui| {
+                    let valid = draft.validate();
+                    if ui
+                        .add_enabled(valid.is_ok(), egui::Button::new("Save"))
+                        .on_disabled_hover_text(valid.err().unwrap_or_default())
+                        .clicked()
+                    {
+                        let toasts_store = self.toasts.clone();
+                        let network_store = self.network_data.clone();
+                        let ctx = ctx.clone();
+                        save_profile(&self.host, &self.stored.auth_token, draft, move |result| {
+                            ctx.request_repaint();
+                            button_pressed_result(result, &network_store, &toasts_store, "");
+                        });
+                    }
+                    let saved = profiles
+                        .iter()
+                        .any(|profile| profile.name.eq_ignore_ascii_case(draft.name.trim()));
+                    if saved && ui.button(egui_phosphor::regular::TRASH).clicked() {
+                        let toasts_store = self.toasts.clone();
+                        let network_store = self.network_data.clone();
+                        let ctx = ctx.clone();
+                        remove_profile(
+                            &self.host,
+                            &self.stored.auth_token,
+                            draft.name.trim(),
+                            move |result| {
+                                ctx.request_repaint();
+                                button_pressed_result(result, &network_store, &toasts_store, "");
+                            },
+                        );
+                        *draft = FetchProfile::default();
+                    }
+                });
+                if !draft.name.trim().is_empty() {
+                    ui.label(
+                        RichText::new(format!(
+                            "http://{}/smartget?profile={}",
+                            self.host,
+                            draft.name.trim()
+                        ))
+                        .weak(),
+                    );
+                }
+
+                ui.separator();
+                if ui.button("Preview Candidates").clicked() {
+                    preview = true;
+                }
+                if let Some(previews) = &dialog.previews {
+                    for profile in previews {
+                        ui.label(format!(
+                            "{}: {} wallpapers match now",
+                            profile.name, profile.candidates
+                        ));
+                    }
+                }
+            });
+        self.profiles.open = open;
+        if preview {
+            self.request_profile_previews(ctx);
+        }
+    }
+
+    /// Pinned wallpapers need the user to type delete before they are removed
+    fn show_account_window(&mut self, ctx: &Context) {
+        let mut open = self.account.open;
+        let mut change_submitted = false;
+        let form = &mut self.account;
+        Window::new("Account Settings")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.strong("Change Password");
+                egui::Grid::new("password_grid")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        for (label, text) in [
+                            ("Current password", &mut form.old_password),
+                            ("New password", &mut form.new_password),
+                            ("Confirm new password", &mut form.confirm_password),
+                        ] {
+                            ui.label(label);
+                            TextEdit::singleline(text).password(true).ui(ui);
+                            ui.end_row();
+                        }
+                    });
+                let matches = form.new_password == form.confirm_password;
+                if !matches && !form.confirm_password.is_empty() {
+                    ui.colored_label(ui.visuals().error_fg_color, "New passwords don't match");
+                }
+                let ready =
+                    matches && !form.old_password.is_empty() && !form.new_password.is_empty();
+                if ui
+                    .add_enabled(ready, egui::Button::new("Change Password"))
+                    .clicked()
+                {
+                    change_submitted = true;
+                }
+
+                ui.separator();
+                ui.colored_label(ui.visuals().error_fg_color, "Danger Zone");
+                if ui
+                    .button(
+                        RichText::new(format!("{} Delete account", egui_phosphor::regular::TRASH))
+                            .color(ui.visuals().error_fg_color),
+                    )
+                    .clicked()
+                {
+                    form.delete_open = true;
+                }
+            });
+
+        if change_submitted {
+            let toasts_store = self.toasts.clone();
+            let ctx = ctx.clone();
+            change_password(
+                &self.host,
+                &self.stored.auth_token,
+                &self.account.old_password,
+                &self.account.new_password,
+                move |result| {
+                    match result {
+                        Ok(()) => {
+                            toasts_store.lock().info("Password changed");
+                        }
+                        Err(e) => {
+                            toasts_store.lock().error(e.to_string());
+                        }
+                    }
+                    ctx.request_repaint();
+                },
+            );
+            self.account.old_password.clear();
+            self.account.new_password.clear();
+            self.account.confirm_password.clear();
+        }
+        if self.account.delete_open && open {
+            self.show_delete_account_confirmation(ctx);
+        }
+        if !open {
+            self.account = AccountWindow::default();
+        }
+    }
+
+    /// Deleting needs the username typed out and the password, which the server checks
+    fn show_delete_account_confirmation(&mut self, ctx: &Context) {
+        let mut open = true;
+        let mut confirmed = false;
+        let username = &self.stored.username;
+        let form = &mut self.account;
+        Window::new("Delete account")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if username.is_empty() {
+                    ui.label("Log in again to delete this account");
+                    return;
+                }
+                ui.label(format!(
+                    "This can't be undone, type {username} and your password to delete the account"
+                ));
+                TextEdit::singleline(&mut form.delete_username)
+                    .hint_text("Username")
+                    .ui(ui);
+                TextEdit::singleline(&mut form.delete_password)
+                    .hint_text("Password")
+                    .password(true)
+                    .ui(ui);
+                let ready = form.delete_username == *username && !form.delete_password.is_empty();
+                if ui
+                    .add_enabled(
+                        ready,
+                        egui::Button::new(
+                            RichText::new("Delete account").color(ui.visuals().error_fg_color),
+                        ),
+                    )
+                    .clicked()
+                {
+                    confirmed = true;
+                }
+            });
+
+        if confirmed {
+            let toasts_store = self.toasts.clone();
+            let network_store = self.network_data.clone();
+            let ctx = ctx.clone();
+            delete_account(
+                &self.host,
+                &self.stored.auth_token,
+                &self.account.delete_password,
+                move |result| {
+                    match result {
+                        Ok(()) => {
+                            toasts_store.lock().info("Account deleted");
+                            network_store.lock().account_deleted = true;
+                        }
+                        Err(e) => {
+                            toasts_store.lock().error(e.to_string());
+                        }
+                    }
+                    ctx.request_repaint();
+                },
+            );
+        }
+        if confirmed || !open {
+            self.account.delete_open = false;
+            self.account.delete_username.clear();
+            self.account.delete_password.clear();
+        }
+    }
+
+    fn show_remove_confirmation(&mut self, ctx: &Context) {
+        let Some((id, typed)) = &mut self.remove_confirmation else {
+            return;
+        };
+        let id = *id;
+
+        let mut open = true;
+        let mut confirmed = false;
+        Window::new("Delete pinned wallpaper")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("This wallpaper is pinned, type delete to remove it");
+                let response = ui.text_edit_singleline(typed);
+                let matches = typed.trim().eq_ignore_ascii_case("delete");
+                if ui
+                    .add_enabled(matches, egui::Button::new("Delete"))
+                    .clicked()
+                    || (matches && response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)))
+                {
+                    confirmed = true;
+                }
+            });
+
+        if confirmed {
+            let toasts_store = self.toasts.clone();
+            let network_store = self.network_data.clone();
+            let ctx = ctx.clone();
+            remove_image(&self.host, &self.stored.auth_token, &id, move |result| {
+                ctx.request_repaint();
+                button_pressed_result(result, &network_store, &toasts_store, "");
+            });
+        }
+        if confirmed || !open {
+            self.remove_confirmation = None;
+        }
+    }
+
+    /// Recreate a wallpaper after tweaking its prompt
+    fn show_recreate_edit_window(&mut self, ctx: &Context) {
+        let Some((id, prompt)) = &mut self.recreate_edit else {
+            return;
+        };
+        let id = *id;
+
+        let mut open = true;
+        let mut confirmed = false;
+        Window::new("Edit & recreate")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label("Change the prompt, a new shortened prompt is written for it");
+                TextEdit::multiline(prompt)
+                    .desired_width(f32::INFINITY)
+                    .desired_rows(6)
+                    .ui(ui);
+                if ui
+                    .add_enabled(
+                        !prompt.trim().is_empty(),
+                        egui::Button::new(format!("{} Recreate", egui_phosphor::regular::REPEAT)),
+                    )
+                    .clicked()
+                {
+                    confirmed = true;
+                }
+            });
+
+        if confirmed {
+            let toasts_store = self.toasts.clone();
+            let network_store = self.network_data.clone();
+            let ctx = ctx.clone();
+            self.toasts.lock().info("Recreating wallpaper");
+            recreate_image_edited(
+                &self.host,
+                &self.stored.auth_token,
+                &id,
+                prompt,
+                move |result| {
+                    ctx.request_repaint();
+                    button_pressed_result(result, &network_store, &toasts_store, "");
+                },
+            );
+        }
+        if confirmed || !open {
+            self.recreate_edit = None;
+        }
+    }
+
+    fn request_clusters(&self, ctx: &Context) {
+        let network_store = self.network_data.clone();
+        let toasts_store = self.toasts.clone();
+        let ctx = ctx.clone();
+        get_clusters(&self.host, CLUSTER_COUNT, move |result| match result {
+            Ok(clusters) => {
+                network_store.lock().clusters = Some(clusters);
+                ctx.request_repaint();
+            }
+            Err(e) => {
+                toasts_store.lock().error(e.to_string());
+            }
+        });
+    }
+
+    fn request_audit_log(&mut self, ctx: &Context) {
+        self.audit.entries = None;
+        let network_store = self.network_data.clone();
+        let toasts_store = self.toasts.clone();
+        let ctx = ctx.clone();
+        get_audit_log(
+            &self.host,
+            &self.stored.auth_token,
+            AUDIT_PAGE_SIZE,
+            self.audit.page * AUDIT_PAGE_SIZE,
+            move |result| match result {
+                Ok(entries) => {
+                    network_store.lock().audit_log = Some(entries);
+                    ctx.request_repaint();
+                }
+                Err(e) => {
+                    toasts_store.lock().error(e.to_string());
+                }
+            },
+        );
+    }
+
+    /// Admin only table of recent mutating actions, newest first
+    fn show_audit_window(&mut self, ctx: &Context) {
+        let mut open = self.audit.open;
+        let mut new_page = None;
+        Window::new("Audit Log")
+            .open(&mut open)
+            .default_size([800.0, 400.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let page = self.audit.page;
+                    if ui
+                        .add_enabled(page > 0, egui::Button::new("Newer"))
+                        .clicked()
+                    {
+                        new_page = Some(page - 1);
+                    }
+                    ui.label(format!("Page {}", page + 1));
+                    let full_page = self
+                        .audit
+                        .entries
+                        .as_ref()
+                        .is_some_and(|entries| entries.len() == AUDIT_PAGE_SIZE);
+                    if ui
+                        .add_enabled(full_page, egui::Button::new("Older"))
+                        .clicked()
+                    {
+                        new_page = Some(page + 1);
+                    }
+                    if ui
+                        .button(egui_phosphor::regular::ARROWS_CLOCKWISE)
+                        .clicked()
+                    {
+                        new_page = Some(page);
+                    }
+                });
+                ui.separator();
+
+                let Some(entries) = &self.audit.entries else {
+                    ui.spinner();
+                    return;
+                };
+                TableBuilder::new(ui)
+                    .striped(true)
+                    .column(Column::auto())
+                    .column(Column::auto())
+                    .column(Column::auto())
+                    .column(Column::remainder().clip(true))
+                    .header(20.0, |mut header| {
+                        for title in ["Date", "Action", "Actor", "Summary"] {
+                            header.col(|ui| {
+                                ui.strong(title);
+                            });
+                        }
+                    })
+                    .body(|body| {
+                        body.rows(20.0, entries.len(), |mut row| {
+                            let entry = &entries[row.index()];
+                            row.col(|ui| {
+                                ui.label(
+                                    entry
+                                        .timestamp
+                                        .with_timezone(&Local)
+                                        .format("%d/%m/%Y %H:%M")
+                                        .to_string(),
+                                );
+                            });
+                            row.col(|ui| {
+                                ui.label(entry.action.name());
+                            });
+                            row.col(|ui| {
+                                ui.label(entry.actor_uuid.to_string()[..8].to_string())
+                                    .on_hover_text(entry.actor_uuid.to_string());
+                            });
+                            row.col(|ui| {
+                                let label = ui.label(&entry.summary);
+                                if let Some(target) = entry.target_uuid {
+                                    label.on_hover_text(format!("Target {target}"));
+                                }
+                            });
+                        });
+                    });
+            });
+        self.audit.open = open;
+        if let Some(page) = new_page {
+            self.audit.page = page;
+            self.request_audit_log(ctx);
+        }
+    }
+
+    fn request_activity(&mut self, ctx: &Context) {
+        self.activity.entries = None;
+        let network_store = self.network_data.clone();
+        let toasts_store = self.toasts.clone();
+        let ctx = ctx.clone();
+        get_activity(
+            &self.host,
+            &self.stored.auth_token,
+            ACTIVITY_LIMIT,
+            move |result| match result {
+                Ok(entries) => {
+                    network_store.lock().activity = Some(entries);
+                    ctx.request_repaint();
+                }
+                Err(e) => {
+                    toasts_store.lock().error(e.to_string());
+                }
+            },
+        );
+    }
+
+    /// Recent changes by every account, so you can see who disliked what
+    fn show_activity_window(&mut self, ctx: &Context) {
+        let mut open = self.activity.open;
+        let mut refresh = false;
+        Window::new("Activity")
+            .open(&mut open)
+            .default_size([600.0, 400.0])
+            .show(ctx, |ui| {
+                if ui
+                    .button(egui_phosphor::regular::ARROWS_CLOCKWISE)
+                    .clicked()
+                {
+                    refresh = true;
+                }
+                ui.separator();
+
+                let Some(entries) = &self.activity.entries else {
+                    ui.spinner();
+                    return;
+                };
+                let cur_time = Utc::now();
+                TableBuilder::new(ui)
+                    .striped(true)
+                    .column(Column::auto())
+                    .column(Column::auto())
+                    .column(Column::auto())
+                    .column(Column::remainder().clip(true))
+                    .header(20.0, |mut header| {
+                        for title in ["When", "Who", "Action", "Summary"] {
+                            header.col(|ui| {
+                                ui.strong(title);
+                            });
+                        }
+                    })
+                    .body(|body| {
+                        body.rows(20.0, entries.len(), |mut row| {
+                            let entry = &entries[row.index()];
+                            row.col(|ui| {
+                                ui.label(format!(
+                                    "{} ago",
+                                    format_duration(cur_time - entry.timestamp)
+                                ))
+                                .on_hover_text(
+                                    entry
+                                        .timestamp
+                                        .with_timezone(&Local)
+                                        .format("%d/%m/%Y %H:%M")
+                                        .to_string(),
+                                );
+                            });
+                            row.col(|ui| {
+                                let name = self
+                                    .database
+                                    .as_ref()
+                                    .and_then(|database| database.users.get(&entry.actor_uuid))
+                                    .map_or("Deleted account", String::as_str);
+                                ui.label(name);
+                            });
+                            row.col(|ui| {
+                                ui.label(entry.action.name());
+                            });
+                            row.col(|ui| {
+                                let label = ui.label(&entry.summary);
+                                let target = entry.target_uuid.map(|target| {
+                                    self.database
+                                        .as_ref()
+                                        .and_then(|database| database.wallpapers.get(&target))
+                                        .map_or_else(
+                                            || format!("Target {target}"),
+                                            |wallpaper| {
+                                                wallpaper.prompt_data.shortened_prompt.clone()
+                                            },
+                                        )
+                                });
+                                if let Some(target) = target {
+                                    label.on_hover_text(target);
+                                }
+                            });
+                        });
+                    });
+            });
+        self.activity.open = open;
+        if refresh {
+            self.request_activity(ctx);
+        }
+    }
+
+    fn request_server_logs(&mut self, ctx: &Context) {
+        self.server_logs.last_fetch = Some(ctx.input(|i| i.time));
+        let network_store = self.network_data.clone();
+        let ctx = ctx.clone();
+        get_server_logs(
+            &self.host,
+            &self.stored.auth_token,
+            self.server_logs.level,
+            move |result| {
+                network_store.lock().server_logs = Some(result);
+                ctx.request_repaint();
+            },
+        );
+    }
+
+    /// Recent lines of the server log, refreshed every few seconds while open
+    fn show_server_logs_window(&mut self, ctx: &Context) {
+        if !self.server_logs.open {
+            return;
+        }
+        let due = self
+            .server_logs
+            .last_fetch
+            .is_none_or(|last| ctx.input(|i| i.time) - last >= LOG_REFRESH_SECS);
+        if due {
+            self.request_server_logs(ctx);
+        }
+        ctx.request_repaint_after(std::time::Duration::from_secs_f64(LOG_REFRESH_SECS));
+
+        let mut open = self.server_logs.open;
+        let mut level_changed = false;
+        Window::new("Server Logs")
+            .open(&mut open)
+            .default_size([700.0, 400.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    for level in LogLevel::ALL {
+                        if ui
+                            .selectable_value(&mut self.server_logs.level, level, level.name())
+                            .clicked()
+                        {
+                            level_changed = true;
+                        }
+                    }
+                });
+                ui.separator();
+
+                let Some(lines) = &self.server_logs.lines else {
+                    ui.spinner();
+                    return;
+                };
+                if lines.is_empty() {
+                    ui.weak("No log lines at this level");
+                    return;
+                }
+                TableBuilder::new(ui)
+                    .striped(true)
+                    .stick_to_bottom(true)
+                    .column(Column::auto())
+                    .column(Column::auto())
+                    .column(Column::auto())
+                    .column(Column::remainder().clip(true))
+                    .header(20.0, |mut header| {
+                        for title in ["Time", "Level", "Module", "Message"] {
+                            header.col(|ui| {
+                                ui.strong(title);
+                            });
+                        }
+                    })
+                    .body(|body| {
+                        body.rows(20.0, lines.len(), |mut row| {
+                            let line = &lines[row.index()];
+                            row.col(|ui| {
+                                ui.label(
+                                    line.timestamp
+                                        .with_timezone(&Local)
+                                        .format("%H:%M:%S")
+                                        .to_string(),
                                 );
                             });
+                            row.col(|ui| {
+                                let color = match line.level {
+                                    LogLevel::Error => Color32::RED,
+                                    LogLevel::Warn => Color32::YELLOW,
+                                    _ => ui.visuals().weak_text_color(),
+                                };
+                                ui.colored_label(color, line.level.name());
+                            });
+                            row.col(|ui| {
+                                ui.weak(&line.target);
+                            });
+                            row.col(|ui| {
+                                ui.label(&line.message).on_hover_text(&line.message);
+                            });
                         });
+                    });
+            });
+        self.server_logs.open = open;
+        if level_changed {
+            self.request_server_logs(ctx);
+        }
+    }
 
-                        // Handle left and right arrow key press
-                        let left_pressed =
-                            ui.input(|i| i.key_pressed(Key::ArrowLeft) || i.key_pressed(Key::A));
-                        let right_pressed =
-                            ui.input(|i| i.key_pressed(Key::ArrowRight) || i.key_pressed(Key::D));
-                        if (left_pressed || right_pressed) && self.database.is_some() {
-                            let mut target_datetime = None;
-                            let mut target_wallpaper = None;
-
-                            let comparison = if left_pressed {
-                                |dt1, dt2| dt1 > dt2
-                            } else {
-                                |dt1, dt2| dt1 < dt2
-                            };
+    fn request_maintenance(&mut self, ctx: &Context, fix: Option<&str>) {
+        self.maintenance.report = None;
+        let network_store = self.network_data.clone();
+        let toasts_store = self.toasts.clone();
+        let ctx = ctx.clone();
+        let fixing = fix.is_some();
+        verify_maintenance(
+            &self.host,
+            &self.stored.auth_token,
+            fix,
+            move |result| match result {
+                Ok(report) => {
+                    let mut network_data = network_store.lock();
+                    if fixing {
+                        toasts_store
+                            .lock()
+                            .success(format!("Fixed {} problems", report.fixed));
+                        network_data.get_database = GetDatabaseState::Wanted;
+                    }
+                    network_data.maintenance_report = Some(report);
+                    ctx.request_repaint();
+                }
+                Err(e) => {
+                    toasts_store.lock().error(e.to_string());
+                }
+            },
+        );
+    }
 
-                            for paper in self.database.as_ref().unwrap().wallpapers.values() {
-                                if comparison(paper.datetime, wallpaper.datetime)
-                                    && (target_datetime.is_none()
-                                        || comparison(target_datetime.unwrap(), paper.datetime))
-                                {
-                                    target_datetime = Some(paper.datetime);
-                                    target_wallpaper = Some(paper.clone());
-                                }
-                            }
+    /// Files the database references that are missing on disk, and files nothing references
+    fn show_maintenance_window(&mut self, ctx: &Context) {
+        let mut open = self.maintenance.open;
+        let mut request = None; // Some(None) to refresh, Some(Some(fix)) to apply a fix
+        Window::new("Maintenance")
+            .open(&mut open)
+            .default_size([500.0, 400.0])
+            .show(ctx, |ui| {
+                let Some(report) = &self.maintenance.report else {
+                    ui.spinner();
+                    return;
+                };
+                if report.is_clean() {
+                    ui.label(format!(
+                        "{} Database and wallpaper files agree",
+                        egui_phosphor::regular::CHECK
+                    ));
+                }
 
-                            if let Some(target_wallpaper) = target_wallpaper {
-                                new_fullscreen = Some(target_wallpaper.id);
-                            }
+                let prompt = |id: &Uuid| {
+                    self.database
+                        .as_ref()
+                        .and_then(|database| database.wallpapers.get(id))
+                        .map_or_else(
+                            || id.to_string(),
+                            |wallpaper| wallpaper.prompt_data.shortened_prompt.clone(),
+                        )
+                };
+                for (title, items, button, fix_name) in [
+                    (
+                        "Missing originals",
+                        report
+                            .missing_originals
+                            .iter()
+                            .map(prompt)
+                            .collect::<Vec<_>>(),
+                        "Remove dangling entries",
+                        "remove-dangling-entries",
+                    ),
+                    (
+                        "Missing thumbnails",
+                        report.missing_thumbnails.iter().map(prompt).collect(),
+                        "Regenerate thumbnails",
+                        "regenerate-thumbs",
+                    ),
+                    (
+                        "Orphan files",
+                        report.orphan_files.clone(),
+                        "Delete orphan files",
+                        "delete-orphan-files",
+                    ),
+                ] {
+                    ui.horizontal(|ui| {
+                        ui.strong(format!("{title} ({})", items.len()));
+                        if ui
+                            .add_enabled(!items.is_empty(), egui::Button::new(button))
+                            .clicked()
+                        {
+                            request = Some(Some(fix_name));
                         }
-                    } else if let Some(database) = self.database.clone() {
-                        // Collect the wallpapers and comments into a single list, sorted by datetime
-                        let mut combined_list = database
-                            .wallpapers
-                            .values()
-                            .filter(|wallpaper| match wallpaper.liked_state {
-                                LikedState::Liked => self.state_filter.contains(StateFilter::LIKED),
-                                LikedState::Loved => self.state_filter.contains(StateFilter::LOVED),
-                                LikedState::Disliked => {
-                                    self.state_filter.contains(StateFilter::DISLIKED)
-                                }
-                                LikedState::Neutral => {
-                                    self.state_filter.contains(StateFilter::NEUTRAL)
+                    });
+                    if !items.is_empty() {
+                        egui::CollapsingHeader::new(title)
+                            .id_salt(fix_name)
+                            .show(ui, |ui| {
+                                for item in &items {
+                                    ui.label(item);
                                 }
-                            })
-                            .map(|wallpaper| (wallpaper.datetime, Some(wallpaper), None))
-                            .chain(
-                                database
-                                    .comments
-                                    .values()
-                                    .filter(|_| self.state_filter.contains(StateFilter::COMMENT))
-                                    .map(|comment| (comment.datetime, None, Some(comment))),
-                            )
-                            .collect::<Vec<_>>();
-                        combined_list.sort_by_key(|(datetime, _, _)| *datetime);
-                        let combined_list = combined_list;
+                            });
+                    }
+                }
 
-                        let available_width = ui.available_width();
-                        let spacing = ui.spacing().item_spacing;
-                        let cell_width = 400.0;
-                        let columns = (available_width / (cell_width + spacing.x))
-                            .floor()
-                            .max(1.0) as usize;
-                        let cell_width = (columns as f32 - 1.0)
-                            .mul_add(-spacing.x, available_width / columns as f32);
-                        let cell_height = cell_width * 0.5625;
+                if ui
+                    .button(egui_phosphor::regular::ARROWS_CLOCKWISE)
+                    .clicked()
+                {
+                    request = Some(None);
+                }
+            });
+        self.maintenance.open = open;
+        if let Some(fix) = request {
+            self.request_maintenance(ctx, fix);
+        }
+    }
 
-                        ui.horizontal_wrapped(|ui| {
-                            for (_, wallpaper, comment) in combined_list.iter().rev() {
-                                if let Some(wallpaper) = wallpaper {
-                                    self.draw_wallpaper_box(ui, wallpaper, cell_width, cell_height);
-                                }
-                                if let Some(comment) = comment {
-                                    self.draw_comment_box(ui, comment, cell_width, cell_height);
-                                }
-                            }
-                        });
+    /// Draw the wallpapers grouped under a divider row for each cluster
+    fn draw_clusters(
+        &mut self,
+        ui: &mut egui::Ui,
+        database: &Database,
+        cell_width: f32,
+        cell_height: f32,
+    ) {
+        let Some(clusters) = self.clusters.clone() else {
+            ui.spinner();
+            return;
+        };
+
+        for (i, cluster) in clusters.iter().enumerate() {
+            let mut members = cluster
+                .members
+                .iter()
+                .filter_map(|id| database.wallpapers.get(id))
+                .filter(|wallpaper| self.passes_filter(wallpaper))
+                .collect::<Vec<_>>();
+            if members.is_empty() {
+                continue;
+            }
+            members.sort_by_key(|wallpaper| std::cmp::Reverse(wallpaper.datetime));
+
+            // Divider in the clusters average color
+            let [hue, saturation, lightness, brightness, contrast] = cluster.centroid;
+            let color = hsl_to_color(hue, saturation, lightness);
+            let text_color = if lightness > 0.5 {
+                Color32::BLACK
+            } else {
+                Color32::WHITE
+            };
+            Frame::none()
+                .fill(color)
+                .rounding(8.0)
+                .inner_margin(8.0)
+                .show(ui, |ui| {
+                    ui.set_width(ui.available_width());
+                    ui.label(
+                        RichText::new(format!(
+                            "Cluster {}  {} wallpapers  Lightness {}%  Top20 {}%  Contrast {:.1}",
+                            i + 1,
+                            members.len(),
+                            (lightness * 100.0) as i32,
+                            (brightness * 100.0) as i32,
+                            contrast
+                        ))
+                        .color(text_color)
+                        .strong(),
+                    );
+                });
+
+            ui.horizontal_wrapped(|ui| {
+                for wallpaper in members {
+                    self.draw_wallpaper_box(ui, wallpaper, cell_width, cell_height);
+                }
+            });
+        }
+    }
+
+    /// Prompts waiting for review, newest first, each with buttons to generate, reroll or discard it
+    fn draw_pending_prompts(&self, ui: &mut egui::Ui, database: &Database) {
+        let mut pending = database.pending_prompts.values().collect::<Vec<_>>();
+        if pending.is_empty() {
+            return;
+        }
+        pending.sort_by_key(|pending| std::cmp::Reverse(pending.datetime));
+
+        for pending in pending {
+            let mut action = None;
+            Frame::group(ui.style()).show(ui, |ui| {
+                ui.set_width(ui.available_width());
+                ui.horizontal(|ui| {
+                    ui.label(
+                        RichText::new(&pending.prompt_data.shortened_prompt)
+                            .strong()
+                            .size(16.0),
+                    );
+                    ui.label(
+                        pending
+                            .datetime
+                            .with_timezone(&Local)
+                            .format("%d/%m/%Y %H:%M")
+                            .to_string(),
+                    );
+                    if let Some(message) = &pending.message {
+                        ui.label(format!("from '{message}'"));
                     }
-                })
+                });
+                ui.label(&pending.prompt_data.prompt);
+                ui.horizontal(|ui| {
+                    if self.offline {
+                        ui.disable();
+                    }
+                    if ui.button("Generate image").clicked() {
+                        action = Some((PromptAction::Accept, "Generating Wallpaper"));
+                    }
+                    if ui.button("Reroll").clicked() {
+                        action = Some((PromptAction::Reroll, "Rewriting prompt"));
+                    }
+                    if ui.button("Discard").clicked() {
+                        action = Some((PromptAction::Discard, ""));
+                    }
+                });
             });
-            if refresh_response.should_refresh() {
-                self.network_data.lock().get_database = GetDatabaseState::Wanted;
-                ui.ctx().forget_all_images();
-                ui.ctx().clear_animations();
+
+            if let Some((action, message)) = action {
+                let toasts_store = self.toasts.clone();
+                let network_store = self.network_data.clone();
+                if !message.is_empty() {
+                    toasts_store.lock().info(message);
+                }
+                let ctx = ui.ctx().clone();
+                review_prompt(
+                    &self.host,
+                    &self.stored.auth_token,
+                    &pending.id,
+                    action,
+                    move |result| {
+                        ctx.request_repaint();
+                        button_pressed_result(result, &network_store, &toasts_store, "");
+                    },
+                );
             }
+        }
+    }
 
-            if new_fullscreen.is_some() {
-                self.fullscreen_image = new_fullscreen;
+    /// Draw the loaded pages as justified rows, fetching the next page when the end scrolls into view
+    fn draw_gallery(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Sort");
+            for sort in GallerySort::ALL {
+                if ui
+                    .selectable_label(self.gallery.sort == sort, sort.name())
+                    .clicked()
+                    && self.gallery.sort != sort
+                {
+                    self.gallery.sort = sort;
+                    self.reset_gallery();
+                }
+            }
+        });
+
+        let wallpapers = self
+            .gallery
+            .wallpapers
+            .iter()
+            .filter(|wallpaper| self.passes_filter(wallpaper))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.selection.order = wallpapers.iter().map(|wallpaper| wallpaper.id).collect();
+        let aspects = wallpapers.iter().map(aspect).collect::<Vec<_>>();
+        let spacing = ui.spacing().item_spacing.x;
+        for row in justified_rows(&aspects, ui.available_width(), GALLERY_ROW_HEIGHT, spacing) {
+            ui.horizontal(|ui| {
+                for i in row.items {
+                    self.draw_wallpaper_box(
+                        ui,
+                        &wallpapers[i],
+                        aspects[i] * row.height,
+                        row.height,
+                    );
+                }
+            });
+        }
+
+        let loaded = self.gallery.wallpapers.len();
+        if self.gallery.total.is_some_and(|total| loaded >= total) {
+            if wallpapers.is_empty() {
+                ui.label("No wallpapers");
+            }
+            return;
+        }
+        let spinner = ui.spinner();
+        if !self.gallery.loading && ui.is_rect_visible(spinner.rect) {
+            self.request_gallery_page(ui.ctx());
+        }
+    }
+
+    fn request_gallery_page(&mut self, ctx: &Context) {
+        self.gallery.loading = true;
+        let key = (self.gallery.sort, self.gallery.wallpapers.len());
+        let network_store = self.network_data.clone();
+        let ctx = ctx.clone();
+        get_gallery_page(
+            &self.host,
+            &self.stored.auth_token,
+            key.1,
+            GALLERY_PAGE_SIZE,
+            key.0,
+            move |result| {
+                network_store.lock().gallery_page = Some((key, result));
+                ctx.request_repaint();
+            },
+        );
+    }
+
+    /// Drop the loaded pages so they are fetched again, a request still in flight is ignored when it lands
+    fn reset_gallery(&mut self) {
+        self.gallery.wallpapers.clear();
+        self.gallery.total = None;
+    }
+
+    fn generate_variation(&self, ctx: &Context, id: Uuid) {
+        let toasts_store = self.toasts.clone();
+        let network_store = self.network_data.clone();
+        toasts_store.lock().info("Generating variation");
+        let ctx = ctx.clone();
+        generate_variation(&self.host, &self.stored.auth_token, &id, move |result| {
+            ctx.request_repaint();
+            button_pressed_result(result, &network_store, &toasts_store, "Generated variation");
+        });
+    }
+
+    fn generate_from_image(&mut self, ctx: &Context, data: Vec<u8>) {
+        let toasts_store = self.toasts.clone();
+        let network_store = self.network_data.clone();
+        toasts_store.lock().info("Generating wallpaper from image");
+        let ctx = ctx.clone();
+        generate_from_image(
+            &self.host,
+            &self.stored.auth_token,
+            self.comment_submission.trim(),
+            self.stored.generation_mode,
+            self.stored.image_model.as_deref(),
+            data,
+            move |result| {
+                ctx.request_repaint();
+                button_pressed_result(result, &network_store, &toasts_store, "Generated wallpaper");
+            },
+        );
+        self.comment_submission = String::new();
+    }
+
+    fn import_from_url(&self, ctx: &Context, url: &str) {
+        let toasts_store = self.toasts.clone();
+        let network_store = self.network_data.clone();
+        toasts_store.lock().info("Importing image");
+        let ctx = ctx.clone();
+        import_from_url(&self.host, &self.stored.auth_token, url, move |result| {
+            ctx.request_repaint();
+            button_pressed_result(result, &network_store, &toasts_store, "Imported image");
+        });
+    }
+
+    fn upload_image(&mut self, ctx: &Context, data: Vec<u8>) {
+        let toasts_store = self.toasts.clone();
+        let network_store = self.network_data.clone();
+        toasts_store.lock().info("Uploading image");
+        let ctx = ctx.clone();
+        upload_image(
+            &self.host,
+            &self.stored.auth_token,
+            self.comment_submission.trim(),
+            data,
+            move |result| {
+                ctx.request_repaint();
+                button_pressed_result(result, &network_store, &toasts_store, "Uploaded image");
+            },
+        );
+        self.comment_submission = String::new();
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn set_moodboard(&self, ctx: &Context, data: Vec<u8>) {
+        let toasts_store = self.toasts.clone();
+        let network_store = self.network_data.clone();
+        let ctx = ctx.clone();
+        set_moodboard(&self.host, &self.stored.auth_token, data, move |result| {
+            ctx.request_repaint();
+            moodboard_result(result, &network_store, &toasts_store, "Moodboard set");
+        });
+    }
+
+    fn clear_moodboard(&self, ctx: &Context) {
+        let toasts_store = self.toasts.clone();
+        let network_store = self.network_data.clone();
+        let ctx = ctx.clone();
+        clear_moodboard(&self.host, &self.stored.auth_token, move |result| {
+            ctx.request_repaint();
+            moodboard_result(result, &network_store, &toasts_store, "Moodboard cleared");
+        });
+    }
+
+    /// Refresh the server status every `STATUS_POLL`, repainting every second while generating
+    fn poll_status(&mut self, ctx: &Context) {
+        let now = Utc::now();
+        if let Some(last) = self.last_status_poll {
+            if (now - last).to_std().unwrap_or_default() < STATUS_POLL {
+                let generating = self
+                    .server_status
+                    .as_ref()
+                    .is_some_and(|status| status.generating);
+                ctx.request_repaint_after(if generating {
+                    std::time::Duration::from_secs(1)
+                } else {
+                    STATUS_POLL
+                });
+                return;
+            }
+        }
+        self.last_status_poll = Some(now);
+        let network_store = self.network_data.clone();
+        let ctx = ctx.clone();
+        get_status(&self.host, move |res| match res {
+            Ok(status) => {
+                network_store.lock().server_status = Some(status);
+                ctx.request_repaint();
+            }
+            Err(e) => log::error!("Failed to fetch server status: {:?}", e),
+        });
+    }
+
+    /// A countdown badge in the corner while a wallpaper is being generated
+    fn show_generation_progress(&self, ctx: &Context) {
+        let Some(started) = self
+            .server_status
+            .as_ref()
+            .and_then(|status| status.generation_started)
+        else {
+            return;
+        };
+        let elapsed = (Utc::now() - started).num_seconds().max(0);
+        let text = match self
+            .server_status
+            .as_ref()
+            .and_then(|status| status.last_duration_secs)
+        {
+            Some(expected) if expected > elapsed => {
+                format!("Generating wallpaper, about {}s left", expected - elapsed)
+            }
+            _ => format!("Generating wallpaper for {elapsed}s"),
+        };
+        egui::Area::new(egui::Id::new("generation_progress"))
+            .anchor(Align2::RIGHT_BOTTOM, vec2(-8.0, -8.0))
+            .show(ctx, |ui| {
+                Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(text);
+                    });
+                });
+            });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn auto_apply_wallpaper(&mut self, ctx: &Context) {
+        if !self.stored.auto_apply {
+            return;
+        }
+
+        let interval = chrono::Duration::hours(i64::from(self.stored.auto_apply_hours.max(1)));
+        let now = Utc::now();
+        if let Some(last) = self.last_auto_apply {
+            if now - last < interval {
+                // Wake up again once the next wallpaper is due
+                ctx.request_repaint_after((last + interval - now).to_std().unwrap_or_default());
+                return;
+            }
+        }
+        self.last_auto_apply = Some(now);
+        self.set_desktop_wallpaper(format!("http://{}/smartget", self.host));
+        ctx.request_repaint_after(interval.to_std().unwrap_or_default());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn set_desktop_wallpaper(&self, url: String) {
+        let toasts_store = self.toasts.clone();
+        set_desktop_wallpaper(url, move |result| match result {
+            Ok(()) => {
+                toasts_store.lock().info("Set desktop wallpaper");
+            }
+            Err(e) => {
+                toasts_store
+                    .lock()
+                    .error(format!("Failed to set wallpaper: {e}"));
             }
         });
     }
@@ -489,6 +4126,8 @@ impl Wallpapy {
         let image_size = Vec2::new(width, height);
         let image_rect =
             if ui.is_rect_visible(Rect::from_min_size(ui.next_widget_position(), image_size)) {
+                // The grid only ever loads thumbnails, the full image waits until one is opened
+                // No spinner, the thumbhash already stands in while the thumbnail loads
                 let image = egui::Image::new(format!(
                     "http://{}/wallpapers/{}",
                     self.host, wallpaper.thumbnail_file.file_name
@@ -504,8 +4143,11 @@ impl Wallpapy {
                 rect
             };
 
-        // Start painting
-        let ui_scale = 12.0;
+        // Start painting, touch screens get bigger buttons that don't rely on hover to stand out
+        let liked_state = wallpaper.liked_state_for(self.viewer());
+        let narrow = ui.ctx().screen_rect().width() < NARROW_WIDTH;
+        let ui_scale = if narrow { 16.0 } else { 12.0 };
+        let idle_opacity = if narrow { 0.6 } else { 0.8 };
         let painter = ui.painter();
         let mut sub_button_hovered = false;
 
@@ -531,7 +4173,92 @@ impl Wallpapy {
         ));
         painter.galley(datetime_rect.min, datetime_galley, Color32::WHITE);
 
-        // Add delete button in top-right corner
+        // Draw color temperature badge next to the date
+        let badge_center = datetime_rect.right_center() + vec2(ui_scale * 1.5, 0.0);
+        painter.circle_filled(
+            badge_center,
+            ui_scale * 0.75,
+            Color32::BLACK.gamma_multiply(0.8),
+        );
+        painter.circle_filled(
+            badge_center,
+            ui_scale * 0.5,
+            match wallpaper.color_data.color_temperature {
+                ColorTemperature::Warm => Color32::from_rgb(220, 60, 40),
+                ColorTemperature::Cool => Color32::from_rgb(50, 110, 220),
+                ColorTemperature::Neutral => Color32::from_gray(230),
+            },
+        );
+
+        // Mark pinned wallpapers beside the temperature badge
+        let mut next_badge = badge_center + vec2(ui_scale * 2.0, 0.0);
+        if wallpaper.pinned {
+            let pin_center = next_badge;
+            next_badge.x += ui_scale * 2.0;
+            painter.circle_filled(
+                pin_center,
+                ui_scale * 0.75,
+                Color32::from_rgb(40, 80, 140).gamma_multiply(0.8),
+            );
+            painter.text(
+                pin_center,
+                egui::Align2::CENTER_CENTER,
+                egui_phosphor::regular::PUSH_PIN,
+                FontId::proportional(ui_scale * 0.75),
+                Color32::WHITE,
+            );
+        }
+
+        // Star rating after the other badges
+        if let Some(rating) = wallpaper.rating {
+            let rating_galley = painter.layout_no_wrap(
+                format!("{} {rating}", egui_phosphor::regular::STAR),
+                FontId::proportional(ui_scale * 0.75),
+                Color32::GOLD,
+            );
+            let rating_rect = egui::Align2::LEFT_CENTER.anchor_size(
+                next_badge - vec2(ui_scale * 0.75, 0.0),
+                rating_galley.size(),
+            );
+            painter.add(Shape::rect_filled(
+                rating_rect.expand(ui_scale * 0.25),
+                ui_scale * 0.5,
+                Color32::BLACK.gamma_multiply(0.8),
+            ));
+            painter.galley(rating_rect.min, rating_galley, Color32::GOLD);
+        }
+
+        // Warn when this wallpaper is due to be auto-deleted
+        if let Some(days) = self
+            .retention_policy
+            .filter(|_| !wallpaper.pinned)
+            .and_then(|policy| policy.retention_days(liked_state))
+        {
+            let remaining = (wallpaper.datetime + chrono::Duration::days(i64::from(days))
+                - Utc::now())
+            .num_days()
+            .max(0);
+            let expiry_galley = painter.layout_no_wrap(
+                format!(
+                    "{} Auto-deleted in {remaining} days",
+                    egui_phosphor::regular::TRASH
+                ),
+                FontId::proportional(ui_scale),
+                Color32::WHITE.gamma_multiply(0.8),
+            );
+            let expiry_rect = egui::Align2::LEFT_TOP.anchor_size(
+                datetime_rect.left_bottom() + vec2(0.0, ui_scale * 1.5),
+                expiry_galley.size(),
+            );
+            painter.add(Shape::rect_filled(
+                expiry_rect.expand(ui_scale * 0.5),
+                ui_scale,
+                Color32::BLACK.gamma_multiply(0.8),
+            ));
+            painter.galley(expiry_rect.min, expiry_galley, Color32::WHITE);
+        }
+
+        // Add delete button in top-right corner, the buttons do nothing while offline
         let delete_button_size = vec2(ui_scale.mul_add(2.0, 2.0), ui_scale.mul_add(2.0, 2.0));
         let delete_button_rect = egui::Align2::RIGHT_TOP.anchor_size(
             image_rect.right_top() + vec2(-20.0, 20.0),
@@ -541,7 +4268,7 @@ impl Wallpapy {
         painter.add(Shape::rect_filled(
             delete_button_rect,
             ui_scale,
-            Color32::BLACK.gamma_multiply(if is_hovering { 1.0 } else { 0.8 }),
+            Color32::BLACK.gamma_multiply(if is_hovering { 1.0 } else { idle_opacity }),
         ));
         painter.text(
             delete_button_rect.center(),
@@ -553,19 +4280,23 @@ impl Wallpapy {
         if is_hovering {
             sub_button_hovered = true;
             ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
-            if ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
-                let toasts_store = self.toasts.clone();
-                let network_store = self.network_data.clone();
-                let ctx = ui.ctx().clone();
-                remove_image(
-                    &self.host,
-                    &self.stored.auth_token,
-                    &wallpaper.id,
-                    move |result| {
-                        ctx.request_repaint();
-                        button_pressed_result(result, &network_store, &toasts_store, "");
-                    },
-                );
+            if !self.offline && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
+                if wallpaper.pinned {
+                    self.remove_confirmation = Some((wallpaper.id, String::new()));
+                } else {
+                    let toasts_store = self.toasts.clone();
+                    let network_store = self.network_data.clone();
+                    let ctx = ui.ctx().clone();
+                    remove_image(
+                        &self.host,
+                        &self.stored.auth_token,
+                        &wallpaper.id,
+                        move |result| {
+                            ctx.request_repaint();
+                            button_pressed_result(result, &network_store, &toasts_store, "");
+                        },
+                    );
+                }
             }
         }
 
@@ -578,12 +4309,12 @@ impl Wallpapy {
         painter.add(Shape::rect_filled(
             thumbs_down_button_rect,
             ui_scale,
-            if wallpaper.liked_state == LikedState::Disliked {
+            if liked_state == LikedState::Disliked {
                 Color32::DARK_RED
             } else {
                 Color32::BLACK
             }
-            .gamma_multiply(if is_hovering { 1.0 } else { 0.8 }),
+            .gamma_multiply(if is_hovering { 1.0 } else { idle_opacity }),
         ));
         painter.text(
             thumbs_down_button_rect.center(),
@@ -595,7 +4326,7 @@ impl Wallpapy {
         if is_hovering {
             sub_button_hovered = true;
             ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
-            if ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
+            if !self.offline && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
                 let toasts_store = self.toasts.clone();
                 let network_store = self.network_data.clone();
                 let ctx = ui.ctx().clone();
@@ -621,12 +4352,12 @@ impl Wallpapy {
         painter.add(Shape::rect_filled(
             thumbs_up_button_rect,
             ui_scale,
-            if wallpaper.liked_state == LikedState::Liked {
+            if liked_state == LikedState::Liked {
                 Color32::DARK_GREEN
             } else {
                 Color32::BLACK
             }
-            .gamma_multiply(if is_hovering { 1.0 } else { 0.8 }),
+            .gamma_multiply(if is_hovering { 1.0 } else { idle_opacity }),
         ));
         painter.text(
             thumbs_up_button_rect.center(),
@@ -638,7 +4369,7 @@ impl Wallpapy {
         if is_hovering {
             sub_button_hovered = true;
             ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
-            if ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
+            if !self.offline && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
                 let toasts_store = self.toasts.clone();
                 let network_store = self.network_data.clone();
                 let ctx = ui.ctx().clone();
@@ -664,12 +4395,12 @@ impl Wallpapy {
         painter.add(Shape::rect_filled(
             loved_button_rect,
             ui_scale,
-            if wallpaper.liked_state == LikedState::Loved {
+            if liked_state == LikedState::Loved {
                 Color32::from_rgb(140, 90, 0)
             } else {
                 Color32::BLACK
             }
-            .gamma_multiply(if is_hovering { 1.0 } else { 0.8 }),
+            .gamma_multiply(if is_hovering { 1.0 } else { idle_opacity }),
         ));
         painter.text(
             loved_button_rect.center(),
@@ -681,7 +4412,7 @@ impl Wallpapy {
         if is_hovering {
             sub_button_hovered = true;
             ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
-            if ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
+            if !self.offline && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
                 let toasts_store = self.toasts.clone();
                 let network_store = self.network_data.clone();
                 let ctx = ui.ctx().clone();
@@ -707,7 +4438,7 @@ impl Wallpapy {
         painter.add(Shape::rect_filled(
             recreate_button_rect,
             ui_scale,
-            Color32::BLACK.gamma_multiply(if is_hovering { 1.0 } else { 0.8 }),
+            Color32::BLACK.gamma_multiply(if is_hovering { 1.0 } else { idle_opacity }),
         ));
         painter.text(
             recreate_button_rect.center(),
@@ -719,7 +4450,11 @@ impl Wallpapy {
         if is_hovering {
             sub_button_hovered = true;
             ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
-            if ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
+            // Right click to tweak the prompt first
+            if !self.offline && ui.input(|i| i.pointer.button_clicked(PointerButton::Secondary)) {
+                self.recreate_edit = Some((wallpaper.id, wallpaper.prompt_data.prompt.clone()));
+            }
+            if !self.offline && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
                 let toasts_store = self.toasts.clone();
                 let network_store = self.network_data.clone();
                 let ctx = ui.ctx().clone();
@@ -735,6 +4470,76 @@ impl Wallpapy {
             }
         }
 
+        // Add variation button
+        let variation_button_rect = egui::Align2::RIGHT_TOP.anchor_size(
+            recreate_button_rect.left_top() + vec2(-10.0, 0.0),
+            delete_button_size,
+        );
+        let is_hovering = ui.rect_contains_pointer(variation_button_rect);
+        painter.add(Shape::rect_filled(
+            variation_button_rect,
+            ui_scale,
+            Color32::BLACK.gamma_multiply(if is_hovering { 1.0 } else { idle_opacity }),
+        ));
+        painter.text(
+            variation_button_rect.center(),
+            egui::Align2::CENTER_CENTER,
+            egui_phosphor::regular::SHUFFLE,
+            FontId::proportional(ui_scale),
+            Color32::WHITE,
+        );
+        if is_hovering {
+            sub_button_hovered = true;
+            ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
+            if !self.offline && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
+                self.generate_variation(ui.ctx(), wallpaper.id);
+            }
+        }
+
+        // Add pin button, only on hover unless the screen is too narrow to hover
+        if narrow || ui.rect_contains_pointer(image_rect) {
+            let pin_button_rect = egui::Align2::RIGHT_TOP.anchor_size(
+                variation_button_rect.left_top() + vec2(-10.0, 0.0),
+                delete_button_size,
+            );
+            let is_hovering = ui.rect_contains_pointer(pin_button_rect);
+            painter.add(Shape::rect_filled(
+                pin_button_rect,
+                ui_scale,
+                if wallpaper.pinned {
+                    Color32::from_rgb(40, 80, 140)
+                } else {
+                    Color32::BLACK
+                }
+                .gamma_multiply(if is_hovering { 1.0 } else { idle_opacity }),
+            ));
+            painter.text(
+                pin_button_rect.center(),
+                egui::Align2::CENTER_CENTER,
+                egui_phosphor::regular::PUSH_PIN,
+                FontId::proportional(ui_scale),
+                Color32::WHITE,
+            );
+            if is_hovering {
+                sub_button_hovered = true;
+                ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
+                if !self.offline && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
+                    let toasts_store = self.toasts.clone();
+                    let network_store = self.network_data.clone();
+                    let ctx = ui.ctx().clone();
+                    pin_image(
+                        &self.host,
+                        &self.stored.auth_token,
+                        &wallpaper.id,
+                        move |result| {
+                            ctx.request_repaint();
+                            button_pressed_result(result, &network_store, &toasts_store, "");
+                        },
+                    );
+                }
+            }
+        }
+
         // Draw shortened prompt in bottom center, click to copy to clipboard
         let prompt_galley = painter.layout(
             wallpaper.prompt_data.shortened_prompt.clone(),
@@ -750,7 +4555,7 @@ impl Wallpapy {
         painter.add(Shape::rect_filled(
             prompt_rect.expand(ui_scale * 0.5625),
             ui_scale,
-            match wallpaper.liked_state {
+            match liked_state {
                 LikedState::Loved => Color32::from_rgb(170, 120, 10),
                 LikedState::Liked => Color32::from_rgb(40, 70, 40),
                 LikedState::Disliked => Color32::from_rgb(100, 20, 20),
@@ -771,17 +4576,369 @@ impl Wallpapy {
             }
         }
 
+        // Highlight selected wallpapers
+        if self.selection.selected.contains(&wallpaper.id) {
+            ui.painter().rect_stroke(
+                image_rect.shrink(2.0),
+                16.0,
+                Stroke::new(4.0, Color32::from_rgb(80, 160, 255)),
+            );
+        }
+
         // Check if image is clicked
         let is_hovering = ui.rect_contains_pointer(image_rect);
         if is_hovering
             && !sub_button_hovered
             && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary))
         {
-            self.fullscreen_image = Some(wallpaper.id);
+            if self.selection.active {
+                let range = ui.input(|i| i.modifiers.shift);
+                self.select_wallpaper(wallpaper.id, range);
+            } else {
+                self.fullscreen_image = Some(wallpaper.id);
+            }
+        }
+    }
+
+    /// Toggle a wallpaper, or with a range select everything between it and the last clicked one
+    fn select_wallpaper(&mut self, id: Uuid, range: bool) {
+        let selection = &mut self.selection;
+        let position = |id| selection.order.iter().position(|other| *other == id);
+        let span = selection
+            .anchor
+            .filter(|_| range)
+            .and_then(position)
+            .zip(position(id));
+        if let Some((anchor, clicked)) = span {
+            let (start, end) = (anchor.min(clicked), anchor.max(clicked));
+            selection
+                .selected
+                .extend(selection.order[start..=end].iter().copied());
+        } else if !selection.selected.remove(&id) {
+            selection.selected.insert(id);
+        }
+        selection.anchor = Some(id);
+    }
+
+    /// Floating bar of actions for the selected wallpapers
+    fn show_selection_bar(&mut self, ctx: &Context) {
+        if !self.selection.active {
+            return;
+        }
+
+        let count = self.selection.selected.len();
+        let mut liked = None;
+        let mut delete = false;
+        egui::Area::new(egui::Id::new("selection_bar"))
+            .anchor(Align2::CENTER_BOTTOM, vec2(0.0, -20.0))
+            .show(ctx, |ui| {
+                Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{count} selected"));
+                        if ui.button("All").clicked() {
+                            self.selection
+                                .selected
+                                .extend(self.selection.order.iter().copied());
+                        }
+                        if ui.button("None").clicked() {
+                            self.selection.selected.clear();
+                            self.selection.anchor = None;
+                        }
+                        ui.separator();
+
+                        ui.add_enabled_ui(count > 0 && !self.offline, |ui| {
+                            for (state, icon) in [
+                                (LikedState::Loved, egui_phosphor::regular::HEART),
+                                (LikedState::Liked, egui_phosphor::regular::THUMBS_UP),
+                                (
+                                    LikedState::Neutral,
+                                    egui_phosphor::regular::ALIGN_CENTER_HORIZONTAL_SIMPLE,
+                                ),
+                                (LikedState::Disliked, egui_phosphor::regular::THUMBS_DOWN),
+                            ] {
+                                if ui.button(icon).clicked() {
+                                    liked = Some(state);
+                                }
+                            }
+                            ui.separator();
+                            if ui
+                                .button(format!("{} Delete", egui_phosphor::regular::TRASH))
+                                .clicked()
+                            {
+                                delete = true;
+                            }
+                        });
+                    });
+                });
+            });
+
+        let ids = self.selection.selected.iter().copied().collect::<Vec<_>>();
+        if let Some(liked) = liked {
+            let toasts_store = self.toasts.clone();
+            let network_store = self.network_data.clone();
+            let ctx = ctx.clone();
+            like_images(
+                &self.host,
+                &self.stored.auth_token,
+                ids,
+                liked,
+                move |result| {
+                    ctx.request_repaint();
+                    button_pressed_result(result, &network_store, &toasts_store, "");
+                },
+            );
+        } else if delete {
+            // Pinned wallpapers need a typed confirmation each, so they are left out
+            let pinned = self
+                .database
+                .as_ref()
+                .map_or_else(HashSet::new, |database| {
+                    ids.iter()
+                        .copied()
+                        .filter(|id| database.wallpapers.get(id).is_some_and(|w| w.pinned))
+                        .collect::<HashSet<_>>()
+                });
+            let ids = ids
+                .into_iter()
+                .filter(|id| !pinned.contains(id))
+                .collect::<Vec<_>>();
+            if !pinned.is_empty() {
+                self.toasts.lock().info(format!(
+                    "Skipped {} pinned wallpaper{}",
+                    pinned.len(),
+                    if pinned.len() == 1 { "" } else { "s" }
+                ));
+            }
+            if !ids.is_empty() {
+                let toasts_store = self.toasts.clone();
+                let network_store = self.network_data.clone();
+                let ctx = ctx.clone();
+                let message = format!("Deleted {} wallpapers", ids.len());
+                remove_images(&self.host, &self.stored.auth_token, ids, move |result| {
+                    ctx.request_repaint();
+                    button_pressed_result(result, &network_store, &toasts_store, &message);
+                });
+            }
+            self.selection.selected = pinned;
+            self.selection.anchor = None;
         }
     }
 
-    fn draw_comment_box(&self, ui: &mut egui::Ui, comment: &CommentData, width: f32, height: f32) {
+    fn show_history_window(&mut self, ctx: &Context) {
+        let Some(database) = &self.database else {
+            return;
+        };
+        let viewer = database.viewer;
+        let history = &mut self.history;
+
+        Window::new("History")
+            .open(&mut history.open)
+            .default_size([800.0, 600.0])
+            .show(ctx, |ui| {
+                // Stats header
+                let count = |state: LikedState| {
+                    database
+                        .wallpapers
+                        .values()
+                        .filter(|wallpaper| wallpaper.liked_state_for(viewer) == state)
+                        .count()
+                };
+                ui.horizontal(|ui| {
+                    for state in [LikedState::Loved, LikedState::Liked, LikedState::Disliked] {
+                        ui.label(format!("{} {}", liked_state_icon(state), count(state)));
+                    }
+                    ui.label(format!("{} prompts", database.wallpapers.len()));
+                });
+                draw_likes_per_month(ui, database);
+
+                // LLM spend, most recent months first
+                if let Some(cost_stats) = &self.cost_stats {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(format!(
+                            "LLM cost ${:.2} ({} tokens)",
+                            cost_stats.total_cost_usd, cost_stats.total_tokens
+                        ));
+                        for month in cost_stats.months.iter().rev().take(6) {
+                            ui.label(format!(
+                                "{:02}/{} ${:.2}",
+                                month.month, month.year, month.cost_usd
+                            ))
+                            .on_hover_text(format!("{} tokens", month.total_tokens));
+                        }
+                    });
+                    ui.horizontal_wrapped(|ui| {
+                        for stats in &cost_stats.models {
+                            ui.label(format!(
+                                "{} {} {}/{}",
+                                stats.model,
+                                liked_state_icon(LikedState::Loved),
+                                stats.loved,
+                                stats.generated
+                            ))
+                            .on_hover_text(format!(
+                                "{} of {} generated wallpapers loved",
+                                stats.loved, stats.generated
+                            ));
+                        }
+                    });
+                    ui.horizontal_wrapped(|ui| {
+                        for stats in &cost_stats.users {
+                            let name = match stats.user {
+                                Some(uuid) => database
+                                    .users
+                                    .get(&uuid)
+                                    .map_or("Deleted account", String::as_str),
+                                None => "Auto-generated",
+                            };
+                            ui.label(format!(
+                                "{} {name} {}",
+                                egui_phosphor::regular::USER,
+                                stats.generated
+                            ))
+                            .on_hover_text(format!("{} wallpapers generated", stats.generated));
+                        }
+                    });
+                }
+
+                let top_words = |state: LikedState| {
+                    word_frequencies(
+                        database
+                            .wallpapers
+                            .values()
+                            .filter(|wallpaper| wallpaper.liked_state_for(viewer) == state)
+                            .map(|wallpaper| wallpaper.prompt_data.shortened_prompt.as_str()),
+                    )
+                    .into_iter()
+                    .take(10)
+                    .map(|(word, count)| format!("{word} ({count})"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+                };
+                ui.label(format!("Loved words: {}", top_words(LikedState::Loved)));
+                ui.label(format!(
+                    "Disliked words: {}",
+                    top_words(LikedState::Disliked)
+                ));
+
+                // Liked state filters
+                ui.horizontal(|ui| {
+                    for (flag, state) in [
+                        (StateFilter::LOVED, LikedState::Loved),
+                        (StateFilter::LIKED, LikedState::Liked),
+                        (StateFilter::NEUTRAL, LikedState::Neutral),
+                        (StateFilter::DISLIKED, LikedState::Disliked),
+                    ] {
+                        render_statefilter_button(
+                            ui,
+                            &mut history.filter,
+                            flag,
+                            liked_state_icon(state),
+                        );
+                    }
+                });
+                ui.separator();
+
+                let mut wallpapers = database
+                    .wallpapers
+                    .values()
+                    .filter(|wallpaper| {
+                        history
+                            .filter
+                            .contains(match wallpaper.liked_state_for(viewer) {
+                                LikedState::Loved => StateFilter::LOVED,
+                                LikedState::Liked => StateFilter::LIKED,
+                                LikedState::Neutral => StateFilter::NEUTRAL,
+                                LikedState::Disliked => StateFilter::DISLIKED,
+                            })
+                    })
+                    .collect::<Vec<_>>();
+                wallpapers.sort_by(|a, b| {
+                    let ordering = match history.sort {
+                        HistorySort::Date => a.datetime.cmp(&b.datetime),
+                        HistorySort::Prompt => a
+                            .prompt_data
+                            .shortened_prompt
+                            .cmp(&b.prompt_data.shortened_prompt),
+                        HistorySort::Liked => liked_state_rank(a.liked_state_for(viewer))
+                            .cmp(&liked_state_rank(b.liked_state_for(viewer)))
+                            .then(a.datetime.cmp(&b.datetime)),
+                    };
+                    if history.ascending {
+                        ordering
+                    } else {
+                        ordering.reverse()
+                    }
+                });
+
+                TableBuilder::new(ui)
+                    .striped(true)
+                    .column(Column::auto())
+                    .column(Column::remainder().clip(true))
+                    .column(Column::auto())
+                    .header(20.0, |mut header| {
+                        for (column, title) in [
+                            (HistorySort::Date, "Date"),
+                            (HistorySort::Prompt, "Prompt"),
+                            (HistorySort::Liked, "Liked"),
+                        ] {
+                            header.col(|ui| {
+                                let title = if history.sort == column {
+                                    format!(
+                                        "{title} {}",
+                                        if history.ascending {
+                                            egui_phosphor::regular::CARET_UP
+                                        } else {
+                                            egui_phosphor::regular::CARET_DOWN
+                                        }
+                                    )
+                                } else {
+                                    title.to_string()
+                                };
+                                if ui.button(title).clicked() {
+                                    if history.sort == column {
+                                        history.ascending = !history.ascending;
+                                    } else {
+                                        history.sort = column;
+                                        history.ascending = false;
+                                    }
+                                }
+                            });
+                        }
+                    })
+                    .body(|body| {
+                        body.rows(20.0, wallpapers.len(), |mut row| {
+                            let wallpaper = wallpapers[row.index()];
+                            row.col(|ui| {
+                                ui.label(
+                                    wallpaper
+                                        .datetime
+                                        .with_timezone(&Local)
+                                        .format("%d/%m/%Y %H:%M")
+                                        .to_string(),
+                                );
+                            });
+                            row.col(|ui| {
+                                ui.label(&wallpaper.prompt_data.shortened_prompt)
+                                    .on_hover_text(&wallpaper.prompt_data.prompt);
+                            });
+                            row.col(|ui| {
+                                ui.label(liked_state_icon(wallpaper.liked_state_for(viewer)));
+                            });
+                        });
+                    });
+            });
+    }
+
+    fn draw_comment_box(
+        &mut self,
+        ui: &mut egui::Ui,
+        comment: &CommentData,
+        author: Option<&str>,   // Username of the account that posted it
+        own_uuid: Option<Uuid>, // Logged in account, to show whether it reacted
+        spawned: Option<Uuid>,  // Wallpaper generated from this comment
+        width: f32,
+        height: f32,
+    ) {
         let (response, painter) = ui.allocate_painter(Vec2::new(width, height), Sense::click());
         let rect = response.rect;
 
@@ -815,30 +4972,137 @@ impl Wallpapy {
         ));
         painter.galley(datetime_rect.min, datetime_galley, Color32::WHITE);
 
+        // Sentiment badge right of the date, neutral comments get none
+        let sentiment_icon = match comment.sentiment() {
+            CommentSentiment::Positive => Some((egui_phosphor::regular::SMILEY, Color32::GREEN)),
+            CommentSentiment::Negative => Some((egui_phosphor::regular::SMILEY_SAD, Color32::RED)),
+            CommentSentiment::Neutral => None,
+        };
+        if let Some((icon, color)) = sentiment_icon {
+            let center = datetime_rect.right_center() + vec2(ui_scale * 2.0, 0.0);
+            painter.circle_filled(center, ui_scale, Color32::BLACK.gamma_multiply(0.8));
+            painter.text(
+                center,
+                egui::Align2::CENTER_CENTER,
+                icon,
+                FontId::proportional(ui_scale * 1.2),
+                color,
+            );
+        }
+
         // Add delete button in top-right corner
-        let delete_button_size = vec2(ui_scale.mul_add(2.0, 2.0), ui_scale.mul_add(2.0, 2.0));
-        let delete_button_rect = egui::Align2::RIGHT_TOP
-            .anchor_size(rect.right_top() + vec2(-20.0, 20.0), delete_button_size);
-        let is_hovering = ui.rect_contains_pointer(delete_button_rect);
-        painter.add(Shape::rect_filled(
+        let button_size = vec2(ui_scale.mul_add(2.0, 2.0), ui_scale.mul_add(2.0, 2.0));
+        let delete_button_rect =
+            egui::Align2::RIGHT_TOP.anchor_size(rect.right_top() + vec2(-20.0, 20.0), button_size);
+        if comment_button(
+            ui,
+            &painter,
             delete_button_rect,
             ui_scale,
-            Color32::BLACK.gamma_multiply(if is_hovering { 1.0 } else { 0.8 }),
-        ));
-        painter.text(
-            delete_button_rect.center(),
-            egui::Align2::CENTER_CENTER,
             egui_phosphor::regular::X,
+        ) && !self.offline
+        {
+            let toasts_store = self.toasts.clone();
+            let network_store = self.network_data.clone();
+            let ctx = ui.ctx().clone();
+            remove_comment(
+                &self.host,
+                &self.stored.auth_token,
+                &comment.id,
+                move |result| {
+                    ctx.request_repaint();
+                    button_pressed_result(result, &network_store, &toasts_store, "");
+                },
+            );
+        }
+
+        // Beside it either open the wallpaper this comment spawned, or generate one from it
+        let generate_button_rect = egui::Align2::RIGHT_TOP.anchor_size(
+            delete_button_rect.left_top() - vec2(ui_scale * 0.5, 0.0),
+            button_size,
+        );
+        if let Some(id) = spawned {
+            if comment_button(
+                ui,
+                &painter,
+                generate_button_rect,
+                ui_scale,
+                egui_phosphor::regular::LINK,
+            ) {
+                self.fullscreen_image = Some(id);
+            }
+        } else if !comment.used
+            && comment_button(
+                ui,
+                &painter,
+                generate_button_rect,
+                ui_scale,
+                egui_phosphor::regular::SPARKLE,
+            )
+            && !self.offline
+        {
+            let toasts_store = self.toasts.clone();
+            let network_store = self.network_data.clone();
+            let ctx = ui.ctx().clone();
+            if begin_request(&network_store, &toasts_store, "/generate") {
+                toasts_store
+                    .lock()
+                    .info("Generating wallpaper from comment");
+                generate_wallpaper(
+                    &self.host,
+                    &self.stored.auth_token,
+                    &comment.comment,
+                    self.stored.generation_mode,
+                    self.stored.image_model.as_deref(),
+                    Some(comment.id),
+                    move |result| {
+                        ctx.request_repaint();
+                        network_store.lock().pending_requests.remove("/generate");
+                        button_pressed_result(
+                            result,
+                            &network_store,
+                            &toasts_store,
+                            "Generated wallpaper",
+                        );
+                    },
+                );
+            }
+        }
+
+        // Thumbs up badge in the bottom right, click to toggle your own reaction
+        let reacted = own_uuid.is_some_and(|uuid| comment.reactions.get(&uuid) == Some(&true));
+        let badge_galley = painter.layout_no_wrap(
+            format!(
+                "{} {}",
+                egui_phosphor::regular::THUMBS_UP,
+                comment.reaction_count()
+            ),
             FontId::proportional(ui_scale),
             Color32::WHITE,
         );
-        if is_hovering {
+        let badge_rect = egui::Align2::RIGHT_BOTTOM.anchor_size(
+            rect.right_bottom() + vec2(-20.0, -20.0),
+            badge_galley.size(),
+        );
+        let badge_hovered = ui.rect_contains_pointer(badge_rect.expand(ui_scale * 0.5));
+        painter.add(Shape::rect_filled(
+            badge_rect.expand(ui_scale * 0.5),
+            ui_scale,
+            if reacted {
+                Color32::from_rgb(40, 80, 140)
+            } else {
+                Color32::BLACK
+            }
+            .gamma_multiply(if badge_hovered { 1.0 } else { 0.8 }),
+        ));
+        painter.galley(badge_rect.min, badge_galley, Color32::WHITE);
+        if badge_hovered {
             ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
-            if ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
+            if !self.offline && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
                 let toasts_store = self.toasts.clone();
                 let network_store = self.network_data.clone();
                 let ctx = ui.ctx().clone();
-                remove_comment(
+                react_comment(
                     &self.host,
                     &self.stored.auth_token,
                     &comment.id,
@@ -850,6 +5114,28 @@ impl Wallpapy {
             }
         }
 
+        // Draw the AI's note on how the feedback was used along the bottom, above the badge
+        let mut text_bottom =
+            rect.center_bottom() - vec2(0.0, 20.0 + badge_rect.height() + ui_scale * 1.5);
+        if let Some(note) = &comment.response {
+            let note_color = Color32::from_rgb(150, 200, 255);
+            let note_galley = painter.layout(
+                format!("{} {note}", egui_phosphor::regular::ROBOT),
+                FontId::proportional(ui_scale * 0.9),
+                note_color,
+                width - 40.0,
+            );
+            let note_rect =
+                egui::Align2::CENTER_BOTTOM.anchor_size(text_bottom, note_galley.size());
+            painter.add(Shape::rect_filled(
+                note_rect.expand(ui_scale * 0.5),
+                ui_scale,
+                Color32::BLACK.gamma_multiply(0.6),
+            ));
+            painter.galley(note_rect.min, note_galley, note_color);
+            text_bottom.y = note_rect.top() - ui_scale * 1.5;
+        }
+
         // Draw comments text in bottom center, click to copy to clipboard
         let text_galley = painter.layout(
             comment.comment.clone(),
@@ -857,8 +5143,7 @@ impl Wallpapy {
             Color32::WHITE.gamma_multiply(0.8),
             width - 40.0,
         );
-        let text_rect = egui::Align2::CENTER_BOTTOM
-            .anchor_size(rect.center_bottom() + vec2(0.0, -20.0), text_galley.size());
+        let text_rect = egui::Align2::CENTER_BOTTOM.anchor_size(text_bottom, text_galley.size());
         let is_hovering = ui.rect_contains_pointer(text_rect);
         painter.add(Shape::rect_filled(
             text_rect.expand(ui_scale * 0.5),
@@ -866,6 +5151,25 @@ impl Wallpapy {
             Color32::BLACK.gamma_multiply(0.8),
         ));
         painter.galley(text_rect.min, text_galley, Color32::WHITE);
+
+        // Credit who wrote it just above the text
+        if let Some(author) = author {
+            let author_galley = painter.layout_no_wrap(
+                format!("{} {author}", egui_phosphor::regular::USER),
+                FontId::proportional(ui_scale * 0.9),
+                Color32::WHITE.gamma_multiply(0.8),
+            );
+            let author_rect = egui::Align2::LEFT_BOTTOM.anchor_size(
+                text_rect.left_top() - vec2(0.0, ui_scale * 1.5),
+                author_galley.size(),
+            );
+            painter.add(Shape::rect_filled(
+                author_rect.expand(ui_scale * 0.5),
+                ui_scale,
+                Color32::BLACK.gamma_multiply(0.6),
+            ));
+            painter.galley(author_rect.min, author_galley, Color32::WHITE);
+        }
         if is_hovering {
             ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
             if ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
@@ -880,14 +5184,108 @@ impl Wallpapy {
     fn get_database(&mut self, ctx: &Context) {
         let network_store = self.network_data.clone();
         let mut network_data_guard = network_store.lock();
+        if let Some(policy) = network_data_guard.retention_policy.take() {
+            self.retention_policy = Some(policy);
+        }
+        if let Some(models) = network_data_guard.image_models.take() {
+            self.image_models = models;
+        }
+        if std::mem::take(&mut network_data_guard.account_deleted) {
+            self.stored.auth_token.clear();
+            self.stored.username.clear();
+            self.account = AccountWindow::default();
+        }
+        if std::mem::take(&mut network_data_guard.moodboard_changed) {
+            self.last_status_poll = None;
+        }
+        if let Some(status) = network_data_guard.server_status.take() {
+            self.server_status = Some(status);
+        }
+        if let Some(clusters) = network_data_guard.clusters.take() {
+            self.clusters = Some(clusters);
+        }
+        if let Some(cost_stats) = network_data_guard.cost_stats.take() {
+            self.cost_stats = Some(cost_stats);
+        }
+        if let Some(audit_log) = network_data_guard.audit_log.take() {
+            self.audit.entries = Some(audit_log);
+        }
+        if let Some(activity) = network_data_guard.activity.take() {
+            self.activity.entries = Some(activity);
+        }
+        match network_data_guard.server_logs.take() {
+            Some(Ok(lines)) => self.server_logs.lines = Some(lines),
+            Some(Err(e)) => {
+                // Stop the auto refresh rather than repeating the error every few seconds
+                self.server_logs.open = false;
+                self.toasts.lock().error(e.to_string());
+            }
+            None => {}
+        }
+        if let Some(report) = network_data_guard.maintenance_report.take() {
+            self.maintenance.report = Some(report);
+        }
+        if let Some(previews) = network_data_guard.profile_previews.take() {
+            self.profiles.previews = Some(previews);
+        }
+        if let Some(context) = network_data_guard.generation_context.take() {
+            self.generation_context = Some(context);
+        }
+        if let Some((id, result)) = network_data_guard.image_stats.take() {
+            if self.stats_overlay.loading == Some(id) {
+                self.stats_overlay.loading = None;
+            }
+            match result {
+                Ok(stats) => self.stats_overlay.stats = Some((id, stats)),
+                Err(e) => {
+                    self.toasts.lock().error(e.to_string());
+                }
+            }
+        }
+        if let Some((key, result)) = network_data_guard.gallery_page.take() {
+            self.gallery.loading = false;
+            match result {
+                // Pages requested before a reset or sort change are stale
+                Ok(page) if key == (self.gallery.sort, self.gallery.wallpapers.len()) => {
+                    self.gallery.wallpapers.extend(page.wallpapers);
+                    self.gallery.total = Some(page.total);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    // Stop at what loaded rather than retrying every frame, a refresh starts over
+                    self.gallery.total = Some(self.gallery.wallpapers.len());
+                    self.toasts.lock().error(e.to_string());
+                }
+            }
+        }
         match &network_data_guard.get_database {
             GetDatabaseState::InProgress | GetDatabaseState::None => {}
             GetDatabaseState::Wanted => {
                 network_data_guard.get_database = GetDatabaseState::InProgress;
                 drop(network_data_guard);
 
+                let retention_store = network_store.clone();
+                let retention_ctx = ctx.clone();
+                get_retention(&self.host, move |res| match res {
+                    Ok(policy) => {
+                        retention_store.lock().retention_policy = Some(policy);
+                        retention_ctx.request_repaint();
+                    }
+                    Err(e) => log::error!("Failed to fetch retention policy: {:?}", e),
+                });
+
+                let models_store = network_store.clone();
+                let models_ctx = ctx.clone();
+                get_models(&self.host, move |res| match res {
+                    Ok(models) => {
+                        models_store.lock().image_models = Some(models);
+                        models_ctx.request_repaint();
+                    }
+                    Err(e) => log::error!("Failed to fetch image models: {:?}", e),
+                });
+
                 let ctx = ctx.clone();
-                get_database(&self.host, move |res| {
+                get_database(&self.host, &self.stored.auth_token, move |res| {
                     network_store.lock().get_database = GetDatabaseState::Done(res);
                     ctx.request_repaint();
                 });
@@ -896,9 +5294,15 @@ impl Wallpapy {
                 match response {
                     Ok(database) => {
                         self.database = Some(database.clone());
+                        self.database_fetched = Some(Utc::now());
+                        self.offline = false;
+                        self.snapshot_pending = true;
+                        self.reset_gallery();
                     }
                     Err(e) => {
                         log::error!("Failed to fetch galleries: {:?}", e);
+                        self.offline = true;
+                        self.last_failed_fetch = Some(ctx.input(|i| i.time));
                     }
                 }
                 network_data_guard.get_database = GetDatabaseState::None;
@@ -908,6 +5312,22 @@ impl Wallpapy {
         }
     }
 
+    /// Fetch the database again every `OFFLINE_RETRY_SECS` until the server is back
+    fn retry_offline(&self, ctx: &Context) {
+        if !self.offline {
+            return;
+        }
+        let due = self
+            .last_failed_fetch
+            .is_none_or(|last| ctx.input(|i| i.time) - last >= OFFLINE_RETRY_SECS);
+        let mut network_data = self.network_data.lock();
+        if due && matches!(network_data.get_database, GetDatabaseState::None) {
+            network_data.get_database = GetDatabaseState::Wanted;
+        }
+        drop(network_data);
+        ctx.request_repaint_after(std::time::Duration::from_secs_f64(OFFLINE_RETRY_SECS));
+    }
+
     fn show_login_panel(&mut self, ctx: &Context) {
         CentralPanel::default()
             .frame(Frame {
@@ -977,6 +5397,10 @@ impl Wallpapy {
                             // If no | is found, treat the entire response as the token
                             self.stored.auth_token.clone_from(response);
                         }
+                        self.stored.username = self.login_form.username.trim().to_string();
+                        network_data_guard.get_database = GetDatabaseState::Wanted;
+                        self.gallery.wallpapers.clear();
+                        self.gallery.total = None;
                     }
                     Err(e) => {
                         self.toasts.lock().error(e.to_string());
@@ -988,6 +5412,19 @@ impl Wallpapy {
     }
 }
 
+/// Claim an endpoint for a request, false with a toast when one to it is already in flight
+fn begin_request(
+    network_store: &Arc<Mutex<DownloadData>>,
+    toasts_store: &Arc<Mutex<Toasts>>,
+    endpoint: &'static str,
+) -> bool {
+    let claimed = network_store.lock().pending_requests.insert(endpoint);
+    if !claimed {
+        toasts_store.lock().info("Request already in progress");
+    }
+    claimed
+}
+
 fn button_pressed_result(
     result: Result<()>,
     network_store: &Arc<Mutex<DownloadData>>,
@@ -1009,6 +5446,379 @@ fn button_pressed_result(
     }
 }
 
+/// Image links in pasted text, http(s) URLs or base64 data URIs separated by whitespace
+fn pasted_image_urls(text: &str) -> Vec<&str> {
+    text.split_whitespace()
+        .filter(|word| {
+            ["http://", "https://", "data:image/"]
+                .iter()
+                .any(|prefix| word.starts_with(prefix))
+        })
+        .collect()
+}
+
+/// Like `button_pressed_result`, but the change shows up in the server status rather than the database
+fn moodboard_result(
+    result: Result<()>,
+    network_store: &Arc<Mutex<DownloadData>>,
+    toasts_store: &Arc<Mutex<Toasts>>,
+    success_str: &str,
+) {
+    match result {
+        Ok(()) => {
+            toasts_store.lock().success(success_str);
+            network_store.lock().moodboard_changed = true;
+        }
+        Err(e) => {
+            toasts_store
+                .lock()
+                .error(format!("Failed to submit request: {e}"));
+        }
+    }
+}
+
+/// Draw a bar strip of how many wallpapers were liked or loved each month
+/// A round icon button painted on a comment card, true when clicked
+fn comment_button(
+    ui: &egui::Ui,
+    painter: &egui::Painter,
+    rect: Rect,
+    ui_scale: f32,
+    icon: &str,
+) -> bool {
+    let is_hovering = ui.rect_contains_pointer(rect);
+    painter.add(Shape::rect_filled(
+        rect,
+        ui_scale,
+        Color32::BLACK.gamma_multiply(if is_hovering { 1.0 } else { 0.8 }),
+    ));
+    painter.text(
+        rect.center(),
+        egui::Align2::CENTER_CENTER,
+        icon,
+        FontId::proportional(ui_scale),
+        Color32::WHITE,
+    );
+    if is_hovering {
+        ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
+    }
+    is_hovering && ui.input(|i| i.pointer.button_clicked(PointerButton::Primary))
+}
+
+/// The database as last fetched, cached in storage for when the server can't be reached
+#[derive(Serialize, Deserialize)]
+struct DatabaseSnapshot {
+    database: Database,
+    fetched: DateTime<Utc>,
+}
+
+/// A copy of the database with only the most recent `CACHED_ENTRIES` wallpapers and comments
+fn cache_snapshot(database: &Database) -> Database {
+    let mut wallpapers = database.wallpapers.values().collect::<Vec<_>>();
+    wallpapers.sort_by_key(|wallpaper| std::cmp::Reverse(wallpaper.datetime));
+    let mut comments = database.comments.values().collect::<Vec<_>>();
+    comments.sort_by_key(|comment| std::cmp::Reverse(comment.datetime));
+    Database {
+        wallpapers: wallpapers
+            .into_iter()
+            .take(CACHED_ENTRIES)
+            .map(|wallpaper| (wallpaper.id, wallpaper.clone()))
+            .collect(),
+        comments: comments
+            .into_iter()
+            .take(CACHED_ENTRIES)
+            .map(|comment| (comment.id, comment.clone()))
+            .collect(),
+        ..database.clone()
+    }
+}
+
+/// The upscaled file if there is one, otherwise the original
+fn full_size_file(wallpaper: &WallpaperData) -> &ImageFile {
+    wallpaper
+        .upscaled_file
+        .as_ref()
+        .map_or(&wallpaper.original_file, |upscaled_file| upscaled_file)
+}
+
+/// Wallpapers in the order the arrow keys step through them, newest first
+fn navigation_order(database: &Database) -> Vec<&WallpaperData> {
+    let mut order = database.wallpapers.values().collect::<Vec<_>>();
+    order.sort_by(|a, b| b.datetime.cmp(&a.datetime));
+    order
+}
+
+fn draw_likes_per_month(ui: &mut egui::Ui, database: &Database) {
+    let mut months: BTreeMap<(i32, u32), usize> = BTreeMap::new();
+    for wallpaper in database.wallpapers.values() {
+        if matches!(
+            wallpaper.liked_state_for(database.viewer),
+            LikedState::Liked | LikedState::Loved
+        ) {
+            let datetime = wallpaper.datetime.with_timezone(&Local);
+            *months
+                .entry((datetime.year(), datetime.month()))
+                .or_default() += 1;
+        }
+    }
+    let (Some(&first), Some(&last)) = (months.keys().next(), months.keys().next_back()) else {
+        return;
+    };
+
+    // Fill in the months with no likes so the strip is continuous
+    let mut counts = Vec::new();
+    let (mut year, mut month) = first;
+    loop {
+        counts.push(months.get(&(year, month)).copied().unwrap_or(0));
+        if (year, month) == last {
+            break;
+        }
+        month += 1;
+        if month > 12 {
+            month = 1;
+            year += 1;
+        }
+    }
+    let max = counts.iter().copied().max().unwrap_or(1).max(1);
+
+    let (rect, response) = ui.allocate_exact_size(vec2(ui.available_width(), 40.0), Sense::hover());
+    let bar_width = rect.width() / counts.len() as f32;
+    for (i, count) in counts.iter().enumerate() {
+        let height = rect.height() * *count as f32 / max as f32;
+        let bar = Rect::from_min_max(
+            rect.left_bottom() + vec2(bar_width * i as f32, -height),
+            rect.left_bottom() + vec2(bar_width * (i + 1) as f32, 0.0),
+        );
+        ui.painter()
+            .rect_filled(bar.shrink2(vec2(1.0, 0.0)), 2.0, Color32::DARK_GREEN);
+    }
+    response.on_hover_text(format!(
+        "Likes per month over {} months, peak {max}",
+        counts.len()
+    ));
+}
+
+/// Shade the image outside the crop rectangle, drag a corner handle to resize or inside to move it
+fn draw_crop_overlay(ui: &egui::Ui, image_rect: Rect, crop: &mut CropTool, image_aspect: f32) {
+    let response = ui.interact(image_rect, ui.id().with("crop_overlay"), Sense::drag());
+    let to_screen = |pos: Pos2| image_rect.min + pos.to_vec2() * image_rect.size();
+    let handle_radius = 8.0;
+    let corners = [
+        crop.rect.left_top(),
+        crop.rect.right_top(),
+        crop.rect.left_bottom(),
+        crop.rect.right_bottom(),
+    ];
+
+    if let Some(pointer) = response.interact_pointer_pos() {
+        if response.drag_started() {
+            // Resize from the opposite corner when a handle is grabbed
+            crop.drag = Some(
+                corners
+                    .iter()
+                    .position(|corner| to_screen(*corner).distance(pointer) < handle_radius * 2.0)
+                    .map_or(CropDrag::Move, |i| CropDrag::Resize(corners[3 - i])),
+            );
+        }
+        match crop.drag {
+            Some(CropDrag::Move) => {
+                let size = crop.rect.size();
+                let min = (crop.rect.min + response.drag_delta() / image_rect.size())
+                    .clamp(Pos2::ZERO, (Vec2::splat(1.0) - size).to_pos2());
+                crop.rect = Rect::from_min_size(min, size);
+            }
+            Some(CropDrag::Resize(anchor)) => {
+                let pointer = ((pointer - image_rect.min) / image_rect.size()).to_pos2();
+                crop.rect = resize_crop_rect(anchor, pointer, image_aspect / crop.aspect.ratio());
+            }
+            None => {}
+        }
+    }
+    if response.drag_stopped() {
+        crop.drag = None;
+    }
+    if response.hovered() {
+        ui.ctx().set_cursor_icon(CursorIcon::Move);
+    }
+
+    let crop_rect = Rect::from_min_max(to_screen(crop.rect.min), to_screen(crop.rect.max));
+    let painter = ui.painter();
+    let shade = Color32::BLACK.gamma_multiply(0.6);
+    for outside in [
+        Rect::from_min_max(image_rect.min, pos2(image_rect.max.x, crop_rect.min.y)),
+        Rect::from_min_max(pos2(image_rect.min.x, crop_rect.max.y), image_rect.max),
+        Rect::from_min_max(
+            pos2(image_rect.min.x, crop_rect.min.y),
+            pos2(crop_rect.min.x, crop_rect.max.y),
+        ),
+        Rect::from_min_max(
+            pos2(crop_rect.max.x, crop_rect.min.y),
+            pos2(image_rect.max.x, crop_rect.max.y),
+        ),
+    ] {
+        painter.rect_filled(outside, 0.0, shade);
+    }
+    painter.rect_stroke(crop_rect, 0.0, Stroke::new(2.0, Color32::WHITE));
+    for corner in corners {
+        painter.circle_filled(to_screen(corner), handle_radius, Color32::WHITE);
+    }
+}
+
+/// Grow a crop rectangle from a fixed corner towards the pointer, keeping the aspect ratio and staying inside the image
+fn resize_crop_rect(anchor: Pos2, pointer: Pos2, height_per_width: f32) -> Rect {
+    let direction = vec2(
+        (pointer.x - anchor.x).signum(),
+        (pointer.y - anchor.y).signum(),
+    );
+    let max_width = if direction.x < 0.0 {
+        anchor.x
+    } else {
+        1.0 - anchor.x
+    };
+    let max_height = if direction.y < 0.0 {
+        anchor.y
+    } else {
+        1.0 - anchor.y
+    };
+    let width = (pointer.x - anchor.x)
+        .abs()
+        .max(0.05)
+        .min(max_width)
+        .min(max_height / height_per_width);
+    Rect::from_two_pos(
+        anchor,
+        anchor + direction * vec2(width, width * height_per_width),
+    )
+}
+
+/// A byte count in the largest unit that keeps it above one, like 2.4 MB
+fn format_bytes(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    let units = ["B", "KB", "MB", "GB"];
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", units[unit])
+    }
+}
+
+/// Brightness histogram in 64 bars, shadows below the 20th percentile in blue and highlights above the 80th in red
+fn draw_brightness_histogram(ui: &mut egui::Ui, color_data: &ColorData) {
+    const BARS: usize = 64;
+    let (rect, _) = ui.allocate_exact_size(vec2(ui.available_width(), 80.0), Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 4.0, Color32::BLACK.gamma_multiply(0.6));
+
+    let bin_size = color_data.brightness_histogram.len().div_ceil(BARS);
+    let bars = color_data
+        .brightness_histogram
+        .chunks(bin_size)
+        .map(|bins| bins.iter().map(|count| u32::from(*count)).sum::<u32>())
+        .collect::<Vec<_>>();
+    let largest = bars.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let bar_width = rect.width() / bars.len() as f32;
+    for (i, total) in bars.iter().enumerate() {
+        let brightness = (i as f32 + 0.5) / bars.len() as f32;
+        let color = if brightness < color_data.bottom_20_percent_brightness {
+            Color32::from_rgb(70, 120, 230)
+        } else if brightness > color_data.top_20_percent_brightness {
+            Color32::from_rgb(220, 70, 60)
+        } else {
+            Color32::WHITE
+        };
+        let left = (i as f32).mul_add(bar_width, rect.left());
+        let height = rect.height() * (*total as f32 / largest);
+        painter.rect_filled(
+            Rect::from_min_max(
+                pos2(left, rect.bottom() - height),
+                pos2(left + bar_width, rect.bottom()),
+            ),
+            0.0,
+            color,
+        );
+    }
+}
+
+/// Mean brightness over a 3x3 grid, with a strip along the bottom showing how much of each hue there is
+fn draw_stats_overlay(ui: &egui::Ui, image_rect: Rect, stats: &ImageStats) {
+    let painter = ui.painter_at(image_rect);
+    let cell_size = image_rect.size() / 3.0;
+    for (i, brightness) in stats.region_brightness.iter().enumerate() {
+        let cell = Rect::from_min_size(
+            image_rect.min + vec2((i % 3) as f32, (i / 3) as f32) * cell_size,
+            cell_size,
+        );
+        painter.rect_stroke(
+            cell,
+            0.0,
+            Stroke::new(1.0, Color32::WHITE.gamma_multiply(0.4)),
+        );
+        let galley = painter.layout_no_wrap(
+            format!("{:.0}%", brightness * 100.0),
+            FontId::proportional(20.0),
+            Color32::WHITE,
+        );
+        let text_rect = Align2::CENTER_CENTER.anchor_size(cell.center(), galley.size());
+        painter.rect_filled(
+            text_rect.expand(6.0),
+            6.0,
+            Color32::BLACK.gamma_multiply(0.6),
+        );
+        painter.galley(text_rect.min, galley, Color32::WHITE);
+    }
+
+    let strip = Rect::from_min_max(
+        pos2(image_rect.left() + 16.0, image_rect.bottom() - 56.0),
+        pos2(image_rect.right() - 16.0, image_rect.bottom() - 16.0),
+    );
+    painter.rect_filled(strip, 4.0, Color32::BLACK.gamma_multiply(0.6));
+    let bin_width = strip.width() / HUE_BINS as f32;
+    for (i, amount) in stats.hues.iter().enumerate() {
+        let left = (i as f32).mul_add(bin_width, strip.left());
+        painter.rect_filled(
+            Rect::from_min_max(
+                pos2(left, strip.height().mul_add(-amount, strip.bottom())),
+                pos2(left + bin_width, strip.bottom()),
+            ),
+            0.0,
+            hsl_to_color((i as f32 + 0.5) / HUE_BINS as f32, 0.8, 0.5),
+        );
+    }
+}
+
+fn hsl_to_color(hue: f32, saturation: f32, lightness: f32) -> Color32 {
+    let value = saturation.mul_add(lightness.min(1.0 - lightness), lightness);
+    let hsv_saturation = if value > 0.0 {
+        2.0 * (1.0 - lightness / value)
+    } else {
+        0.0
+    };
+    egui::ecolor::Hsva::new(hue, hsv_saturation, value, 1.0).into()
+}
+
+const fn liked_state_icon(state: LikedState) -> &'static str {
+    match state {
+        LikedState::Loved => egui_phosphor::regular::HEART,
+        LikedState::Liked => egui_phosphor::regular::THUMBS_UP,
+        LikedState::Neutral => egui_phosphor::regular::ALIGN_CENTER_HORIZONTAL_SIMPLE,
+        LikedState::Disliked => egui_phosphor::regular::THUMBS_DOWN,
+    }
+}
+
+const fn liked_state_rank(state: LikedState) -> u8 {
+    match state {
+        LikedState::Disliked => 0,
+        LikedState::Neutral => 1,
+        LikedState::Liked => 2,
+        LikedState::Loved => 3,
+    }
+}
+
 fn render_statefilter_button(
     ui: &mut egui::Ui,
     state: &mut StateFilter,
@@ -0,0 +1,45 @@
+use crate::common::WallpaperData;
+use std::ops::Range;
+
+/// A row of the justified layout, the items in it and the height that makes them fill the width
+pub struct Row {
+    pub items: Range<usize>,
+    pub height: f32,
+}
+
+/// Width over height of the wallpapers thumbnail, falling back to widescreen if it has no size
+pub fn aspect(wallpaper: &WallpaperData) -> f32 {
+    let file = &wallpaper.thumbnail_file;
+    if file.width == 0 || file.height == 0 {
+        16.0 / 9.0
+    } else {
+        file.width as f32 / file.height as f32
+    }
+}
+
+/// Pack items into rows around the target height, each full row scaled to exactly fill the width
+/// The last row keeps the target height rather than stretching a few items across the page
+pub fn justified_rows(aspects: &[f32], width: f32, target_height: f32, spacing: f32) -> Vec<Row> {
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut aspect_sum = 0.0;
+    for (i, aspect) in aspects.iter().enumerate() {
+        aspect_sum += aspect;
+        let gaps = (i - start) as f32 * spacing;
+        if aspect_sum.mul_add(target_height, gaps) >= width {
+            rows.push(Row {
+                items: start..i + 1,
+                height: (width - gaps) / aspect_sum,
+            });
+            start = i + 1;
+            aspect_sum = 0.0;
+        }
+    }
+    if start < aspects.len() {
+        rows.push(Row {
+            items: start..aspects.len(),
+            height: target_height,
+        });
+    }
+    rows
+}
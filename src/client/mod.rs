@@ -1,2 +1,5 @@
 pub mod app;
+mod gallery;
 mod networking;
+mod palette;
+mod widgets;
@@ -1,8 +1,16 @@
 use crate::common::{
-    Database, LikedState, LoginPacket, SetStylePacket, StyleVariant, TokenPacket,
-    TokenStringPacket, TokenUuidLikedPacket, TokenUuidPacket,
+    image_stats::ImageStats, AuditEntry, ChangePasswordPacket, Cluster, CostStats, Database,
+    FetchProfile, FetchProfilePacket, GalleryPage, GallerySort, GenerateFromImagePacket,
+    GenerateFromTemplatePacket, GeneratePacket, GenerationMode, HistoryConfig, ImageCropPacket,
+    ImageDarkenPacket, ImageModelInfo, ImageNotePacket, ImageTransformPacket, ImageUploadPacket,
+    LikedState, LogLevel, LogLine, LoginPacket, MaintenanceReport, MoodboardPacket, ProfilePreview,
+    RecreateEditedPacket, RetentionPolicy, Season, ServerStatus, SetHistoryConfigPacket,
+    SetSeasonPacket, SetStylePacket, StyleVariant, TemplateAddPacket, TokenPacket,
+    TokenStringPacket, TokenUuidLikedPacket, TokenUuidPacket, TokenUuidRatingPacket,
+    TokenUuidsLikedPacket, TokenUuidsPacket,
 };
 use anyhow::Result;
+use std::collections::HashMap;
 use uuid::Uuid;
 
 pub fn login(
@@ -29,8 +37,9 @@ pub fn login(
                             .ok_or_else(|| anyhow::anyhow!("Failed to extract text from response"))
                     } else {
                         Err(anyhow::anyhow!(
-                            "Login failed: {}",
-                            res.text().unwrap_or_default()
+                            "Login failed: {}{}",
+                            res.text().unwrap_or_default(),
+                            request_id_note(&res)
                         ))
                     }
                 }
@@ -40,18 +49,987 @@ pub fn login(
     );
 }
 
+pub fn logout(host: &str, token: &str, on_done: impl 'static + Send + FnOnce(Result<()>)) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/logout"),
+            bincode::serialize(&TokenPacket {
+                token: token.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to logout", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error logging out: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn change_password(
+    host: &str,
+    token: &str,
+    old_password: &str,
+    new_password: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/auth/changepassword"),
+            bincode::serialize(&ChangePasswordPacket {
+                token: token.to_string(),
+                old_password: old_password.to_string(),
+                new_password: new_password.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                // The server explains a rejected password in the body
+                Ok(res) if res.text().is_some_and(|text| !text.is_empty()) => Err(anyhow::anyhow!(
+                    "Failed to change password: {}{}",
+                    res.text().unwrap_or_default(),
+                    request_id_note(&res)
+                )),
+                Ok(res) => Err(status_error("Failed to change password", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error changing password: {}", e)),
+            });
+        }),
+    );
+}
+
+/// Delete the logged in account, the server checks the password again first
+pub fn delete_account(
+    host: &str,
+    token: &str,
+    password: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    let request = ehttp::Request {
+        method: "DELETE".to_string(),
+        ..ehttp::Request::post(
+            format!("http://{host}/auth/account"),
+            bincode::serialize(&TokenStringPacket {
+                token: token.to_string(),
+                string: password.to_string(),
+            })
+            .unwrap(),
+        )
+    };
+    ehttp::fetch(
+        request,
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 204 => Ok(()),
+                Ok(res) if res.status == 401 => Err(anyhow::anyhow!("Incorrect password")),
+                Ok(res) if res.status == 409 => Err(anyhow::anyhow!(
+                    "Make another account an admin before deleting the last admin"
+                )),
+                Ok(res) => Err(status_error("Failed to delete account", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error deleting account: {}", e)),
+            });
+        }),
+    );
+}
+
 pub fn generate_wallpaper(
     host: &str,
     token: &str,
-    message: &str,
+    message: &str,
+    mode: GenerationMode,
+    model: Option<&str>,
+    comment: Option<Uuid>,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/generate"),
+            bincode::serialize(&GeneratePacket {
+                token: token.to_string(),
+                message: message.to_string(),
+                mode,
+                model: model.map(str::to_string),
+                comment,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |_| {
+            on_done(Ok(()));
+        }),
+    );
+}
+
+pub fn generate_prompt(
+    host: &str,
+    token: &str,
+    message: &str,
+    mode: GenerationMode,
+    model: Option<&str>,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/promptgenerate"),
+            bincode::serialize(&GeneratePacket {
+                token: token.to_string(),
+                message: message.to_string(),
+                mode,
+                model: model.map(str::to_string),
+                comment: None,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to generate prompt", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error generating prompt: {}", e)),
+            });
+        }),
+    );
+}
+
+#[derive(Clone, Copy)]
+pub enum PromptAction {
+    Accept,
+    Reroll,
+    Discard,
+}
+
+impl PromptAction {
+    const fn route(self) -> &'static str {
+        match self {
+            Self::Accept => "promptaccept",
+            Self::Reroll => "promptreroll",
+            Self::Discard => "promptdiscard",
+        }
+    }
+
+    const fn verb(self) -> &'static str {
+        match self {
+            Self::Accept => "generate from",
+            Self::Reroll => "reroll",
+            Self::Discard => "discard",
+        }
+    }
+}
+
+/// Accept, reroll or discard a prompt waiting for review
+pub fn review_prompt(
+    host: &str,
+    token: &str,
+    prompt_id: &Uuid,
+    action: PromptAction,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/{}", action.route()),
+            bincode::serialize(&TokenUuidPacket {
+                token: token.to_string(),
+                uuid: *prompt_id,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error(
+                    &format!("Failed to {} prompt", action.verb()),
+                    &res,
+                )),
+                Err(e) => Err(anyhow::anyhow!(
+                    "Network error trying to {} prompt: {}",
+                    action.verb(),
+                    e
+                )),
+            });
+        }),
+    );
+}
+
+#[derive(Clone, Copy)]
+pub enum PresetAction {
+    Save,
+    Apply,
+    Delete,
+}
+
+impl PresetAction {
+    const fn route(self) -> &'static str {
+        match self {
+            Self::Save => "presetsave",
+            Self::Apply => "presetapply",
+            Self::Delete => "presetdelete",
+        }
+    }
+
+    const fn verb(self) -> &'static str {
+        match self {
+            Self::Save => "save",
+            Self::Apply => "apply",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// Save the current style as a named preset, or apply or delete one
+pub fn style_preset(
+    host: &str,
+    token: &str,
+    action: PresetAction,
+    name: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/{}", action.route()),
+            bincode::serialize(&TokenStringPacket {
+                token: token.to_string(),
+                string: name.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error(
+                    &format!("Failed to {} style preset", action.verb()),
+                    &res,
+                )),
+                Err(e) => Err(anyhow::anyhow!(
+                    "Network error trying to {} style preset: {}",
+                    action.verb(),
+                    e
+                )),
+            });
+        }),
+    );
+}
+
+pub fn generate_from_template(
+    host: &str,
+    token: &str,
+    template_id: &Uuid,
+    slot_values: HashMap<String, String>,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/generatefromtemplate"),
+            bincode::serialize(&GenerateFromTemplatePacket {
+                token: token.to_string(),
+                template_id: *template_id,
+                slot_values,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to generate from template", &res)),
+                Err(e) => Err(anyhow::anyhow!(
+                    "Network error generating from template: {}",
+                    e
+                )),
+            });
+        }),
+    );
+}
+
+pub fn add_template(
+    host: &str,
+    token: &str,
+    name: &str,
+    template: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/template/add"),
+            bincode::serialize(&TemplateAddPacket {
+                token: token.to_string(),
+                name: name.to_string(),
+                template: template.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to add template", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error adding template: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn remove_template(
+    host: &str,
+    token: &str,
+    template_id: &Uuid,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/template/remove"),
+            bincode::serialize(&TokenUuidPacket {
+                token: token.to_string(),
+                uuid: *template_id,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to remove template", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error removing template: {}", e)),
+            });
+        }),
+    );
+}
+
+/// How many wallpapers each fetch profile could pick right now
+pub fn get_profile_previews(
+    host: &str,
+    on_done: impl 'static + Send + FnOnce(Result<Vec<ProfilePreview>>),
+) {
+    ehttp::fetch(
+        ehttp::Request::get(format!("http://{host}/profiles")),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => serde_json::from_slice(&res.bytes)
+                    .map_err(|e| anyhow::anyhow!("Failed to read profile previews: {}", e)),
+                Ok(res) => Err(status_error("Failed to load profile previews", &res)),
+                Err(e) => Err(anyhow::anyhow!(
+                    "Network error loading profile previews: {}",
+                    e
+                )),
+            });
+        }),
+    );
+}
+
+pub fn save_profile(
+    host: &str,
+    token: &str,
+    profile: &FetchProfile,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/profiles"),
+            bincode::serialize(&FetchProfilePacket {
+                token: token.to_string(),
+                profile: profile.clone(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to save profile", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error saving profile: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn remove_profile(
+    host: &str,
+    token: &str,
+    name: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/profiles/remove"),
+            bincode::serialize(&TokenStringPacket {
+                token: token.to_string(),
+                string: name.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to remove profile", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error removing profile: {}", e)),
+            });
+        }),
+    );
+}
+
+/// Logged in the likes are the account's own, otherwise only the shared ones from before likes were per account
+pub fn get_database(
+    host: &str,
+    token: &str,
+    on_done: impl 'static + Send + FnOnce(Result<Database>),
+) {
+    let url = if token.is_empty() {
+        format!("http://{host}/get")
+    } else {
+        format!("http://{host}/get?token={token}")
+    };
+    ehttp::fetch(
+        ehttp::Request::get(url),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) => {
+                    if res.status == 200 {
+                        bincode::deserialize(&res.bytes)
+                            .map_or_else(|_| Err(anyhow::anyhow!("Failed to load database")), Ok)
+                    } else {
+                        Err(status_error("Failed to load database", &res))
+                    }
+                }
+                Err(e) => Err(anyhow::anyhow!("Network error loading database: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn get_gallery_page(
+    host: &str,
+    token: &str,
+    offset: usize,
+    limit: usize,
+    sort: GallerySort,
+    on_done: impl 'static + Send + FnOnce(Result<GalleryPage>),
+) {
+    ehttp::fetch(
+        ehttp::Request::get(format!(
+            "http://{host}/get?offset={offset}&limit={limit}&sort={}{}",
+            sort.query(),
+            if token.is_empty() {
+                String::new()
+            } else {
+                format!("&token={token}")
+            }
+        )),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) => {
+                    if res.status == 200 {
+                        bincode::deserialize(&res.bytes)
+                            .map_or_else(|_| Err(anyhow::anyhow!("Failed to load gallery")), Ok)
+                    } else {
+                        Err(status_error("Failed to load gallery", &res))
+                    }
+                }
+                Err(e) => Err(anyhow::anyhow!("Network error loading gallery: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn get_models(host: &str, on_done: impl 'static + Send + FnOnce(Result<Vec<ImageModelInfo>>)) {
+    ehttp::fetch(
+        ehttp::Request::get(format!("http://{host}/models")),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => bincode::deserialize(&res.bytes)
+                    .map_err(|e| anyhow::anyhow!("Failed to read image models: {}", e)),
+                Ok(res) => Err(status_error("Failed to load image models", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error loading image models: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn get_retention(host: &str, on_done: impl 'static + Send + FnOnce(Result<RetentionPolicy>)) {
+    ehttp::fetch(
+        ehttp::Request::get(format!("http://{host}/retention")),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) => {
+                    if res.status == 200 {
+                        bincode::deserialize(&res.bytes).map_or_else(
+                            |_| Err(anyhow::anyhow!("Failed to load retention policy")),
+                            Ok,
+                        )
+                    } else {
+                        Err(status_error("Failed to load retention policy", &res))
+                    }
+                }
+                Err(e) => Err(anyhow::anyhow!(
+                    "Network error loading retention policy: {}",
+                    e
+                )),
+            });
+        }),
+    );
+}
+
+pub fn get_status(host: &str, on_done: impl 'static + Send + FnOnce(Result<ServerStatus>)) {
+    ehttp::fetch(
+        ehttp::Request::get(format!("http://{host}/status")),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => serde_json::from_slice(&res.bytes)
+                    .map_err(|e| anyhow::anyhow!("Failed to read server status: {}", e)),
+                Ok(res) => Err(status_error("Failed to load server status", &res)),
+                Err(e) => Err(anyhow::anyhow!(
+                    "Network error loading server status: {}",
+                    e
+                )),
+            });
+        }),
+    );
+}
+
+/// Download a thumbnail and measure its brightness regions and hues
+pub fn get_image_stats(
+    host: &str,
+    file_name: &str,
+    on_done: impl 'static + Send + FnOnce(Result<ImageStats>),
+) {
+    ehttp::fetch(
+        ehttp::Request::get(format!("http://{host}/wallpapers/{file_name}")),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => image::load_from_memory(&res.bytes).map_or_else(
+                    |e| Err(anyhow::anyhow!("Failed to decode thumbnail: {}", e)),
+                    |image| {
+                        let rgba = image.to_rgba8();
+                        Ok(ImageStats::from_rgba(
+                            rgba.width(),
+                            rgba.height(),
+                            rgba.as_raw(),
+                        ))
+                    },
+                ),
+                Ok(res) => Err(status_error("Failed to load thumbnail", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error loading thumbnail: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn get_clusters(
+    host: &str,
+    k: usize,
+    on_done: impl 'static + Send + FnOnce(Result<Vec<Cluster>>),
+) {
+    ehttp::fetch(
+        ehttp::Request::get(format!("http://{host}/clusters?k={k}")),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) => {
+                    if res.status == 200 {
+                        bincode::deserialize(&res.bytes)
+                            .map_or_else(|_| Err(anyhow::anyhow!("Failed to load clusters")), Ok)
+                    } else {
+                        Err(status_error("Failed to load clusters", &res))
+                    }
+                }
+                Err(e) => Err(anyhow::anyhow!("Network error loading clusters: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn add_comment(
+    host: &str,
+    token: &str,
+    comment: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/commentadd"),
+            bincode::serialize(&TokenStringPacket {
+                token: token.to_string(),
+                string: comment.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |_| {
+            on_done(Ok(()));
+        }),
+    );
+}
+
+pub fn remove_comment(
+    host: &str,
+    token: &str,
+    comment_id: &Uuid,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/commentremove"),
+            bincode::serialize(&TokenUuidPacket {
+                token: token.to_string(),
+                uuid: *comment_id,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) if res.status == 403 => Err(anyhow::anyhow!(
+                    "Only admins can remove other users' comments"
+                )),
+                Ok(res) => Err(status_error("Failed to remove comment", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error removing comment: {}", e)),
+            });
+        }),
+    );
+}
+
+/// Toggle the logged in account's thumbs up on a comment
+pub fn react_comment(
+    host: &str,
+    token: &str,
+    comment_id: &Uuid,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/commentreact"),
+            bincode::serialize(&TokenUuidPacket {
+                token: token.to_string(),
+                uuid: *comment_id,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to react to comment", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error reacting to comment: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn like_image(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    liked: LikedState,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/imageliked"),
+            bincode::serialize(&TokenUuidLikedPacket {
+                token: token.to_string(),
+                uuid: *image_id,
+                liked,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |_| {
+            on_done(Ok(()));
+        }),
+    );
+}
+
+pub fn rate_image(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    rating: Option<u8>,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/imagerate"),
+            bincode::serialize(&TokenUuidRatingPacket {
+                token: token.to_string(),
+                uuid: *image_id,
+                rating,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to rate image", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error rating image: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn remove_image(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/imageremove"),
+            bincode::serialize(&TokenUuidPacket {
+                token: token.to_string(),
+                uuid: *image_id,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |_| {
+            on_done(Ok(()));
+        }),
+    );
+}
+
+pub fn like_images(
+    host: &str,
+    token: &str,
+    image_ids: Vec<Uuid>,
+    liked: LikedState,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/imageliked/batch"),
+            bincode::serialize(&TokenUuidsLikedPacket {
+                token: token.to_string(),
+                uuids: image_ids,
+                liked,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to like images", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error liking images: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn remove_images(
+    host: &str,
+    token: &str,
+    image_ids: Vec<Uuid>,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/imageremove/batch"),
+            bincode::serialize(&TokenUuidsPacket {
+                token: token.to_string(),
+                uuids: image_ids,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to remove images", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error removing images: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn pin_image(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/imagepin"),
+            bincode::serialize(&TokenUuidPacket {
+                token: token.to_string(),
+                uuid: *image_id,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to pin image", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error pinning image: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn set_image_note(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    note: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/imagenote"),
+            bincode::serialize(&ImageNotePacket {
+                token: token.to_string(),
+                uuid: *image_id,
+                note: note.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to save note", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error saving note: {}", e)),
+            });
+        }),
+    );
+}
+
+/// Returns the token of a link to the wallpaper that works without logging in until it expires
+pub fn create_share_link(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    on_done: impl 'static + Send + FnOnce(Result<String>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/sharelink"),
+            bincode::serialize(&TokenUuidPacket {
+                token: token.to_string(),
+                uuid: *image_id,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => res
+                    .text()
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow::anyhow!("Failed to read share link")),
+                Ok(res) => Err(status_error("Failed to create share link", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error creating share link: {}", e)),
+            });
+        }),
+    );
+}
+
+/// The LLM context a wallpaper was generated from
+pub fn get_image_context(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    on_done: impl 'static + Send + FnOnce(Result<String>),
+) {
+    ehttp::fetch(
+        ehttp::Request::get(format!(
+            "http://{host}/imagecontext/{image_id}?token={token}"
+        )),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => res
+                    .text()
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow::anyhow!("Failed to read generation context")),
+                Ok(res) => Err(status_error("Failed to load generation context", &res)),
+                Err(e) => Err(anyhow::anyhow!(
+                    "Network error loading generation context: {}",
+                    e
+                )),
+            });
+        }),
+    );
+}
+
+pub fn recreate_image(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/imagerecreate"),
+            bincode::serialize(&TokenUuidPacket {
+                token: token.to_string(),
+                uuid: *image_id,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |_| {
+            on_done(Ok(()));
+        }),
+    );
+}
+
+/// Recreate a wallpaper from an edited prompt, the server writes a new shortened prompt for it
+pub fn recreate_image_edited(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    prompt: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/imagerecreate/edited"),
+            bincode::serialize(&RecreateEditedPacket {
+                token: token.to_string(),
+                uuid: *image_id,
+                prompt: prompt.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to recreate wallpaper", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error recreating wallpaper: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn generate_variation(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/generatevariation"),
+            bincode::serialize(&TokenUuidPacket {
+                token: token.to_string(),
+                uuid: *image_id,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to generate variation", &res)),
+                Err(e) => Err(anyhow::anyhow!("Failed to generate variation {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn transform_image(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    rotation: Option<i32>,
+    flip_horizontal: bool,
+    flip_vertical: bool,
     on_done: impl 'static + Send + FnOnce(Result<()>),
 ) {
     ehttp::fetch(
         ehttp::Request::post(
-            format!("http://{host}/generate"),
-            bincode::serialize(&TokenStringPacket {
+            format!("http://{host}/imagetransform"),
+            bincode::serialize(&ImageTransformPacket {
                 token: token.to_string(),
-                string: message.to_string(),
+                uuid: *image_id,
+                rotation,
+                flip_horizontal,
+                flip_vertical,
             })
             .unwrap(),
         ),
@@ -61,131 +1039,193 @@ pub fn generate_wallpaper(
     );
 }
 
-pub fn get_database(host: &str, on_done: impl 'static + Send + FnOnce(Result<Database>)) {
+/// Store a darkened copy of a wallpaper for night use
+pub fn darken_image(
+    host: &str,
+    token: &str,
+    image_id: &Uuid,
+    max_brightness: f32,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
     ehttp::fetch(
-        ehttp::Request::get(format!("http://{host}/get")),
+        ehttp::Request::post(
+            format!("http://{host}/imagedarken"),
+            bincode::serialize(&ImageDarkenPacket {
+                token: token.to_string(),
+                uuid: *image_id,
+                max_brightness,
+            })
+            .unwrap(),
+        ),
         Box::new(move |res: Result<ehttp::Response, String>| {
             on_done(match res {
-                Ok(res) => {
-                    if res.status == 200 {
-                        bincode::deserialize(&res.bytes)
-                            .map_or_else(|_| Err(anyhow::anyhow!("Failed to load database")), Ok)
-                    } else {
-                        Err(anyhow::anyhow!(
-                            "Failed to load database, status code: {}",
-                            res.status
-                        ))
-                    }
-                }
-                Err(e) => Err(anyhow::anyhow!("Network error loading database: {}", e)),
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to darken image", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error darkening image: {}", e)),
             });
         }),
     );
 }
 
-pub fn add_comment(
+pub fn crop_image(
     host: &str,
     token: &str,
-    comment: &str,
+    image_id: &Uuid,
+    rect: egui::Rect,
     on_done: impl 'static + Send + FnOnce(Result<()>),
 ) {
     ehttp::fetch(
         ehttp::Request::post(
-            format!("http://{host}/commentadd"),
-            bincode::serialize(&TokenStringPacket {
+            format!("http://{host}/imagecrop"),
+            bincode::serialize(&ImageCropPacket {
                 token: token.to_string(),
-                string: comment.to_string(),
+                uuid: *image_id,
+                x: rect.min.x,
+                y: rect.min.y,
+                width: rect.width(),
+                height: rect.height(),
             })
             .unwrap(),
         ),
-        Box::new(move |_| {
-            on_done(Ok(()));
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to crop image", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error cropping image: {}", e)),
+            });
         }),
     );
 }
 
-pub fn remove_comment(
+pub fn generate_from_image(
     host: &str,
     token: &str,
-    comment_id: &Uuid,
+    message: &str,
+    mode: GenerationMode,
+    model: Option<&str>,
+    data: Vec<u8>,
     on_done: impl 'static + Send + FnOnce(Result<()>),
 ) {
     ehttp::fetch(
         ehttp::Request::post(
-            format!("http://{host}/commentremove"),
-            bincode::serialize(&TokenUuidPacket {
+            format!("http://{host}/generatefrom"),
+            bincode::serialize(&GenerateFromImagePacket {
                 token: token.to_string(),
-                uuid: *comment_id,
+                message: message.to_string(),
+                mode,
+                model: model.map(str::to_string),
+                data,
             })
             .unwrap(),
         ),
-        Box::new(move |_| {
-            on_done(Ok(()));
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to generate from image", &res)),
+                Err(e) => Err(anyhow::anyhow!("Failed to generate from image {}", e)),
+            });
         }),
     );
 }
 
-pub fn like_image(
+/// Import an image from a pasted URL or data URI, the error says so if it was imported before
+pub fn import_from_url(
     host: &str,
     token: &str,
-    image_id: &Uuid,
-    liked: LikedState,
+    url: &str,
     on_done: impl 'static + Send + FnOnce(Result<()>),
 ) {
     ehttp::fetch(
         ehttp::Request::post(
-            format!("http://{host}/imageliked"),
-            bincode::serialize(&TokenUuidLikedPacket {
+            format!("http://{host}/importurl"),
+            bincode::serialize(&TokenStringPacket {
                 token: token.to_string(),
-                uuid: *image_id,
-                liked,
+                string: url.to_string(),
             })
             .unwrap(),
         ),
-        Box::new(move |_| {
-            on_done(Ok(()));
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) if res.status == 409 => Err(anyhow::anyhow!("Image was already imported")),
+                Ok(res) => Err(status_error("Failed to import image", &res)),
+                Err(e) => Err(anyhow::anyhow!("Failed to import image {}", e)),
+            });
         }),
     );
 }
 
-pub fn remove_image(
+pub fn set_moodboard(
     host: &str,
     token: &str,
-    image_id: &Uuid,
+    data: Vec<u8>,
     on_done: impl 'static + Send + FnOnce(Result<()>),
 ) {
     ehttp::fetch(
         ehttp::Request::post(
-            format!("http://{host}/imageremove"),
-            bincode::serialize(&TokenUuidPacket {
+            format!("http://{host}/moodboard/set"),
+            bincode::serialize(&MoodboardPacket {
                 token: token.to_string(),
-                uuid: *image_id,
+                data,
             })
             .unwrap(),
         ),
-        Box::new(move |_| {
-            on_done(Ok(()));
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to set moodboard", &res)),
+                Err(e) => Err(anyhow::anyhow!("Failed to set moodboard {}", e)),
+            });
         }),
     );
 }
 
-pub fn recreate_image(
+pub fn clear_moodboard(host: &str, token: &str, on_done: impl 'static + Send + FnOnce(Result<()>)) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/moodboard/clear"),
+            bincode::serialize(&TokenPacket {
+                token: token.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Failed to clear moodboard", &res)),
+                Err(e) => Err(anyhow::anyhow!("Failed to clear moodboard {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn upload_image(
     host: &str,
     token: &str,
-    image_id: &Uuid,
+    title: &str,
+    data: Vec<u8>,
     on_done: impl 'static + Send + FnOnce(Result<()>),
 ) {
     ehttp::fetch(
         ehttp::Request::post(
-            format!("http://{host}/imagerecreate"),
-            bincode::serialize(&TokenUuidPacket {
+            format!("http://{host}/imageupload"),
+            bincode::serialize(&ImageUploadPacket {
                 token: token.to_string(),
-                uuid: *image_id,
+                title: if title.is_empty() {
+                    None
+                } else {
+                    Some(title.to_string())
+                },
+                data,
             })
             .unwrap(),
         ),
-        Box::new(move |_| {
-            on_done(Ok(()));
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => Ok(()),
+                Ok(res) => Err(status_error("Upload failed", &res)),
+                Err(e) => Err(anyhow::anyhow!("Upload failed {}", e)),
+            });
         }),
     );
 }
@@ -194,7 +1234,6 @@ pub fn edit_styles(
     host: &str,
     token: &str,
     variant: StyleVariant,
-    new: &str,
     on_done: impl 'static + Send + FnOnce(Result<()>),
 ) {
     ehttp::fetch(
@@ -203,7 +1242,6 @@ pub fn edit_styles(
             bincode::serialize(&SetStylePacket {
                 token: token.to_string(),
                 variant,
-                string: new.to_string(),
             })
             .unwrap(),
         ),
@@ -213,6 +1251,188 @@ pub fn edit_styles(
     );
 }
 
+pub fn edit_history_config(
+    host: &str,
+    token: &str,
+    config: HistoryConfig,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/historyconfig"),
+            bincode::serialize(&SetHistoryConfigPacket {
+                token: token.to_string(),
+                config,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |_| {
+            on_done(Ok(()));
+        }),
+    );
+}
+
+pub fn edit_season(
+    host: &str,
+    token: &str,
+    season: Option<Season>,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/season"),
+            bincode::serialize(&SetSeasonPacket {
+                token: token.to_string(),
+                season,
+            })
+            .unwrap(),
+        ),
+        Box::new(move |_| {
+            on_done(Ok(()));
+        }),
+    );
+}
+
+pub fn get_cost_stats(
+    host: &str,
+    token: &str,
+    on_done: impl 'static + Send + FnOnce(Result<CostStats>),
+) {
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/stats/costs"),
+            bincode::serialize(&TokenPacket {
+                token: token.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) => {
+                    if res.status == 200 {
+                        bincode::deserialize(&res.bytes)
+                            .map_or_else(|_| Err(anyhow::anyhow!("Failed to load costs")), Ok)
+                    } else {
+                        Err(status_error("Failed to load costs", &res))
+                    }
+                }
+                Err(e) => Err(anyhow::anyhow!("Network error loading costs: {}", e)),
+            });
+        }),
+    );
+}
+
+pub fn get_audit_log(
+    host: &str,
+    token: &str,
+    limit: usize,
+    offset: usize,
+    on_done: impl 'static + Send + FnOnce(Result<Vec<AuditEntry>>),
+) {
+    ehttp::fetch(
+        ehttp::Request::get(format!(
+            "http://{host}/audit?token={token}&limit={limit}&offset={offset}"
+        )),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) => {
+                    if res.status == 200 {
+                        bincode::deserialize(&res.bytes)
+                            .map_or_else(|_| Err(anyhow::anyhow!("Failed to load audit log")), Ok)
+                    } else {
+                        Err(status_error("Failed to load audit log", &res))
+                    }
+                }
+                Err(e) => Err(anyhow::anyhow!("Network error loading audit log: {}", e)),
+            });
+        }),
+    );
+}
+
+/// The latest changes by every account, newest first
+pub fn get_activity(
+    host: &str,
+    token: &str,
+    limit: usize,
+    on_done: impl 'static + Send + FnOnce(Result<Vec<AuditEntry>>),
+) {
+    ehttp::fetch(
+        ehttp::Request::get(format!(
+            "http://{host}/activity?token={token}&limit={limit}"
+        )),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) => {
+                    if res.status == 200 {
+                        bincode::deserialize(&res.bytes)
+                            .map_or_else(|_| Err(anyhow::anyhow!("Failed to load activity")), Ok)
+                    } else {
+                        Err(status_error("Failed to load activity", &res))
+                    }
+                }
+                Err(e) => Err(anyhow::anyhow!("Network error loading activity: {}", e)),
+            });
+        }),
+    );
+}
+
+/// Recent server log lines at least as severe as the level, only available to admins
+pub fn get_server_logs(
+    host: &str,
+    token: &str,
+    level: LogLevel,
+    on_done: impl 'static + Send + FnOnce(Result<Vec<LogLine>>),
+) {
+    ehttp::fetch(
+        ehttp::Request::get(format!(
+            "http://{host}/logs?token={token}&level={}",
+            level.name().to_lowercase()
+        )),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => serde_json::from_slice(&res.bytes)
+                    .map_err(|e| anyhow::anyhow!("Failed to read server logs: {}", e)),
+                Ok(res) => Err(status_error("Failed to load server logs", &res)),
+                Err(e) => Err(anyhow::anyhow!("Network error loading server logs: {}", e)),
+            });
+        }),
+    );
+}
+
+/// Check the wallpaper files against the database, applying a fix if given
+pub fn verify_maintenance(
+    host: &str,
+    token: &str,
+    fix: Option<&str>,
+    on_done: impl 'static + Send + FnOnce(Result<MaintenanceReport>),
+) {
+    let query = fix.map_or_else(String::new, |fix| format!("?fix={fix}"));
+    ehttp::fetch(
+        ehttp::Request::post(
+            format!("http://{host}/maintenance/verify{query}"),
+            bincode::serialize(&TokenPacket {
+                token: token.to_string(),
+            })
+            .unwrap(),
+        ),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) => {
+                    if res.status == 200 {
+                        serde_json::from_slice(&res.bytes).map_or_else(
+                            |_| Err(anyhow::anyhow!("Failed to load maintenance report")),
+                            Ok,
+                        )
+                    } else {
+                        Err(status_error("Failed to verify files", &res))
+                    }
+                }
+                Err(e) => Err(anyhow::anyhow!("Network error verifying files: {}", e)),
+            });
+        }),
+    );
+}
+
 pub fn query_prompt(
     host: &str,
     token: &str,
@@ -235,8 +1455,9 @@ pub fn query_prompt(
                             .ok_or_else(|| anyhow::anyhow!("Failed to extract text from response"))
                     } else {
                         Err(anyhow::anyhow!(
-                            "Querying prompt failed {}",
-                            res.text().unwrap_or_default()
+                            "Querying prompt failed {}{}",
+                            res.text().unwrap_or_default(),
+                            request_id_note(&res)
                         ))
                     }
                 }
@@ -245,3 +1466,118 @@ pub fn query_prompt(
         }),
     );
 }
+
+/// Error for an unsuccessful response
+fn status_error(message: &str, res: &ehttp::Response) -> anyhow::Error {
+    anyhow::anyhow!(
+        "{message}, status code: {}{}",
+        res.status,
+        request_id_note(res)
+    )
+}
+
+/// The id the server tagged the request with, so a failure can be found in its log
+fn request_id_note(res: &ehttp::Response) -> String {
+    res.headers
+        .get("x-request-id")
+        .map_or_else(String::new, |id| format!(" (request {id})"))
+}
+
+/// Download an image and set it as the desktop wallpaper
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_desktop_wallpaper(url: String, on_done: impl 'static + Send + FnOnce(Result<()>)) {
+    ehttp::fetch(
+        ehttp::Request::get(url),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) => {
+                    if res.status == 200 {
+                        apply_desktop_wallpaper(&res.bytes)
+                    } else {
+                        Err(status_error("Failed to download wallpaper", &res))
+                    }
+                }
+                Err(e) => Err(anyhow::anyhow!(
+                    "Network error downloading wallpaper: {}",
+                    e
+                )),
+            });
+        }),
+    );
+}
+
+/// Save a full resolution wallpaper wherever the save dialog says, `on_done` isn't called if it's cancelled
+#[cfg(not(target_arch = "wasm32"))]
+pub fn download_wallpaper(
+    host: &str,
+    file_name: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    let Some(path) = rfd::FileDialog::new().set_file_name(file_name).save_file() else {
+        return;
+    };
+    ehttp::fetch(
+        ehttp::Request::get(format!("http://{host}/wallpapers/{file_name}")),
+        Box::new(move |res: Result<ehttp::Response, String>| {
+            on_done(match res {
+                Ok(res) if res.status == 200 => std::fs::write(&path, &res.bytes)
+                    .map_err(|e| anyhow::anyhow!("Failed to save wallpaper: {}", e)),
+                Ok(res) => Err(status_error("Failed to download wallpaper", &res)),
+                Err(e) => Err(anyhow::anyhow!(
+                    "Network error downloading wallpaper: {}",
+                    e
+                )),
+            });
+        }),
+    );
+}
+
+/// Save a full resolution wallpaper through the browser, by clicking a download link to it
+#[cfg(target_arch = "wasm32")]
+pub fn download_wallpaper(
+    host: &str,
+    file_name: &str,
+    on_done: impl 'static + Send + FnOnce(Result<()>),
+) {
+    use eframe::wasm_bindgen::JsCast as _;
+    let result = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.create_element("a").ok())
+        .and_then(|element| element.dyn_into::<web_sys::HtmlAnchorElement>().ok())
+        .map(|anchor| {
+            anchor.set_href(&format!("http://{host}/wallpapers/{file_name}"));
+            anchor.set_download(file_name);
+            anchor.click();
+        })
+        .ok_or_else(|| anyhow::anyhow!("Failed to create download link"));
+    on_done(result);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn apply_desktop_wallpaper(bytes: &[u8]) -> Result<()> {
+    let format = image::guess_format(bytes)?;
+    let extension = format.extensions_str().first().unwrap_or(&"png");
+
+    // Some desktops cache by path, so use a fresh file name each time and clean up the old ones
+    let temp_dir = std::env::temp_dir();
+    for entry in std::fs::read_dir(&temp_dir)?.flatten() {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with("wallpapy-desktop-")
+        {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+    let path = temp_dir.join(format!(
+        "wallpapy-desktop-{}.{extension}",
+        chrono::Utc::now().timestamp_millis()
+    ));
+    std::fs::write(&path, bytes)?;
+
+    let path = path
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Temp path is not valid unicode"))?;
+    wallpaper::set_from_path(path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    Ok(())
+}
@@ -0,0 +1,90 @@
+/// An action the command palette can run, add a variant and list it in `ALL` to register another
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Generate,
+    GenerateWithMessage,
+    ToggleFilters,
+    Search,
+    Account,
+    Logout,
+}
+
+impl Command {
+    pub const ALL: [Self; 6] = [
+        Self::Generate,
+        Self::GenerateWithMessage,
+        Self::ToggleFilters,
+        Self::Search,
+        Self::Account,
+        Self::Logout,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Generate => "Generate wallpaper",
+            Self::GenerateWithMessage => "Generate wallpaper with message",
+            Self::ToggleFilters => "Toggle filters",
+            Self::Search => "Search wallpapers",
+            Self::Account => "Account settings",
+            Self::Logout => "Logout",
+        }
+    }
+
+    pub const fn icon(self) -> &'static str {
+        match self {
+            Self::Generate => egui_phosphor::regular::SPARKLE,
+            Self::GenerateWithMessage => egui_phosphor::regular::CHAT_TEXT,
+            Self::ToggleFilters => egui_phosphor::regular::FUNNEL,
+            Self::Search => egui_phosphor::regular::MAGNIFYING_GLASS,
+            Self::Account => egui_phosphor::regular::GEAR,
+            Self::Logout => egui_phosphor::regular::SIGN_OUT,
+        }
+    }
+
+    /// Hint for the text typed inline before it runs, None runs it as soon as it's chosen
+    pub const fn argument(self) -> Option<&'static str> {
+        match self {
+            Self::GenerateWithMessage => Some("Message for the next wallpaper"),
+            _ => None,
+        }
+    }
+
+    /// Changes things on the server, so it's hidden while offline
+    pub const fn needs_server(self) -> bool {
+        matches!(self, Self::Generate | Self::GenerateWithMessage)
+    }
+}
+
+/// Commands whose name fuzzy matches the query, best match first
+pub fn matching(query: &str, offline: bool) -> Vec<Command> {
+    let mut scored = Command::ALL
+        .into_iter()
+        .filter(|command| !(offline && command.needs_server()))
+        .filter_map(|command| Some((fuzzy_score(query, command.name())?, command)))
+        .collect::<Vec<_>>();
+    // Stable, so equal scores keep the order of `ALL`
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().map(|(_, command)| command).collect()
+}
+
+/// How well the query matches, None unless all its characters appear in order
+/// Runs of consecutive characters and matches at the start of words score higher
+fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text = text.to_lowercase().chars().collect::<Vec<_>>();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + text[position..].iter().position(|&t| t == c)?;
+        score += 1;
+        if previous.is_some_and(|previous| previous + 1 == found) {
+            score += 3;
+        }
+        if found == 0 || text[found - 1] == ' ' {
+            score += 2;
+        }
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
@@ -0,0 +1,80 @@
+use egui::{Color32, RichText, Stroke};
+use std::collections::HashMap;
+
+/// Tags seen fewer times than this are left out of the cloud
+pub const MIN_TAG_COUNT: usize = 2;
+const TAG_BASE_SIZE: f32 = 10.0;
+const TAG_MAX_SIZE: f32 = 40.0;
+/// Seconds for the outline of a pending button to fade out and back in
+const PENDING_PULSE_SECS: f64 = 1.2;
+
+/// Pulse a border around a button while the request it sent is waiting on the server
+pub fn pending_outline(ui: &egui::Ui, response: &egui::Response) {
+    let phase = ui.input(|i| i.time) / PENDING_PULSE_SECS * std::f64::consts::TAU;
+    let strength = (phase.sin() * 0.5 + 0.5) as f32;
+    ui.painter().rect_stroke(
+        response.rect.expand(2.0),
+        ui.visuals().widgets.inactive.rounding,
+        Stroke::new(
+            2.0,
+            ui.visuals()
+                .selection
+                .stroke
+                .color
+                .gamma_multiply(0.3 + 0.7 * strength),
+        ),
+    );
+    ui.ctx().request_repaint();
+}
+
+/// Tags laid out in a wrapping cloud, sized by how often they're used and coloured by their hue
+pub struct TagCloud<'a> {
+    counts: &'a HashMap<String, usize>,
+    hues: &'a HashMap<String, f32>, // Average hue in degrees of the wallpapers with each tag
+    selected: Option<&'a str>,
+}
+
+impl<'a> TagCloud<'a> {
+    pub const fn new(counts: &'a HashMap<String, usize>, hues: &'a HashMap<String, f32>) -> Self {
+        Self {
+            counts,
+            hues,
+            selected: None,
+        }
+    }
+
+    /// Highlight the tag currently being filtered by
+    pub const fn selected(mut self, selected: Option<&'a str>) -> Self {
+        self.selected = selected;
+        self
+    }
+
+    /// Draw the cloud, returning the tag that was clicked
+    pub fn show(self, ui: &mut egui::Ui) -> Option<String> {
+        let mut tags = self
+            .counts
+            .iter()
+            .filter(|(_, count)| **count >= MIN_TAG_COUNT)
+            .collect::<Vec<_>>();
+        tags.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut clicked = None;
+        ui.horizontal_wrapped(|ui| {
+            for (tag, count) in tags {
+                let size = (TAG_BASE_SIZE * (*count as f32 + 1.0).log2()).min(TAG_MAX_SIZE);
+                let color = self.hues.get(tag).map_or(Color32::LIGHT_GRAY, |hue| {
+                    egui::ecolor::Hsva::new(hue / 360.0, 0.6, 0.9, 1.0).into()
+                });
+                let text = RichText::new(tag).size(size).color(color);
+                if ui
+                    .selectable_label(self.selected == Some(tag.as_str()), text)
+                    .on_hover_text(format!("{count} wallpapers"))
+                    .clicked()
+                {
+                    clicked = Some(tag.clone());
+                }
+            }
+        });
+        clicked
+    }
+}
@@ -0,0 +1,91 @@
+/// Hue bins in the histogram, 15 degrees each
+pub const HUE_BINS: usize = 24;
+
+/// Pixels with less chroma than this are too grey to have a meaningful hue
+const MIN_CHROMA: f32 = 0.1;
+
+/// Where the light sits in an image and which hues it is made of
+#[derive(Clone, Copy)]
+pub struct ImageStats {
+    pub region_brightness: [f32; 9], // Mean brightness of a 3x3 grid, row by row from the top left
+    pub hues: [f32; HUE_BINS],       // Colourful pixels in each hue bin, scaled so the largest is 1
+}
+
+impl ImageStats {
+    /// Compute the stats from RGBA8 pixels, meant for thumbnails so it stays cheap
+    pub fn from_rgba(width: u32, height: u32, rgba: &[u8]) -> Self {
+        let (width, height) = (width.max(1) as usize, height.max(1) as usize);
+        let mut brightness = [0.0; 9];
+        let mut counts = [0usize; 9];
+        let mut hues = [0.0; HUE_BINS];
+        for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+            let (x, y) = (i % width, i / width);
+            let region = (y * 3 / height).min(2) * 3 + (x * 3 / width).min(2);
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| f32::from(c) / 255.0);
+            brightness[region] += 0.2126f32.mul_add(r, 0.7152f32.mul_add(g, 0.0722 * b));
+            counts[region] += 1;
+            if let Some(hue) = hue(r, g, b) {
+                hues[((hue * HUE_BINS as f32) as usize).min(HUE_BINS - 1)] += 1.0;
+            }
+        }
+
+        for (brightness, count) in brightness.iter_mut().zip(counts) {
+            *brightness /= count.max(1) as f32;
+        }
+        let largest = hues.iter().copied().fold(0.0, f32::max);
+        if largest > 0.0 {
+            for bin in &mut hues {
+                *bin /= largest;
+            }
+        }
+        Self {
+            region_brightness: brightness,
+            hues,
+        }
+    }
+}
+
+/// Hue from 0 to 1, none for greys
+fn hue(r: f32, g: f32, b: f32) -> Option<f32> {
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+    if chroma < MIN_CHROMA {
+        return None;
+    }
+    let sector = if r >= g && r >= b {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if g >= b {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    Some(sector / 6.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_regions_and_hues() {
+        // 6x3 image, black on the left half and red on the right
+        let (width, height) = (6, 3);
+        let rgba = (0..width * height)
+            .flat_map(|i| {
+                if i % width < width / 2 {
+                    [0, 0, 0, 255]
+                } else {
+                    [255, 0, 0, 255]
+                }
+            })
+            .collect::<Vec<u8>>();
+        let stats = ImageStats::from_rgba(width as u32, height as u32, &rgba);
+
+        for row in 0..3 {
+            assert!(stats.region_brightness[row * 3] < 0.01);
+            assert!((stats.region_brightness[row * 3 + 2] - 0.2126).abs() < 0.01);
+        }
+        assert!((stats.hues[0] - 1.0).abs() < f32::EPSILON);
+        assert!(stats.hues[1..].iter().all(|bin| *bin < f32::EPSILON));
+    }
+}
@@ -1,20 +1,191 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
+#[cfg(any(feature = "gui", test))]
+pub mod image_stats;
+pub mod utils;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Database {
     pub style: DatabaseStyle,
+    #[serde(default)]
+    pub history_config: HistoryConfig,
+    #[serde(default)]
+    pub prompt_templates: Vec<PromptTemplate>,
     pub wallpapers: HashMap<Uuid, WallpaperData>,
     pub comments: HashMap<Uuid, CommentData>,
+    #[serde(default)]
+    pub pending_prompts: HashMap<Uuid, PendingPrompt>,
+    #[serde(default)]
+    pub fetch_profiles: Vec<FetchProfile>,
+    #[serde(default)]
+    pub users: HashMap<Uuid, String>, // Usernames by account, only filled in when served by /get
+    #[serde(default)]
+    pub viewer: Option<Uuid>, // Account the likes were filtered for, only filled in when served by /get
+    #[serde(default)]
+    pub thumbnail_settings: ThumbnailSettings, // What the current thumbnails were written with
+    #[serde(default)]
+    pub stale_thumbnails: Vec<Uuid>, // Written with older settings, rebuilt oldest first in the background
+    #[serde(default)]
+    pub presets: HashMap<String, DatabaseStyle>, // Named snapshots of the style to swap between
+    #[serde(default)]
+    pub share_links: Vec<ShareLink>, // Live share links, kept on the server and left out of /get
 }
 
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct DatabaseStyle {
     pub style: String, // The style that should be included in every prompt, painted etc
-    pub contents: String, // What kind of prompts to create, epic fantasy etc
+    #[serde(deserialize_with = "deserialize_contents")]
+    pub contents: Vec<ContentTheme>, // What kind of prompts to create in priority order, epic fantasy etc
     pub negative_contents: String, // What to avoid including in the prompt
+    #[serde(default)]
+    pub season_override: Option<Season>, // Force a season regardless of the calendar
+    #[serde(default)]
+    pub prompt_length: PromptLength,
+    #[serde(default)]
+    pub extra_instructions: String, // Passed on to the prompt writer as written
+}
+
+/// How long written prompts should be, image models lose track of the details in very long ones
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PromptLength {
+    Short,
+    #[default]
+    Medium,
+    Long,
+}
+
+impl PromptLength {
+    #[cfg(feature = "gui")]
+    pub const ALL: [Self; 3] = [Self::Short, Self::Medium, Self::Long];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Short => "Short",
+            Self::Medium => "Medium",
+            Self::Long => "Long",
+        }
+    }
+}
+
+/// One kind of content to aim for, weighted against the others
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ContentTheme {
+    pub text: String,
+    pub weight: f32, // 1 is a plain mention, higher is preferred and lower only occasional
+}
+
+impl ContentTheme {
+    pub const MIN_WEIGHT: f32 = 0.5;
+    pub const MAX_WEIGHT: f32 = 3.0;
+
+    /// Split a comma separated list into themes of equal weight
+    pub fn parse_list(contents: &str) -> Vec<Self> {
+        contents
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(|text| Self {
+                text: text.to_string(),
+                weight: 1.0,
+            })
+            .collect()
+    }
+
+    /// How strongly to lean towards this theme, None for a plain mention
+    pub fn emphasis(&self) -> Option<&'static str> {
+        if self.weight >= 2.0 {
+            Some("strongly prefer")
+        } else if self.weight > 1.0 {
+            Some("prefer")
+        } else if self.weight < 1.0 {
+            Some("occasionally")
+        } else {
+            None
+        }
+    }
+}
+
+/// Older database files stored the contents as one comma separated string
+fn deserialize_contents<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ContentTheme>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Contents {
+        Themes(Vec<ContentTheme>),
+        Legacy(String),
+    }
+
+    // Bincode can't tell the layouts apart and only ever carries the new one
+    if !deserializer.is_human_readable() {
+        return Vec::deserialize(deserializer);
+    }
+    Ok(match Contents::deserialize(deserializer)? {
+        Contents::Themes(themes) => themes,
+        Contents::Legacy(contents) => ContentTheme::parse_list(&contents),
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Season {
+    Winter,
+    Spring,
+    Summer,
+    Autumn,
+}
+
+impl Season {
+    pub const fn from_month(month: u32) -> Self {
+        match month {
+            3..=5 => Self::Spring,
+            6..=8 => Self::Summer,
+            9..=11 => Self::Autumn,
+            _ => Self::Winter,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Winter => "Winter",
+            Self::Spring => "Spring",
+            Self::Summer => "Summer",
+            Self::Autumn => "Autumn",
+        }
+    }
+
+    pub const fn hint(self) -> &'static str {
+        match self {
+            Self::Winter => "winter themes, snow, cold light",
+            Self::Spring => "spring awakening, blossoms",
+            Self::Summer => "summer heat, golden hours",
+            Self::Autumn => "autumn foliage, misty mornings",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub max_loved: usize,      // Loved prompts are always included up to this cap
+    pub recent_count: usize,   // How many of the latest liked and neutral prompts to include
+    pub max_disliked: usize,   // How many of the latest disliked prompts to include
+    pub max_comments: usize,   // How many of the latest comments to include
+    pub max_summarised: usize, // How many older prompts to summarise into key concepts
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_loved: 30,
+            recent_count: 15,
+            max_disliked: 5,
+            max_comments: 10,
+            max_summarised: 60,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -30,7 +201,168 @@ pub struct WallpaperData {
     pub thumbnail_file: ImageFile,
     pub thumbhash: Vec<u8>,
 
-    pub liked_state: LikedState,
+    #[serde(default, rename = "liked_state")]
+    pub shared_liked_state: LikedState, // From before likes were per account, for accounts without their own
+    #[serde(default)]
+    pub liked_states: HashMap<Uuid, LikedState>, // Each account's own vote
+    #[serde(default)]
+    pub source: Source,
+    #[serde(default)]
+    pub applied_transforms: Vec<Transform>,
+    #[serde(default)]
+    pub llm_usage: Option<LlmUsage>,
+    #[serde(default)]
+    pub llm_provider_used: String,
+    #[serde(default)]
+    pub pinned: bool, // Pinned wallpapers are never auto-deleted
+    #[serde(default)]
+    pub parent: Option<Uuid>, // Wallpaper this was cropped from
+    #[serde(default)]
+    pub note: Option<String>, // The users own remark on this wallpaper, fed into prompt history
+    #[serde(default)]
+    pub last_served: Option<DateTime<Utc>>, // When a device last reported applying it
+    #[serde(default)]
+    pub last_modified_by: Option<Uuid>, // Account that last set the liked state
+    #[serde(default)]
+    pub generation_mode: Option<GenerationMode>, // None for uploads and older wallpapers
+    #[serde(default)]
+    pub rating: Option<u8>, // 1 to 5 stars, finer grained than the liked state
+    #[serde(default)]
+    pub context_file: Option<String>, // Compressed LLM context it was generated from, relative to the wallpapers dir
+    #[serde(default)]
+    pub reference_file: Option<ImageFile>, // Image the user supplied as inspiration
+    #[serde(default)]
+    pub candidates_evaluated: u32, // Images diffused to pick this one from, 0 for uploads and older wallpapers
+    #[serde(default)]
+    pub model: Option<String>, // Id of the image model it was diffused with, None for uploads and older wallpapers
+    #[serde(default)]
+    pub variation_of: Option<Uuid>, // Wallpaper whose prompt this was varied from
+    #[serde(default)]
+    pub source_comment: Option<Uuid>, // Comment it was generated from with the comment cards shortcut
+    #[serde(default)]
+    pub original_id: Option<Uuid>, // Wallpaper this was recreated from
+    #[serde(default)]
+    pub variations: Vec<Uuid>, // Recreations and variations made from its prompt, oldest first
+    #[serde(default)]
+    pub generated_by: Option<Uuid>, // Account that asked for it, none when scheduled
+    #[serde(default)]
+    pub dark_variant: bool, // A darkened copy of its parent for night use
+    #[serde(default)]
+    pub tags: Vec<String>, // Extracted from the prompt by the LLM after generating
+    #[serde(default)]
+    pub weather_context: Option<String>, // Weather outside when it was generated, with `WEATHER_LOCATION` set
+    #[serde(default)]
+    pub moodboard_influence: bool, // The prompt was biased towards the moodboard's aesthetic
+}
+
+impl WallpaperData {
+    /// The account's own vote, or the shared one if it hasn't voted
+    pub fn liked_state(&self, account: Uuid) -> LikedState {
+        self.liked_states
+            .get(&account)
+            .copied()
+            .unwrap_or(self.shared_liked_state)
+    }
+
+    /// The warmest vote from anyone, for what happens to the wallpaper as a whole
+    pub fn combined_liked_state(&self) -> LikedState {
+        self.liked_states
+            .values()
+            .copied()
+            .fold(self.shared_liked_state, LikedState::max)
+    }
+
+    /// One account's vote, or the warmest from anyone without an account
+    pub fn liked_state_for(&self, account: Option<Uuid>) -> LikedState {
+        account.map_or_else(
+            || self.combined_liked_state(),
+            |account| self.liked_state(account),
+        )
+    }
+
+    /// Every file it owns relative to the wallpapers dir, including other encodings and the context file
+    pub fn all_files(&self) -> Vec<String> {
+        [
+            Some(&self.original_file),
+            self.upscaled_file.as_ref(),
+            Some(&self.thumbnail_file),
+            self.reference_file.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .flat_map(|file| std::iter::once(file.file_name.clone()).chain(file.alt_file_names()))
+        .chain(self.context_file.clone())
+        .collect()
+    }
+}
+
+#[cfg(test)]
+impl WallpaperData {
+    /// A generated wallpaper with blank colour data, tests override the fields they look at
+    pub fn sample() -> Self {
+        let file = |file_name: &str| ImageFile {
+            file_name: file_name.to_string(),
+            width: 640,
+            height: 360,
+            resolution_preset: String::new(),
+            alt_formats: Vec::new(),
+        };
+        Self {
+            id: Uuid::new_v4(),
+            datetime: Utc::now(),
+            prompt_data: PromptData {
+                prompt: "A quiet harbour at dusk".to_string(),
+                shortened_prompt: "Quiet harbour".to_string(),
+                seasonal_hint: None,
+                time_of_day: None,
+                negative_prompt: None,
+                was_refined: false,
+            },
+            original_file: file("original.webp"),
+            upscaled_file: None,
+            color_data: ColorData {
+                average_color: (0.0, 0.0, 0.0),
+                hue: 0.0,
+                saturation: 0.0,
+                lightness: 0.0,
+                chroma: 0.0,
+                top_20_percent_brightness: 0.0,
+                bottom_20_percent_brightness: 0.0,
+                contrast_ratio: 0.0,
+                sharpness_score: 0.0,
+                color_temperature: ColorTemperature::Neutral,
+                brightness_histogram: Vec::new(),
+            },
+            thumbnail_file: file("thumb.webp"),
+            thumbhash: Vec::new(),
+            shared_liked_state: LikedState::Neutral,
+            liked_states: HashMap::new(),
+            source: Source::Generated,
+            applied_transforms: Vec::new(),
+            llm_usage: None,
+            llm_provider_used: String::new(),
+            pinned: false,
+            parent: None,
+            note: None,
+            last_served: None,
+            last_modified_by: None,
+            context_file: None,
+            reference_file: None,
+            candidates_evaluated: 0,
+            model: None,
+            variation_of: None,
+            source_comment: None,
+            original_id: None,
+            variations: Vec::new(),
+            generated_by: None,
+            dark_variant: false,
+            tags: Vec::new(),
+            rating: None,
+            generation_mode: None,
+            weather_context: None,
+            moodboard_influence: false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -38,20 +370,153 @@ pub struct CommentData {
     pub id: Uuid,
     pub datetime: DateTime<Utc>,
     pub comment: String,
+    #[serde(default)]
+    pub response: Option<String>, // How the generator applied this feedback
+    #[serde(default)]
+    pub author_uuid: Option<Uuid>, // Account that posted it, none once anonymised
+    #[serde(default)]
+    pub used: bool, // A wallpaper has been generated from it
+    #[serde(default)]
+    pub reactions: HashMap<Uuid, bool>, // Thumbs up by account
+    #[serde(default)]
+    pub sentiment: Option<CommentSentiment>, // Classified when posted, None for older comments
+}
+
+impl CommentData {
+    #[cfg(any(feature = "gui", test))]
+    pub fn reaction_count(&self) -> usize {
+        self.reactions.values().filter(|reacted| **reacted).count()
+    }
+
+    /// The recorded sentiment, or classified now for comments from before it was recorded
+    pub fn sentiment(&self) -> CommentSentiment {
+        self.sentiment
+            .unwrap_or_else(|| utils::comment_sentiment(&self.comment))
+    }
+}
+
+/// Whether a comment asks for more of something or less of it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommentSentiment {
+    Positive,
+    Negative,
+    Neutral,
 }
 
 // Sub data types
+/// Size and webp quality thumbnails are written at
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ThumbnailSettings {
+    pub width: u32,
+    pub height: u32,
+    pub quality: u8, // Webp quality from 1 to 100
+}
+
+impl Default for ThumbnailSettings {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 360,
+            quality: 90,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ImageFile {
     pub file_name: String,
     pub width: u32,
     pub height: u32,
+    #[serde(default)]
+    pub resolution_preset: String, // The monitor resolution preset this image targets
+    #[serde(default)]
+    pub alt_formats: Vec<String>, // Extensions of other encodings saved beside the file
 }
 
+impl ImageFile {
+    /// File names of the other encodings of this image
+    pub fn alt_file_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.alt_formats.iter().map(|extension| {
+            std::path::Path::new(&self.file_name)
+                .with_extension(extension)
+                .to_string_lossy()
+                .to_string()
+        })
+    }
+
+    /// Rough file size from the pixel count, for showing before a download
+    #[cfg(feature = "gui")]
+    pub fn estimated_size_bytes(&self) -> u64 {
+        let bytes_per_pixel = match std::path::Path::new(&self.file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("avif") => 0.15,
+            Some("webp") => 0.3,
+            Some("jpg" | "jpeg") => 0.5,
+            _ => 3.0, // Lossless formats like png
+        };
+        (f64::from(self.width) * f64::from(self.height) * bytes_per_pixel) as u64
+    }
+}
+
+/// A link that lets anyone download a wallpaper until it expires
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ShareLink {
+    pub token: String, // Signature of the wallpaper id and expiry
+    pub uuid: Uuid,
+    pub expiry: DateTime<Utc>,
+}
+
+/// A generated prompt held back for review so no image credits are spent until it's accepted
 #[derive(Serialize, Deserialize, Clone)]
+pub struct PendingPrompt {
+    pub id: Uuid,
+    pub prompt_data: PromptData,
+    pub datetime: DateTime<Utc>,
+    pub message: Option<String>, // What the user asked for, reused when rerolling
+    pub comment_ids: Vec<Uuid>,  // Comments that influenced the prompt
+    pub llm_usage: LlmUsage,
+    pub llm_provider: String,
+    #[serde(default)]
+    pub mode: GenerationMode,
+    #[serde(default)]
+    pub context: String, // System messages the prompt was written from, saved beside the wallpaper on accept
+    #[serde(default)]
+    pub model: Option<String>, // Image model to diffuse with on accept, None for the default
+    #[serde(default)]
+    pub weather_context: Option<String>, // Weather the prompt was written for, kept for the wallpaper
+    #[serde(default)]
+    pub moodboard_influence: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct PromptData {
     pub prompt: String,
     pub shortened_prompt: String,
+    #[serde(default)]
+    pub seasonal_hint: Option<String>, // The seasonal theme active when this was generated
+    #[serde(default)]
+    pub time_of_day: Option<TimeOfDay>, // The time of day targeted to rebalance the library
+    #[serde(default)]
+    pub negative_prompt: Option<String>, // What the image model was told to avoid
+    #[serde(default)]
+    pub was_refined: bool, // Critiqued and rewritten by a second LLM pass
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TimeOfDay {
+    Day,
+    Night,
+}
+
+impl TimeOfDay {
+    pub const fn directive(self) -> &'static str {
+        match self {
+            Self::Day => "aim for a bright daylight scene",
+            Self::Night => "aim for a darker nighttime scene",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -64,16 +529,422 @@ pub struct ColorData {
     pub top_20_percent_brightness: f32,
     pub bottom_20_percent_brightness: f32,
     pub contrast_ratio: f32,
+    #[serde(default)]
+    pub sharpness_score: f32, // Variance of the Laplacian, low values indicate a blurry image
+    #[serde(default)]
+    pub color_temperature: ColorTemperature,
+    #[serde(default)]
+    pub brightness_histogram: Vec<u16>, // Pixels at each of 256 brightness levels, scaled so the largest is u16::MAX
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorTemperature {
+    Warm,
+    Cool,
+    #[default]
+    Neutral,
+}
+
+impl ColorTemperature {
+    /// Classify from a hue in the range [0,1], reds through yellows are warm, cyans through blues are cool
+    pub fn from_hue(hue: f32) -> Self {
+        let degrees = hue * 360.0;
+        if !(60.0..330.0).contains(&degrees) {
+            Self::Warm
+        } else if (180.0..=270.0).contains(&degrees) {
+            Self::Cool
+        } else {
+            Self::Neutral
+        }
+    }
+}
+
+/// Ordered so the max of several votes is the one that counts, any like beats a dislike beats no vote
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub enum LikedState {
+    #[default]
     Neutral,
     Disliked,
     Liked,
     Loved,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Source {
+    #[default]
+    Generated,
+    Uploaded,
+    Imported, // Brought in from a folder of existing images
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+}
+
+/// A user written prompt with `{slot_name}` placeholders
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PromptTemplate {
+    pub id: Uuid,
+    pub name: String,
+    pub template: String,
+    pub slots: Vec<String>,
+}
+
+impl PromptTemplate {
+    /// The unique placeholder names in a template, in order of appearance
+    pub fn parse_slots(template: &str) -> Vec<String> {
+        let mut slots: Vec<String> = Vec::new();
+        let mut rest = template;
+        while let Some((_, after)) = rest.split_once('{') {
+            let Some((slot, after)) = after.split_once('}') else {
+                break;
+            };
+            if !slot.trim().is_empty() && !slot.contains('{') && !slots.iter().any(|s| s == slot) {
+                slots.push(slot.to_string());
+            }
+            rest = after;
+        }
+        slots
+    }
+
+    /// Replace every placeholder with its value, every slot must be given a value
+    pub fn fill(&self, slot_values: &HashMap<String, String>) -> Result<String, String> {
+        let mut prompt = self.template.clone();
+        for slot in &self.slots {
+            let value = slot_values
+                .get(slot)
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("Missing value for slot '{slot}'"))?;
+            prompt = prompt.replace(&format!("{{{slot}}}"), value);
+        }
+        Ok(prompt)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+/// Smartget constraints saved for one device, applied with `/smartget?profile=name`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct FetchProfile {
+    pub name: String,
+    pub orientation: Option<Orientation>,
+    pub brightness_range: Option<(f32, f32)>, // Replaces the time of day brightness range
+    pub min_rating: Option<u8>,               // Unrated wallpapers count as three stars
+    pub loved_only: bool,                     // Otherwise liked wallpapers are picked too
+    pub rest_hours: Option<u32>, // How long a served wallpaper is skipped, a day by default
+}
+
+impl FetchProfile {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Profile needs a name".to_string());
+        }
+        if let Some((min, max)) = self.brightness_range {
+            if !(0.0..=1.0).contains(&min) || !(0.0..=1.0).contains(&max) || min > max {
+                return Err("Brightness range must be within 0 to 1".to_string());
+            }
+        }
+        if self
+            .min_rating
+            .is_some_and(|rating| !(1..=5).contains(&rating))
+        {
+            return Err("Minimum rating must be 1 to 5 stars".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// How many wallpapers a profile could pick right now, served as JSON from `/profiles`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProfilePreview {
+    pub name: String,
+    pub candidates: usize,
+}
+
+/// Tokens used by the LLM requests that wrote a prompt
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct LlmUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+    pub estimated_cost_usd: f32,
+}
+
+impl std::ops::AddAssign for LlmUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.estimated_cost_usd += other.estimated_cost_usd;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct CostStats {
+    pub total_tokens: u64,
+    pub total_cost_usd: f32,
+    pub months: Vec<MonthlyCost>, // Oldest first
+    pub models: Vec<ModelStats>,  // Most generated first
+    pub users: Vec<UserStats>,    // Most generated first
+}
+
+/// Wallpapers generated at the request of one account
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserStats {
+    pub user: Option<Uuid>, // None for the scheduled generations
+    pub generated: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ModelStats {
+    pub model: String,
+    pub generated: u32,
+    pub loved: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MonthlyCost {
+    pub year: i32,
+    pub month: u32,
+    pub total_tokens: u64,
+    pub cost_usd: f32,
+}
+
+/// A group of visually similar wallpapers
+/// Centroid features are hue, saturation, lightness, top 20% brightness and contrast ratio
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Cluster {
+    pub centroid: [f32; 5],
+    pub members: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct RetentionPolicy {
+    pub disliked_days: u32, // Days before disliked wallpapers are deleted, 0 to keep forever
+    pub neutral_days: u32,  // Days before neutral wallpapers are deleted, 0 to keep forever
+}
+
+impl RetentionPolicy {
+    /// How many days a wallpaper with this state is kept, if it is auto-deleted at all
+    pub fn retention_days(self, state: LikedState) -> Option<u32> {
+        match state {
+            LikedState::Disliked => Some(self.disliked_days),
+            LikedState::Neutral => Some(self.neutral_days),
+            LikedState::Liked | LikedState::Loved => None,
+        }
+        .filter(|days| *days > 0)
+    }
+}
+
+/// What the generation scheduler is up to, served as JSON from `/status`
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ServerStatus {
+    pub last_generation: Option<DateTime<Utc>>,
+    pub next_generation: Option<DateTime<Utc>>, // When the scheduler will next generate, accurate to its check interval
+    pub generating: bool,
+    pub generation_started: Option<DateTime<Utc>>, // Start of the oldest generation still running
+    pub last_duration_secs: Option<i64>,           // How long the last finished generation took
+    pub queue_length: usize,                       // Prompts waiting for review
+    pub api_keys_configured: bool,
+    #[serde(default)]
+    pub moodboard_updated: Option<DateTime<Utc>>, // When the reference image was set, None without one
+}
+
+/// Order of the paginated gallery
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GallerySort {
+    #[default]
+    Newest,
+    Oldest,
+    Liked, // Loved first, newest first within each state
+}
+
+#[cfg(feature = "gui")]
+impl GallerySort {
+    pub const ALL: [Self; 3] = [Self::Newest, Self::Oldest, Self::Liked];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Newest => "Newest",
+            Self::Oldest => "Oldest",
+            Self::Liked => "Liked",
+        }
+    }
+
+    /// The value of the `sort` query parameter
+    pub const fn query(self) -> &'static str {
+        match self {
+            Self::Newest => "newest",
+            Self::Oldest => "oldest",
+            Self::Liked => "liked",
+        }
+    }
+}
+
+/// One page of wallpapers from `/get?offset=&limit=`
+#[derive(Serialize, Deserialize)]
+pub struct GalleryPage {
+    pub wallpapers: Vec<WallpaperData>,
+    pub total: usize, // Wallpapers across all pages
+}
+
+/// Disagreements between the database and the wallpapers directory
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct MaintenanceReport {
+    pub missing_originals: Vec<Uuid>, // Entries with a full size image missing from disk
+    pub missing_thumbnails: Vec<Uuid>,
+    pub orphan_files: Vec<String>, // Files on disk that no entry references
+    pub fixed: usize,              // How many problems the requested fix resolved
+}
+
+impl MaintenanceReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing_originals.is_empty()
+            && self.missing_thumbnails.is_empty()
+            && self.orphan_files.is_empty()
+    }
+}
+
+/// A line from the servers recent log, so admins can read it without a shell
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogLine {
+    pub timestamp: DateTime<Utc>,
+    pub level: LogLevel,
+    pub target: String, // Module that logged it
+    pub message: String,
+}
+
+/// Severity of a log line, most severe first so `level <= LogLevel::Warn` keeps warnings and errors
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<log::Level> for LogLevel {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => Self::Error,
+            log::Level::Warn => Self::Warn,
+            log::Level::Info => Self::Info,
+            log::Level::Debug => Self::Debug,
+            log::Level::Trace => Self::Trace,
+        }
+    }
+}
+
+#[cfg(feature = "gui")]
+impl LogLevel {
+    pub const ALL: [Self; 5] = [
+        Self::Error,
+        Self::Warn,
+        Self::Info,
+        Self::Debug,
+        Self::Trace,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Error => "Error",
+            Self::Warn => "Warn",
+            Self::Info => "Info",
+            Self::Debug => "Debug",
+            Self::Trace => "Trace",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Generate,
+    Upload,
+    Delete,
+    Like,
+    Pin,
+    Recreate,
+    Transform,
+    Crop,
+    Style,
+    HistoryConfig,
+    Season,
+    Comment,
+    RemoveComment,
+    AddTemplate,
+    RemoveTemplate,
+    Restore,
+    Maintenance,
+    Note,
+    Prompt,
+    Config,
+    DeleteUserData,
+    Rate,
+    Share,
+    Profile,
+    ChangePassword,
+    Import,
+    React,
+    Moodboard,
+}
+
+#[cfg(feature = "gui")]
+impl AuditAction {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Generate => "Generate",
+            Self::Upload => "Upload",
+            Self::Delete => "Delete",
+            Self::Like => "Like",
+            Self::Pin => "Pin",
+            Self::Recreate => "Recreate",
+            Self::Transform => "Transform",
+            Self::Crop => "Crop",
+            Self::Style => "Style",
+            Self::HistoryConfig => "History Config",
+            Self::Season => "Season",
+            Self::Comment => "Comment",
+            Self::RemoveComment => "Remove Comment",
+            Self::AddTemplate => "Add Template",
+            Self::RemoveTemplate => "Remove Template",
+            Self::Restore => "Restore",
+            Self::Maintenance => "Maintenance",
+            Self::Note => "Note",
+            Self::Prompt => "Prompt",
+            Self::Config => "Config",
+            Self::DeleteUserData => "Delete User Data",
+            Self::Rate => "Rate",
+            Self::Share => "Share",
+            Self::Profile => "Profile",
+            Self::ChangePassword => "Change Password",
+            Self::Import => "Import",
+            Self::React => "React",
+            Self::Moodboard => "Moodboard",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub actor_uuid: Uuid,
+    pub action: AuditAction,
+    pub target_uuid: Option<Uuid>,
+    pub timestamp: DateTime<Utc>,
+    pub summary: String,
+}
+
 // Network packets
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LoginPacket {
@@ -86,6 +957,61 @@ pub struct TokenPacket {
     pub token: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ChangePasswordPacket {
+    pub token: String,
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// Recreate a wallpaper with its prompt edited
+#[derive(Serialize, Deserialize)]
+pub struct RecreateEditedPacket {
+    pub token: String,
+    pub uuid: Uuid,
+    pub prompt: String,
+}
+
+/// Import every image in a directory on the server
+#[derive(Serialize, Deserialize)]
+pub struct ImportDirPacket {
+    pub token: String,
+    pub path: String,
+    pub caption: bool, // Describe each image with the LLM so the history has useful text
+    pub liked: bool,   // Mark them liked so generation learns from them
+}
+
+/// How closely a new prompt follows the history and configured style
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum GenerationMode {
+    #[default]
+    Normal,
+    Wild,        // Ignore history and style for a completely random pick
+    StrictStyle, // Stay very close to the loved prompts
+}
+
+#[cfg(feature = "gui")]
+impl GenerationMode {
+    pub const ALL: [Self; 3] = [Self::Normal, Self::Wild, Self::StrictStyle];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "Normal",
+            Self::Wild => "Surprise me",
+            Self::StrictStyle => "Strict style",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GeneratePacket {
+    pub token: String,
+    pub message: String,
+    pub mode: GenerationMode,
+    pub model: Option<String>, // None for the servers default image model
+    pub comment: Option<Uuid>, // Comment the message was taken from, linked to the new wallpaper
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TokenStringPacket {
     pub token: String,
@@ -98,6 +1024,26 @@ pub struct TokenUuidPacket {
     pub uuid: Uuid,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct TemplateAddPacket {
+    pub token: String,
+    pub name: String,
+    pub template: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FetchProfilePacket {
+    pub token: String,
+    pub profile: FetchProfile,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GenerateFromTemplatePacket {
+    pub token: String,
+    pub template_id: Uuid,
+    pub slot_values: HashMap<String, String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TokenUuidLikedPacket {
     pub token: String,
@@ -105,16 +1051,112 @@ pub struct TokenUuidLikedPacket {
     pub liked: LikedState,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct TokenUuidRatingPacket {
+    pub token: String,
+    pub uuid: Uuid,
+    pub rating: Option<u8>, // None clears the rating
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenUuidsPacket {
+    pub token: String,
+    pub uuids: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenUuidsLikedPacket {
+    pub token: String,
+    pub uuids: Vec<Uuid>,
+    pub liked: LikedState,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImageTransformPacket {
+    pub token: String,
+    pub uuid: Uuid,
+    pub rotation: Option<i32>,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImageDarkenPacket {
+    pub token: String,
+    pub uuid: Uuid,
+    pub max_brightness: f32, // Brightest the darkened image gets, from 0 to 1
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImageCropPacket {
+    pub token: String,
+    pub uuid: Uuid,
+    // Crop rectangle normalized to the image size
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImageNotePacket {
+    pub token: String,
+    pub uuid: Uuid,
+    pub note: String, // Empty to clear the note
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImageUploadPacket {
+    pub token: String,
+    pub title: Option<String>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MoodboardPacket {
+    pub token: String,
+    pub data: Vec<u8>, // Reference image in any format the server can decode
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GenerateFromImagePacket {
+    pub token: String,
+    pub message: String,
+    pub mode: GenerationMode,
+    pub model: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// An image model the server can diffuse with, from `/models`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ImageModelInfo {
+    pub id: String,
+    pub name: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SetStylePacket {
     pub token: String,
     pub variant: StyleVariant,
-    pub string: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetHistoryConfigPacket {
+    pub token: String,
+    pub config: HistoryConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetSeasonPacket {
+    pub token: String,
+    pub season: Option<Season>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum StyleVariant {
-    Style,
-    Contents,
-    NegativeContents,
+    Style(String),
+    ContentThemes(Vec<ContentTheme>),
+    NegativeContents(String),
+    PromptLength(PromptLength),
+    ExtraInstructions(String),
 }
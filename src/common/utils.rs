@@ -0,0 +1,235 @@
+use crate::common::CommentSentiment;
+use chrono::Duration;
+#[cfg(any(feature = "gui", test))]
+use std::collections::HashMap;
+
+/// Words too common to say anything about a prompt
+#[cfg(any(feature = "gui", test))]
+const STOP_WORDS: [&str; 32] = [
+    "the", "and", "with", "for", "from", "into", "onto", "over", "under", "its", "their", "that",
+    "this", "are", "was", "were", "has", "have", "use", "using", "while", "where", "which", "each",
+    "through", "between", "against", "above", "below", "near", "beneath", "amid",
+];
+
+/// Count how often each word appears across the texts, most common first
+/// Words are lowercased, stripped of punctuation, and short or stop words are skipped
+#[cfg(any(feature = "gui", test))]
+pub fn word_frequencies<'a>(texts: impl IntoIterator<Item = &'a str>) -> Vec<(String, usize)> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for text in texts {
+        for word in text.split(|c: char| !c.is_alphanumeric() && c != '\'') {
+            let word = word.trim_matches('\'').to_lowercase();
+            if word.chars().count() < 3 || STOP_WORDS.contains(&word.as_str()) {
+                continue;
+            }
+            *counts.entry(word).or_default() += 1;
+        }
+    }
+
+    let mut frequencies = counts.into_iter().collect::<Vec<_>>();
+    frequencies.sort_by(|(a_word, a_count), (b_word, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_word.cmp(b_word))
+    });
+    frequencies
+}
+
+/// The largest whole unit of a duration, like "3 days"
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes();
+    let hours = duration.num_hours();
+    let days = duration.num_days();
+    let weeks = duration.num_weeks();
+
+    match (weeks, days, hours, minutes) {
+        (w, _, _, _) if w >= 1 => format!("{} week{}", w, if w == 1 { "" } else { "s" }),
+        (_, d, _, _) if d >= 1 => format!("{} day{}", d, if d == 1 { "" } else { "s" }),
+        (_, _, h, _) if h >= 1 => format!("{} hour{}", h, if h == 1 { "" } else { "s" }),
+        (_, _, _, m) if m >= 1 => format!("{} minute{}", m, if m == 1 { "" } else { "s" }),
+        _ => "less than a minute".to_string(),
+    }
+}
+
+/// Words that say a comment wants more of something
+const POSITIVE_WORDS: [&str; 22] = [
+    "love",
+    "loved",
+    "loving",
+    "like",
+    "liked",
+    "likes",
+    "great",
+    "beautiful",
+    "amazing",
+    "awesome",
+    "nice",
+    "gorgeous",
+    "perfect",
+    "stunning",
+    "favourite",
+    "favorite",
+    "wonderful",
+    "enjoy",
+    "enjoyed",
+    "best",
+    "more",
+    "lovely",
+];
+
+/// Words that say a comment wants less of something
+const NEGATIVE_WORDS: [&str; 20] = [
+    "hate", "hated", "dislike", "disliked", "ugly", "boring", "bad", "awful", "terrible", "worse",
+    "worst", "tired", "enough", "less", "fewer", "avoid", "stop", "meh", "bland", "dull",
+];
+
+/// Words that flip the sentiment of the next few words, or count against on their own
+const NEGATIONS: [&str; 8] = [
+    "not", "no", "never", "don't", "dont", "doesn't", "isn't", "without",
+];
+
+/// How many words after a negation it still applies to
+const NEGATION_REACH: usize = 3;
+
+/// Classify a comment with word lists, a negation like "not" or "no more" flips the words after it
+pub fn comment_sentiment(text: &str) -> CommentSentiment {
+    let mut score = 0;
+    let mut negation = None; // Words left that a pending negation applies to
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '\'') {
+        let word = word.trim_matches('\'').to_lowercase();
+        if word.is_empty() {
+            continue;
+        }
+        if NEGATIONS.contains(&word.as_str()) {
+            if negation.is_some() {
+                score -= 1;
+            }
+            negation = Some(NEGATION_REACH);
+            continue;
+        }
+        let polarity = if POSITIVE_WORDS.contains(&word.as_str()) {
+            1
+        } else if NEGATIVE_WORDS.contains(&word.as_str()) {
+            -1
+        } else {
+            0
+        };
+        if polarity != 0 {
+            score += if negation.take().is_some() {
+                -polarity
+            } else {
+                polarity
+            };
+        } else if let Some(reach) = negation {
+            // A negation that runs out without flipping anything, like "no cats", still counts against
+            if reach <= 1 {
+                negation = None;
+                score -= 1;
+            } else {
+                negation = Some(reach - 1);
+            }
+        }
+    }
+    if negation.is_some() {
+        score -= 1;
+    }
+
+    match score {
+        score if score > 0 => CommentSentiment::Positive,
+        score if score < 0 => CommentSentiment::Negative,
+        _ => CommentSentiment::Neutral,
+    }
+}
+
+/// Degrees between two hues going the short way around the colour wheel, from 0 to 180
+pub fn hue_distance(a: f32, b: f32) -> f32 {
+    let distance = (a - b).rem_euclid(360.0);
+    distance.min(360.0 - distance)
+}
+
+/// Average of hues in degrees taken around the colour wheel, so 350 and 10 average to 0 not 180
+#[cfg(any(feature = "gui", test))]
+pub fn mean_hue(hues: impl IntoIterator<Item = f32>) -> Option<f32> {
+    let (mut x, mut y, mut count) = (0.0, 0.0, 0);
+    for hue in hues {
+        let (sin, cos) = hue.to_radians().sin_cos();
+        x += cos;
+        y += sin;
+        count += 1;
+    }
+    (count > 0).then(|| f32::atan2(y, x).to_degrees().rem_euclid(360.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_hue_distance_around_the_wheel() {
+        assert!((hue_distance(10.0, 350.0) - 20.0).abs() < 1e-4);
+        assert!((hue_distance(350.0, 10.0) - 20.0).abs() < 1e-4);
+        assert!((hue_distance(0.0, 180.0) - 180.0).abs() < 1e-4);
+        assert!((hue_distance(210.0, 150.0) - 60.0).abs() < 1e-4);
+        // Hues outside 0 to 360 wrap onto the wheel
+        assert!(hue_distance(750.0, 30.0).abs() < 1e-4);
+        assert!((hue_distance(-30.0, 30.0) - 60.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn averages_hues_around_the_wheel() {
+        assert!(hue_distance(mean_hue([350.0, 10.0]).unwrap(), 0.0) < 1e-3);
+        assert!((mean_hue([100.0, 140.0]).unwrap() - 120.0).abs() < 1e-3);
+        assert!((mean_hue([200.0]).unwrap() - 200.0).abs() < 1e-3);
+        assert!(mean_hue([]).is_none());
+    }
+
+    #[test]
+    fn classifies_comment_sentiment() {
+        for (text, sentiment) in [
+            ("More oceans please", CommentSentiment::Positive),
+            ("Loved the misty forest one!", CommentSentiment::Positive),
+            ("Not bad at all", CommentSentiment::Positive),
+            ("No more cats", CommentSentiment::Negative),
+            ("I don't like the neon ones", CommentSentiment::Negative),
+            ("Too many boring sunsets", CommentSentiment::Negative),
+            ("no cats", CommentSentiment::Negative),
+            ("Try a watercolour style", CommentSentiment::Neutral),
+            ("", CommentSentiment::Neutral),
+        ] {
+            assert_eq!(comment_sentiment(text), sentiment, "{text}");
+        }
+    }
+
+    #[test]
+    fn counts_words_across_texts() {
+        let frequencies = word_frequencies(["A misty forest", "Forest at dawn, misty lake"]);
+        assert_eq!(
+            frequencies,
+            vec![
+                ("forest".to_string(), 2),
+                ("misty".to_string(), 2),
+                ("dawn".to_string(), 1),
+                ("lake".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_case_punctuation_and_stop_words() {
+        let frequencies = word_frequencies(["The OCEAN, the ocean! And the 'ocean'"]);
+        assert_eq!(frequencies, vec![("ocean".to_string(), 3)]);
+    }
+
+    #[test]
+    fn keeps_apostrophes_inside_words() {
+        let frequencies = word_frequencies(["A dragon's lair"]);
+        assert_eq!(
+            frequencies,
+            vec![("dragon's".to_string(), 1), ("lair".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn empty_input_has_no_words() {
+        assert!(word_frequencies([]).is_empty());
+        assert!(word_frequencies(["a an of"]).is_empty());
+    }
+}
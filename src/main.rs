@@ -16,48 +16,120 @@ mod client;
 mod server;
 
 pub static PORT: u16 = 4560;
-pub static WALLPAPERS_DIR: &str = "data/wallpapers";
 
 #[cfg(not(target_arch = "wasm32"))]
 #[tokio::main]
 async fn main() {
+    use clap::Parser;
+
     if cfg!(debug_assertions) {
         dotenvy::dotenv().ok();
     }
-    simple_logger::SimpleLogger::new()
-        .with_level(log::LevelFilter::Info)
-        .init()
-        .unwrap();
+    let cli = server::cli::Cli::parse();
+    // Recent lines are also kept in memory for the admin client
+    server::logs::init(simple_logger::SimpleLogger::new().with_level(if cli.json {
+        // Keep JSON output clean for scripts
+        log::LevelFilter::Warn
+    } else {
+        log::LevelFilter::Info
+    }))
+    .unwrap();
+
+    // Fail early with a readable message if the config file or data dirs can't be used
+    let config = server::config::load().and_then(|()| {
+        let config = server::paths::ServerConfig::from_env();
+        config.validate()?;
+        Ok(config)
+    });
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    // Run a headless command instead of serving
+    if let Some(command) = cli.command {
+        let result = async {
+            // The server rewrites the database whenever it likes, so only reading is safe alongside it
+            let _lock = if command.is_read_only() {
+                None
+            } else {
+                Some(server::paths::DataLock::acquire(&config)?)
+            };
+            let state = server::AppState::load(config).await?;
+            server::cli::run(&state, command, cli.json).await
+        }
+        .await;
+        if let Err(e) = result {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Held until exit so commands that change the data refuse to run alongside the server
+    let _data_lock = match server::paths::DataLock::acquire(&config) {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    // Fall back to a backup before anything reads a corrupt database
+    if let Err(e) = server::backups::recover_database(&config).await {
+        log::error!("{:?}", e);
+    }
+    let state = match server::AppState::load(config).await {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+    };
 
-    // Make data dir if it doesn't exist
-    std::fs::create_dir_all(WALLPAPERS_DIR).unwrap();
+    // Changes are kept in memory and written out in the background, and once more before exiting
+    state.store.spawn_persistence();
+    let store = state.store.clone();
+    tokio::spawn(async move {
+        server::listen::shutdown_signal().await;
+        if let Err(e) = store.flush().await {
+            log::error!("Failed to write database on shutdown {:?}", e);
+        }
+        std::process::exit(0);
+    });
 
     // Set up router
     let app = server::routing::setup_routes(
         axum::Router::new()
-            .nest_service("/", tower_http::services::ServeDir::new("dist"))
+            .fallback_service(tower_http::services::ServeDir::new("dist"))
             .nest_service(
                 "/wallpapers",
-                tower_http::services::ServeDir::new(WALLPAPERS_DIR),
+                server::caching::wallpapers_service(&state.config.wallpapers_dir),
             )
             .layer(tower_http::compression::CompressionLayer::new()),
+        state.clone(),
     );
 
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], PORT));
-    println!("Listening on {addr}");
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-
+    let scheduler_state = state.clone();
     tokio::spawn(async move {
-        Box::pin(server::routing::start_server()).await;
+        Box::pin(server::routing::start_server(scheduler_state)).await;
     });
 
     #[cfg(not(feature = "gui"))]
-    axum::serve(listener, app).await.unwrap();
+    if let Err(e) = server::listen::serve(app).await {
+        eprintln!("Error: {e:#}");
+        std::process::exit(1);
+    }
 
     #[cfg(feature = "gui")]
     {
         tokio::spawn(async move {
-            axum::serve(listener, app).await.unwrap();
+            if let Err(e) = server::listen::serve(app).await {
+                log::error!("Server stopped {:?}", e);
+            }
         });
         let native_options = eframe::NativeOptions {
             viewport: egui::ViewportBuilder::default()
@@ -76,6 +148,9 @@ async fn main() {
             native_options,
             Box::new(|cc| Ok(Box::new(client::app::Wallpapy::new(cc)))),
         );
+        if let Err(e) = state.store.flush().await {
+            log::error!("Failed to write database on exit {:?}", e);
+        }
     }
 }
 
@@ -1,14 +1,17 @@
-use crate::common::LoginPacket;
-use anyhow::{anyhow, Result};
+use crate::common::{AuditAction, ChangePasswordPacket, LoginPacket, TokenPacket};
+use crate::server::{audit, error_status, AppState};
+use anyhow::{anyhow, bail, Result};
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use axum::{body::Bytes, http::StatusCode, response::IntoResponse};
-use chrono::{DateTime, Utc};
+use axum::{body::Bytes, extract::State, http::StatusCode, response::IntoResponse};
+use blake2::{Blake2s256, Digest};
+use chrono::{DateTime, TimeDelta, Utc};
 use rand::{distributions, thread_rng, Rng};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, path::PathBuf};
+use subtle::ConstantTimeEq;
 use tokio::{
     fs::{self, OpenOptions},
     io::AsyncReadExt,
@@ -17,7 +20,8 @@ use uuid::Uuid;
 
 const MIN_PASSWORD_LENGTH: usize = 6;
 const TOKEN_LENGTH: usize = 20;
-const AUTH_FILE: &str = "data/auth.ron";
+/// A token's `last_used` is only rewritten once it's this stale, so checking a token rarely writes
+const LAST_USED_PRECISION: TimeDelta = TimeDelta::minutes(10);
 
 #[derive(Serialize, Deserialize)]
 struct Account {
@@ -26,19 +30,40 @@ struct Account {
     username: String,
     password_hash: String,
     tokens: Vec<Token>,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>, // Unknown for accounts made before this was recorded
 }
 
+/// The parts of an account that are safe to hand out, without the password hash or tokens
+#[derive(Serialize)]
+pub struct AccountInfo {
+    pub uuid: Uuid,
+    pub username: String,
+    pub admin: bool,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Only a hash is stored, tokens are long and random so a fast unsalted hash is enough
 #[derive(Serialize, Deserialize)]
 struct Token {
-    token: String,
+    #[serde(alias = "token")]
+    hash: String, // Files from before hashing hold the plain token here until migrated
+    #[serde(default)]
+    hashed: bool,
     last_used: DateTime<Utc>,
 }
 
+impl Token {
+    fn matches(&self, input_hash: &str) -> bool {
+        self.hash.as_bytes().ct_eq(input_hash.as_bytes()).into()
+    }
+}
+
 type Accounts = HashMap<Uuid, Account>;
 
-pub async fn login_server(packet: Bytes) -> impl IntoResponse {
+pub async fn login_server(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
     match bincode::deserialize::<LoginPacket>(&packet) {
-        Ok(packet) => match login_impl(&packet).await {
+        Ok(packet) => match login_impl(&state, &packet).await {
             Ok(token) => (StatusCode::OK, token),
             Err(e) => {
                 log::error!("Failed to login: {:?}", e);
@@ -52,114 +77,256 @@ pub async fn login_server(packet: Bytes) -> impl IntoResponse {
     }
 }
 
-async fn read_accounts() -> Result<Accounts> {
-    if fs::metadata(AUTH_FILE).await.is_err() {
-        return Ok(HashMap::new());
+/// Revoke the presented token, other sessions on the same account stay logged in
+pub async fn logout(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialise logout packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    match revoke_token(&state, &packet.token).await {
+        Ok(true) => StatusCode::OK,
+        Ok(false) => StatusCode::UNAUTHORIZED,
+        Err(e) => {
+            log::error!("Errored logout {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
     }
-
-    let mut file = OpenOptions::new().read(true).open(AUTH_FILE).await?;
-    let mut data = String::new();
-    file.read_to_string(&mut data).await?;
-    let accounts: Accounts = ron::from_str(&data)?;
-    Ok(accounts)
 }
 
-async fn write_accounts(accounts: &Accounts) -> Result<()> {
-    let pretty = ron::ser::PrettyConfig::new().compact_arrays(true);
-    let data = ron::ser::to_string_pretty(accounts, pretty)?;
-    fs::write(AUTH_FILE, data).await?;
-    Ok(())
+/// A password change turned down because of what was typed, rather than a failure on the server
+#[derive(Debug)]
+enum PasswordRejected {
+    Incorrect,
+    Unchanged,
 }
 
-/// Login to account, returning a token
-/// If no password is set, it will set the password
-/// If no accounts exist, it will create an admin account
-async fn login_impl(packet: &LoginPacket) -> Result<String> {
-    let mut accounts = read_accounts().await.unwrap_or_default();
-
-    // Create initial admin account if no accounts exist
-    if accounts.is_empty() {
-        if packet.password.len() < MIN_PASSWORD_LENGTH {
-            return Err(anyhow!(
-                "Password must be at least {} characters long",
-                MIN_PASSWORD_LENGTH
-            ));
+impl fmt::Display for PasswordRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Incorrect => write!(f, "Current password is incorrect"),
+            Self::Unchanged => write!(f, "New password must be different from the current one"),
         }
+    }
+}
 
-        // Hash the password
-        let password_hash = Argon2::default()
-            .hash_password(
-                packet.password.as_bytes(),
-                &SaltString::generate(&mut OsRng),
+impl std::error::Error for PasswordRejected {}
+
+/// Change the password of the account holding the token, its other sessions are logged out
+pub async fn change_password(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: ChangePasswordPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialise change_password packet: {:?}", e);
+            return (StatusCode::BAD_REQUEST, String::new());
+        }
+    };
+    match change_password_impl(&state, &packet).await {
+        Ok(true) => {
+            let summary = "Changed password".to_string();
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::ChangePassword,
+                None,
+                summary,
             )
-            .map_err(|_| anyhow!("Failed to hash password"))?
-            .to_string();
+            .await;
+            (StatusCode::OK, String::new())
+        }
+        Ok(false) => (StatusCode::UNAUTHORIZED, String::new()),
+        Err(e) => match e.downcast_ref::<PasswordRejected>() {
+            Some(PasswordRejected::Incorrect) => (StatusCode::FORBIDDEN, e.to_string()),
+            Some(PasswordRejected::Unchanged) => (StatusCode::BAD_REQUEST, e.to_string()),
+            None => {
+                log::error!("Failed to change password: {:?}", e);
+                (error_status(&e), String::new())
+            }
+        },
+    }
+}
 
-        // Create a new admin account
-        let (token_entry, token) = generate_token();
-        let new_account = Account {
-            admin: true,
-            uuid: Uuid::new_v4(),
-            username: packet.username.clone(),
-            password_hash,
-            tokens: vec![token_entry],
+/// Returns false when no account holds the token
+async fn change_password_impl(state: &AppState, packet: &ChangePasswordPacket) -> Result<bool> {
+    let input_hash = hash_token(&packet.token);
+    with_accounts(state, |accounts| {
+        let Some(account) = accounts.values_mut().find(|account| {
+            account
+                .tokens
+                .iter()
+                .any(|token| token.matches(&input_hash))
+        }) else {
+            return Ok(false);
         };
 
-        // Serialize and save the admin account to the database
-        accounts.insert(new_account.uuid, new_account);
-        write_accounts(&accounts).await?;
+        let parsed_hash =
+            PasswordHash::new(&account.password_hash).map_err(|_| PasswordRejected::Incorrect)?;
+        if Argon2::default()
+            .verify_password(packet.old_password.as_bytes(), &parsed_hash)
+            .is_err()
+        {
+            return Err(PasswordRejected::Incorrect.into());
+        }
+        if packet.new_password == packet.old_password {
+            return Err(PasswordRejected::Unchanged.into());
+        }
+
+        account.password_hash = hash_password(&packet.new_password)?;
+        account.tokens.retain(|token| token.matches(&input_hash));
+        Ok(true)
+    })
+    .await
+}
+
+fn auth_file(state: &AppState) -> PathBuf {
+    state.config.data_dir.join("auth.ron")
+}
+
+/// Accounts for reading only, anything written back goes through `with_accounts`
+async fn read_accounts(state: &AppState) -> Result<Accounts> {
+    let (accounts, migrated) = parse_accounts(state).await?;
+    if migrated {
+        // Save the hashes so plain tokens don't stay on disk
+        with_accounts(state, |_| Ok(())).await?;
+    }
+    Ok(accounts)
+}
+
+/// Read, modify and write the accounts while holding the auth lock so concurrent changes aren't lost
+async fn with_accounts<T>(
+    state: &AppState,
+    modify: impl FnOnce(&mut Accounts) -> Result<T>,
+) -> Result<T> {
+    let _lock = state.auth_lock.lock().await;
+    let (mut accounts, _) = parse_accounts(state).await?;
+    let value = modify(&mut accounts)?;
+    write_accounts(state, &accounts).await?;
+    Ok(value)
+}
 
-        return Ok(format!("Admin Account Created|{token}"));
+/// The accounts in auth.ron with any plain tokens hashed, and whether there were any
+async fn parse_accounts(state: &AppState) -> Result<(Accounts, bool)> {
+    let auth_file = auth_file(state);
+    if fs::metadata(&auth_file).await.is_err() {
+        return Ok((HashMap::new(), false));
     }
 
-    // Retrieve account data using username as the key
-    let account = accounts
+    let mut file = OpenOptions::new().read(true).open(&auth_file).await?;
+    let mut data = String::new();
+    file.read_to_string(&mut data).await?;
+    let mut accounts: Accounts = ron::from_str(&data)?;
+    let migrated = migrate_tokens(&mut accounts);
+    Ok((accounts, migrated))
+}
+
+/// Hash any tokens still stored in plain text, returning whether anything changed
+fn migrate_tokens(accounts: &mut Accounts) -> bool {
+    let mut migrated = false;
+    for token in accounts
         .values_mut()
-        .find(|acc| acc.username == packet.username);
-    if let Some(account) = account {
-        if account.password_hash.is_empty() {
-            // This is a new account setup case
-            if packet.password.len() < MIN_PASSWORD_LENGTH {
-                return Err(anyhow!("Password must be at least 6 characters long"));
-            }
+        .flat_map(|account| account.tokens.iter_mut())
+        .filter(|token| !token.hashed)
+    {
+        token.hash = hash_token(&token.hash);
+        token.hashed = true;
+        migrated = true;
+    }
+    migrated
+}
 
-            // Hash the new password
-            let password_hash = Argon2::default()
-                .hash_password(
-                    packet.password.as_bytes(),
-                    &SaltString::generate(&mut OsRng),
-                )
-                .map_err(|_| anyhow!("Failed to hash password"))?
-                .to_string();
+fn hash_token(token: &str) -> String {
+    Blake2s256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
 
-            // Update the account with the new password and add a token
+async fn write_accounts(state: &AppState, accounts: &Accounts) -> Result<()> {
+    let pretty = ron::ser::PrettyConfig::new().compact_arrays(true);
+    let data = ron::ser::to_string_pretty(accounts, pretty)?;
+    // Write beside the file then rename over it, so a reader never sees it half written
+    let auth_file = auth_file(state);
+    let temp_file = auth_file.with_extension("ron.tmp");
+    fs::write(&temp_file, data).await?;
+    fs::rename(&temp_file, &auth_file).await?;
+    Ok(())
+}
+
+/// Login to account, returning a token
+/// If no password is set, it will set the password
+/// If no accounts exist, it will create an admin account
+async fn login_impl(state: &AppState, packet: &LoginPacket) -> Result<String> {
+    with_accounts(state, |accounts| {
+        // Create initial admin account if no accounts exist
+        if accounts.is_empty() {
+            let password_hash = hash_password(&packet.password)?;
+
+            // Create a new admin account
             let (token_entry, token) = generate_token();
-            account.tokens.push(token_entry);
-            account.password_hash = password_hash;
+            let new_account = Account {
+                admin: true,
+                uuid: Uuid::new_v4(),
+                username: packet.username.clone(),
+                password_hash,
+                tokens: vec![token_entry],
+                created_at: Some(Utc::now()),
+            };
 
-            write_accounts(&accounts).await?;
+            // Saved once the closure returns
+            accounts.insert(new_account.uuid, new_account);
 
-            return Ok(format!("Admin Set|{token}"));
+            return Ok(format!("Admin Account Created|{token}"));
         }
 
-        // Verify password for an existing account
-        let parsed_hash = PasswordHash::new(&account.password_hash)
-            .map_err(|_| anyhow!("Incorrect username or password"))?;
+        // Retrieve account data using username as the key
+        let account = accounts
+            .values_mut()
+            .find(|acc| acc.username == packet.username);
+        if let Some(account) = account {
+            if account.password_hash.is_empty() {
+                // This is a new account setup case
+                let password_hash = hash_password(&packet.password)?;
 
-        if Argon2::default()
-            .verify_password(packet.password.as_bytes(), &parsed_hash)
-            .is_ok()
-        {
-            let (token_entry, token) = generate_token();
-            account.tokens.push(token_entry);
-            write_accounts(&accounts).await?;
-            return Ok(token);
+                // Update the account with the new password and add a token
+                let (token_entry, token) = generate_token();
+                account.tokens.push(token_entry);
+                account.password_hash = password_hash;
+
+                return Ok(format!("Admin Set|{token}"));
+            }
+
+            // Verify password for an existing account
+            let parsed_hash = PasswordHash::new(&account.password_hash)
+                .map_err(|_| anyhow!("Incorrect username or password"))?;
+
+            if Argon2::default()
+                .verify_password(packet.password.as_bytes(), &parsed_hash)
+                .is_ok()
+            {
+                let (token_entry, token) = generate_token();
+                account.tokens.push(token_entry);
+                return Ok(token);
+            }
         }
+        Err(anyhow!("Incorrect username or password"))
+    })
+    .await
+}
+
+/// Check a new password is long enough and hash it for storing
+fn hash_password(password: &str) -> Result<String> {
+    if password.len() < MIN_PASSWORD_LENGTH {
+        bail!("Password must be at least {MIN_PASSWORD_LENGTH} characters long");
     }
-    Err(anyhow!("Incorrect username or password"))
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+        .map_err(|_| anyhow!("Failed to hash password"))?
+        .to_string())
 }
 
-/// Helper function to generate a random token
+/// Helper function to generate a random token, returning the entry to store and the token to hand out
 fn generate_token() -> (Token, String) {
     let new_token: String = thread_rng()
         .sample_iter(&distributions::Alphanumeric)
@@ -167,27 +334,158 @@ fn generate_token() -> (Token, String) {
         .map(char::from)
         .collect();
     let token = Token {
-        token: new_token.clone(),
+        hash: hash_token(&new_token),
+        hashed: true,
         last_used: Utc::now(),
     };
     (token, new_token)
 }
 
-/// Verify tokens, updating the `last_used`
-pub async fn verify_token(input_token: &str) -> Result<bool> {
-    let mut accounts = read_accounts().await?;
+/// Find the account uuid and admin flag that owns a token
+pub async fn token_account(state: &AppState, input_token: &str) -> Result<Option<(Uuid, bool)>> {
+    let input_hash = hash_token(input_token);
+    let accounts = read_accounts(state).await?;
+    Ok(accounts
+        .values()
+        .find(|account| {
+            account
+                .tokens
+                .iter()
+                .any(|token| token.matches(&input_hash))
+        })
+        .map(|account| (account.uuid, account.admin)))
+}
 
-    for account in accounts.values_mut() {
-        if let Some(token_entry) = account
+/// Every account, without the password hashes or tokens
+pub async fn list_accounts(state: &AppState) -> Result<Vec<AccountInfo>> {
+    let accounts = read_accounts(state).await?;
+    Ok(accounts
+        .values()
+        .map(|account| AccountInfo {
+            uuid: account.uuid,
+            username: account.username.clone(),
+            admin: account.admin,
+            created_at: account.created_at,
+        })
+        .collect())
+}
+
+/// The account holding the token, if the password is also right
+pub async fn check_password(
+    state: &AppState,
+    input_token: &str,
+    password: &str,
+) -> Result<Option<Uuid>> {
+    let input_hash = hash_token(input_token);
+    let accounts = read_accounts(state).await?;
+    let Some(account) = accounts.values().find(|account| {
+        account
             .tokens
-            .iter_mut()
-            .find(|token| token.token == input_token)
-        {
-            token_entry.last_used = Utc::now();
-            write_accounts(&accounts).await?;
-            return Ok(true);
-        }
+            .iter()
+            .any(|token| token.matches(&input_hash))
+    }) else {
+        return Ok(None);
+    };
+    let Ok(parsed_hash) = PasswordHash::new(&account.password_hash) else {
+        return Ok(None);
+    };
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok()
+        .then_some(account.uuid))
+}
+
+/// Look up an account by uuid
+pub async fn account_info(state: &AppState, uuid: Uuid) -> Result<Option<AccountInfo>> {
+    let accounts = read_accounts(state).await?;
+    Ok(accounts.get(&uuid).map(|account| AccountInfo {
+        uuid: account.uuid,
+        username: account.username.clone(),
+        admin: account.admin,
+        created_at: account.created_at,
+    }))
+}
+
+/// Delete an account along with all its tokens, returning whether it existed
+pub async fn remove_account(state: &AppState, uuid: Uuid) -> Result<bool> {
+    with_accounts(state, |accounts| Ok(accounts.remove(&uuid).is_some())).await
+}
+
+/// Verify tokens, updating the `last_used` once it's older than `LAST_USED_PRECISION`
+pub async fn verify_token(state: &AppState, input_token: &str) -> Result<bool> {
+    let input_hash = hash_token(input_token);
+    let accounts = read_accounts(state).await?;
+    let Some(last_used) = accounts
+        .values()
+        .flat_map(|account| &account.tokens)
+        .find(|token| token.matches(&input_hash))
+        .map(|token| token.last_used)
+    else {
+        return Ok(false);
+    };
+    if Utc::now() - last_used < LAST_USED_PRECISION {
+        return Ok(true);
     }
 
-    Ok(false)
+    // Checked again under the lock, the token may have been revoked since
+    with_accounts(state, |accounts| {
+        let token = accounts
+            .values_mut()
+            .flat_map(|account| account.tokens.iter_mut())
+            .find(|token| token.matches(&input_hash));
+        Ok(token.map(|token| token.last_used = Utc::now()).is_some())
+    })
+    .await
+}
+
+/// Remove a token from whichever account holds it, returning whether it was found
+async fn revoke_token(state: &AppState, input_token: &str) -> Result<bool> {
+    let input_hash = hash_token(input_token);
+    with_accounts(state, |accounts| {
+        for account in accounts.values_mut() {
+            if let Some(index) = account
+                .tokens
+                .iter()
+                .position(|token| token.matches(&input_hash))
+            {
+                account.tokens.remove(index);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_plain_tokens_once() {
+        let (new_token, token) = generate_token();
+        let account = Account {
+            admin: false,
+            uuid: Uuid::new_v4(),
+            username: "user".to_string(),
+            password_hash: String::new(),
+            tokens: vec![
+                Token {
+                    hash: "plaintexttoken".to_string(),
+                    hashed: false,
+                    last_used: Utc::now(),
+                },
+                new_token,
+            ],
+            created_at: None,
+        };
+        let mut accounts = HashMap::from([(account.uuid, account)]);
+
+        assert!(migrate_tokens(&mut accounts));
+        assert!(!migrate_tokens(&mut accounts));
+        let tokens = &accounts.values().next().unwrap().tokens;
+        assert!(tokens[0].matches(&hash_token("plaintexttoken")));
+        assert!(!tokens[0].matches("plaintexttoken"));
+        assert!(tokens[1].matches(&hash_token(&token)));
+    }
 }
@@ -0,0 +1,274 @@
+use crate::common::{AuditAction, Database, TokenStringPacket};
+use crate::server::{
+    audit, auth::token_account, config, database_file, error_status, paths::ServerConfig, AppState,
+};
+use anyhow::{anyhow, Result};
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::{NaiveDateTime, Utc};
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::fs;
+
+const DEFAULT_BACKUP_COUNT: usize = 5;
+const DEFAULT_BACKUP_INTERVAL_MINS: u64 = 60;
+const TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S%3f";
+
+#[derive(Deserialize)]
+pub struct BackupsQuery {
+    token: String,
+}
+
+/// List the database backups newest first, only available to admins
+pub async fn list(
+    State(state): State<AppState>,
+    Query(query): Query<BackupsQuery>,
+) -> impl IntoResponse {
+    match token_account(&state, &query.token).await {
+        Ok(Some((_, true))) => {}
+        Ok(_) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    match list_backups(state.store.file()).await {
+        Ok(backups) => {
+            let file_names = backups
+                .iter()
+                .filter_map(|backup| backup.file_name())
+                .map(|file_name| file_name.to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            match bincode::serialize(&file_names) {
+                Ok(data) => (StatusCode::OK, data).into_response(),
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Copy a backup, named by the packet string, over the database, only available to admins
+pub async fn restore(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenStringPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize restore packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    match token_account(&state, &packet.token).await {
+        Ok(Some((_, true))) => {}
+        Ok(_) => return StatusCode::UNAUTHORIZED,
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+
+    match restore_impl(&state, &packet.string).await {
+        Ok(()) => {
+            let summary = format!("Restored {}", packet.string);
+            audit(&state, &packet.token, AuditAction::Restore, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored restore {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+async fn restore_impl(state: &AppState, file_name: &str) -> Result<()> {
+    let database_file = state.store.file();
+
+    // Only accept the name of an existing backup so the request can't reach outside the data dir
+    let backup = list_backups(database_file)
+        .await?
+        .into_iter()
+        .find(|backup| backup.file_name().is_some_and(|name| name == file_name))
+        .ok_or_else(|| anyhow!("Backup not found"))?;
+
+    let _lock = state.lock_database().await?;
+
+    // Read it before backing up the current database, which may rotate this backup away
+    let data = fs::read_to_string(&backup).await?;
+    ron::from_str::<Database>(&data)?;
+    // Always keep the state being rolled back from, however recent the last backup
+    state.store.flush().await?;
+    backup_file(database_file, backup_count(), Duration::ZERO).await?;
+    fs::write(database_file, data).await?;
+    state.store.reload().await
+}
+
+fn backup_count() -> usize {
+    config::var("DB_BACKUP_COUNT")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_BACKUP_COUNT)
+}
+
+/// Copy the database aside before it is overwritten, keeping the newest `DB_BACKUP_COUNT` backups
+/// Only one backup is taken per `DB_BACKUP_INTERVAL_MINS` so frequent writes don't churn them
+pub async fn backup_database(database_file: &Path) -> Result<()> {
+    let interval_mins = config::var("DB_BACKUP_INTERVAL_MINS")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_BACKUP_INTERVAL_MINS);
+    backup_file(
+        database_file,
+        backup_count(),
+        Duration::from_secs(interval_mins * 60),
+    )
+    .await
+}
+
+/// If the database doesn't parse, replace it with the newest backup that does
+/// The broken file is kept beside it as `{file}.corrupt.{timestamp}`
+pub async fn recover_database(config: &ServerConfig) -> Result<()> {
+    let database_file = database_file(config);
+    let database_file = database_file.as_path();
+    let Ok(data) = fs::read_to_string(database_file).await else {
+        return Ok(());
+    };
+    let Err(parse_error) = ron::from_str::<Database>(&data) else {
+        return Ok(());
+    };
+    log::error!(
+        "DATABASE IS CORRUPT, failed to parse {}: {parse_error}",
+        database_file.display()
+    );
+
+    for backup in list_backups(database_file).await? {
+        let Ok(data) = fs::read_to_string(&backup).await else {
+            continue;
+        };
+        if ron::from_str::<Database>(&data).is_err() {
+            log::error!("Backup {} is also corrupt, skipping", backup.display());
+            continue;
+        }
+
+        let mut corrupt = database_file.as_os_str().to_owned();
+        corrupt.push(format!(".corrupt.{}", Utc::now().format(TIMESTAMP_FORMAT)));
+        fs::rename(database_file, &corrupt).await?;
+        fs::write(database_file, data).await?;
+        log::error!(
+            "RESTORED DATABASE from backup {}, changes since then are lost, the corrupt file was moved to {}",
+            backup.display(),
+            Path::new(&corrupt).display()
+        );
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Database is corrupt and no usable backup was found"
+    ))
+}
+
+/// Copy a file to `{file}.bak.{timestamp}` and delete all but the newest `keep` backups, 0 disables backups
+/// Does nothing if the newest backup is younger than `min_interval`
+async fn backup_file(path: &Path, keep: usize, min_interval: Duration) -> Result<()> {
+    if keep == 0 || fs::metadata(path).await.is_err() {
+        return Ok(());
+    }
+    let newest = list_backups(path)
+        .await?
+        .first()
+        .and_then(|backup| backup_time(backup));
+    if newest.is_some_and(|newest| {
+        (Utc::now().naive_utc() - newest)
+            .to_std()
+            .is_ok_and(|age| age < min_interval)
+    }) {
+        return Ok(());
+    }
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".bak.{}", Utc::now().format(TIMESTAMP_FORMAT)));
+    fs::copy(path, &backup).await?;
+
+    for old_backup in list_backups(path).await?.into_iter().skip(keep) {
+        fs::remove_file(old_backup).await?;
+    }
+    Ok(())
+}
+
+/// When a backup was taken, read from the timestamp in its name
+fn backup_time(backup: &Path) -> Option<NaiveDateTime> {
+    let name = backup.file_name()?.to_string_lossy();
+    let (_, timestamp) = name.rsplit_once(".bak.")?;
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()
+}
+
+/// Backups of a file, newest first
+async fn list_backups(path: &Path) -> Result<Vec<PathBuf>> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Path has no file name"))?;
+    let prefix = format!("{}.bak.", file_name.to_string_lossy());
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    let mut backups = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            backups.push(entry.path());
+        }
+    }
+    // Timestamps are fixed width so they sort chronologically
+    backups.sort_unstable();
+    backups.reverse();
+    Ok(backups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_only_the_newest_backups() {
+        let dir =
+            std::env::temp_dir().join(format!("wallpapy-backup-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("database.ron");
+
+        // Nothing to back up yet
+        backup_file(&path, 2, Duration::ZERO).await.unwrap();
+        assert!(list_backups(&path).await.unwrap().is_empty());
+
+        for version in 0..4 {
+            std::fs::write(&path, format!("version {version}")).unwrap();
+            backup_file(&path, 2, Duration::ZERO).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        let backups = list_backups(&path).await.unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(std::fs::read_to_string(&backups[0]).unwrap(), "version 3");
+        assert_eq!(std::fs::read_to_string(&backups[1]).unwrap(), "version 2");
+        assert!(backup_time(&backups[0]).unwrap() > backup_time(&backups[1]).unwrap());
+
+        // A recent backup holds off the next one until the interval has passed
+        std::fs::write(&path, "version 4").unwrap();
+        backup_file(&path, 2, Duration::from_secs(3600))
+            .await
+            .unwrap();
+        let backups = list_backups(&path).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&backups[0]).unwrap(), "version 3");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
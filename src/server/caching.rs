@@ -0,0 +1,181 @@
+use crate::server::encoding::accepts_avif;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use std::path::{Path, PathBuf};
+use tower_http::services::ServeDir;
+
+/// Wallpaper files are never rewritten, edits are saved under a new file name
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Serve the wallpaper files with long lived cache headers and file name based `ETags`
+/// Webp requests get the AVIF copy instead when there is one and the client accepts it
+pub fn wallpapers_service(dir: impl AsRef<Path>) -> Router {
+    let dir = dir.as_ref().to_path_buf();
+    Router::new()
+        .fallback_service(ServeDir::new(&dir))
+        .layer(middleware::from_fn_with_state(dir, cache_headers))
+}
+
+async fn cache_headers(State(dir): State<PathBuf>, mut request: Request, next: Next) -> Response {
+    // Wallpaper files sit directly in the dir, subdirectories like the generation contexts aren't public
+    let path = request.uri().path().trim_start_matches('/').to_lowercase();
+    if path.contains('/') || path.contains("%2f") {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let negotiable = request.uri().path().ends_with(".webp");
+    if negotiable {
+        if let Some(uri) = avif_uri(&dir, &request) {
+            *request.uri_mut() = uri;
+        }
+    }
+
+    let Ok(etag) = HeaderValue::from_str(&format!(
+        "\"{}\"",
+        request.uri().path().trim_start_matches('/')
+    )) else {
+        return next.run(request).await;
+    };
+
+    let not_modified = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .any(|tag| tag.trim() == "*" || tag.trim().trim_start_matches("W/") == etag)
+        });
+    let mut response = if not_modified {
+        (
+            StatusCode::NOT_MODIFIED,
+            [
+                (header::ETAG, etag),
+                (
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_static(CACHE_CONTROL),
+                ),
+            ],
+        )
+            .into_response()
+    } else {
+        let mut response = next.run(request).await;
+        if response.status() == StatusCode::OK {
+            let headers = response.headers_mut();
+            headers.insert(header::ETAG, etag);
+            headers.insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static(CACHE_CONTROL),
+            );
+        }
+        response
+    };
+    if negotiable {
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept"));
+    }
+    response
+}
+
+/// The uri of the AVIF copy of the requested file, if the client accepts AVIF and the copy exists
+fn avif_uri(dir: &Path, request: &Request) -> Option<Uri> {
+    let accept = request.headers().get(header::ACCEPT)?.to_str().ok()?;
+    if !accepts_avif(accept) {
+        return None;
+    }
+    let path = Path::new(request.uri().path()).with_extension("avif");
+    let path = path.to_str()?;
+    if !dir.join(path.trim_start_matches('/')).is_file() {
+        return None;
+    }
+    path.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn second_thumbnail_request_is_not_modified() {
+        let dir =
+            std::env::temp_dir().join(format!("wallpapy-cache-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("thumb.webp"), b"image data").unwrap();
+
+        let app = Router::new().nest_service("/wallpapers", wallpapers_service(&dir));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{addr}/wallpapers/thumb.webp");
+        let first = client.get(&url).send().await.unwrap();
+        assert_eq!(first.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            first.headers()[reqwest::header::CACHE_CONTROL],
+            CACHE_CONTROL
+        );
+        let etag = first.headers()[reqwest::header::ETAG].clone();
+        assert_eq!(etag, "\"thumb.webp\"");
+
+        let second = client
+            .get(&url)
+            .header(reqwest::header::IF_NONE_MATCH, etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(second.status(), reqwest::StatusCode::NOT_MODIFIED);
+
+        std::fs::create_dir_all(dir.join("context")).unwrap();
+        std::fs::write(dir.join("context/id.txt.zst"), b"context").unwrap();
+        for path in ["context/id.txt.zst", "context%2Fid.txt.zst"] {
+            let hidden = client
+                .get(format!("http://{addr}/wallpapers/{path}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(hidden.status(), reqwest::StatusCode::NOT_FOUND);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_avif_when_accepted() {
+        let dir = std::env::temp_dir().join(format!("wallpapy-avif-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("image.webp"), b"webp data").unwrap();
+        std::fs::write(dir.join("image.avif"), b"avif data").unwrap();
+
+        let app = Router::new().nest_service("/wallpapers", wallpapers_service(&dir));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{addr}/wallpapers/image.webp");
+        let avif = client
+            .get(&url)
+            .header(reqwest::header::ACCEPT, "image/avif,image/webp")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(avif.headers()[reqwest::header::ETAG], "\"image.avif\"");
+        assert_eq!(avif.bytes().await.unwrap(), "avif data");
+
+        let webp = client.get(&url).send().await.unwrap();
+        assert_eq!(webp.headers()[reqwest::header::VARY], "accept");
+        assert_eq!(webp.bytes().await.unwrap(), "webp data");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+use crate::common::{GenerationMode, WallpaperData};
+use crate::server::{image, importing, resolution::ResolutionPreset, routing, AppState};
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "AI wallpaper generator")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Print JSON instead of plain text
+    #[arg(long, global = true)]
+    pub json: bool,
+}
+
+/// Headless commands that work on the data directory directly, without starting the server
+#[derive(Subcommand)]
+pub enum Command {
+    /// Generate a new wallpaper
+    Generate {
+        /// Message to guide the prompt, optionally starting with [PRESET:name]
+        #[arg(long)]
+        message: Option<String>,
+        /// Ignore history and style for a random pick
+        #[arg(long, conflicts_with = "strict_style")]
+        wild: bool,
+        /// Stay very close to the loved prompts
+        #[arg(long)]
+        strict_style: bool,
+    },
+    /// Show the latest wallpaper
+    Latest {
+        /// Copy the image file to this path
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Import a folder of existing images, rerun to resume as already imported files are skipped
+    ImportDir {
+        path: PathBuf,
+        /// Describe each image with the LLM so the prompt history has useful text
+        #[arg(long)]
+        caption: bool,
+        /// Mark the imported images as liked
+        #[arg(long)]
+        liked: bool,
+    },
+    /// Delete wallpapers past their retention period
+    Prune {
+        /// List what would be deleted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+impl Command {
+    /// Commands that only read can run alongside the server, the rest take the data dir lock first
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Self::Latest { .. } => true,
+            Self::Prune { dry_run } => *dry_run,
+            Self::Generate { .. } | Self::ImportDir { .. } => false,
+        }
+    }
+}
+
+pub async fn run(state: &AppState, command: Command, json: bool) -> Result<()> {
+    match command {
+        Command::Generate {
+            message,
+            wild,
+            strict_style,
+        } => {
+            let mode = if wild {
+                GenerationMode::Wild
+            } else if strict_style {
+                GenerationMode::StrictStyle
+            } else {
+                GenerationMode::Normal
+            };
+            let (preset, message) = message.as_deref().map_or((None, None), |message| {
+                let (preset, rest) = ResolutionPreset::strip_prefix(message);
+                (
+                    preset,
+                    Some(rest.to_string()).filter(|rest| !rest.is_empty()),
+                )
+            });
+            image::generate_wallpaper_impl(
+                state,
+                None,
+                message,
+                mode,
+                preset.unwrap_or_else(ResolutionPreset::from_env),
+                None,
+            )
+            .await?;
+            let wallpaper = latest_wallpaper(state).await?;
+            print_wallpaper(state, &wallpaper, json);
+        }
+        Command::Latest { out } => {
+            let wallpaper = latest_wallpaper(state).await?;
+            if let Some(out) = out {
+                let file = wallpaper
+                    .upscaled_file
+                    .as_ref()
+                    .unwrap_or(&wallpaper.original_file);
+                tokio::fs::copy(state.config.wallpapers_dir.join(&file.file_name), &out).await?;
+            }
+            print_wallpaper(state, &wallpaper, json);
+        }
+        Command::ImportDir {
+            path,
+            caption,
+            liked,
+        } => {
+            let summary = importing::import_dir(state, &path, caption, liked).await?;
+            if json {
+                println!("{}", serde_json::to_string(&summary)?);
+            } else {
+                for failure in &summary.failed {
+                    println!("Failed {}: {}", failure.path, failure.error);
+                }
+                println!(
+                    "Imported {}, skipped {} already imported, {} failed",
+                    summary.imported,
+                    summary.skipped,
+                    summary.failed.len()
+                );
+            }
+        }
+        Command::Prune { dry_run } => {
+            let policy = routing::retention_policy();
+            let pruned = if dry_run {
+                image::expired_wallpapers(state, policy).await?
+            } else {
+                image::remove_expired_wallpapers(state, policy).await?
+            };
+            if json {
+                let ids = pruned
+                    .iter()
+                    .map(|wallpaper| wallpaper.id)
+                    .collect::<Vec<_>>();
+                println!("{}", json!({ "dry_run": dry_run, "pruned": ids }));
+            } else {
+                for wallpaper in &pruned {
+                    println!(
+                        "{} {} '{}'",
+                        wallpaper.id,
+                        wallpaper.datetime.format("%d/%m/%Y %H:%M"),
+                        wallpaper.prompt_data.shortened_prompt
+                    );
+                }
+                println!(
+                    "{} {} wallpapers",
+                    if dry_run { "Would delete" } else { "Deleted" },
+                    pruned.len()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn latest_wallpaper(state: &AppState) -> Result<WallpaperData> {
+    state
+        .read_database()
+        .await
+        .wallpapers
+        .into_values()
+        .max_by_key(|wallpaper| wallpaper.datetime)
+        .ok_or_else(|| anyhow!("No wallpapers found"))
+}
+
+fn print_wallpaper(state: &AppState, wallpaper: &WallpaperData, json: bool) {
+    let file = wallpaper
+        .upscaled_file
+        .as_ref()
+        .unwrap_or(&wallpaper.original_file);
+    if json {
+        println!(
+            "{}",
+            json!({
+                "id": wallpaper.id,
+                "datetime": wallpaper.datetime,
+                "prompt": wallpaper.prompt_data.shortened_prompt,
+                "file": state.config.wallpapers_dir.join(&file.file_name),
+            })
+        );
+    } else {
+        println!(
+            "{} {} '{}'\n{}",
+            wallpaper.id,
+            wallpaper.datetime.format("%d/%m/%Y %H:%M"),
+            wallpaper.prompt_data.shortened_prompt,
+            state.config.wallpapers_dir.join(&file.file_name).display()
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::common::{Cluster, ColorData, WallpaperData};
+use crate::server::AppState;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+const DEFAULT_CLUSTERS: usize = 5;
+const MAX_CLUSTERS: usize = 50;
+const MAX_ITERATIONS: usize = 100;
+
+/// Contrast ratio ranges from 1 to 21, scale it down so it doesn't dominate the other 0-1 features
+const CONTRAST_SCALE: f32 = 21.0;
+
+#[derive(Deserialize)]
+pub struct ClusterQuery {
+    k: Option<usize>,
+}
+
+pub async fn clusters(
+    State(state): State<AppState>,
+    Query(query): Query<ClusterQuery>,
+) -> impl IntoResponse {
+    let k = query.k.unwrap_or(DEFAULT_CLUSTERS);
+    if k == 0 || k > MAX_CLUSTERS {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let database = state.read_database().await;
+    let mut wallpapers: Vec<WallpaperData> = database.wallpapers.into_values().collect();
+    // Sort so the initial centroids, and so the clusters, are stable between requests
+    wallpapers.sort_by_key(|wallpaper| wallpaper.datetime);
+
+    let points = wallpapers
+        .iter()
+        .map(|wallpaper| (wallpaper.id, features(&wallpaper.color_data)))
+        .collect::<Vec<_>>();
+    match bincode::serialize(&kmeans(&points, k)) {
+        Ok(data) => (StatusCode::OK, data).into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+const fn features(color_data: &ColorData) -> [f32; 5] {
+    [
+        color_data.hue,
+        color_data.saturation,
+        color_data.lightness,
+        color_data.top_20_percent_brightness,
+        color_data.contrast_ratio,
+    ]
+}
+
+fn distance(a: &[f32; 5], b: &[f32; 5]) -> f32 {
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .map(|(i, (a, b))| {
+            let diff = if i == 4 {
+                (a - b) / CONTRAST_SCALE
+            } else {
+                a - b
+            };
+            diff * diff
+        })
+        .sum()
+}
+
+/// Group the points into at most k clusters, largest first
+fn kmeans(points: &[(Uuid, [f32; 5])], k: usize) -> Vec<Cluster> {
+    if points.is_empty() {
+        return Vec::new();
+    }
+
+    // Farthest point initialisation, starting from the first point
+    let mut centroids = vec![points[0].1];
+    while centroids.len() < k.min(points.len()) {
+        let farthest = points
+            .iter()
+            .map(|(_, point)| {
+                let nearest = centroids
+                    .iter()
+                    .map(|centroid| distance(point, centroid))
+                    .fold(f32::INFINITY, f32::min);
+                (point, nearest)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        match farthest {
+            Some((point, nearest)) if nearest > 0.0 => centroids.push(*point),
+            // Every remaining point is a duplicate of a centroid
+            _ => break,
+        }
+    }
+
+    let mut assignments = vec![0; points.len()];
+    for iteration in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for ((_, point), assignment) in points.iter().zip(assignments.iter_mut()) {
+            let nearest = centroids
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| distance(point, a).total_cmp(&distance(point, b)))
+                .map_or(0, |(i, _)| i);
+            if *assignment != nearest {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+        if !changed && iteration > 0 {
+            break;
+        }
+
+        // Move each centroid to the mean of its members
+        for (i, centroid) in centroids.iter_mut().enumerate() {
+            let members = points
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, assignment)| **assignment == i)
+                .map(|((_, point), _)| point)
+                .collect::<Vec<_>>();
+            if members.is_empty() {
+                continue;
+            }
+            for (f, value) in centroid.iter_mut().enumerate() {
+                *value = members.iter().map(|point| point[f]).sum::<f32>() / members.len() as f32;
+            }
+        }
+    }
+
+    let mut clusters = centroids
+        .into_iter()
+        .enumerate()
+        .map(|(i, centroid)| Cluster {
+            centroid,
+            members: points
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, assignment)| **assignment == i)
+                .map(|((id, _), _)| *id)
+                .collect(),
+        })
+        .filter(|cluster| !cluster.members.is_empty())
+        .collect::<Vec<_>>();
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.members.len()));
+    clusters
+}
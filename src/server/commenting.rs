@@ -1,12 +1,20 @@
 use crate::common::{
-    CommentData, SetStylePacket, StyleVariant, TokenPacket, TokenStringPacket, TokenUuidPacket,
+    utils::comment_sentiment, AuditAction, CommentData, ContentTheme, Database, DatabaseStyle,
+    GenerationMode, SetHistoryConfigPacket, SetSeasonPacket, SetStylePacket, StyleVariant,
+    TokenPacket, TokenStringPacket, TokenUuidPacket,
 };
-use crate::server::{auth::verify_token, gpt, read_database, write_database};
-use axum::{body::Bytes, http::StatusCode, response::IntoResponse};
+use crate::server::{
+    audit,
+    auth::{token_account, verify_token},
+    error_status, gpt, AppState,
+};
+use anyhow::{anyhow, Result};
+use axum::{body::Bytes, extract::State, http::StatusCode, response::IntoResponse};
 use chrono::Utc;
+use std::collections::HashMap;
 use uuid::Uuid;
 
-pub async fn add(packet: Bytes) -> impl IntoResponse {
+pub async fn add(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
     let packet: TokenStringPacket = match bincode::deserialize(&packet) {
         Ok(packet) => packet,
         Err(e) => {
@@ -14,39 +22,79 @@ pub async fn add(packet: Bytes) -> impl IntoResponse {
             return StatusCode::BAD_REQUEST;
         }
     };
-    if !verify_token(&packet.token).await.unwrap_or(false) {
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
         return StatusCode::UNAUTHORIZED;
     }
 
     // Store a new database entry
-    let result = async {
-        let mut database = read_database().await?;
-        let id = Uuid::new_v4();
-        let datetime = Utc::now();
+    let summary = format!("Commented '{}'", packet.string);
+    let author_uuid = token_account(&state, &packet.token)
+        .await
+        .ok()
+        .flatten()
+        .map(|(uuid, _)| uuid);
+    let sentiment = comment_sentiment(&packet.string);
+    let result: Result<Uuid> = state
+        .with_database(|database| {
+            let id = Uuid::new_v4();
+            let datetime = Utc::now();
 
-        database.comments.insert(
-            id,
-            CommentData {
+            database.comments.insert(
                 id,
-                datetime,
-                comment: packet.string,
-            },
-        );
-
-        write_database(&database).await
-    }
-    .await;
+                CommentData {
+                    id,
+                    datetime,
+                    comment: packet.string,
+                    response: None,
+                    author_uuid,
+                    used: false,
+                    reactions: HashMap::new(),
+                    sentiment: Some(sentiment),
+                },
+            );
+            Ok(id)
+        })
+        .await;
 
     match result {
-        Ok(()) => StatusCode::OK,
+        Ok(id) => {
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Comment,
+                Some(id),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
         Err(e) => {
             log::error!("Errored add_comment {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            error_status(&e)
         }
     }
 }
 
-pub async fn remove(packet: Bytes) -> impl IntoResponse {
+/// Mark a comment used and record it on the wallpaper generated from it
+pub async fn link_generated(state: &AppState, comment_id: Uuid, wallpaper_id: Uuid) -> Result<()> {
+    state
+        .with_database(|database| {
+            database
+                .comments
+                .get_mut(&comment_id)
+                .ok_or_else(|| anyhow!("Comment not found"))?
+                .used = true;
+            database
+                .wallpapers
+                .get_mut(&wallpaper_id)
+                .ok_or_else(|| anyhow!("Wallpaper not found"))?
+                .source_comment = Some(comment_id);
+            Ok(())
+        })
+        .await
+}
+
+pub async fn remove(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
     let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
         Ok(packet) => packet,
         Err(e) => {
@@ -54,28 +102,113 @@ pub async fn remove(packet: Bytes) -> impl IntoResponse {
             return StatusCode::BAD_REQUEST;
         }
     };
-    if !verify_token(&packet.token).await.unwrap_or(false) {
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
         return StatusCode::UNAUTHORIZED;
     }
+    let (account_uuid, admin) = match token_account(&state, &packet.token).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return StatusCode::UNAUTHORIZED,
+        Err(e) => {
+            log::error!("Errored remove_comment {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
 
-    // Remove the database entry
-    let result = async {
-        let mut database = read_database().await?;
-        database.comments.retain(|id, _| *id != packet.uuid);
-        write_database(&database).await
-    }
-    .await;
+    // Admins can remove any comment, everyone else only their own
+    let result = state
+        .with_database(|database| {
+            let allowed = database
+                .comments
+                .get(&packet.uuid)
+                .is_none_or(|comment| admin || comment.author_uuid == Some(account_uuid));
+            if allowed {
+                database.comments.remove(&packet.uuid);
+            }
+            Ok(allowed)
+        })
+        .await;
 
     match result {
-        Ok(()) => StatusCode::OK,
+        Ok(false) => StatusCode::FORBIDDEN,
+        Ok(true) => {
+            let summary = "Removed comment".to_string();
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::RemoveComment,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
         Err(e) => {
             log::error!("Errored remove_comment {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            error_status(&e)
+        }
+    }
+}
+
+/// Toggle the thumbs up of the token's account on a comment
+pub async fn react(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize react_comment packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let account_uuid = match token_account(&state, &packet.token).await {
+        Ok(Some((uuid, _))) => uuid,
+        Ok(None) => return StatusCode::UNAUTHORIZED,
+        Err(e) => {
+            log::error!("Errored react_comment {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    let result = state
+        .with_database(|database| {
+            let Some(comment) = database.comments.get_mut(&packet.uuid) else {
+                return Ok(None);
+            };
+            let reacted = comment.reactions.remove(&account_uuid).is_none();
+            if reacted {
+                comment.reactions.insert(account_uuid, true);
+            }
+            Ok(Some(reacted))
+        })
+        .await;
+
+    match result {
+        Ok(Some(reacted)) => {
+            let summary = if reacted {
+                "Reacted to comment"
+            } else {
+                "Removed comment reaction"
+            };
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::React,
+                Some(packet.uuid),
+                summary.to_string(),
+            )
+            .await;
+            StatusCode::OK
+        }
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            log::error!("Errored react_comment {:?}", e);
+            error_status(&e)
         }
     }
 }
 
-pub async fn styles(packet: Bytes) -> impl IntoResponse {
+pub async fn styles(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
     let packet: SetStylePacket = match bincode::deserialize(&packet) {
         Ok(packet) => packet,
         Err(e) => {
@@ -83,37 +216,278 @@ pub async fn styles(packet: Bytes) -> impl IntoResponse {
             return StatusCode::BAD_REQUEST;
         }
     };
-    if !verify_token(&packet.token).await.unwrap_or(false) {
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
         return StatusCode::UNAUTHORIZED;
     }
 
-    let result = async {
-        let mut database = read_database().await?;
-        match packet.variant {
-            StyleVariant::Style => {
-                database.style.style = packet.string;
-            }
-            StyleVariant::Contents => {
-                database.style.contents = packet.string;
-            }
-            StyleVariant::NegativeContents => {
-                database.style.negative_contents = packet.string;
+    let summary = match &packet.variant {
+        StyleVariant::Style(style) => format!("Set style to '{style}'"),
+        StyleVariant::ContentThemes(themes) => format!(
+            "Set contents to '{}'",
+            themes
+                .iter()
+                .map(|theme| format!("{} x{}", theme.text, theme.weight))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        StyleVariant::NegativeContents(negative_contents) => {
+            format!("Set negative contents to '{negative_contents}'")
+        }
+        StyleVariant::PromptLength(length) => format!("Set prompt length to {}", length.name()),
+        StyleVariant::ExtraInstructions(instructions) => {
+            format!("Set extra instructions to '{instructions}'")
+        }
+    };
+    let result = state
+        .with_database(|database| {
+            match packet.variant {
+                StyleVariant::Style(style) => {
+                    database.style.style = style;
+                }
+                StyleVariant::ContentThemes(mut themes) => {
+                    for theme in &mut themes {
+                        theme.weight = theme
+                            .weight
+                            .clamp(ContentTheme::MIN_WEIGHT, ContentTheme::MAX_WEIGHT);
+                    }
+                    database.style.contents = themes;
+                }
+                StyleVariant::NegativeContents(negative_contents) => {
+                    database.style.negative_contents = negative_contents;
+                }
+                StyleVariant::PromptLength(length) => {
+                    database.style.prompt_length = length;
+                }
+                StyleVariant::ExtraInstructions(instructions) => {
+                    database.style.extra_instructions = instructions;
+                }
             }
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => {
+            audit(&state, &packet.token, AuditAction::Style, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored styles {:?}", e);
+            error_status(&e)
         }
-        write_database(&database).await
     }
-    .await;
+}
+
+/// Snapshot the current style under a name, replacing any preset already called that
+pub async fn save_preset(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenStringPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize save_preset packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let name = packet.string.trim().to_string();
+    if name.is_empty() {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let summary = format!("Saved style preset '{name}'");
+    let result = state
+        .with_database(|database| {
+            database.presets.insert(name, database.style.clone());
+            Ok(())
+        })
+        .await;
 
     match result {
-        Ok(()) => StatusCode::OK,
+        Ok(()) => {
+            audit(&state, &packet.token, AuditAction::Style, None, summary).await;
+            StatusCode::OK
+        }
         Err(e) => {
-            log::error!("Errored styles {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            log::error!("Errored save_preset {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+/// Swap the whole style for a preset, noting the switch in the history so the LLM expects the change
+pub async fn apply_preset(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenStringPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize apply_preset packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let author_uuid = token_account(&state, &packet.token)
+        .await
+        .ok()
+        .flatten()
+        .map(|(uuid, _)| uuid);
+    let result = state
+        .with_database(|database| Ok(switch_preset(database, &packet.string, author_uuid)))
+        .await;
+
+    match result {
+        Ok(false) => StatusCode::NOT_FOUND,
+        Ok(true) => {
+            let summary = format!("Applied style preset '{}'", packet.string);
+            audit(&state, &packet.token, AuditAction::Style, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored apply_preset {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+/// Replace the style with a preset and add a comment saying so, false if there's no such preset
+/// The season override is left alone, it's set for the calendar rather than the style
+fn switch_preset(database: &mut Database, name: &str, author_uuid: Option<Uuid>) -> bool {
+    let Some(preset) = database.presets.get(name) else {
+        return false;
+    };
+    database.style = DatabaseStyle {
+        season_override: database.style.season_override,
+        ..preset.clone()
+    };
+    let id = Uuid::new_v4();
+    database.comments.insert(
+        id,
+        CommentData {
+            id,
+            datetime: Utc::now(),
+            comment: format!("Switched style preset to '{name}'"),
+            response: None,
+            author_uuid,
+            used: true, // Nothing to generate from, so the comment doesn't offer to
+            reactions: HashMap::new(),
+            sentiment: None,
+        },
+    );
+    true
+}
+
+pub async fn delete_preset(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenStringPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize delete_preset packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let result = state
+        .with_database(|database| {
+            database.presets.remove(&packet.string);
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => {
+            let summary = format!("Deleted style preset '{}'", packet.string);
+            audit(&state, &packet.token, AuditAction::Style, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored delete_preset {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+pub async fn season(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: SetSeasonPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize season packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let summary = packet.season.map_or_else(
+        || "Cleared season override".to_string(),
+        |season| format!("Set season override to {}", season.name()),
+    );
+    let result = state
+        .with_database(|database| {
+            database.style.season_override = packet.season;
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => {
+            audit(&state, &packet.token, AuditAction::Season, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored season {:?}", e);
+            error_status(&e)
         }
     }
 }
 
-pub async fn query_prompt(packet: Bytes) -> impl IntoResponse {
+pub async fn history_config(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: SetHistoryConfigPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize history_config packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let summary = format!(
+        "Set history to {} recent, {} loved, {} disliked",
+        packet.config.recent_count, packet.config.max_loved, packet.config.max_disliked
+    );
+    let result = state
+        .with_database(|database| {
+            database.history_config = packet.config;
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => {
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::HistoryConfig,
+                None,
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored history_config {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+pub async fn query_prompt(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
     let packet: TokenPacket = match bincode::deserialize(&packet) {
         Ok(packet) => packet,
         Err(e) => {
@@ -121,21 +495,71 @@ pub async fn query_prompt(packet: Bytes) -> impl IntoResponse {
             return (StatusCode::BAD_REQUEST, String::new());
         }
     };
-    if !verify_token(&packet.token).await.unwrap_or(false) {
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
         return (StatusCode::UNAUTHORIZED, String::new());
     }
 
     // Query GPT for the prompt it would send to create an image
-    let generate_result = gpt::generate_prompt(
-        &reqwest::Client::new(),
-        &std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set"),
-    )
-    .await;
+    let generate_result = gpt::generate_prompt(&state, GenerationMode::Normal).await;
     match generate_result {
-        Ok((request_body, _)) => (StatusCode::OK, request_body),
+        Ok(context) => (StatusCode::OK, context.history),
         Err(e) => {
             log::error!("Errored query_prompt {:?}", e);
             (StatusCode::INTERNAL_SERVER_ERROR, String::new())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{HistoryConfig, PromptLength, Season, ThumbnailSettings};
+
+    #[test]
+    fn switches_preset_and_notes_it() {
+        let minimal = DatabaseStyle {
+            style: "Flat vector shapes".to_string(),
+            prompt_length: PromptLength::Short,
+            ..DatabaseStyle::default()
+        };
+        let mut database = Database {
+            style: DatabaseStyle {
+                style: "Oil painting".to_string(),
+                season_override: Some(Season::Winter),
+                ..DatabaseStyle::default()
+            },
+            history_config: HistoryConfig::default(),
+            prompt_templates: Vec::new(),
+            wallpapers: HashMap::new(),
+            comments: HashMap::new(),
+            pending_prompts: HashMap::new(),
+            fetch_profiles: Vec::new(),
+            users: HashMap::new(),
+            viewer: None,
+            thumbnail_settings: ThumbnailSettings::default(),
+            stale_thumbnails: Vec::new(),
+            presets: HashMap::from([("minimal geometric".to_string(), minimal)]),
+            share_links: Vec::new(),
+        };
+
+        assert!(!switch_preset(&mut database, "painterly fantasy", None));
+        assert_eq!(database.style.style, "Oil painting");
+        assert!(database.comments.is_empty());
+
+        let author = Uuid::new_v4();
+        assert!(switch_preset(
+            &mut database,
+            "minimal geometric",
+            Some(author)
+        ));
+        assert_eq!(database.style.style, "Flat vector shapes");
+        assert!(database.style.prompt_length == PromptLength::Short);
+        assert!(database.style.season_override == Some(Season::Winter));
+        let comment = database.comments.values().next().unwrap();
+        assert_eq!(
+            comment.comment,
+            "Switched style preset to 'minimal geometric'"
+        );
+        assert_eq!(comment.author_uuid, Some(author));
+    }
+}
@@ -0,0 +1,301 @@
+use crate::common::{AuditAction, TokenPacket};
+use crate::server::{audit, auth::token_account, maintenance, models::ImageModel, AppState};
+use crate::PORT;
+use anyhow::{Context, Result};
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fs, io,
+    path::PathBuf,
+    sync::LazyLock,
+};
+
+/// TOML file of settings, set with `CONFIG_FILE`
+/// Keys are the env var names in lower case, and a set env var always wins over the file
+static CONFIG_FILE: LazyLock<PathBuf> = LazyLock::new(|| {
+    env::var_os("CONFIG_FILE").map_or_else(|| PathBuf::from("data/config.toml"), PathBuf::from)
+});
+
+/// Values from the config file as strings, the same form env vars take
+static FILE_VALUES: LazyLock<RwLock<HashMap<String, String>>> = LazyLock::new(RwLock::default);
+
+/// Settings that are read once at startup, changing them in the file needs a restart
+const RESTART_ONLY: [&str; 7] = [
+    "port",
+    "data_dir",
+    "wallpapers_dir",
+    "socket_path",
+    "csp_override",
+    "cors_allowed_origins",
+    "cors_max_age_secs",
+];
+
+/// Every setting the config file accepts, secrets like API keys stay in the environment
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ServerConfig {
+    port: Option<u16>,
+    data_dir: Option<String>,
+    wallpapers_dir: Option<String>,
+    socket_path: Option<String>,
+    generation_interval_hours: Option<u32>,
+    wallpaper_resolution: Option<String>,
+    image_encoding: Option<String>,
+    thumbnail_width: Option<u32>,
+    thumbnail_height: Option<u32>,
+    thumbnail_quality: Option<u8>,
+    min_sharpness: Option<f32>,
+    generation_candidates: Option<u32>,
+    warmup_on_start: Option<bool>,
+    delete_user_content: Option<bool>,
+    models: Option<Vec<ImageModel>>,
+    global_negative_prompt: Option<String>,
+    seasonal_mode: Option<String>,
+    weather_location: Option<String>,
+    llm_fallback_provider: Option<String>,
+    prompt_refinement: Option<bool>,
+    auto_delete_disliked_days: Option<u32>,
+    auto_delete_neutral_days: Option<u32>,
+    db_backup_count: Option<usize>,
+    db_backup_interval_mins: Option<u64>,
+    db_write_timeout_secs: Option<u64>,
+    share_link_expiry_hours: Option<i64>,
+    webhook_url: Option<String>,
+    public_host: Option<String>,
+    public_pages: Option<bool>,
+    https_only: Option<bool>,
+    csp_override: Option<String>,
+    cors_allowed_origins: Option<String>,
+    cors_max_age_secs: Option<u64>,
+}
+
+/// Look up a setting by its env var name, from the environment first and then the config file
+pub fn var(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .or_else(|| FILE_VALUES.read().get(&name.to_lowercase()).cloned())
+}
+
+/// The port to serve on, set with `PORT`
+pub fn port() -> u16 {
+    var("PORT")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(PORT)
+}
+
+/// Read the config file if there is one, a broken file fails startup rather than being ignored
+pub fn load() -> Result<()> {
+    let values = read_file()?;
+    if !values.is_empty() {
+        log::info!(
+            "Loaded {} settings from {}",
+            values.len(),
+            CONFIG_FILE.display()
+        );
+    }
+    *FILE_VALUES.write() = values;
+    Ok(())
+}
+
+fn read_file() -> Result<HashMap<String, String>> {
+    let path = CONFIG_FILE.as_path();
+    match fs::read_to_string(path) {
+        Ok(data) => {
+            parse(&data).with_context(|| format!("Invalid config file '{}'", path.display()))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => {
+            Err(e).with_context(|| format!("Failed to read config file '{}'", path.display()))
+        }
+    }
+}
+
+fn parse(data: &str) -> Result<HashMap<String, String>> {
+    let config: ServerConfig = toml::from_str(data)?;
+    let Value::Object(fields) = serde_json::to_value(config)? else {
+        return Ok(HashMap::new());
+    };
+    Ok(fields
+        .into_iter()
+        .filter_map(|(key, value)| match value {
+            Value::Null => None,
+            Value::String(value) => Some((key, value)),
+            value => Some((key, value.to_string())),
+        })
+        .collect())
+}
+
+#[derive(Serialize)]
+struct Reload {
+    applied: Vec<String>,
+    needs_restart: Vec<String>, // Changed in the file but only read at startup
+}
+
+/// Re-read the config file, applying every changed setting that can change while running
+fn reload_impl() -> Result<Reload> {
+    let new_values = read_file()?;
+    let mut values = FILE_VALUES.write();
+    let keys = values
+        .keys()
+        .chain(new_values.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    let mut reload = Reload {
+        applied: Vec::new(),
+        needs_restart: Vec::new(),
+    };
+    for key in keys {
+        let new_value = new_values.get(&key);
+        if values.get(&key) == new_value {
+            continue;
+        }
+        if RESTART_ONLY.contains(&key.as_str()) {
+            reload.needs_restart.push(key);
+            continue;
+        }
+        match new_value {
+            Some(value) => values.insert(key.clone(), value.clone()),
+            None => values.remove(&key),
+        };
+        reload.applied.push(key);
+    }
+    Ok(reload)
+}
+
+/// Every setting with its value and where it came from
+fn effective() -> Value {
+    let setting_names = match serde_json::to_value(ServerConfig::default()) {
+        Ok(Value::Object(fields)) => fields.into_iter().map(|(key, _)| key).collect(),
+        _ => Vec::new(),
+    };
+    let values = FILE_VALUES.read();
+    let settings = setting_names
+        .into_iter()
+        .map(|key| {
+            let (value, source) = match (env::var(key.to_uppercase()).ok(), values.get(&key)) {
+                (Some(value), _) => (Some(value), "env"),
+                (None, Some(value)) => (Some(value.clone()), "file"),
+                (None, None) => (None, "default"),
+            };
+            (key, json!({ "value": value, "source": source }))
+        })
+        .collect::<serde_json::Map<_, _>>();
+    json!({
+        "config_file": CONFIG_FILE.display().to_string(),
+        "settings": settings,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ConfigQuery {
+    token: String,
+}
+
+/// The settings in effect, only available to admins
+pub async fn get(
+    State(state): State<AppState>,
+    Query(query): Query<ConfigQuery>,
+) -> impl IntoResponse {
+    match token_account(&state, &query.token).await {
+        Ok(Some((_, true))) => {}
+        Ok(_) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        effective().to_string(),
+    )
+        .into_response()
+}
+
+/// Reload the config file without restarting, only available to admins
+pub async fn reload(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize reload_config packet: {:?}", e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    match token_account(&state, &packet.token).await {
+        Ok(Some((_, true))) => {}
+        Ok(_) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    match reload_impl() {
+        Ok(reload) => {
+            if !reload.needs_restart.is_empty() {
+                log::warn!(
+                    "Config changes need a restart to apply: {}",
+                    reload.needs_restart.join(", ")
+                );
+            }
+            // Thumbnails written at the old size or quality get rebuilt in the background
+            if reload
+                .applied
+                .iter()
+                .any(|key| key.starts_with("thumbnail_"))
+            {
+                if let Err(e) = maintenance::check_thumbnail_settings(&state).await {
+                    log::error!("Failed to check thumbnail settings {:?}", e);
+                }
+            }
+            let summary = if reload.applied.is_empty() {
+                "Reloaded config, nothing changed".to_string()
+            } else {
+                format!("Reloaded config, applied {}", reload.applied.join(", "))
+            };
+            audit(&state, &packet.token, AuditAction::Config, None, summary).await;
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/json")],
+                json!(reload).to_string(),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            log::error!("Errored reload_config {:?}", e);
+            StatusCode::BAD_REQUEST.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_settings_as_env_strings() {
+        let values = parse(
+            "min_sharpness = 120.5\npublic_pages = true\nimage_encoding = \"avif\"\nauto_delete_neutral_days = 14\n",
+        )
+        .unwrap();
+        assert_eq!(values.len(), 4);
+        assert_eq!(values["min_sharpness"], "120.5");
+        assert_eq!(values["public_pages"], "true");
+        assert_eq!(values["image_encoding"], "avif");
+        assert_eq!(values["auto_delete_neutral_days"], "14");
+
+        assert!(parse("openai_api_key = \"secret\"").is_err());
+        assert!(parse("[[models]]\nid = \"flux\"").is_err());
+        assert!(parse("port = \"not a number\"").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use crate::common::ImageFile;
+use crate::server::config;
+use anyhow::Result;
+use image::{codecs::avif::AvifEncoder, DynamicImage, ExtendedColorType, ImageEncoder};
+use std::path::Path;
+
+/// Which extra encodings are saved beside the webp files
+/// Webp is always kept, it's what gets edited on the server and decoded by the app
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ImageEncoding {
+    Webp,
+    Avif, // AVIF copies of the full size images
+    Both, // AVIF copies of the thumbnails as well
+}
+
+impl ImageEncoding {
+    /// Read the encoding from the `IMAGE_ENCODING` env var, defaulting to webp only
+    pub fn from_env() -> Self {
+        match config::var("IMAGE_ENCODING").as_deref() {
+            None | Some("webp") => Self::Webp,
+            Some("avif") => Self::Avif,
+            Some("both") => Self::Both,
+            Some(value) => {
+                log::warn!("Invalid IMAGE_ENCODING '{value}', using webp");
+                Self::Webp
+            }
+        }
+    }
+
+    const fn includes_avif(self, thumbnail: bool) -> bool {
+        match self {
+            Self::Webp => false,
+            Self::Avif => !thumbnail,
+            Self::Both => true,
+        }
+    }
+}
+
+/// Save an AVIF copy beside the file if the encoding setting asks for one, failures are only logged
+pub async fn write_alternates(
+    dir: &Path,
+    image: &DynamicImage,
+    file: &mut ImageFile,
+    thumbnail: bool,
+) {
+    if !ImageEncoding::from_env().includes_avif(thumbnail) {
+        return;
+    }
+
+    // Encoding AVIF is slow, keep it off the async runtime
+    let path = dir.join(&file.file_name).with_extension("avif");
+    let image = image.clone();
+    let result = tokio::task::spawn_blocking(move || encode_avif(&image, &path))
+        .await
+        .unwrap_or_else(|e| Err(e.into()));
+    match result {
+        Ok(()) => file.alt_formats.push("avif".to_string()),
+        Err(e) => log::error!("Failed to encode avif for {}: {:?}", file.file_name, e),
+    }
+}
+
+fn encode_avif(image: &DynamicImage, path: &Path) -> Result<()> {
+    let rgba = image.to_rgba8();
+    let mut data = Vec::new();
+    AvifEncoder::new_with_speed_quality(&mut data, 6, 80).write_image(
+        rgba.as_raw(),
+        rgba.width(),
+        rgba.height(),
+        ExtendedColorType::Rgba8,
+    )?;
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+/// Whether an Accept header lists AVIF without ruling it out with a zero quality
+pub fn accepts_avif(accept: &str) -> bool {
+    accept.split(',').any(|range| {
+        let mut params = range.split(';').map(str::trim);
+        params.next() == Some("image/avif")
+            && params.all(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_none_or(|q| q > 0.0)
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_accept_header() {
+        assert!(accepts_avif("image/avif,image/webp,*/*"));
+        assert!(accepts_avif("image/webp, image/avif;q=0.9"));
+        assert!(!accepts_avif("image/avif;q=0, image/webp"));
+        assert!(!accepts_avif("image/webp,*/*"));
+    }
+}
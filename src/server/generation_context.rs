@@ -0,0 +1,95 @@
+use crate::server::{auth::verify_token, AppState};
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use tokio::fs;
+use uuid::Uuid;
+
+/// Subdirectory of the wallpapers dir the contexts are saved in, kept out of the public file service
+pub const CONTEXT_DIR: &str = "context";
+const COMPRESSION_LEVEL: i32 = 19;
+
+#[derive(Deserialize)]
+pub struct ContextQuery {
+    token: String,
+}
+
+/// Compress and save the LLM context a wallpaper was generated from, returning its file name
+pub async fn write(state: &AppState, id: Uuid, context: &str) -> Result<String> {
+    let file_name = format!("{CONTEXT_DIR}/{id}.txt.zst");
+    fs::create_dir_all(state.config.wallpapers_dir.join(CONTEXT_DIR)).await?;
+    fs::write(
+        state.config.wallpapers_dir.join(&file_name),
+        compress(context)?,
+    )
+    .await?;
+    Ok(file_name)
+}
+
+fn compress(context: &str) -> Result<Vec<u8>> {
+    Ok(zstd::encode_all(context.as_bytes(), COMPRESSION_LEVEL)?)
+}
+
+fn decompress(data: &[u8]) -> Result<String> {
+    Ok(String::from_utf8(zstd::decode_all(data)?)?)
+}
+
+/// The decompressed context a wallpaper was generated from, as plain text
+pub async fn get(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Query(query): Query<ContextQuery>,
+) -> impl IntoResponse {
+    if !verify_token(&state, &query.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let result = async {
+        let file_name = state
+            .read_database()
+            .await
+            .wallpapers
+            .remove(&uuid)
+            .and_then(|wallpaper| wallpaper.context_file);
+        let Some(file_name) = file_name else {
+            return Ok(None);
+        };
+        let data = fs::read(state.config.wallpapers_dir.join(&file_name))
+            .await
+            .map_err(|e| anyhow!("Failed to read {file_name}: {e}"))?;
+        decompress(&data).map(Some)
+    }
+    .await;
+
+    match result {
+        Ok(Some(context)) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            context,
+        )
+            .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            log::error!("Errored image_context {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_context() {
+        let context =
+            "History of previous prompts and comments:\n1 day ago - 'Quiet harbour'\n".repeat(50);
+        let compressed = compress(&context).unwrap();
+        assert!(compressed.len() < context.len());
+        assert_eq!(decompress(&compressed).unwrap(), context);
+    }
+}
@@ -1,11 +1,22 @@
-use crate::common::{Database, DatabaseStyle, LikedState, PromptData};
-use crate::server::{format_duration, read_database};
+use crate::common::{
+    utils::format_duration, CommentSentiment, ContentTheme, DatabaseStyle, GenerationMode,
+    LikedState, LlmUsage, PromptData, PromptLength, PromptTemplate, Season, TimeOfDay,
+};
+use crate::server::{
+    config,
+    history::{history_text, select_history, HistoryEntry},
+    moodboard,
+    net::{check_status, with_retry, RetryPolicy},
+    providers::LlmProvider,
+    time_of_day, weather, AppState,
+};
 use anyhow::{anyhow, Result};
-use chrono::Utc;
+use async_trait::async_trait;
+use chrono::{Datelike, Utc};
 use reqwest::Client;
 use serde_json::{json, Value};
-use std::collections::HashMap;
 use std::env;
+use uuid::Uuid;
 
 const PROMPT_GUIDELINES: &str = "A well-crafted FLUX.1 prompt typically includes the following components:
     Subject: The main focus of the image.
@@ -76,78 +87,236 @@ Design a mythical creature that combines elements of a lion, an eagle, and a dra
 Create an abstract representation of the emotion 'hope' using a palette of warm colors. Incorporate flowing shapes and subtle human silhouettes to suggest a sense of movement and aspiration
 ";
 
-pub async fn generate_prompt(client: &Client, api_key: &str) -> Result<(String, DatabaseStyle)> {
-    // Read the database
-    let database = match read_database().await {
-        Ok(db) => db,
-        Err(e) => {
-            log::error!("Failed accessing database {:?}", e);
-            Database {
-                style: DatabaseStyle::default(),
-                wallpapers: HashMap::new(),
-                comments: HashMap::new(),
+const CHAT_COMPLETIONS_URL: &str = "https://api.openai.com/v1/chat/completions";
+const FALLBACK_MODEL: &str = "gpt-4o-mini";
+const MIN_TAGS: usize = 3;
+const MAX_TAGS: usize = 8;
+
+/// Price per million prompt and completion tokens in USD
+const GPT_4O_PRICING: (f32, f32) = (2.5, 10.0);
+const GPT_4O_MINI_PRICING: (f32, f32) = (0.15, 0.6);
+
+pub struct PromptContext {
+    pub history: String,
+    pub style: DatabaseStyle,
+    pub comment_ids: Vec<Uuid>, // Comments included in the history
+    pub templates: Vec<PromptTemplate>,
+    pub usage: LlmUsage,
+    pub time_of_day: Option<TimeOfDay>, // Set when the library leans too bright or too dark
+}
+
+pub struct GeneratedPrompt {
+    pub prompt_data: PromptData,
+    pub comment_ids: Vec<Uuid>,
+    pub usage: LlmUsage,
+    pub llm_provider: String, // Provider and model that wrote the final prompt
+    pub context: String,      // System messages the prompt was written from
+    pub weather_context: Option<String>, // Weather the image was asked to match
+    pub moodboard_influence: bool, // The moodboard's aesthetic was in the context
+}
+
+/// Word count guidance for the prompt writer
+const fn length_guidance(length: PromptLength) -> &'static str {
+    match length {
+        PromptLength::Short => "Keep the prompt to one or two sentences, 20 to 40 words",
+        PromptLength::Medium => "Write the prompt in a few sentences, 50 to 80 words",
+        PromptLength::Long => "Write a detailed prompt of several sentences, 120 to 160 words",
+    }
+}
+
+/// The length to aim for and any extra instructions from the style, for the prompt writer
+fn writer_style_message(style: &DatabaseStyle) -> String {
+    let guidance = length_guidance(style.prompt_length);
+    let extra_instructions = style.extra_instructions.trim();
+    if extra_instructions.is_empty() {
+        format!("\n{guidance}")
+    } else {
+        format!(
+            "\n{guidance}\nAdditional instructions from the user: {}",
+            extra_instructions.replace('\n', " ")
+        )
+    }
+}
+
+/// The content themes in priority order, each marked with how strongly to lean towards it
+fn describe_themes(themes: &[ContentTheme]) -> String {
+    themes
+        .iter()
+        .filter(|theme| !theme.text.trim().is_empty())
+        .map(|theme| match theme.emphasis() {
+            Some(emphasis) => format!("{} ({emphasis})", theme.text.trim()),
+            None => theme.text.trim().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The system messages sent to the LLM, kept so an odd generation can be traced back to its inputs
+/// The prompt guidelines are left out as they never change
+fn system_context(messages: &[Value]) -> String {
+    messages
+        .iter()
+        .filter(|message| message["role"] == "system" && message["name"] != "prompt_guidelines")
+        .filter_map(|message| message["content"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Falls back to `FALLBACK_MODEL` when the primary model errors if `LLM_FALLBACK_PROVIDER` is set to openai
+fn fallback_enabled() -> bool {
+    match config::var("LLM_FALLBACK_PROVIDER") {
+        Some(provider) if provider.eq_ignore_ascii_case("openai") => true,
+        Some(provider) => {
+            log::warn!("Unsupported LLM_FALLBACK_PROVIDER '{provider}', fallback disabled");
+            false
+        }
+        None => false,
+    }
+}
+
+/// Chat completions from the OpenAI API, reading `OPENAI_API_KEY` on every request
+#[derive(Default)]
+pub struct OpenAi {
+    client: Client,
+}
+
+#[async_trait]
+impl LlmProvider for OpenAi {
+    async fn chat_completion(&self, mut request_body: Value) -> Result<(Value, String)> {
+        let api_key =
+            env::var("OPENAI_API_KEY").map_err(|_| anyhow!("OPENAI_API_KEY must be set"))?;
+        let model = request_body["model"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match send_chat_completion(&self.client, &api_key, &request_body).await {
+            Ok(response_json) => Ok((response_json, format!("openai/{model}"))),
+            Err(e) if model != FALLBACK_MODEL && fallback_enabled() => {
+                log::warn!("LLM {model} failed, falling back to {FALLBACK_MODEL}: {e:?}");
+                request_body["model"] = json!(FALLBACK_MODEL);
+                let response_json =
+                    send_chat_completion(&self.client, &api_key, &request_body).await?;
+                Ok((response_json, format!("openai/{FALLBACK_MODEL}")))
             }
+            Err(e) => Err(e),
         }
+    }
+}
+
+async fn send_chat_completion(
+    client: &Client,
+    api_key: &str,
+    request_body: &Value,
+) -> Result<Value> {
+    let text = with_retry(RetryPolicy::default(), "LLM request", || async {
+        let response = client
+            .post(CHAT_COMPLETIONS_URL)
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {api_key}"))
+            .json(request_body)
+            .send()
+            .await?;
+        Ok(check_status(response).await?.text().await?)
+    })
+    .await?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Read the token usage from a chat completion response and estimate its cost
+fn parse_usage(response_json: &Value) -> LlmUsage {
+    let Some(usage) = response_json.pointer("/usage") else {
+        return LlmUsage::default();
+    };
+    let tokens = |key: &str| {
+        usage[key]
+            .as_u64()
+            .and_then(|value| u32::try_from(value).ok())
+            .unwrap_or(0)
+    };
+    let (prompt_tokens, completion_tokens) = (tokens("prompt_tokens"), tokens("completion_tokens"));
+
+    // Versioned model names like gpt-4o-mini-2024-07-18 are returned, so match on the prefix
+    let model = response_json["model"].as_str().unwrap_or_default();
+    let (prompt_price, completion_price) = if model.starts_with("gpt-4o-mini") {
+        GPT_4O_MINI_PRICING
+    } else {
+        GPT_4O_PRICING
     };
 
-    // Collect the images and comments into a single list, sorted by datetime
-    let mut database_history = database
-        .wallpapers
-        .into_values()
-        .map(|wallpaper| (wallpaper.datetime, Some(wallpaper), None))
-        .chain(
-            database
-                .comments
-                .into_values()
-                .map(|comment| (comment.datetime, None, Some(comment))),
-        )
-        .collect::<Vec<_>>();
-    database_history.sort_by_key(|(datetime, _, _)| *datetime);
+    LlmUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: tokens("total_tokens"),
+        estimated_cost_usd: (prompt_tokens as f32)
+            .mul_add(prompt_price, completion_tokens as f32 * completion_price)
+            / 1_000_000.0,
+    }
+}
+
+/// Build the history context for the LLM
+pub async fn generate_prompt(state: &AppState, mode: GenerationMode) -> Result<PromptContext> {
+    // Read the database
+    let database = state.read_database().await;
+
+    // Wild prompts start from nothing, which also saves the summary request
+    if mode == GenerationMode::Wild {
+        return Ok(PromptContext {
+            history: String::new(),
+            time_of_day: time_of_day::rebalance_target(&database),
+            style: database.style,
+            comment_ids: Vec::new(),
+            templates: Vec::new(),
+            usage: LlmUsage::default(),
+        });
+    }
+
+    // Select the images and comments to include, newest first
+    let mut selection = select_history(&database, &database.history_config);
+    if mode == GenerationMode::StrictStyle {
+        selection.entries.retain(|entry| {
+            matches!(entry, HistoryEntry::Wallpaper(wallpaper) if wallpaper.combined_liked_state() == LikedState::Loved)
+        });
+        selection.discarded_likes.clear();
+        selection.discarded_dislikes.clear();
+        selection.discarded_others.clear();
+    }
 
     let cur_time = Utc::now();
     let mut history_string = Vec::new();
-    let (mut discarded_loves, mut discarded_likes, mut discarded_dislikes, mut discarded_others) =
-        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
-    for (i, (date, wallpaper, comment)) in database_history.iter().rev().enumerate() {
-        let datetime_text = format_duration(cur_time - date);
-        if let Some(wallpaper) = wallpaper {
-            if i < match wallpaper.liked_state {
-                LikedState::Loved => 30,
-                LikedState::Liked | LikedState::Disliked => 15,
-                LikedState::Neutral => 10,
-            } {
+    let mut comment_ids = Vec::new();
+    for entry in &selection.entries {
+        match entry {
+            HistoryEntry::Wallpaper(wallpaper) => {
                 history_string.push(format!(
-                    "{datetime_text} ago -{} '{}'",
-                    match wallpaper.liked_state {
+                    "{} ago -{}{} '{}'{}",
+                    format_duration(cur_time - wallpaper.datetime),
+                    match wallpaper.combined_liked_state() {
                         LikedState::Loved => " (user LOVED this)",
                         LikedState::Liked => " (user liked this)",
                         LikedState::Disliked => " (user disliked this)",
                         LikedState::Neutral => "",
                     },
-                    wallpaper.prompt_data.shortened_prompt
+                    wallpaper
+                        .rating
+                        .map_or_else(String::new, |rating| format!(" (rated {rating}/5 stars)")),
+                    history_text(wallpaper),
+                    wallpaper
+                        .note
+                        .as_ref()
+                        .map_or_else(String::new, |note| format!(" (user noted: '{note}')"))
                 ));
-            } else if i < 60 {
-                let text = wallpaper.prompt_data.shortened_prompt.clone();
-                match wallpaper.liked_state {
-                    LikedState::Loved => {
-                        discarded_loves.push(text);
-                    }
-                    LikedState::Liked => {
-                        discarded_likes.push(text);
-                    }
-                    LikedState::Disliked => {
-                        discarded_dislikes.push(text);
-                    }
-                    LikedState::Neutral => {
-                        discarded_others.push(text);
-                    }
-                }
             }
-        }
-        if let Some(comment) = comment {
-            if i < 10 {
+            HistoryEntry::Comment(comment) => {
+                comment_ids.push(comment.id);
+                // Spell out which way the feedback leans so it isn't read as a request either way
                 history_string.push(format!(
-                    "{datetime_text} - User commented: '{}'",
+                    "{} - {} '{}'",
+                    format_duration(cur_time - comment.datetime),
+                    match comment.sentiment() {
+                        CommentSentiment::Positive => "USER LIKES:",
+                        CommentSentiment::Negative => "USER DISLIKES:",
+                        CommentSentiment::Neutral => "User commented:",
+                    },
                     comment.comment
                 ));
             }
@@ -162,23 +331,17 @@ pub async fn generate_prompt(client: &Client, api_key: &str) -> Result<(String,
                 "role": "user",
                 "content": format!(
                     "Summarise this history of image descriptions, taking out just the key concepts to create 3 comma separated lists of them without new lines, do not include common things like seasons, time of day etc, do not repeat similar items and err on the side of fewer items, ideally 1 word per item, max 3 words per item if needed\nExample output: (user LOVED: item, item) (user liked: item, item, item) (user disliked: item, item) (others: item, item)\n\nLoved items: {}\nLiked items: {}\nDisliked items: {}\nOther items: {}\nOutput:",
-                    discarded_loves.join(", "),
-                    discarded_likes.join(", "),
-                    discarded_dislikes.join(", "),
-                    discarded_others.join(", ")
+                    selection.discarded_loves.join(", "),
+                    selection.discarded_likes.join(", "),
+                    selection.discarded_dislikes.join(", "),
+                    selection.discarded_others.join(", ")
                 )
             }
         ],
         "max_completion_tokens": 512
     });
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {api_key}"))
-        .json(&request_body)
-        .send()
-        .await?;
-    let response_json: Value = response.json().await?;
+    let (response_json, _) = state.llm.chat_completion(request_body).await?;
+    let usage = parse_usage(&response_json);
     let discarded_summary = response_json["choices"]
         .get(0)
         .and_then(|choice| choice["message"]["content"].as_str())
@@ -191,49 +354,130 @@ pub async fn generate_prompt(client: &Client, api_key: &str) -> Result<(String,
     // Create the image description
     let history_string = history_string.join("\n");
 
-    Ok((history_string, database.style))
+    Ok(PromptContext {
+        history: history_string,
+        time_of_day: time_of_day::rebalance_target(&database),
+        style: database.style,
+        comment_ids,
+        templates: database.prompt_templates,
+        usage,
+    })
 }
 
-pub async fn generate(message: Option<String>) -> Result<PromptData> {
+/// Write a new prompt, or a variation of `variation_of` when given the prompt of an existing wallpaper
+pub async fn generate(
+    state: &AppState,
+    message: Option<String>,
+    mode: GenerationMode,
+    variation_of: Option<&str>,
+) -> Result<GeneratedPrompt> {
     let client = Client::new();
-    let api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
 
     let user_message = message.map_or_else(String::new, |message| format!("'User messaged '{message}', this takes precedence over any previous comments and prompts', "));
 
-    let (history_string, style) = generate_prompt(&client, &api_key).await?;
+    let PromptContext {
+        history: history_string,
+        style,
+        comment_ids,
+        templates,
+        mut usage,
+        time_of_day,
+    } = generate_prompt(state, mode).await?;
+
+    // Lean towards the current season if enabled, or the users override
+    let season = style.season_override.or_else(|| {
+        config::var("SEASONAL_MODE")
+            .is_some_and(|mode| mode.eq_ignore_ascii_case("on"))
+            .then(|| Season::from_month(Utc::now().month()))
+    });
+    if let Some(season) = season {
+        log::info!("Using {} seasonal theme", season.name());
+    }
+    let seasonal_hint = season.map(Season::hint);
+    let seasonal_message = seasonal_hint.map_or_else(String::new, |hint| {
+        format!("\nLean towards the seasonal theme '{hint}'")
+    });
+
+    // Rebalance the library between bright daytime and moody nighttime images
+    if let Some(time_of_day) = time_of_day {
+        log::info!("Targeting time of day: {}", time_of_day.directive());
+    }
+    let time_of_day_message = time_of_day.map_or_else(String::new, |time_of_day| {
+        format!("\nThe library is skewed, {}", time_of_day.directive())
+    });
+
+    // Match the atmosphere to the weather outside, generation goes ahead without it if the lookup fails
+    let weather_context = weather::current(&client).await;
+    let weather_message = weather_context
+        .as_ref()
+        .map_or_else(String::new, |weather| {
+            format!("\nCurrent weather: {weather}, lean towards an atmosphere that matches it")
+        });
+
+    // The moodboard steers the look of the full prompt, the description stays free to pick any subject
+    let moodboard_description = moodboard::description(state).await;
+    let moodboard_message = moodboard_description
+        .as_ref()
+        .map_or_else(String::new, |description| {
+            format!("\nReference aesthetic: {description}, lean towards it")
+        });
+
+    let direction = match mode {
+        GenerationMode::Normal => format!(
+            "prioritise users comments as feedback and weigh notes on specific images above general comments, aim for variety above all else, every image should be totally refreshing with little in common with the previous few\nTypes of content to include (not exhaustive just take inspiration) '{}'",
+            describe_themes(&style.contents)
+        ),
+        GenerationMode::Wild => "ignore any usual preferences, pick a completely random artistic movement, subject and colour palette that the user would never expect".to_string(),
+        GenerationMode::StrictStyle => format!(
+            "stay very close to the loved prompts in the history, keeping their subjects and mood and only varying the details\nTypes of content to include '{}'",
+            describe_themes(&style.contents)
+        ),
+    };
+    // Sent to both requests so the description and the full prompt both move away from the original
+    let variation_message = variation_of.map(|original| {
+        json!({
+            "role": "system",
+            "name": "variation",
+            "content": format!(
+                "Create a variation of this prompt that preserves the theme but changes the composition, lighting, and colour palette significantly: {original}"
+            )
+        })
+    });
+
+    let mut messages = Vec::new();
+    if !history_string.is_empty() {
+        messages.push(json!({
+            "role": "system",
+            "name": "history",
+            "content": format!("History of previous prompts and comments:\n{history_string}")
+        }));
+    }
+    messages.push(json!({
+        "role": "system",
+        "content": format!(
+            "You are a wallpaper image description generator, describe a wallpaper image within 10 words\nDescribe in the simplest of terms without detail, {}\nNever include anything '{}'{}{}{}",
+            direction,
+            style.negative_contents.replace('\n', " "),
+            seasonal_message,
+            time_of_day_message,
+            weather_message
+        )
+    }));
+    messages.extend(variation_message.clone());
+    messages.push(json!({
+        "role": "user",
+        "content": format!("Create me a new image prompt, {}Prompt:", user_message)
+    }));
+    let mut context = system_context(&messages);
     let request_body = json!({
         "model": "gpt-4o",
-        "messages": [
-            {
-                "role": "system",
-                "name": "history",
-                "content": format!("History of previous prompts and comments:\n{history_string}")
-            },
-            {
-                "role": "system",
-                "content": format!(
-                    "You are a wallpaper image description generator, describe a wallpaper image within 10 words\nDescribe in the simplest of terms without detail, prioritise users comments as feedback, aim for variety above all else, every image should be totally refreshing with little in common with the previous few\nTypes of content to include (not exhaustive just take inspiration) '{}'\nNever include anything '{}'",
-                    style.contents.replace('\n', " "),
-                    style.negative_contents.replace('\n', " ")
-                )
-            },
-            {
-                "role": "user",
-                "content": format!("Create me a new image prompt, {}Prompt:", user_message)
-            }
-        ],
+        "messages": messages,
         "max_completion_tokens": 60,
         "temperature": 1.4,
         "presence_penalty": 0.6
     });
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {api_key}"))
-        .json(&request_body)
-        .send()
-        .await?;
-    let response_json: Value = response.json().await?;
+    let (response_json, _) = state.llm.chat_completion(request_body).await?;
+    usage += parse_usage(&response_json);
     let image_description = response_json["choices"]
         .get(0)
         .and_then(|choice| choice["message"]["content"].as_str())
@@ -243,28 +487,64 @@ pub async fn generate(message: Option<String>) -> Result<PromptData> {
         )?;
     log::info!("Generated description: {}", image_description);
 
+    // The users own templates are examples of the kind of prompts they like
+    let template_message = if templates.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "\nExample prompt templates written by the user, {{slot}} marks a part to fill in: {}",
+            templates
+                .iter()
+                .map(|template| format!("'{}'", template.template.replace('\n', " ")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    };
+
+    let style_message = match mode {
+        GenerationMode::Normal => format!(
+            "The overall style direction is '{}' (include the guiding style in every prompt, not exact wording but the meaning)",
+            style.style.replace('\n', " ")
+        ),
+        GenerationMode::Wild => "Choose a bold artistic style that suits the description, not a safe or common one".to_string(),
+        GenerationMode::StrictStyle => format!(
+            "The style must be exactly '{}' (include the guiding style in every prompt, not exact wording but the meaning)",
+            style.style.replace('\n', " ")
+        ),
+    };
+
     // Make another gpt request to write out the full prompt in the correct format
+    let mut prompt_messages = vec![
+        json!({
+            "role": "system",
+            "name": "prompt_guidelines",
+            "content": PROMPT_GUIDELINES
+        }),
+        json!({
+            "role": "system",
+            "content": format!(
+                "You are a wallpaper image prompt generator, write a prompt for an wallpaper image without new lines, follow the prompt guidelines for best results\n{}\nNever include anything '{}'{}{}{}{}",
+                style_message,
+                style.negative_contents.replace('\n', " "),
+                writer_style_message(&style),
+                template_message,
+                weather_message,
+                moodboard_message
+            )
+        }),
+        json!({
+            "role": "user",
+            "content": format!("Create me a new image prompt from this description (use this only as a guide not a strict command, expand on it, alter details etc as you see fit) '{}', {}Prompt:", image_description, user_message)
+        }),
+    ];
+    if let Some(variation_message) = variation_message {
+        prompt_messages.insert(prompt_messages.len() - 1, variation_message);
+    }
+    context.push_str("\n\n");
+    context.push_str(&system_context(&prompt_messages));
     let request_body = json!({
         "model": "gpt-4o",
-        "messages": [
-            {
-                "role": "system",
-                "name": "prompt_guidelines",
-                "content": PROMPT_GUIDELINES
-            },
-            {
-                "role": "system",
-                "content": format!(
-                    "You are a wallpaper image prompt generator, write a prompt for an wallpaper image in a few sentences without new lines, follow the prompt guidelines for best results\nThe overall style direction is '{}' (include the guiding style in every prompt, not exact wording but the meaning)\nNever include anything '{}'",
-                    style.style.replace('\n', " "),
-                    style.negative_contents.replace('\n', " ")
-                )
-            },
-            {
-                "role": "user",
-                "content": format!("Create me a new image prompt from this description (use this only as a guide not a strict command, expand on it, alter details etc as you see fit) '{}', {}Prompt:", image_description, user_message)
-            }
-        ],
+        "messages": prompt_messages,
         "response_format": {
             "type": "json_schema",
             "json_schema": {
@@ -284,17 +564,14 @@ pub async fn generate(message: Option<String>) -> Result<PromptData> {
                 "strict": true
             }
         },
-        "max_completion_tokens": 256
+        "max_completion_tokens": match style.prompt_length {
+            PromptLength::Long => 512,
+            PromptLength::Short | PromptLength::Medium => 256,
+        }
     });
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {api_key}"))
-        .json(&request_body)
-        .send()
-        .await?;
-    let response_json: Value = response.json().await?;
-    let parsed_response: PromptData = serde_json::from_str(
+    let (response_json, llm_provider) = state.llm.chat_completion(request_body).await?;
+    usage += parse_usage(&response_json);
+    let mut parsed_response: PromptData = serde_json::from_str(
         &response_json["choices"]
             .get(0)
             .and_then(|choice| choice["message"]["content"].as_str())
@@ -303,6 +580,318 @@ pub async fn generate(message: Option<String>) -> Result<PromptData> {
                 |content| Ok(content.to_string()),
             )?,
     )?;
+    parsed_response.seasonal_hint = seasonal_hint.map(str::to_string);
+    parsed_response.time_of_day = time_of_day;
+
+    // Optionally have the LLM critique and rewrite its own prompt, keeping the first draft if that fails
+    if refinement_enabled() {
+        match refine_prompt(&*state.llm, &parsed_response.prompt).await {
+            Ok((prompt, refine_usage)) => {
+                usage += refine_usage;
+                log::info!("Refined prompt: {}", prompt);
+                match shorten_prompt(&*state.llm, &prompt).await {
+                    Ok(shortened_prompt) => parsed_response.shortened_prompt = shortened_prompt,
+                    Err(e) => log::error!("Failed to shorten refined prompt {:?}", e),
+                }
+                parsed_response.prompt = prompt;
+                parsed_response.was_refined = true;
+            }
+            Err(e) => log::error!("Failed to refine prompt {:?}", e),
+        }
+    }
+
+    log::info!(
+        "Prompt used {} tokens, estimated cost ${:.4}",
+        usage.total_tokens,
+        usage.estimated_cost_usd
+    );
+
+    Ok(GeneratedPrompt {
+        prompt_data: parsed_response,
+        comment_ids,
+        usage,
+        llm_provider,
+        context,
+        weather_context,
+        moodboard_influence: moodboard_description.is_some(),
+    })
+}
+
+/// `PROMPT_REFINEMENT` adds a critique pass to every prompt, off by default as it doubles the LLM calls
+fn refinement_enabled() -> bool {
+    config::var("PROMPT_REFINEMENT").is_some_and(|value| value.eq_ignore_ascii_case("true"))
+}
+
+/// Send a prompt back to the LLM to critique and rewrite it
+async fn refine_prompt(llm: &dyn LlmProvider, prompt: &str) -> Result<(String, LlmUsage)> {
+    let request_body = json!({
+        "model": "gpt-4o",
+        "messages": [
+            {
+                "role": "system",
+                "content": "Critique this prompt for a wallpaper image and rewrite it to be more visually compelling and specific. Return only the improved prompt."
+            },
+            {
+                "role": "user",
+                "content": prompt
+            }
+        ],
+        "max_completion_tokens": 256
+    });
+    let (response_json, _) = llm.chat_completion(request_body).await?;
+    let refined = response_json["choices"]
+        .get(0)
+        .and_then(|choice| choice["message"]["content"].as_str())
+        .map(|content| content.trim().replace('\n', " "))
+        .filter(|content| !content.is_empty())
+        .ok_or_else(|| anyhow!("No content found in response {}", response_json))?;
+    Ok((refined, parse_usage(&response_json)))
+}
+
+/// Ask for a one sentence note on how a comment influenced the new prompt
+pub async fn feedback_note(
+    llm: &dyn LlmProvider,
+    comment: &str,
+    prompt_data: &PromptData,
+) -> Result<String> {
+    let request_body = json!({
+        "model": "gpt-4o-mini",
+        "messages": [
+            {
+                "role": "system",
+                "content": "You explain to a user how their feedback on previous wallpapers was taken into account when writing a new wallpaper prompt, reply with a single short sentence addressed to the user"
+            },
+            {
+                "role": "user",
+                "content": format!(
+                    "Feedback: '{comment}'\nNew prompt: '{}'\nNote:",
+                    prompt_data.shortened_prompt
+                )
+            }
+        ],
+        "max_completion_tokens": 60
+    });
+    let (response_json, _) = llm.chat_completion(request_body).await?;
+    response_json["choices"]
+        .get(0)
+        .and_then(|choice| choice["message"]["content"].as_str())
+        .map_or_else(
+            || Err(anyhow!("No content found in response {}", response_json)),
+            |content| Ok(content.trim().to_string()),
+        )
+}
 
-    Ok(parsed_response)
+/// Write the shortened prompt for a prompt the user edited by hand
+pub async fn shorten_prompt(llm: &dyn LlmProvider, prompt: &str) -> Result<String> {
+    let request_body = json!({
+        "model": "gpt-4o-mini",
+        "messages": [
+            {
+                "role": "system",
+                "content": "Shorten the wallpaper image prompt to only the image description not style, max 25 words, reply with just the shortened prompt"
+            },
+            {
+                "role": "user",
+                "content": prompt
+            }
+        ],
+        "max_completion_tokens": 60
+    });
+    let (response_json, _) = llm.chat_completion(request_body).await?;
+    response_json["choices"]
+        .get(0)
+        .and_then(|choice| choice["message"]["content"].as_str())
+        .map_or_else(
+            || Err(anyhow!("No content found in response {}", response_json)),
+            |content| Ok(content.trim().to_string()),
+        )
+}
+
+/// Pull a handful of short tags like "forest" or "golden hour" out of a prompt
+pub async fn extract_tags(llm: &dyn LlmProvider, prompt: &str) -> Result<(Vec<String>, LlmUsage)> {
+    let request_body = json!({
+        "model": "gpt-4o-mini",
+        "messages": [
+            {
+                "role": "system",
+                "content": format!(
+                    "Extract {MIN_TAGS} to {MAX_TAGS} tags from the wallpaper image prompt, each one or two lowercase words covering the subject, setting, lighting or art style, like 'forest', 'golden hour', 'digital painting' or 'abstract'"
+                )
+            },
+            {
+                "role": "user",
+                "content": prompt
+            }
+        ],
+        "response_format": {
+            "type": "json_schema",
+            "json_schema": {
+                "name": "tags",
+                "schema": {
+                    "type": "object",
+                    "properties": {
+                        "tags": { "type": "array", "items": { "type": "string" } },
+                    },
+                    "required": ["tags"],
+                    "additionalProperties": false
+                },
+                "strict": true
+            }
+        },
+        "max_completion_tokens": 80
+    });
+    let (response_json, _) = llm.chat_completion(request_body).await?;
+    let content = response_json["choices"]
+        .get(0)
+        .and_then(|choice| choice["message"]["content"].as_str())
+        .ok_or_else(|| anyhow!("No content found in response {}", response_json))?;
+    let tags = serde_json::from_str::<Value>(content)?["tags"]
+        .as_array()
+        .map(|tags| {
+            tags.iter()
+                .filter_map(|tag| tag.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok((clean_tags(tags), parse_usage(&response_json)))
+}
+
+/// Lowercase, drop anything longer than two words and duplicates, keeping at most `MAX_TAGS`
+fn clean_tags(tags: Vec<String>) -> Vec<String> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        if tag.is_empty() || tag.split(' ').count() > 2 || cleaned.contains(&tag) {
+            continue;
+        }
+        cleaned.push(tag);
+    }
+    cleaned.truncate(MAX_TAGS);
+    cleaned
+}
+
+/// Caption a reference image the user wants a wallpaper in the mood of
+pub async fn describe_image(llm: &dyn LlmProvider, image_uri: &str) -> Result<String> {
+    let request_body = json!({
+        "model": "gpt-4o",
+        "messages": [
+            {
+                "role": "system",
+                "content": "Describe the image in two or three sentences for an artist painting a wallpaper inspired by it, cover the subject, setting, colours, lighting and mood"
+            },
+            {
+                "role": "user",
+                "content": [
+                    {
+                        "type": "image_url",
+                        "image_url": { "url": image_uri, "detail": "low" }
+                    }
+                ]
+            }
+        ],
+        "max_completion_tokens": 150
+    });
+    let (response_json, _) = llm.chat_completion(request_body).await?;
+    response_json["choices"]
+        .get(0)
+        .and_then(|choice| choice["message"]["content"].as_str())
+        .map_or_else(
+            || Err(anyhow!("No content found in response {}", response_json)),
+            |content| Ok(content.trim().to_string()),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_weighted_themes_in_order() {
+        let mut themes = ContentTheme::parse_list("epic fantasy, surreal,\n abstract,, landscapes");
+        assert_eq!(themes.len(), 4);
+        themes[0].weight = 2.5;
+        themes[1].weight = 1.5;
+        themes[3].weight = 0.5;
+        assert_eq!(
+            describe_themes(&themes),
+            "epic fantasy (strongly prefer), surreal (prefer), abstract, landscapes (occasionally)"
+        );
+    }
+
+    #[test]
+    fn guides_prompt_length() {
+        let mut style = DatabaseStyle::default();
+        for (length, words) in [
+            (PromptLength::Short, "20 to 40 words"),
+            (PromptLength::Medium, "50 to 80 words"),
+            (PromptLength::Long, "120 to 160 words"),
+        ] {
+            style.prompt_length = length;
+            let message = writer_style_message(&style);
+            assert!(message.contains(words), "{message}");
+            assert!(!message.contains("Additional instructions"));
+        }
+
+        style.extra_instructions = "Avoid text\nmention the lens ".to_string();
+        assert!(writer_style_message(&style)
+            .ends_with("Additional instructions from the user: Avoid text mention the lens"));
+    }
+
+    #[test]
+    fn cleans_extracted_tags() {
+        let tags = [
+            "Forest",
+            " golden   hour ",
+            "\"Digital Painting\"",
+            "forest",
+            "a very long tag",
+            "",
+            "abstract",
+            "mist",
+            "dawn",
+            "river",
+            "pines",
+            "calm",
+        ]
+        .map(str::to_string)
+        .to_vec();
+        assert_eq!(
+            clean_tags(tags),
+            vec![
+                "forest",
+                "golden hour",
+                "digital painting",
+                "abstract",
+                "mist",
+                "dawn",
+                "river",
+                "pines"
+            ]
+        );
+    }
+
+    #[test]
+    fn migrates_contents_string() {
+        let style: DatabaseStyle = ron::from_str(
+            "(style: \"painted\", contents: \"epic fantasy, surreal\", negative_contents: \"\")",
+        )
+        .unwrap();
+        assert!(style.contents == ContentTheme::parse_list("epic fantasy, surreal"));
+        assert!(style
+            .contents
+            .iter()
+            .all(|theme| theme.emphasis().is_none()));
+
+        let data = ron::to_string(&style).unwrap();
+        let style: DatabaseStyle = ron::from_str(&data).unwrap();
+        assert_eq!(style.contents.len(), 2);
+        let style: DatabaseStyle =
+            bincode::deserialize(&bincode::serialize(&style).unwrap()).unwrap();
+        assert_eq!(style.contents[1].text, "surreal");
+    }
 }
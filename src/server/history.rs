@@ -0,0 +1,307 @@
+use crate::common::{CommentData, Database, HistoryConfig, LikedState, Source, WallpaperData};
+use std::collections::HashSet;
+
+/// Word overlap above which two prompts are treated as the same
+const DUPLICATE_SIMILARITY: f32 = 0.8;
+
+pub enum HistoryEntry<'a> {
+    Wallpaper(&'a WallpaperData),
+    Comment(&'a CommentData),
+}
+
+/// History to include in the LLM context, newest first
+#[derive(Default)]
+pub struct HistorySelection<'a> {
+    pub entries: Vec<HistoryEntry<'a>>,
+
+    // Older prompts that only get summarised into key concepts
+    pub discarded_loves: Vec<&'a str>,
+    pub discarded_likes: Vec<&'a str>,
+    pub discarded_dislikes: Vec<&'a str>,
+    pub discarded_others: Vec<&'a str>,
+}
+
+/// Pick which wallpapers and comments are shown to the LLM
+/// Loved prompts are always included up to a cap, then the most recent liked and neutral, and only a few disliked
+pub fn select_history<'a>(database: &'a Database, config: &HistoryConfig) -> HistorySelection<'a> {
+    let mut wallpapers = database
+        .wallpapers
+        .values()
+        .filter(|wallpaper| matches!(wallpaper.source, Source::Generated | Source::Imported))
+        .collect::<Vec<_>>();
+    wallpapers.sort_by_key(|wallpaper| std::cmp::Reverse(wallpaper.datetime));
+    let mut comments = database.comments.values().collect::<Vec<_>>();
+    comments.sort_by_key(|comment| std::cmp::Reverse(comment.datetime));
+
+    let mut selection = HistorySelection::default();
+    let mut seen: Vec<HashSet<String>> = Vec::new();
+    let (mut loved, mut recent, mut disliked, mut summarised) = (0, 0, 0, 0);
+    let mut included = Vec::new();
+    for wallpaper in wallpapers {
+        // Skip prompts that are near identical to a newer one
+        let words = prompt_words(history_text(wallpaper));
+        if seen
+            .iter()
+            .any(|other| similarity(&words, other) >= DUPLICATE_SIMILARITY)
+        {
+            continue;
+        }
+        seen.push(words);
+
+        let counter = match wallpaper.combined_liked_state() {
+            LikedState::Loved => (&mut loved, config.max_loved),
+            LikedState::Liked | LikedState::Neutral => (&mut recent, config.recent_count),
+            LikedState::Disliked => (&mut disliked, config.max_disliked),
+        };
+        if *counter.0 < counter.1 {
+            *counter.0 += 1;
+            included.push(wallpaper);
+        } else if summarised < config.max_summarised {
+            summarised += 1;
+            let text = history_text(wallpaper);
+            match wallpaper.combined_liked_state() {
+                LikedState::Loved => selection.discarded_loves.push(text),
+                LikedState::Liked => selection.discarded_likes.push(text),
+                LikedState::Disliked => selection.discarded_dislikes.push(text),
+                LikedState::Neutral => selection.discarded_others.push(text),
+            }
+        }
+    }
+
+    // Merge the included wallpapers with the recent comments, newest first
+    let mut entries = included
+        .into_iter()
+        .map(|wallpaper| (wallpaper.datetime, HistoryEntry::Wallpaper(wallpaper)))
+        .chain(
+            comments
+                .into_iter()
+                .take(config.max_comments)
+                .map(|comment| (comment.datetime, HistoryEntry::Comment(comment))),
+        )
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|(datetime, _)| std::cmp::Reverse(*datetime));
+    selection.entries = entries.into_iter().map(|(_, entry)| entry).collect();
+
+    selection
+}
+
+/// How a wallpaper is described to the LLM, imports are titled by file name so their caption says more
+pub fn history_text(wallpaper: &WallpaperData) -> &str {
+    match wallpaper.source {
+        Source::Imported => &wallpaper.prompt_data.prompt,
+        _ => &wallpaper.prompt_data.shortened_prompt,
+    }
+}
+
+fn prompt_words(prompt: &str) -> HashSet<String> {
+    prompt
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Jaccard similarity of two word sets
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{DatabaseStyle, ThumbnailSettings};
+    use chrono::{DateTime, Duration, Utc};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn wallpaper(prompt: &str, liked_state: LikedState, datetime: DateTime<Utc>) -> WallpaperData {
+        let mut wallpaper = WallpaperData {
+            datetime,
+            shared_liked_state: liked_state,
+            ..WallpaperData::sample()
+        };
+        wallpaper.prompt_data.prompt = prompt.to_string();
+        wallpaper.prompt_data.shortened_prompt = prompt.to_string();
+        wallpaper
+    }
+
+    /// Build a database where entry i is i hours old
+    fn database(entries: &[(&str, LikedState)]) -> Database {
+        let now = Utc::now();
+        Database {
+            style: DatabaseStyle::default(),
+            history_config: HistoryConfig::default(),
+            prompt_templates: Vec::new(),
+            wallpapers: entries
+                .iter()
+                .enumerate()
+                .map(|(i, (prompt, state))| {
+                    let wallpaper = wallpaper(prompt, *state, now - Duration::hours(i as i64));
+                    (wallpaper.id, wallpaper)
+                })
+                .collect(),
+            comments: HashMap::new(),
+            pending_prompts: HashMap::new(),
+            fetch_profiles: Vec::new(),
+            users: HashMap::new(),
+            viewer: None,
+            thumbnail_settings: ThumbnailSettings::default(),
+            stale_thumbnails: Vec::new(),
+            presets: HashMap::new(),
+            share_links: Vec::new(),
+        }
+    }
+
+    fn included_prompts<'a>(selection: &'a HistorySelection) -> Vec<&'a str> {
+        selection
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                HistoryEntry::Wallpaper(wallpaper) => {
+                    Some(wallpaper.prompt_data.shortened_prompt.as_str())
+                }
+                HistoryEntry::Comment(_) => None,
+            })
+            .collect()
+    }
+
+    fn config(max_loved: usize, recent_count: usize, max_disliked: usize) -> HistoryConfig {
+        HistoryConfig {
+            max_loved,
+            recent_count,
+            max_disliked,
+            ..HistoryConfig::default()
+        }
+    }
+
+    #[test]
+    fn includes_old_loved_prompts() {
+        let database = database(&[
+            ("red desert", LikedState::Neutral),
+            ("blue ocean", LikedState::Neutral),
+            ("green forest", LikedState::Neutral),
+            ("golden castle", LikedState::Loved),
+        ]);
+        let selection = select_history(&database, &config(5, 1, 5));
+        assert_eq!(
+            included_prompts(&selection),
+            vec!["red desert", "golden castle"]
+        );
+        assert_eq!(
+            selection.discarded_others,
+            vec!["blue ocean", "green forest"]
+        );
+    }
+
+    #[test]
+    fn caps_loved_and_disliked() {
+        let database = database(&[
+            ("loved one", LikedState::Loved),
+            ("hated one", LikedState::Disliked),
+            ("loved two", LikedState::Loved),
+            ("hated two", LikedState::Disliked),
+        ]);
+        let selection = select_history(&database, &config(1, 5, 1));
+        assert_eq!(included_prompts(&selection), vec!["loved one", "hated one"]);
+        assert_eq!(selection.discarded_loves, vec!["loved two"]);
+        assert_eq!(selection.discarded_dislikes, vec!["hated two"]);
+    }
+
+    #[test]
+    fn orders_newest_first() {
+        let database = database(&[
+            ("first", LikedState::Liked),
+            ("second", LikedState::Loved),
+            ("third", LikedState::Disliked),
+        ]);
+        let selection = select_history(&database, &HistoryConfig::default());
+        assert_eq!(
+            included_prompts(&selection),
+            vec!["first", "second", "third"]
+        );
+    }
+
+    #[test]
+    fn skips_near_duplicate_prompts() {
+        let database = database(&[
+            ("A misty forest at dawn with deer", LikedState::Neutral),
+            ("a misty forest at dawn, with deer", LikedState::Liked),
+            ("A neon city at night", LikedState::Neutral),
+        ]);
+        let selection = select_history(&database, &HistoryConfig::default());
+        assert_eq!(
+            included_prompts(&selection),
+            vec!["A misty forest at dawn with deer", "A neon city at night"]
+        );
+    }
+
+    #[test]
+    fn describes_imported_images_by_caption() {
+        let mut database = database(&[("misty forest", LikedState::Liked)]);
+        for wallpaper in database.wallpapers.values_mut() {
+            wallpaper.source = Source::Imported;
+            wallpaper.prompt_data.prompt = "A misty pine forest at dawn".to_string();
+        }
+        let selection = select_history(&database, &HistoryConfig::default());
+        let HistoryEntry::Wallpaper(wallpaper) = &selection.entries[0] else {
+            panic!("Expected a wallpaper entry");
+        };
+        assert_eq!(history_text(wallpaper), "A misty pine forest at dawn");
+    }
+
+    #[test]
+    fn excludes_uploaded_images() {
+        let mut database = database(&[("my holiday photo", LikedState::Loved)]);
+        for wallpaper in database.wallpapers.values_mut() {
+            wallpaper.source = Source::Uploaded;
+        }
+        let selection = select_history(&database, &HistoryConfig::default());
+        assert!(selection.entries.is_empty());
+        assert!(selection.discarded_loves.is_empty());
+    }
+
+    #[test]
+    fn limits_comments_and_summaries() {
+        let mut database = database(&[
+            ("one", LikedState::Neutral),
+            ("two", LikedState::Neutral),
+            ("three", LikedState::Neutral),
+        ]);
+        for i in 0..3 {
+            let id = Uuid::new_v4();
+            database.comments.insert(
+                id,
+                CommentData {
+                    id,
+                    datetime: Utc::now() - Duration::minutes(i),
+                    comment: format!("comment {i}"),
+                    response: None,
+                    author_uuid: None,
+                    used: false,
+                    reactions: HashMap::new(),
+                    sentiment: None,
+                },
+            );
+        }
+        let selection = select_history(
+            &database,
+            &HistoryConfig {
+                recent_count: 1,
+                max_comments: 2,
+                max_summarised: 1,
+                ..HistoryConfig::default()
+            },
+        );
+        let comments = selection
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry, HistoryEntry::Comment(_)))
+            .count();
+        assert_eq!(comments, 2);
+        assert_eq!(selection.discarded_others, vec!["two"]);
+    }
+}
@@ -1,209 +1,717 @@
 use crate::common::{
-    ColorData, ImageFile, LikedState, PromptData, TokenStringPacket, TokenUuidLikedPacket,
-    TokenUuidPacket, WallpaperData,
+    utils::hue_distance, AuditAction, ColorData, ColorTemperature, Database, FetchProfile,
+    GenerateFromImagePacket, GeneratePacket, GenerationMode, ImageCropPacket, ImageDarkenPacket,
+    ImageFile, ImageNotePacket, ImageTransformPacket, ImageUploadPacket, LikedState, LlmUsage,
+    Orientation, PromptData, RecreateEditedPacket, RetentionPolicy, Source, TokenUuidLikedPacket,
+    TokenUuidPacket, TokenUuidRatingPacket, TokenUuidsLikedPacket, TokenUuidsPacket, Transform,
+    WallpaperData,
 };
-use crate::server::{auth::verify_token, gpt, read_database, write_database};
-use crate::WALLPAPERS_DIR;
-use anyhow::{anyhow, Result};
+use crate::server::{
+    audit,
+    auth::{token_account, verify_token},
+    commenting, config, encoding, error_status, generation_context, gpt,
+    models::{self, ImageModel},
+    net::{check_status, with_retry, RetryPolicy},
+    providers::ImageProvider,
+    resolution::ResolutionPreset,
+    status::GenerationGuard,
+    thumbnails, AppState,
+};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
 use axum::{
     body::Bytes,
-    http::{HeaderMap, HeaderValue, StatusCode},
+    extract::{self, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
 };
 use base64::{engine::general_purpose::STANDARD, Engine as _};
-use chrono::{Timelike, Utc};
+use chrono::{DateTime, Timelike, Utc};
 use image::codecs::jpeg::JpegEncoder;
 use image::imageops::FilterType;
 use image::{DynamicImage, GenericImageView, ImageReader, Pixel};
 use rand::seq::SliceRandom;
 use reqwest::Client;
+use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashMap;
 use std::io::Cursor;
-use std::{env, path::Path, time::Duration};
+use std::path::Path;
+use std::sync::Arc;
+use std::{env, time::Duration};
 use thumbhash::rgba_to_thumb_hash;
-use tokio::fs;
+use tokio::{fs, task::JoinSet};
 use uuid::Uuid;
 
 const TIMEOUT: u64 = 360;
-
-pub async fn generate(packet: Bytes) -> impl IntoResponse {
-    let packet: TokenStringPacket = match bincode::deserialize(&packet) {
+const SHARPNESS_RETRIES: usize = 3;
+const NEGATIVE_PROMPT_LIMIT: usize = 500;
+const MAX_GENERATION_CANDIDATES: u32 = 4;
+const SHORTENED_PROMPT_WORDS: usize = 25;
+/// Above 1 so shadows sink further than highlights, which keep their separation
+const DARKEN_GAMMA: f32 = 1.6;
+/// Recent wallpapers a candidate's hue is compared against for the diversity bonus
+const RECENT_HUES: usize = 10;
+
+pub async fn generate(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: GeneratePacket = match bincode::deserialize(&packet) {
         Ok(packet) => packet,
         Err(e) => {
             log::error!("Failed to deserialize generate_wallpaper packet: {:?}", e);
             return StatusCode::BAD_REQUEST;
         }
     };
-    if !verify_token(&packet.token).await.unwrap_or(false) {
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
         return StatusCode::UNAUTHORIZED;
     }
 
+    // A leading [PRESET:name] overrides the configured resolution
+    let (resolution, message) = ResolutionPreset::strip_prefix(&packet.message);
+    let summary = if message.is_empty() {
+        "Generated wallpaper".to_string()
+    } else {
+        format!("Generated wallpaper from '{message}'")
+    };
     match generate_wallpaper_impl(
+        &state,
         None,
-        if packet.string.is_empty() {
+        if message.is_empty() {
             None
         } else {
-            Some(packet.string)
+            Some(message.to_string())
         },
+        packet.mode,
+        resolution.unwrap_or_else(ResolutionPreset::from_env),
+        packet.model.as_deref(),
     )
     .await
     {
-        Ok(()) => StatusCode::OK,
+        Ok(id) => {
+            // The wallpaper is already stored, so a lost link is only logged
+            if let Some(comment_id) = packet.comment {
+                if let Err(e) = commenting::link_generated(&state, comment_id, id).await {
+                    log::error!("Failed to link comment {comment_id} to {id}: {:?}", e);
+                }
+            }
+            attribute(&state, id, &packet.token).await;
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Generate,
+                Some(id),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
         Err(e) => {
             log::error!("Failed to generate wallpaper: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            error_status(&e)
         }
     }
 }
 
-pub async fn latest() -> impl IntoResponse {
-    match read_database().await {
-        Ok(database) => {
-            let latest_image = database
-                .wallpapers
-                .into_values()
-                .max_by_key(|wallpaper| wallpaper.datetime);
+/// Generate a wallpaper in the mood of an image the user supplied, captioned for the LLM as recraft takes no image input
+pub async fn generate_from(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: GenerateFromImagePacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize generate_from packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let reference = match decode_upload(packet.data) {
+        Ok(image) => image,
+        Err(e) => {
+            log::error!("Failed to decode reference image: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
 
-            if let Some(wallpaper) = latest_image {
-                let file_name = wallpaper.upscaled_file.as_ref().map_or_else(
-                    || wallpaper.original_file.file_name.clone(),
-                    |upscaled_file| upscaled_file.file_name.clone(),
-                );
+    let (resolution, message) = ResolutionPreset::strip_prefix(&packet.message);
+    let result = async {
+        let image_uri = jpeg_data_uri(&reference.thumbnail(1024, 1024))?;
+        let caption = gpt::describe_image(&*state.llm, &image_uri).await?;
+        log::info!("Reference image described as: {caption}");
+        let mut reference_message = format!(
+            "The user supplied a reference image described as '{caption}', capture its mood without copying it"
+        );
+        if !message.is_empty() {
+            reference_message.push_str(&format!(". The user also said: {message}"));
+        }
+        let id = generate_wallpaper_impl(
+            &state,
+            None,
+            Some(reference_message),
+            packet.mode,
+            resolution.unwrap_or_else(ResolutionPreset::from_env),
+            packet.model.as_deref(),
+        )
+        .await?;
 
-                let image_path = Path::new(WALLPAPERS_DIR).join(&file_name);
-                match fs::read(&image_path).await {
-                    Ok(data) => {
-                        let mime_type = mime_guess::from_path(&image_path).first_or_octet_stream();
-                        let mut headers = HeaderMap::new();
-                        headers.insert(
-                            "Content-Type",
-                            HeaderValue::from_str(mime_type.as_ref()).unwrap(),
-                        );
-                        (StatusCode::OK, headers, data).into_response()
-                    }
-                    Err(e) => {
-                        log::error!("Failed to read image file: {:?}", e);
-                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                    }
-                }
+        // The wallpaper is already stored, so a lost reference is only logged
+        if let Err(e) = store_reference(&state, id, &reference).await {
+            log::error!("Failed to store reference image for {id}: {:?}", e);
+        }
+        Ok::<_, anyhow::Error>(id)
+    }
+    .await;
+
+    match result {
+        Ok(id) => {
+            let summary = if message.is_empty() {
+                "Generated wallpaper from a reference image".to_string()
             } else {
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            }
+                format!("Generated wallpaper from a reference image and '{message}'")
+            };
+            attribute(&state, id, &packet.token).await;
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Generate,
+                Some(id),
+                summary,
+            )
+            .await;
+            StatusCode::OK
         }
         Err(e) => {
-            log::error!("{:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            log::error!("Failed to generate wallpaper from reference: {:?}", e);
+            error_status(&e)
         }
     }
 }
 
-pub async fn favourites() -> impl IntoResponse {
-    match read_database().await {
-        Ok(database) => {
-            let liked_image: Option<WallpaperData> = database
+/// Save the reference image beside the wallpaper generated from it
+async fn store_reference(state: &AppState, id: Uuid, image: &DynamicImage) -> Result<()> {
+    let file_name = format!("{id}_reference.webp");
+    // Copied out of the encoder's buffer, which isn't Send and so can't be held across the write
+    let data = webp::Encoder::from_image(image)
+        .map_err(|e| anyhow!("Failed to encode reference image: {e}"))?
+        .encode(90.0)
+        .to_vec();
+    fs::write(state.config.wallpapers_dir.join(&file_name), data).await?;
+    let reference_file = ImageFile {
+        file_name,
+        width: image.width(),
+        height: image.height(),
+        resolution_preset: ResolutionPreset::Custom(image.width(), image.height()).to_string(),
+        alt_formats: Vec::new(),
+    };
+    state
+        .with_database(|database| {
+            let wallpaper = database
                 .wallpapers
-                .into_values()
-                .filter(|wallpaper| matches!(wallpaper.liked_state, LikedState::Liked))
-                .collect::<Vec<_>>()
-                .choose(&mut rand::thread_rng())
-                .cloned();
+                .get_mut(&id)
+                .ok_or_else(|| anyhow!("Wallpaper not found"))?;
+            wallpaper.reference_file = Some(reference_file);
+            Ok(())
+        })
+        .await
+}
 
-            if let Some(wallpaper) = liked_image {
-                let file_name = wallpaper.upscaled_file.as_ref().map_or_else(
-                    || wallpaper.original_file.file_name.clone(),
-                    |upscaled_file| upscaled_file.file_name.clone(),
+pub async fn latest(State(state): State<AppState>) -> impl IntoResponse {
+    let database = state.read_database().await;
+    let latest_image = database
+        .wallpapers
+        .into_values()
+        .max_by_key(|wallpaper| wallpaper.datetime);
+
+    if let Some(wallpaper) = latest_image {
+        let file_name = wallpaper.upscaled_file.as_ref().map_or_else(
+            || wallpaper.original_file.file_name.clone(),
+            |upscaled_file| upscaled_file.file_name.clone(),
+        );
+
+        let image_path = state.config.wallpapers_dir.join(&file_name);
+        match fs::read(&image_path).await {
+            Ok(data) => {
+                let mime_type = mime_guess::from_path(&image_path).first_or_octet_stream();
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    "Content-Type",
+                    HeaderValue::from_str(mime_type.as_ref()).unwrap(),
                 );
+                (StatusCode::OK, headers, data).into_response()
+            }
+            Err(e) => {
+                log::error!("Failed to read image file: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
 
-                let image_path = Path::new(WALLPAPERS_DIR).join(&file_name);
-                match fs::read(&image_path).await {
-                    Ok(data) => {
-                        let mime_type = mime_guess::from_path(&image_path).first_or_octet_stream();
-                        let mut headers = HeaderMap::new();
-                        headers.insert(
-                            "Content-Type",
-                            HeaderValue::from_str(mime_type.as_ref()).unwrap(),
-                        );
-                        (StatusCode::OK, headers, data).into_response()
-                    }
-                    Err(e) => {
-                        log::error!("Failed to read image file: {:?}", e);
-                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                    }
-                }
-            } else {
+#[derive(Deserialize)]
+pub struct FavouritesQuery {
+    token: Option<String>,
+}
+
+pub async fn favourites(
+    State(state): State<AppState>,
+    Query(query): Query<FavouritesQuery>,
+) -> impl IntoResponse {
+    let account = match likes_account(&state, query.token.as_deref()).await {
+        Ok(account) => account,
+        Err(status) => return status.into_response(),
+    };
+    let database = state.read_database().await;
+    let liked_image: Option<WallpaperData> = database
+        .wallpapers
+        .into_values()
+        .filter(|wallpaper| wallpaper.liked_state_for(account) == LikedState::Liked)
+        .collect::<Vec<_>>()
+        .choose(&mut rand::thread_rng())
+        .cloned();
+
+    if let Some(wallpaper) = liked_image {
+        let file_name = wallpaper.upscaled_file.as_ref().map_or_else(
+            || wallpaper.original_file.file_name.clone(),
+            |upscaled_file| upscaled_file.file_name.clone(),
+        );
+
+        let image_path = state.config.wallpapers_dir.join(&file_name);
+        match fs::read(&image_path).await {
+            Ok(data) => {
+                let mime_type = mime_guess::from_path(&image_path).first_or_octet_stream();
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    "Content-Type",
+                    HeaderValue::from_str(mime_type.as_ref()).unwrap(),
+                );
+                (StatusCode::OK, headers, data).into_response()
+            }
+            Err(e) => {
+                log::error!("Failed to read image file: {:?}", e);
                 StatusCode::INTERNAL_SERVER_ERROR.into_response()
             }
         }
-        Err(e) => {
-            log::error!("{:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
+
+/// Wallpapers served this recently are skipped by smartget while there are others to pick
+const RECENTLY_SERVED: chrono::Duration = chrono::Duration::hours(24);
+/// Degrees either side of a requested hue when no tolerance is given
+const DEFAULT_HUE_TOLERANCE: f32 = 30.0;
+/// How far the hue tolerance widens each time nothing matches
+const HUE_TOLERANCE_STEP: f32 = 30.0;
+
+#[derive(Deserialize)]
+pub struct SmartgetQuery {
+    temperature: Option<String>,
+    orientation: Option<String>,
+    profile: Option<String>,
+    hue: Option<f32>, // Degrees, to match a desktop accent colour
+    hue_tolerance: Option<f32>,
+    token: Option<String>, // Pick from this account's likes rather than anyone's
+}
+
+#[derive(Clone)]
+pub struct SmartgetFilter {
+    temperature: Option<ColorTemperature>,
+    orientation: Option<Orientation>,
+    hue: Option<(f32, f32)>, // Degrees and how far either side is accepted
+    brightness_range: Option<(f32, f32)>, // None follows the time of day
+    min_rating: Option<u8>,
+    loved_only: bool,
+    rest: chrono::Duration,
+    account: Option<Uuid>, // Whose likes count, anyone's when None
+}
+
+impl SmartgetFilter {
+    /// The constraints a saved profile adds, filters given in the query take priority
+    pub fn with_profile(self, profile: &FetchProfile) -> Self {
+        Self {
+            orientation: self.orientation.or(profile.orientation),
+            brightness_range: profile.brightness_range,
+            min_rating: profile.min_rating,
+            loved_only: profile.loved_only,
+            rest: profile.rest_hours.map_or(RECENTLY_SERVED, |hours| {
+                chrono::Duration::hours(hours.into())
+            }),
+            ..self
         }
     }
+
+    /// Accept hues further from the one asked for, false once every hue already matches
+    fn widen_hue(&mut self) -> bool {
+        match &mut self.hue {
+            Some((_, tolerance)) if *tolerance < 180.0 => {
+                *tolerance = (*tolerance + HUE_TOLERANCE_STEP).min(180.0);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn is_loved_dark_variant(&self, wallpaper: &WallpaperData) -> bool {
+        wallpaper.dark_variant && wallpaper.liked_state_for(self.account) == LikedState::Loved
+    }
+
+    fn matches(&self, wallpaper: &WallpaperData, now: DateTime<Utc>) -> bool {
+        let (min_brightness, max_brightness) = self
+            .brightness_range
+            .unwrap_or_else(|| time_of_day_brightness(now.hour()));
+        let brightness = wallpaper.color_data.top_20_percent_brightness;
+        let file = full_size_file(wallpaper);
+        let liked_state = wallpaper.liked_state_for(self.account);
+        let liked = if self.loved_only {
+            liked_state == LikedState::Loved
+        } else {
+            matches!(liked_state, LikedState::Liked | LikedState::Loved)
+        };
+        liked
+            && (min_brightness..=max_brightness).contains(&brightness)
+            && self
+                .min_rating
+                .is_none_or(|min_rating| wallpaper.rating.unwrap_or(3) >= min_rating)
+            && self
+                .temperature
+                .is_none_or(|temperature| wallpaper.color_data.color_temperature == temperature)
+            && self.hue.is_none_or(|(hue, tolerance)| {
+                hue_distance(wallpaper.color_data.hue * 360.0, hue) <= tolerance
+            })
+            && self
+                .orientation
+                .is_none_or(|orientation| match orientation {
+                    Orientation::Portrait => file.height > file.width,
+                    Orientation::Landscape => file.width >= file.height,
+                })
+    }
 }
 
-pub async fn smartget() -> impl IntoResponse {
-    let now = Utc::now();
-    let hour = now.hour();
+impl Default for SmartgetFilter {
+    fn default() -> Self {
+        Self {
+            temperature: None,
+            orientation: None,
+            hue: None,
+            brightness_range: None,
+            min_rating: None,
+            loved_only: false,
+            rest: RECENTLY_SERVED,
+            account: None,
+        }
+    }
+}
+
+impl SmartgetQuery {
+    /// The filters asked for, or None if a value isn't recognised
+    fn filter(&self) -> Option<SmartgetFilter> {
+        let temperature = match self
+            .temperature
+            .as_deref()
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            None => None,
+            Some("warm") => Some(ColorTemperature::Warm),
+            Some("cool") => Some(ColorTemperature::Cool),
+            Some("neutral") => Some(ColorTemperature::Neutral),
+            Some(_) => return None,
+        };
+        let orientation = match self
+            .orientation
+            .as_deref()
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            None => None,
+            Some("portrait") => Some(Orientation::Portrait),
+            Some("landscape") => Some(Orientation::Landscape),
+            Some(_) => return None,
+        };
+        let hue = match (self.hue, self.hue_tolerance) {
+            (None, _) => None,
+            (Some(hue), tolerance) => {
+                let tolerance = tolerance.unwrap_or(DEFAULT_HUE_TOLERANCE);
+                if !hue.is_finite() || !(0.0..=180.0).contains(&tolerance) {
+                    return None;
+                }
+                Some((hue.rem_euclid(360.0), tolerance))
+            }
+        };
+        Some(SmartgetFilter {
+            temperature,
+            orientation,
+            hue,
+            ..SmartgetFilter::default()
+        })
+    }
+
+    /// The filters asked for with any named profile applied, or the status to answer with
+    fn resolve(&self, database: &Database) -> Result<SmartgetFilter, StatusCode> {
+        let filter = self.filter().ok_or(StatusCode::BAD_REQUEST)?;
+        let Some(name) = &self.profile else {
+            return Ok(filter);
+        };
+        database
+            .fetch_profiles
+            .iter()
+            .find(|profile| profile.name.eq_ignore_ascii_case(name.trim()))
+            .map(|profile| filter.with_profile(profile))
+            .ok_or(StatusCode::NOT_FOUND)
+    }
+}
+
+/// The highest quality file of a wallpaper
+pub fn full_size_file(wallpaper: &WallpaperData) -> &ImageFile {
+    wallpaper
+        .upscaled_file
+        .as_ref()
+        .unwrap_or(&wallpaper.original_file)
+}
+
+/// How likely a wallpaper is to be picked, unrated ones count as three stars
+fn smartget_weight(wallpaper: &WallpaperData) -> u32 {
+    u32::from(wallpaper.rating.unwrap_or(3)).pow(2)
+}
 
-    // Define acceptable brightness range based on the time of day.
-    let acceptable_brightness_range = if (hour > 6 && hour < 10) || hour > 16 && hour < 22 {
+/// Acceptable brightness for the hour, dim at night and bright around midday
+fn time_of_day_brightness(hour: u32) -> (f32, f32) {
+    if (hour > 6 && hour < 10) || hour > 16 && hour < 22 {
         (0.3, 0.6)
     } else if (10..=16).contains(&hour) {
         (0.5, 1.0)
     } else {
         (0.0, 0.55)
+    }
+}
+
+fn is_night(hour: u32) -> bool {
+    !(7..22).contains(&hour)
+}
+
+/// The account a token belongs to, unauthorised if it doesn't match one and None without a token
+async fn likes_account(state: &AppState, token: Option<&str>) -> Result<Option<Uuid>, StatusCode> {
+    let Some(token) = token else {
+        return Ok(None);
     };
+    match token_account(state, token).await {
+        Ok(Some((uuid, _))) => Ok(Some(uuid)),
+        Ok(None) => Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            log::error!("{:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
 
-    match read_database().await {
-        Ok(database) => {
-            let liked_image: Option<WallpaperData> = database
-                .wallpapers
-                .into_values()
-                .filter(|wallpaper| {
-                    matches!(wallpaper.liked_state, LikedState::Liked | LikedState::Loved)
-                        && (wallpaper.color_data.top_20_percent_brightness
-                            >= acceptable_brightness_range.0
-                            && wallpaper.color_data.top_20_percent_brightness
-                                <= acceptable_brightness_range.1)
-                })
-                .collect::<Vec<_>>()
-                .choose(&mut rand::thread_rng())
-                .cloned();
+/// How many wallpapers smartget could pick from with these filters right now
+pub fn smartget_candidates<'a>(
+    wallpapers: impl IntoIterator<Item = &'a WallpaperData>,
+    filter: &SmartgetFilter,
+    now: DateTime<Utc>,
+) -> usize {
+    wallpapers
+        .into_iter()
+        .filter(|wallpaper| filter.matches(wallpaper, now))
+        .count()
+}
 
-            if let Some(wallpaper) = liked_image {
-                let file_name = wallpaper.upscaled_file.as_ref().map_or_else(
-                    || wallpaper.original_file.file_name.clone(),
-                    |upscaled_file| upscaled_file.file_name.clone(),
-                );
+/// Pick a liked wallpaper that suits the time of day and filters, avoiding recently served ones
+/// Higher rated wallpapers are drawn more often, and a hue that matches nothing is widened until something does
+fn smartget_choice(
+    wallpapers: impl IntoIterator<Item = WallpaperData>,
+    filter: &SmartgetFilter,
+    now: DateTime<Utc>,
+) -> Option<WallpaperData> {
+    let mut wallpapers = wallpapers.into_iter().collect::<Vec<_>>();
+    let mut filter = filter.clone();
+    while !wallpapers
+        .iter()
+        .any(|wallpaper| filter.matches(wallpaper, now))
+    {
+        if !filter.widen_hue() {
+            break;
+        }
+    }
+    wallpapers.retain(|wallpaper| filter.matches(wallpaper, now));
+    // At night a darkened copy of a loved wallpaper beats the rest when following the time of day
+    if filter.brightness_range.is_none()
+        && is_night(now.hour())
+        && wallpapers
+            .iter()
+            .any(|wallpaper| filter.is_loved_dark_variant(wallpaper))
+    {
+        wallpapers.retain(|wallpaper| filter.is_loved_dark_variant(wallpaper));
+    }
+    let (fresh, recent): (Vec<_>, Vec<_>) = wallpapers.into_iter().partition(|wallpaper| {
+        wallpaper
+            .last_served
+            .is_none_or(|served| now - served >= filter.rest)
+    });
+    let pool = if fresh.is_empty() { recent } else { fresh };
+    pool.choose_weighted(&mut rand::thread_rng(), smartget_weight)
+        .ok()
+        .cloned()
+}
 
-                let image_path = Path::new(WALLPAPERS_DIR).join(&file_name);
-                match fs::read(&image_path).await {
-                    Ok(data) => {
-                        let mime_type = mime_guess::from_path(&image_path).first_or_octet_stream();
-                        let mut headers = HeaderMap::new();
-                        headers.insert(
-                            "Content-Type",
-                            HeaderValue::from_str(mime_type.as_ref()).unwrap(),
-                        );
-                        (StatusCode::OK, headers, data).into_response()
-                    }
-                    Err(e) => {
-                        log::error!("Failed to read image file: {:?}", e);
-                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                    }
-                }
-            } else {
+pub async fn smartget(
+    State(state): State<AppState>,
+    Query(query): Query<SmartgetQuery>,
+) -> impl IntoResponse {
+    let account = match likes_account(&state, query.token.as_deref()).await {
+        Ok(account) => account,
+        Err(status) => return status.into_response(),
+    };
+    let database = state.read_database().await;
+    let filter = match query.resolve(&database) {
+        Ok(filter) => SmartgetFilter { account, ..filter },
+        Err(status) => return status.into_response(),
+    };
+    let liked_image = smartget_choice(database.wallpapers.into_values(), &filter, Utc::now());
+
+    if let Some(wallpaper) = liked_image {
+        let file_name = wallpaper.upscaled_file.as_ref().map_or_else(
+            || wallpaper.original_file.file_name.clone(),
+            |upscaled_file| upscaled_file.file_name.clone(),
+        );
+
+        let image_path = state.config.wallpapers_dir.join(&file_name);
+        match fs::read(&image_path).await {
+            Ok(data) => {
+                let mime_type = mime_guess::from_path(&image_path).first_or_octet_stream();
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    "Content-Type",
+                    HeaderValue::from_str(mime_type.as_ref()).unwrap(),
+                );
+                (StatusCode::OK, headers, data).into_response()
+            }
+            Err(e) => {
+                log::error!("Failed to read image file: {:?}", e);
                 StatusCode::INTERNAL_SERVER_ERROR.into_response()
             }
         }
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
+
+/// The wallpaper smartget would pick, as JSON for phone automations to fetch separately
+pub async fn smartget_meta(
+    State(state): State<AppState>,
+    Query(query): Query<SmartgetQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let account = match likes_account(&state, query.token.as_deref()).await {
+        Ok(account) => account,
+        Err(status) => return status.into_response(),
+    };
+    let database = state.read_database().await;
+    let filter = match query.resolve(&database) {
+        Ok(filter) => SmartgetFilter { account, ..filter },
+        Err(status) => return status.into_response(),
+    };
+    let Some(wallpaper) = smartget_choice(database.wallpapers.into_values(), &filter, Utc::now())
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map_or_else(String::new, |host| format!("http://{host}"));
+    let liked_state = wallpaper.liked_state_for(account);
+    let meta = smartget_meta_json(&wallpaper, liked_state, &host);
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        meta.to_string(),
+    )
+        .into_response()
+}
+
+fn smartget_meta_json(
+    wallpaper: &WallpaperData,
+    liked_state: LikedState,
+    base_url: &str,
+) -> serde_json::Value {
+    let file = full_size_file(wallpaper);
+    let (r, g, b) = wallpaper.color_data.average_color;
+    json!({
+        "id": wallpaper.id,
+        "prompt": wallpaper.prompt_data.shortened_prompt,
+        "full_prompt": wallpaper.prompt_data.prompt,
+        "liked_state": match liked_state {
+            LikedState::Loved => "loved",
+            LikedState::Liked => "liked",
+            LikedState::Neutral => "neutral",
+            LikedState::Disliked => "disliked",
+        },
+        "colors": {
+            "average": format!(
+                "#{:02x}{:02x}{:02x}",
+                (r * 255.0) as u8,
+                (g * 255.0) as u8,
+                (b * 255.0) as u8
+            ),
+            "hue": wallpaper.color_data.hue,
+            "saturation": wallpaper.color_data.saturation,
+            "lightness": wallpaper.color_data.lightness,
+            "brightness": wallpaper.color_data.top_20_percent_brightness,
+            "temperature": match wallpaper.color_data.color_temperature {
+                ColorTemperature::Warm => "warm",
+                ColorTemperature::Cool => "cool",
+                ColorTemperature::Neutral => "neutral",
+            },
+        },
+        "width": file.width,
+        "height": file.height,
+        "image_url": format!("{base_url}/wallpapers/{}", file.file_name),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ServedQuery {
+    token: String,
+}
+
+/// Record that a device applied a wallpaper, so smartget rests it for a while
+pub async fn served(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+    Query(query): Query<ServedQuery>,
+) -> impl IntoResponse {
+    if !verify_token(&state, &query.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let now = Utc::now();
+    let result = state
+        .with_database(|database| {
+            let wallpaper = database
+                .wallpapers
+                .get_mut(&id)
+                .ok_or_else(|| anyhow!("Image not found"))?;
+            wallpaper.last_served = Some(now);
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            json!({ "id": id, "last_served": now }).to_string(),
+        )
+            .into_response(),
         Err(e) => {
-            log::error!("{:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            log::error!("Errored served {:?}", e);
+            error_status(&e).into_response()
         }
     }
 }
 
-pub async fn remove(packet: Bytes) -> impl IntoResponse {
+pub async fn remove(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
     let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
         Ok(packet) => packet,
         Err(e) => {
@@ -211,65 +719,261 @@ pub async fn remove(packet: Bytes) -> impl IntoResponse {
             return StatusCode::BAD_REQUEST;
         }
     };
-    if !verify_token(&packet.token).await.unwrap_or(false) {
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
         return StatusCode::UNAUTHORIZED;
     }
 
-    match Box::pin(remove_wallpaper_impl(packet)).await {
-        Ok(()) => StatusCode::OK,
+    match Box::pin(remove_wallpaper_impl(&state, packet.uuid)).await {
+        Ok(()) => {
+            let summary = "Deleted wallpaper".to_string();
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Delete,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
         Err(e) => {
             log::error!("Errored remove_image {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            error_status(&e)
         }
     }
 }
 
-pub async fn like(packet: Bytes) -> impl IntoResponse {
-    let packet: TokenUuidLikedPacket = match bincode::deserialize(&packet) {
+/// Delete many wallpapers with a single database write
+pub async fn remove_batch(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidsPacket = match bincode::deserialize(&packet) {
         Ok(packet) => packet,
         Err(e) => {
-            log::error!("Failed to deserialize like_image packet: {:?}", e);
-            return StatusCode::BAD_REQUEST.into_response();
+            log::error!("Failed to deserialize remove_images packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
         }
     };
-    if !verify_token(&packet.token).await.unwrap_or(false) {
-        return StatusCode::UNAUTHORIZED.into_response();
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    match Box::pin(remove_wallpapers_impl(&state, &packet.uuids)).await {
+        Ok(()) => {
+            let summary = format!("Deleted {} wallpapers", packet.uuids.len());
+            audit(&state, &packet.token, AuditAction::Delete, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored remove_images {:?}", e);
+            error_status(&e)
+        }
     }
+}
 
-    // Set the vote state
-    let result: Result<WallpaperData> = async {
-        let mut database = read_database().await?;
+pub async fn pin(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize pin_image packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
 
-        if let Some((_, wallpaper)) = database
-            .wallpapers
-            .iter_mut()
-            .find(|(id, _)| **id == packet.uuid)
-        {
-            if wallpaper.liked_state == packet.liked {
-                wallpaper.liked_state = LikedState::Neutral;
-            } else {
-                wallpaper.liked_state = packet.liked;
-            }
-            let cloned = wallpaper.clone();
+    let result: Result<bool> = state
+        .with_database(|database| {
+            let wallpaper = database
+                .wallpapers
+                .get_mut(&packet.uuid)
+                .ok_or_else(|| anyhow!("Image not found"))?;
+            wallpaper.pinned = !wallpaper.pinned;
+            Ok(wallpaper.pinned)
+        })
+        .await;
 
-            write_database(&database).await?;
+    match result {
+        Ok(pinned) => {
+            let summary = if pinned { "Pinned" } else { "Unpinned" }.to_string();
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Pin,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored pin_image {:?}", e);
+            error_status(&e)
+        }
+    }
+}
 
-            Ok(cloned)
-        } else {
-            Err(anyhow::anyhow!("Image not found"))
+pub async fn rate(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidRatingPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize rate_image packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
         }
+    };
+    if packet
+        .rating
+        .is_some_and(|rating| !(1..=5).contains(&rating))
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
     }
-    .await;
+
+    let result = state
+        .with_database(|database| {
+            let wallpaper = database
+                .wallpapers
+                .get_mut(&packet.uuid)
+                .ok_or_else(|| anyhow!("Image not found"))?;
+            wallpaper.rating = packet.rating;
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => {
+            let summary = packet.rating.map_or_else(
+                || "Cleared rating".to_string(),
+                |rating| format!("Rated {rating} stars"),
+            );
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Rate,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored rate_image {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+pub async fn note(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: ImageNotePacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize image_note packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let note = Some(packet.note.trim().to_string()).filter(|note| !note.is_empty());
+    let summary = note.as_ref().map_or_else(
+        || "Cleared note".to_string(),
+        |note| format!("Noted '{note}'"),
+    );
+    let result = state
+        .with_database(|database| {
+            let wallpaper = database
+                .wallpapers
+                .get_mut(&packet.uuid)
+                .ok_or_else(|| anyhow!("Image not found"))?;
+            wallpaper.note = note;
+            Ok(())
+        })
+        .await;
 
     match result {
-        Ok(wallpaper) => {
+        Ok(()) => {
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Note,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored image_note {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+pub async fn like(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidLikedPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize like_image packet: {:?}", e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    let account = match token_account(&state, &packet.token).await {
+        Ok(Some((uuid, _))) => uuid,
+        Ok(None) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // Set the vote state, only for this account
+    let result: Result<(WallpaperData, LikedState)> = state
+        .with_database(|database| {
+            if let Some((_, wallpaper)) = database
+                .wallpapers
+                .iter_mut()
+                .find(|(id, _)| **id == packet.uuid)
+            {
+                let liked_state = if wallpaper.liked_state(account) == packet.liked {
+                    LikedState::Neutral
+                } else {
+                    packet.liked
+                };
+                wallpaper.liked_states.insert(account, liked_state);
+                wallpaper.last_modified_by = Some(account);
+                Ok((wallpaper.clone(), liked_state))
+            } else {
+                Err(anyhow::anyhow!("Image not found"))
+            }
+        })
+        .await;
+
+    match result {
+        Ok((wallpaper, liked_state)) => {
+            let summary = match liked_state {
+                LikedState::Neutral => "Set to neutral",
+                LikedState::Disliked => "Set to disliked",
+                LikedState::Liked => "Set to liked",
+                LikedState::Loved => "Set to loved",
+            };
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Like,
+                Some(packet.uuid),
+                summary.to_string(),
+            )
+            .await;
+
             // Rerun the upscaling if the image was liked, with quality upscaler
             if wallpaper.upscaled_file.is_none()
-                && (wallpaper.liked_state == LikedState::Liked
-                    || wallpaper.liked_state == LikedState::Loved)
+                && wallpaper.source == Source::Generated
+                && (liked_state == LikedState::Liked || liked_state == LikedState::Loved)
             {
                 tokio::spawn(async move {
-                    let _ = upscale_wallpaper_impl(packet.uuid, wallpaper).await;
+                    let _ = upscale_wallpaper_impl(&state, packet.uuid, wallpaper).await;
                 });
             }
 
@@ -277,12 +981,205 @@ pub async fn like(packet: Bytes) -> impl IntoResponse {
         }
         Err(e) => {
             log::error!("Failed to like image: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            error_status(&e).into_response()
+        }
+    }
+}
+
+/// Set the vote state of many wallpapers at once, unlike the single version this never toggles
+pub async fn like_batch(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidsLikedPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize like_images packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    let account = match token_account(&state, &packet.token).await {
+        Ok(Some((uuid, _))) => uuid,
+        Ok(None) => return StatusCode::UNAUTHORIZED,
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    let result: Result<Vec<WallpaperData>> = state
+        .with_database(|database| {
+            if let Some(id) = packet
+                .uuids
+                .iter()
+                .find(|id| !database.wallpapers.contains_key(id))
+            {
+                return Err(anyhow!("Image not found {id}"));
+            }
+            let mut wallpapers = Vec::with_capacity(packet.uuids.len());
+            for id in &packet.uuids {
+                if let Some(wallpaper) = database.wallpapers.get_mut(id) {
+                    wallpaper.liked_states.insert(account, packet.liked);
+                    wallpaper.last_modified_by = Some(account);
+                    wallpapers.push(wallpaper.clone());
+                }
+            }
+            Ok(wallpapers)
+        })
+        .await;
+
+    match result {
+        Ok(wallpapers) => {
+            let liked = match packet.liked {
+                LikedState::Neutral => "neutral",
+                LikedState::Disliked => "disliked",
+                LikedState::Liked => "liked",
+                LikedState::Loved => "loved",
+            };
+            let summary = format!("Set {} wallpapers to {liked}", wallpapers.len());
+            audit(&state, &packet.token, AuditAction::Like, None, summary).await;
+
+            // Upscale newly liked generated images one at a time rather than all at once
+            if packet.liked == LikedState::Liked || packet.liked == LikedState::Loved {
+                let to_upscale = wallpapers
+                    .into_iter()
+                    .filter(|wallpaper| {
+                        wallpaper.upscaled_file.is_none() && wallpaper.source == Source::Generated
+                    })
+                    .collect::<Vec<_>>();
+                if !to_upscale.is_empty() {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        for wallpaper in to_upscale {
+                            let _ = upscale_wallpaper_impl(&state, wallpaper.id, wallpaper).await;
+                        }
+                    });
+                }
+            }
+
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Failed to like images: {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+pub async fn transform(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: ImageTransformPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize transform_image packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let (token, uuid) = (packet.token.clone(), packet.uuid);
+    let summary = format!(
+        "Rotated {} degrees, flipped horizontally {}, flipped vertically {}",
+        packet.rotation.unwrap_or(0),
+        packet.flip_horizontal,
+        packet.flip_vertical
+    );
+    match transform_wallpaper_impl(&state, packet).await {
+        Ok(()) => {
+            audit(&state, &token, AuditAction::Transform, Some(uuid), summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored transform_image {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+pub async fn crop(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: ImageCropPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize crop_image packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    if ![packet.x, packet.y, packet.width, packet.height]
+        .iter()
+        .all(|value| value.is_finite() && (0.0..=1.0).contains(value))
+        || packet.width <= 0.0
+        || packet.height <= 0.0
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    match crop_wallpaper_impl(&state, &packet).await {
+        Ok(id) => {
+            let summary = format!(
+                "Cropped {:.0}x{:.0}% at {:.0},{:.0}% into {id}",
+                packet.width * 100.0,
+                packet.height * 100.0,
+                packet.x * 100.0,
+                packet.y * 100.0
+            );
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Crop,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored crop_image {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+/// Store a darkened copy of a wallpaper for night use, parented to it
+pub async fn darken(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: ImageDarkenPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize darken_image packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    if !packet.max_brightness.is_finite() || !(0.05..=1.0).contains(&packet.max_brightness) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    match darken_wallpaper_impl(&state, &packet).await {
+        Ok(id) => {
+            let summary = format!(
+                "Darkened to {:.0}% brightness into {id}",
+                packet.max_brightness * 100.0
+            );
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Transform,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored darken_image {:?}", e);
+            error_status(&e)
         }
     }
 }
 
-pub async fn recreate(packet: Bytes) -> impl IntoResponse {
+pub async fn recreate(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
     let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
         Ok(packet) => packet,
         Err(e) => {
@@ -290,69 +1187,700 @@ pub async fn recreate(packet: Bytes) -> impl IntoResponse {
             return StatusCode::BAD_REQUEST.into_response();
         }
     };
-    if !verify_token(&packet.token).await.unwrap_or(false) {
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
         return StatusCode::UNAUTHORIZED.into_response();
     }
 
-    // Get the prompt
-    let prompt_data = match read_database().await.and_then(|db| {
-        db.wallpapers
-            .iter()
-            .find(|(id, _)| **id == packet.uuid)
-            .map(|(_, wallpaper)| wallpaper.prompt_data.clone())
-            .ok_or_else(|| anyhow::anyhow!("Image not found"))
-    }) {
-        Ok(data) => data,
+    let source = match recreate_source(&state, packet.uuid).await {
+        Ok(source) => source,
         Err(e) => {
             log::error!("Failed to retrieve prompt data: {:?}", e);
             return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
     };
 
-    match generate_wallpaper_impl(Some(prompt_data), None).await {
-        Ok(()) => StatusCode::OK.into_response(),
+    let summary = format!("Recreated '{}'", source.prompt_data.shortened_prompt);
+    match recreate_from(&state, packet.uuid, source).await {
+        Ok(id) => {
+            attribute(&state, id, &packet.token).await;
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Recreate,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK.into_response()
+        }
         Err(e) => {
             log::error!("Failed to recreate image: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            error_status(&e).into_response()
+        }
+    }
+}
+
+/// Recreate a wallpaper from a prompt the user edited, the shortened prompt is written again to match
+pub async fn recreate_edited(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: RecreateEditedPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize recreate_edited packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let prompt = packet.prompt.trim().to_string();
+    if prompt.is_empty() {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let mut source = match recreate_source(&state, packet.uuid).await {
+        Ok(source) => source,
+        Err(e) => {
+            log::error!("Failed to retrieve prompt data: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    let summary = format!(
+        "Recreated '{}' with an edited prompt",
+        source.prompt_data.shortened_prompt
+    );
+    // A stale shortened prompt would mislabel the wallpaper, so fall back to the start of the new one
+    source.prompt_data.shortened_prompt = gpt::shorten_prompt(&*state.llm, &prompt)
+        .await
+        .unwrap_or_else(|e| {
+            log::warn!("Failed to shorten edited prompt: {:?}", e);
+            prompt
+                .split_whitespace()
+                .take(SHORTENED_PROMPT_WORDS)
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+    source.prompt_data.prompt = prompt;
+
+    match recreate_from(&state, packet.uuid, source).await {
+        Ok(id) => {
+            attribute(&state, id, &packet.token).await;
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Recreate,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Failed to recreate edited image: {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+/// What a wallpaper was generated with, to generate it again
+struct RecreateSource {
+    prompt_data: PromptData,
+    mode: GenerationMode,
+    resolution: ResolutionPreset,
+    model: Option<String>,
+}
+
+async fn recreate_source(state: &AppState, id: Uuid) -> Result<RecreateSource> {
+    let wallpaper = state
+        .read_database()
+        .await
+        .wallpapers
+        .remove(&id)
+        .ok_or_else(|| anyhow!("Image not found"))?;
+    Ok(RecreateSource {
+        prompt_data: wallpaper.prompt_data,
+        mode: wallpaper.generation_mode.unwrap_or_default(),
+        resolution: wallpaper
+            .original_file
+            .resolution_preset
+            .parse()
+            .unwrap_or_else(|_| ResolutionPreset::from_env()),
+        model: wallpaper.model,
+    })
+}
+
+/// Generate from the source and link the result back to the wallpaper it was recreated from
+async fn recreate_from(
+    state: &AppState,
+    original_id: Uuid,
+    source: RecreateSource,
+) -> Result<Uuid> {
+    let id = generate_wallpaper_impl(
+        state,
+        Some(source.prompt_data),
+        None,
+        source.mode,
+        source.resolution,
+        source.model.as_deref(),
+    )
+    .await?;
+    link_to_original(state, id, original_id, |wallpaper| {
+        wallpaper.original_id = Some(original_id);
+    })
+    .await?;
+    Ok(id)
+}
+
+/// Record which account asked for a wallpaper, failures are only logged as it's already stored
+pub async fn attribute(state: &AppState, id: Uuid, token: &str) {
+    let result = async {
+        let Some((account, _)) = token_account(state, token).await? else {
+            return Ok(());
+        };
+        state
+            .with_database(|database| {
+                if let Some(wallpaper) = database.wallpapers.get_mut(&id) {
+                    wallpaper.generated_by = Some(account);
+                }
+                Ok(())
+            })
+            .await
+    }
+    .await;
+    if let Err(e) = result {
+        log::error!("Failed to record who generated {id}: {:?}", e);
+    }
+}
+
+/// Generate a new wallpaper on the same theme as an existing one but looking clearly different
+pub async fn variation(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize generate_variation packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let mut database = state.read_database().await;
+    let original = database.wallpapers.remove(&packet.uuid);
+    let Some(original) = original else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    let summary = format!(
+        "Generated a variation of '{}'",
+        original.prompt_data.shortened_prompt
+    );
+    match generate_variation_impl(&state, &original).await {
+        Ok(id) => {
+            attribute(&state, id, &packet.token).await;
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Generate,
+                Some(id),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Failed to generate variation: {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+async fn generate_variation_impl(state: &AppState, original: &WallpaperData) -> Result<Uuid> {
+    let _running = GenerationGuard::start(state);
+    let mode = original.generation_mode.unwrap_or_default();
+    let resolution = original
+        .original_file
+        .resolution_preset
+        .parse()
+        .unwrap_or_else(|_| ResolutionPreset::from_env());
+    let new = gpt::generate(state, None, mode, Some(&original.prompt_data.prompt)).await?;
+    log::info!(
+        "Generated variation with {}: {}",
+        new.llm_provider,
+        new.prompt_data.prompt
+    );
+    let request = ImageRequest {
+        mode,
+        resolution,
+        model: original.model.clone(),
+        ..ImageRequest::from(new)
+    };
+    let id = generate_image_impl(state, request).await?;
+
+    link_to_original(state, id, original.id, |wallpaper| {
+        wallpaper.variation_of = Some(original.id);
+    })
+    .await?;
+    Ok(id)
+}
+
+/// List a wallpaper made from another's prompt on the original, `mark` records the link on the new one
+async fn link_to_original(
+    state: &AppState,
+    id: Uuid,
+    original_id: Uuid,
+    mark: impl FnOnce(&mut WallpaperData),
+) -> Result<()> {
+    state
+        .with_database(|database| {
+            mark(
+                database
+                    .wallpapers
+                    .get_mut(&id)
+                    .ok_or_else(|| anyhow!("Wallpaper not found"))?,
+            );
+            // The original may have been removed while this one generated
+            if let Some(original) = database.wallpapers.get_mut(&original_id) {
+                original.variations.push(id);
+            }
+            Ok(())
+        })
+        .await
+}
+
+pub async fn upload(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: ImageUploadPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize upload_image packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let image = match decode_upload(packet.data) {
+        Ok(image) => image,
+        Err(e) => {
+            log::error!("Failed to decode uploaded image: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    let title = packet
+        .title
+        .filter(|title| !title.trim().is_empty())
+        .unwrap_or_else(|| "Uploaded image".to_string());
+    let thumb_image = thumbnails::resize(&image);
+    let color_data = calculate_color_data(&thumb_image);
+    let resolution = ResolutionPreset::Custom(image.width(), image.height());
+
+    match store_wallpaper(
+        &state,
+        &image,
+        &thumb_image,
+        color_data,
+        NewWallpaper {
+            prompt_data: PromptData {
+                prompt: title.clone(),
+                shortened_prompt: title.clone(),
+                ..Default::default()
+            },
+            resolution_preset: resolution.to_string(),
+            source: Source::Uploaded,
+            ..Default::default()
+        },
+    )
+    .await
+    {
+        Ok(id) => {
+            let summary = format!("Uploaded '{title}' at {resolution}");
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Upload,
+                Some(id),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Failed to upload image: {:?}", e);
+            error_status(&e)
         }
     }
 }
 
+/// Store an existing image found on disk, titled by its file name
+pub async fn store_imported(
+    state: &AppState,
+    data: Vec<u8>,
+    title: &str,
+    caption: bool,
+    liked: bool,
+) -> Result<Uuid> {
+    let image = decode_upload(data)?;
+    let prompt = if caption {
+        let image_uri = jpeg_data_uri(&image.thumbnail(1024, 1024))?;
+        gpt::describe_image(&*state.llm, &image_uri).await?
+    } else {
+        title.to_string()
+    };
+    let thumb_image = thumbnails::resize(&image);
+    let color_data = calculate_color_data(&thumb_image);
+    let resolution = ResolutionPreset::Custom(image.width(), image.height());
+
+    let id = store_wallpaper(
+        state,
+        &image,
+        &thumb_image,
+        color_data,
+        NewWallpaper {
+            prompt_data: PromptData {
+                prompt,
+                shortened_prompt: title.to_string(),
+                ..Default::default()
+            },
+            resolution_preset: resolution.to_string(),
+            source: Source::Imported,
+            ..Default::default()
+        },
+    )
+    .await?;
+    if liked {
+        state
+            .with_database(|database| {
+                if let Some(wallpaper) = database.wallpapers.get_mut(&id) {
+                    wallpaper.shared_liked_state = LikedState::Liked;
+                }
+                Ok(())
+            })
+            .await?;
+    }
+    Ok(id)
+}
+
+/// Decode an image sent by the client, converted to 8 bit rgb so any input format can be webp encoded
+pub fn decode_upload(data: Vec<u8>) -> Result<DynamicImage> {
+    let image = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .decode()?;
+    Ok(DynamicImage::ImageRgb8(image.to_rgb8()))
+}
+
 pub async fn generate_wallpaper_impl(
+    state: &AppState,
     prompt_data: Option<PromptData>,
     message: Option<String>,
-) -> Result<()> {
-    log::info!("Generating wallpaper");
-
-    let id = Uuid::new_v4();
-    let datetime = Utc::now();
-    let client = Client::new();
-    let api_token =
-        env::var("REPLICATE_API_TOKEN").expect("REPLICATE_API_TOKEN environment variable not set");
+    mode: GenerationMode,
+    resolution: ResolutionPreset,
+    model: Option<&str>,
+) -> Result<Uuid> {
+    log::info!("Generating wallpaper at {resolution} resolution in {mode:?} mode");
+    let _running = GenerationGuard::start(state);
 
     // Generate image prompt
-    let prompt_data = if let Some(prompt_data) = prompt_data {
-        prompt_data
+    let request = if let Some(prompt_data) = prompt_data {
+        ImageRequest {
+            prompt_data,
+            mode,
+            resolution,
+            model: model.map(str::to_string),
+            ..Default::default()
+        }
     } else {
-        let new = gpt::generate(message).await?;
-        log::info!("Generated prompt: {}", new.prompt);
-        new
+        let new = gpt::generate(state, message, mode, None).await?;
+        log::info!(
+            "Generated prompt with {}: {}",
+            new.llm_provider,
+            new.prompt_data.prompt
+        );
+        ImageRequest {
+            mode,
+            resolution,
+            model: model.map(str::to_string),
+            ..ImageRequest::from(new)
+        }
     };
 
-    // Generate image
-    let (image_url, image) = image_diffusion(&client, &api_token, &prompt_data.prompt).await?;
-    log::info!("Generated image: {}", &image_url);
+    generate_image_impl(state, request).await
+}
 
-    // Resize the image to thumbnail
-    let thumbnail = image.thumbnail(32, 32);
-    let thumbhash = rgba_to_thumb_hash(
-        thumbnail.width() as usize,
-        thumbnail.height() as usize,
-        thumbnail.into_rgba8().as_raw(),
+/// A prompt that has already been written and how to diffuse it
+#[derive(Default)]
+pub struct ImageRequest {
+    pub prompt_data: PromptData,
+    pub comment_ids: Vec<Uuid>, // Comments the prompt was written from, answered once it's stored
+    pub llm_usage: Option<LlmUsage>,
+    pub llm_provider: String,
+    pub context: Option<String>,
+    pub mode: GenerationMode,
+    pub resolution: ResolutionPreset,
+    pub model: Option<String>, // Image model id, None for the default
+    pub weather_context: Option<String>,
+    pub moodboard_influence: bool,
+}
+
+impl From<gpt::GeneratedPrompt> for ImageRequest {
+    fn from(new: gpt::GeneratedPrompt) -> Self {
+        Self {
+            prompt_data: new.prompt_data,
+            comment_ids: new.comment_ids,
+            llm_usage: Some(new.usage),
+            llm_provider: new.llm_provider,
+            context: Some(new.context),
+            weather_context: new.weather_context,
+            moodboard_influence: new.moodboard_influence,
+            ..Default::default()
+        }
+    }
+}
+
+/// Diffuse and store an image for a prompt that has already been written
+pub async fn generate_image_impl(state: &AppState, request: ImageRequest) -> Result<Uuid> {
+    let ImageRequest {
+        mut prompt_data,
+        comment_ids,
+        llm_usage,
+        llm_provider,
+        context,
+        mode,
+        resolution,
+        model,
+        weather_context,
+        moodboard_influence,
+    } = request;
+    let model = models::resolve(model.as_deref());
+
+    let database = state.read_database().await;
+    prompt_data.negative_prompt = negative_prompt(
+        config::var("GLOBAL_NEGATIVE_PROMPT").as_deref(),
+        &database.style.negative_contents,
     );
+    let mut recent = database.wallpapers.into_values().collect::<Vec<_>>();
+    recent.sort_by_key(|wallpaper| std::cmp::Reverse(wallpaper.datetime));
+    let recent_hues = recent
+        .iter()
+        .take(RECENT_HUES)
+        .map(|wallpaper| wallpaper.color_data.hue)
+        .collect::<Vec<_>>();
+
+    // Generate images, keeping the best candidate and retrying if they all come out too blurry
+    let min_sharpness = config::var("MIN_SHARPNESS").and_then(|value| value.parse::<f32>().ok());
+    let candidate_count = generation_candidates();
+    let mut candidates_evaluated = 0;
+    let mut attempt = 0;
+    let (image, thumb_image, color_data) = loop {
+        let images = diffuse_candidates(
+            &state.images,
+            &model,
+            &prompt_data,
+            resolution.diffusion_size(),
+            candidate_count,
+        )
+        .await?;
+        candidates_evaluated += images.len() as u32;
+
+        // Downscale for the thumbnail file and calculate average color and brightness
+        let mut candidates = images
+            .into_iter()
+            .map(|image| {
+                let thumb_image = thumbnails::resize(&image);
+                let color_data = calculate_color_data(&thumb_image);
+                (image, thumb_image, color_data)
+            })
+            .collect::<Vec<_>>();
+        let max_sharpness = candidates
+            .iter()
+            .map(|(_, _, color_data)| color_data.sharpness_score)
+            .fold(0.0, f32::max);
+
+        match min_sharpness {
+            Some(min_sharpness) if max_sharpness < min_sharpness => {
+                if attempt >= SHARPNESS_RETRIES {
+                    return Err(anyhow!(
+                        "Generated image too blurry after {} attempts, sharpness {:.0} below {:.0}",
+                        attempt + 1,
+                        max_sharpness,
+                        min_sharpness
+                    ));
+                }
+                log::warn!(
+                    "Discarding blurry images, sharpness {:.0} below {:.0}",
+                    max_sharpness,
+                    min_sharpness
+                );
+                attempt += 1;
+            }
+            _ => {
+                candidates.retain(|(_, _, color_data)| {
+                    min_sharpness.is_none_or(|min| color_data.sharpness_score >= min)
+                });
+                let best = candidates
+                    .into_iter()
+                    .map(|candidate| {
+                        let score = candidate_score(&candidate.2, max_sharpness, &recent_hues);
+                        (score, candidate)
+                    })
+                    .max_by(|a, b| a.0.total_cmp(&b.0))
+                    .map(|(_, candidate)| candidate)
+                    .ok_or_else(|| anyhow!("No candidate images generated"))?;
+                break best;
+            }
+        }
+    };
+    if candidates_evaluated > 1 {
+        log::info!("Picked the best of {candidates_evaluated} candidate images");
+    }
+
+    let id = store_wallpaper(
+        state,
+        &image,
+        &thumb_image,
+        color_data,
+        NewWallpaper {
+            prompt_data: prompt_data.clone(),
+            resolution_preset: resolution.to_string(),
+            source: Source::Generated,
+            llm_usage,
+            llm_provider_used: llm_provider,
+            generation_mode: Some(mode),
+            context,
+            candidates_evaluated,
+            model: Some(model.id),
+            weather_context,
+            moodboard_influence,
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    // Let the user know how their comments were used, a failure here shouldn't fail the generation
+    if let Err(e) = respond_to_comments(state, &comment_ids, &prompt_data).await {
+        log::error!("Failed to write comment responses: {:?}", e);
+    }
+    if let Err(e) = tag_wallpaper(state, id, &prompt_data.prompt).await {
+        log::error!("Failed to tag wallpaper {id}: {:?}", e);
+    }
+
+    Ok(id)
+}
+
+/// Store tags extracted from the prompt, adding the extra tokens to the wallpapers usage
+async fn tag_wallpaper(state: &AppState, id: Uuid, prompt: &str) -> Result<()> {
+    let (tags, usage) = gpt::extract_tags(&*state.llm, prompt).await?;
+    log::info!("Tagged wallpaper {id}: {}", tags.join(", "));
+    state
+        .with_database(|database| {
+            let wallpaper = database
+                .wallpapers
+                .get_mut(&id)
+                .ok_or_else(|| anyhow!("Wallpaper not found"))?;
+            wallpaper.tags = tags;
+            *wallpaper.llm_usage.get_or_insert_with(LlmUsage::default) += usage;
+            Ok(())
+        })
+        .await
+}
+
+/// Add a feedback note to each used comment that hasn't had one yet
+async fn respond_to_comments(
+    state: &AppState,
+    comment_ids: &[Uuid],
+    prompt_data: &PromptData,
+) -> Result<()> {
+    let pending = state
+        .read_database()
+        .await
+        .comments
+        .into_values()
+        .filter(|comment| comment.response.is_none() && comment_ids.contains(&comment.id))
+        .collect::<Vec<_>>();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let mut notes = Vec::new();
+    for comment in pending {
+        let note = gpt::feedback_note(&*state.llm, &comment.comment, prompt_data).await?;
+        log::info!("Comment '{}' response: {}", comment.comment, note);
+        notes.push((comment.id, note));
+    }
+
+    state
+        .with_database(|database| {
+            for (id, note) in notes {
+                if let Some(comment) = database.comments.get_mut(&id) {
+                    comment.response = Some(note);
+                }
+            }
+            Ok(())
+        })
+        .await
+}
+
+/// Everything stored with a new wallpaper that isn't read from its image
+#[derive(Default)]
+struct NewWallpaper {
+    prompt_data: PromptData,
+    resolution_preset: String,
+    source: Source,
+    llm_usage: Option<LlmUsage>,
+    llm_provider_used: String,
+    parent: Option<Uuid>,
+    generation_mode: Option<GenerationMode>,
+    context: Option<String>, // Saved to its own file rather than the database
+    candidates_evaluated: u32,
+    model: Option<String>,
+    weather_context: Option<String>,
+    moodboard_influence: bool,
+}
+
+impl NewWallpaper {
+    /// Keeps the prompt and origin of an edited wallpaper, parent and resolution are left for the edit
+    fn derived_from(wallpaper: &WallpaperData) -> Self {
+        Self {
+            prompt_data: wallpaper.prompt_data.clone(),
+            source: wallpaper.source,
+            generation_mode: wallpaper.generation_mode,
+            model: wallpaper.model.clone(),
+            weather_context: wallpaper.weather_context.clone(),
+            moodboard_influence: wallpaper.moodboard_influence,
+            ..Default::default()
+        }
+    }
+}
+
+/// Save the image files and add a new database entry for them
+async fn store_wallpaper(
+    state: &AppState,
+    image: &DynamicImage,
+    thumb_image: &DynamicImage,
+    color_data: ColorData,
+    new: NewWallpaper,
+) -> Result<Uuid> {
+    let NewWallpaper {
+        prompt_data,
+        resolution_preset,
+        source,
+        llm_usage,
+        llm_provider_used,
+        parent,
+        generation_mode,
+        context,
+        candidates_evaluated,
+        model,
+        weather_context,
+        moodboard_influence,
+    } = new;
+    let id = Uuid::new_v4();
+    let datetime = Utc::now();
+
+    let thumbhash = calculate_thumbhash(image);
 
     // Save to file
-    let dir = Path::new(WALLPAPERS_DIR);
+    let dir = state.config.wallpapers_dir.as_path();
     fs::create_dir_all(dir).await?;
 
     let datetime_str = datetime.to_rfc3339();
@@ -361,31 +1889,40 @@ pub async fn generate_wallpaper_impl(
     let file_name = format!("{datetime_str}.webp");
     std::fs::write(
         dir.join(&file_name),
-        &*webp::Encoder::from_image(&image).unwrap().encode(90.0),
+        &*webp::Encoder::from_image(image).unwrap().encode(90.0),
     )?;
-    let original_file = ImageFile {
+    let mut original_file = ImageFile {
         file_name,
         width: image.width(),
         height: image.height(),
+        resolution_preset: resolution_preset.clone(),
+        alt_formats: Vec::new(),
     };
+    encoding::write_alternates(dir, image, &mut original_file, false).await;
 
-    // Downscale to 480p and save as thumbnail file
-    let thumb_image = image.resize_to_fill(640, 360, FilterType::Lanczos3);
+    // Save the thumbnail file
     let thumb_file_name = format!("{datetime_str}_thumb.webp");
-    std::fs::write(
-        dir.join(&thumb_file_name),
-        &*webp::Encoder::from_image(&thumb_image)
-            .unwrap()
-            .encode(90.0),
-    )?;
-    let thumbnail_file = ImageFile {
+    std::fs::write(dir.join(&thumb_file_name), thumbnails::encode(thumb_image)?)?;
+    let mut thumbnail_file = ImageFile {
         file_name: thumb_file_name,
         width: thumb_image.width(),
         height: thumb_image.height(),
+        resolution_preset,
+        alt_formats: Vec::new(),
+    };
+    encoding::write_alternates(dir, thumb_image, &mut thumbnail_file, true).await;
+
+    // Losing the context only makes debugging harder, the wallpaper is still worth keeping
+    let context_file = match context {
+        Some(context) => match generation_context::write(state, id, &context).await {
+            Ok(file_name) => Some(file_name),
+            Err(e) => {
+                log::error!("Failed to save generation context: {:?}", e);
+                None
+            }
+        },
+        None => None,
     };
-
-    // Calculate average color and brightness
-    let color_data = calculate_color_data(&thumb_image);
 
     let wallpaper = WallpaperData {
         id,
@@ -398,42 +1935,75 @@ pub async fn generate_wallpaper_impl(
 
         thumbnail_file,
         thumbhash,
-        liked_state: LikedState::Neutral,
+        shared_liked_state: LikedState::Neutral,
+        liked_states: HashMap::new(),
+        source,
+        applied_transforms: Vec::new(),
+        llm_usage,
+        llm_provider_used,
+        pinned: false,
+        parent,
+        note: None,
+        last_served: None,
+        last_modified_by: None,
+        context_file,
+        reference_file: None,
+        candidates_evaluated,
+        model,
+        variation_of: None,
+        source_comment: None,
+        original_id: None,
+        variations: Vec::new(),
+        generated_by: None,
+        dark_variant: false,
+        tags: Vec::new(),
+        rating: None,
+        generation_mode,
+        weather_context,
+        moodboard_influence,
     };
 
     // Store a new database entry
-    let mut database = read_database().await?;
-    database.wallpapers.insert(id, wallpaper);
-    write_database(&database).await?;
+    state
+        .with_database(|database| {
+            database.wallpapers.insert(id, wallpaper);
+            Ok(())
+        })
+        .await?;
 
-    Ok(())
+    Ok(id)
 }
 
-pub async fn upscale_wallpaper_impl(id: Uuid, wallpaper: WallpaperData) -> Result<()> {
+pub async fn upscale_wallpaper_impl(
+    state: &AppState,
+    id: Uuid,
+    wallpaper: WallpaperData,
+) -> Result<()> {
     log::info!("Upscaling wallpaper {id}");
 
-    // Prepare client
-    let client = Client::new();
-    let api_token =
-        env::var("REPLICATE_API_TOKEN").expect("REPLICATE_API_TOKEN environment variable not set");
-
     // Open image file
-    let image_path = Path::new(WALLPAPERS_DIR).join(wallpaper.original_file.file_name.clone());
+    let image_path = state
+        .config
+        .wallpapers_dir
+        .join(wallpaper.original_file.file_name.clone());
     let image = image::open(&image_path)?;
 
     // Upscale the image using the high quality upscaler
-    let (upscaled_url, upscaled_image) = upscale_image(
-        &client,
-        &api_token,
-        &image,
-        &wallpaper.prompt_data.shortened_prompt,
-    )
-    .await?;
+    let (upscaled_url, upscaled_image) = state
+        .images
+        .upscale(&image, &wallpaper.prompt_data.shortened_prompt)
+        .await?;
     log::info!("Upscaled image: {}", &upscaled_url);
-    let upscaled_image = upscaled_image.resize_to_fill(2560, 1440, FilterType::Lanczos3);
+    let resolution = wallpaper
+        .original_file
+        .resolution_preset
+        .parse()
+        .unwrap_or_else(|_| ResolutionPreset::from_env());
+    let (width, height) = resolution.dimensions();
+    let upscaled_image = upscaled_image.resize_to_fill(width, height, FilterType::Lanczos3);
 
     // Save to file
-    let dir = Path::new(WALLPAPERS_DIR);
+    let dir = state.config.wallpapers_dir.as_path();
     fs::create_dir_all(dir).await?;
     let datetime_str = wallpaper.datetime.to_rfc3339();
 
@@ -445,46 +2015,296 @@ pub async fn upscale_wallpaper_impl(id: Uuid, wallpaper: WallpaperData) -> Resul
             .unwrap()
             .encode(90.0),
     )?;
-    let upscaled_file = Some(ImageFile {
+    let mut upscaled_file = ImageFile {
         file_name: upscaled_file_name,
         width: upscaled_image.width(),
         height: upscaled_image.height(),
-    });
+        resolution_preset: resolution.to_string(),
+        alt_formats: Vec::new(),
+    };
+    encoding::write_alternates(dir, &upscaled_image, &mut upscaled_file, false).await;
 
-    // Downscale to 480p and save as thumbnail file
-    let thumb_image = upscaled_image.resize_to_fill(640, 360, FilterType::Lanczos3);
-    let thumb_file_name = format!("{datetime_str}_thumb.webp");
+    // Downscale and save as a new thumbnail file, the old name is cached by clients
+    let thumb_image = thumbnails::resize(&upscaled_image);
+    let thumb_file_name = format!("{datetime_str}_upscaled_thumb.webp");
     std::fs::write(
         dir.join(&thumb_file_name),
-        &*webp::Encoder::from_image(&thumb_image)
-            .unwrap()
-            .encode(90.0),
+        thumbnails::encode(&thumb_image)?,
     )?;
-    let thumbnail_file = ImageFile {
+    let mut thumbnail_file = ImageFile {
         file_name: thumb_file_name,
         width: thumb_image.width(),
         height: thumb_image.height(),
+        resolution_preset: resolution.to_string(),
+        alt_formats: Vec::new(),
     };
+    encoding::write_alternates(dir, &thumb_image, &mut thumbnail_file, true).await;
 
     // Calculate average color and brightness
     let color_data = calculate_color_data(&thumb_image);
 
+    let old_thumb_paths = std::iter::once(wallpaper.thumbnail_file.file_name.clone())
+        .chain(wallpaper.thumbnail_file.alt_file_names())
+        .map(|file_name| dir.join(file_name))
+        .collect::<Vec<_>>();
     let wallpaper = WallpaperData {
-        upscaled_file,
+        upscaled_file: Some(upscaled_file),
         color_data,
         thumbnail_file,
         ..wallpaper
     };
 
     // Update the database entry
-    let mut database = read_database().await?;
-    database.wallpapers.insert(id, wallpaper);
-    write_database(&database).await?;
+    state
+        .with_database(|database| {
+            database.wallpapers.insert(id, wallpaper);
+            Ok(())
+        })
+        .await?;
+    for old_thumb_path in old_thumb_paths {
+        if let Err(e) = fs::remove_file(&old_thumb_path).await {
+            log::error!(
+                "Failed to remove old thumbnail {:?}: {:?}",
+                old_thumb_path,
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply a rotation and flips to the wallpapers image files, regenerating the thumbnail
+async fn transform_wallpaper_impl(state: &AppState, packet: ImageTransformPacket) -> Result<()> {
+    let mut transforms = Vec::new();
+    match packet.rotation.map(|rotation| rotation.rem_euclid(360)) {
+        None | Some(0) => {}
+        Some(90) => transforms.push(Transform::Rotate90),
+        Some(180) => transforms.push(Transform::Rotate180),
+        Some(270) => transforms.push(Transform::Rotate270),
+        Some(rotation) => return Err(anyhow!("Unsupported rotation {rotation}")),
+    }
+    if packet.flip_horizontal {
+        transforms.push(Transform::FlipHorizontal);
+    }
+    if packet.flip_vertical {
+        transforms.push(Transform::FlipVertical);
+    }
+    if transforms.is_empty() {
+        return Ok(());
+    }
 
+    let wallpaper = state
+        .read_database()
+        .await
+        .wallpapers
+        .remove(&packet.uuid)
+        .ok_or_else(|| anyhow!("Image not found"))?;
+    let dir = state.config.wallpapers_dir.as_path();
+
+    // Encode before taking the lock, files are cached as immutable so transformed images get new versioned names
+    let datetime_str = wallpaper.datetime.to_rfc3339();
+    let version = Utc::now().timestamp_millis();
+    let mut transformed = wallpaper.clone();
+    let mut thumb_source = None;
+    for (file, suffix) in std::iter::once((&mut transformed.original_file, "")).chain(
+        transformed
+            .upscaled_file
+            .as_mut()
+            .map(|file| (file, "_upscaled")),
+    ) {
+        let mut image = image::open(dir.join(&file.file_name))?;
+        for transform in &transforms {
+            image = match transform {
+                Transform::Rotate90 => image.rotate90(),
+                Transform::Rotate180 => image.rotate180(),
+                Transform::Rotate270 => image.rotate270(),
+                Transform::FlipHorizontal => image.fliph(),
+                Transform::FlipVertical => image.flipv(),
+            };
+        }
+        let file_name = format!("{datetime_str}{suffix}_v{version}.webp");
+        std::fs::write(
+            dir.join(&file_name),
+            &*webp::Encoder::from_image(&image).unwrap().encode(90.0),
+        )?;
+        file.file_name = file_name;
+        file.width = image.width();
+        file.height = image.height();
+        file.alt_formats.clear();
+        encoding::write_alternates(dir, &image, file, false).await;
+        thumb_source = Some(image);
+    }
+    let image = thumb_source.ok_or_else(|| anyhow!("No image files to transform"))?;
+
+    // Regenerate the thumbnail from the highest quality file
+    let thumb_image = thumbnails::resize(&image);
+    let thumb_file_name = format!("{datetime_str}_thumb_v{version}.webp");
+    std::fs::write(
+        dir.join(&thumb_file_name),
+        thumbnails::encode(&thumb_image)?,
+    )?;
+    transformed.thumbnail_file.file_name = thumb_file_name;
+    transformed.thumbnail_file.width = thumb_image.width();
+    transformed.thumbnail_file.height = thumb_image.height();
+    transformed.thumbnail_file.alt_formats.clear();
+    encoding::write_alternates(dir, &thumb_image, &mut transformed.thumbnail_file, true).await;
+
+    // Only swap the names over if nothing replaced the files while encoding
+    let swapped = state
+        .with_database(|database| {
+            let Some(current) = database.wallpapers.get_mut(&packet.uuid) else {
+                return Ok(false);
+            };
+            if image_file_names(current) != image_file_names(&wallpaper) {
+                return Ok(false);
+            }
+            current.original_file = transformed.original_file.clone();
+            current.upscaled_file = transformed.upscaled_file.clone();
+            current.thumbnail_file = transformed.thumbnail_file.clone();
+            current.thumbhash = calculate_thumbhash(&image);
+            current.color_data = calculate_color_data(&thumb_image);
+            current.applied_transforms.extend(transforms);
+            Ok(true)
+        })
+        .await;
+
+    // The old files once swapped, otherwise the new ones as nothing points at them
+    let unused = if matches!(swapped, Ok(true)) {
+        &wallpaper
+    } else {
+        &transformed
+    };
+    for file_name in image_file_names(unused) {
+        let path = dir.join(file_name);
+        if let Err(e) = fs::remove_file(&path).await {
+            log::error!("Failed to remove unused file {:?}: {:?}", path, e);
+        }
+    }
+    if !swapped? {
+        bail!("Image changed while it was being transformed");
+    }
     Ok(())
 }
 
-fn calculate_color_data(img: &DynamicImage) -> ColorData {
+/// The original, upscaled and thumbnail files with their other encodings, everything a transform replaces
+fn image_file_names(wallpaper: &WallpaperData) -> Vec<String> {
+    [
+        Some(&wallpaper.original_file),
+        wallpaper.upscaled_file.as_ref(),
+        Some(&wallpaper.thumbnail_file),
+    ]
+    .into_iter()
+    .flatten()
+    .flat_map(|file| std::iter::once(file.file_name.clone()).chain(file.alt_file_names()))
+    .collect()
+}
+
+/// Crop a wallpaper into a new entry parented to it, the original is left untouched
+async fn crop_wallpaper_impl(state: &AppState, packet: &ImageCropPacket) -> Result<Uuid> {
+    let database = state.read_database().await;
+    let wallpaper = database
+        .wallpapers
+        .get(&packet.uuid)
+        .ok_or_else(|| anyhow!("Image not found"))?;
+
+    // Crop from the highest quality file
+    let file = wallpaper
+        .upscaled_file
+        .as_ref()
+        .unwrap_or(&wallpaper.original_file);
+    let image = image::open(state.config.wallpapers_dir.join(&file.file_name))?;
+    let (width, height) = image.dimensions();
+    let left = ((packet.x * width as f32).round() as u32).min(width - 1);
+    let top = ((packet.y * height as f32).round() as u32).min(height - 1);
+    let crop_width = ((packet.width * width as f32).round() as u32).clamp(1, width - left);
+    let crop_height = ((packet.height * height as f32).round() as u32).clamp(1, height - top);
+    let image = image.crop_imm(left, top, crop_width, crop_height);
+
+    let thumb_image = thumbnails::resize(&image);
+    let color_data = calculate_color_data(&thumb_image);
+    store_wallpaper(
+        state,
+        &image,
+        &thumb_image,
+        color_data,
+        NewWallpaper {
+            resolution_preset: ResolutionPreset::Custom(crop_width, crop_height).to_string(),
+            parent: Some(packet.uuid),
+            ..NewWallpaper::derived_from(wallpaper)
+        },
+    )
+    .await
+}
+
+/// Darken a wallpaper into a new entry parented to it, keeping its liked state so smartget can prefer it at night
+async fn darken_wallpaper_impl(state: &AppState, packet: &ImageDarkenPacket) -> Result<Uuid> {
+    let database = state.read_database().await;
+    let wallpaper = database
+        .wallpapers
+        .get(&packet.uuid)
+        .ok_or_else(|| anyhow!("Image not found"))?;
+
+    let file = full_size_file(wallpaper);
+    let image = darken_image(
+        &image::open(state.config.wallpapers_dir.join(&file.file_name))?,
+        packet.max_brightness,
+    );
+    let thumb_image = thumbnails::resize(&image);
+    let color_data = calculate_color_data(&thumb_image);
+    let id = store_wallpaper(
+        state,
+        &image,
+        &thumb_image,
+        color_data,
+        NewWallpaper {
+            resolution_preset: file.resolution_preset.clone(),
+            parent: Some(packet.uuid),
+            ..NewWallpaper::derived_from(wallpaper)
+        },
+    )
+    .await?;
+    state
+        .with_database(|database| {
+            if let Some(dark) = database.wallpapers.get_mut(&id) {
+                dark.dark_variant = true;
+                dark.shared_liked_state = wallpaper.shared_liked_state;
+                dark.liked_states = wallpaper.liked_states.clone();
+                dark.tags = wallpaper.tags.clone();
+            }
+            Ok(())
+        })
+        .await?;
+    Ok(id)
+}
+
+/// Tone map an image so its brightest pixel reaches at most `max_brightness`
+/// Luminance follows `max * l^gamma` rather than a flat multiply, each pixel is scaled evenly so hues are kept
+fn darken_image(image: &DynamicImage, max_brightness: f32) -> DynamicImage {
+    let mut rgb = image.to_rgb8();
+    for pixel in rgb.pixels_mut() {
+        let [r, g, b] = pixel.0.map(|channel| f32::from(channel) / 255.0);
+        let luminance = 0.114f32.mul_add(b, 0.299f32.mul_add(r, 0.587f32 * g));
+        if luminance <= 0.0 {
+            continue;
+        }
+        let scale = max_brightness * luminance.powf(DARKEN_GAMMA) / luminance;
+        pixel.0 =
+            [r, g, b].map(|channel| (channel * scale * 255.0).round().clamp(0.0, 255.0) as u8);
+    }
+    DynamicImage::ImageRgb8(rgb)
+}
+
+fn calculate_thumbhash(image: &DynamicImage) -> Vec<u8> {
+    let thumbnail = image.thumbnail(32, 32);
+    rgba_to_thumb_hash(
+        thumbnail.width() as usize,
+        thumbnail.height() as usize,
+        thumbnail.into_rgba8().as_raw(),
+    )
+}
+
+pub fn calculate_color_data(img: &DynamicImage) -> ColorData {
     let (width, height) = img.dimensions();
     let total_pixels = (width * height) as f32;
 
@@ -511,6 +2331,8 @@ fn calculate_color_data(img: &DynamicImage) -> ColorData {
     let (hue, saturation, lightness) = rgb_to_hsl(avg_r, avg_g, avg_b);
     let chroma = calculate_chroma_hsl(lightness, saturation);
 
+    let brightness_histogram = brightness_histogram(&brightness_values);
+
     // Compute brightness percentiles
     brightness_values.sort_by(|a, b| a.partial_cmp(b).unwrap());
     let top_20_percent_brightness =
@@ -521,6 +2343,8 @@ fn calculate_color_data(img: &DynamicImage) -> ColorData {
     // Calculate contrast ratio
     let contrast_ratio = (top_20_percent_brightness + 0.05) / (bottom_20_percent_brightness + 0.05);
 
+    let sharpness_score = calculate_sharpness(img);
+
     ColorData {
         average_color: (avg_r, avg_b, avg_g),
         hue,
@@ -530,11 +2354,55 @@ fn calculate_color_data(img: &DynamicImage) -> ColorData {
         top_20_percent_brightness,
         bottom_20_percent_brightness,
         contrast_ratio,
+        sharpness_score,
+        color_temperature: ColorTemperature::from_hue(hue),
+        brightness_histogram,
+    }
+}
+
+/// Count brightness values into 256 bins, scaled so the fullest bin is `u16::MAX`
+fn brightness_histogram(brightness_values: &[f32]) -> Vec<u16> {
+    let mut counts = [0u32; 256];
+    for brightness in brightness_values {
+        counts[(brightness.clamp(0.0, 1.0) * 255.0).round() as usize] += 1;
+    }
+    let largest = counts.iter().copied().max().unwrap_or(0).max(1);
+    counts
+        .iter()
+        .map(|count| (u64::from(*count) * u64::from(u16::MAX) / u64::from(largest)) as u16)
+        .collect()
+}
+
+/// Calculate sharpness as the variance of the Laplacian over 8-bit luma, low values indicate a blurry image
+fn calculate_sharpness(img: &DynamicImage) -> f32 {
+    let luma = img.to_luma8();
+    let (width, height) = luma.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
     }
+
+    let value = |x: u32, y: u32| f32::from(luma.get_pixel(x, y).0[0]);
+    let (mut sum, mut sum_squared) = (0.0, 0.0);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = 4.0f32
+                .mul_add(
+                    value(x, y),
+                    -value(x - 1, y) - value(x + 1, y) - value(x, y - 1) - value(x, y + 1),
+                )
+                .abs();
+            sum += laplacian;
+            sum_squared += laplacian * laplacian;
+        }
+    }
+
+    let count = ((width - 2) * (height - 2)) as f32;
+    let mean = sum / count;
+    mean.mul_add(-mean, sum_squared / count)
 }
 
 /// Convert RGB to HSL, each value is in the range [0,1]
-fn rgb_to_hsl(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+pub fn rgb_to_hsl(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
     let max = r.max(g).max(b);
     let min = r.min(g).min(b);
     let lightness = (max + min) / 2.0;
@@ -544,14 +2412,14 @@ fn rgb_to_hsl(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
     if (max - min).abs() > f32::EPSILON {
         let d = max - min;
         saturation = if lightness > 0.5 {
-            d / (2.0 - d)
+            d / (2.0 - max - min)
         } else {
             d / (max + min)
         };
 
-        if (max - r).abs() > f32::EPSILON {
+        if (max - r).abs() < f32::EPSILON {
             hue = (g - b) / d + if g < b { 6.0 } else { 0.0 };
-        } else if (max - g).abs() > f32::EPSILON {
+        } else if (max - g).abs() < f32::EPSILON {
             hue = (b - r) / d + 2.0;
         } else {
             hue = (r - g) / d + 4.0;
@@ -567,106 +2435,283 @@ fn calculate_chroma_hsl(lightness: f32, saturation: f32) -> f32 {
     (1.0 - 2.0f32.mul_add(lightness, -1.0).abs()) * saturation
 }
 
-async fn remove_wallpaper_impl(packet: TokenUuidPacket) -> Result<()> {
-    let mut database = read_database().await?;
+/// Delete wallpapers that have outlived the retention period for their liked state
+pub async fn remove_expired_wallpapers(
+    state: &AppState,
+    policy: RetentionPolicy,
+) -> Result<Vec<WallpaperData>> {
+    let expired = expired_wallpapers(state, policy).await?;
+    for wallpaper in &expired {
+        log::info!(
+            "Auto-deleting wallpaper {} '{}'",
+            wallpaper.id,
+            wallpaper.prompt_data.shortened_prompt
+        );
+    }
+    if !expired.is_empty() {
+        let ids = expired
+            .iter()
+            .map(|wallpaper| wallpaper.id)
+            .collect::<Vec<_>>();
+        remove_wallpapers_impl(state, &ids).await?;
+    }
 
-    let wallpaper = database
+    Ok(expired)
+}
+
+/// Wallpapers that have outlived the retention period, oldest first
+pub async fn expired_wallpapers(
+    state: &AppState,
+    policy: RetentionPolicy,
+) -> Result<Vec<WallpaperData>> {
+    let cur_time = Utc::now();
+    let mut expired = state
+        .read_database()
+        .await
         .wallpapers
-        .remove(&packet.uuid)
-        .ok_or_else(|| anyhow!("No entry found for UUID"))?;
+        .into_values()
+        .filter(|wallpaper| !wallpaper.pinned)
+        .filter(|wallpaper| {
+            policy
+                .retention_days(wallpaper.combined_liked_state())
+                .is_some_and(|days| {
+                    wallpaper.datetime < cur_time - chrono::Duration::days(i64::from(days))
+                })
+        })
+        .collect::<Vec<_>>();
+    expired.sort_by_key(|wallpaper| wallpaper.datetime);
+    Ok(expired)
+}
+
+async fn remove_wallpaper_impl(state: &AppState, id: Uuid) -> Result<()> {
+    remove_wallpapers_impl(state, &[id]).await
+}
+
+/// Remove the entries and their files, failing before anything is deleted if any id is unknown
+async fn remove_wallpapers_impl(state: &AppState, ids: &[Uuid]) -> Result<()> {
+    let _lock = state.lock_database().await?;
+    let mut database = state.read_database().await;
 
-    // Remove all associated files
-    for file_name in vec![
-        Some(&wallpaper.original_file.file_name),
-        Some(&wallpaper.thumbnail_file.file_name),
-        wallpaper.upscaled_file.as_ref().map(|f| &f.file_name),
+    if let Some(id) = ids.iter().find(|id| !database.wallpapers.contains_key(id)) {
+        return Err(anyhow!("No entry found for UUID {id}"));
+    }
+    let wallpapers = ids
+        .iter()
+        .filter_map(|id| database.wallpapers.remove(id))
+        .collect::<Vec<_>>();
+    unlink_removed(&mut database, ids);
+
+    // Save the updated database before the files go, so nothing is left pointing at a missing file
+    state.write_database(&database).await?;
+    for wallpaper in &wallpapers {
+        remove_wallpaper_files(&state.config.wallpapers_dir, wallpaper).await;
+    }
+
+    Ok(())
+}
+
+/// Clear links from the remaining wallpapers to removed ones, crops and variations outlive their source
+pub fn unlink_removed(database: &mut Database, ids: &[Uuid]) {
+    for wallpaper in database.wallpapers.values_mut() {
+        for link in [
+            &mut wallpaper.parent,
+            &mut wallpaper.variation_of,
+            &mut wallpaper.original_id,
+        ] {
+            if link.is_some_and(|id| ids.contains(&id)) {
+                *link = None;
+            }
+        }
+        wallpaper.variations.retain(|id| !ids.contains(id));
+    }
+}
+
+/// Delete every file a wallpaper owns, failures are only logged as its entry is already gone
+pub async fn remove_wallpaper_files(dir: &Path, wallpaper: &WallpaperData) {
+    for file_name in wallpaper.all_files() {
+        match fs::remove_file(dir.join(&file_name)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::warn!(
+                    "File {file_name} of wallpaper {} was already missing",
+                    wallpaper.id
+                );
+            }
+            Err(e) => log::error!("Failed to remove {file_name}: {:?}", e),
+        }
+    }
+}
+
+/// The always-on `GLOBAL_NEGATIVE_PROMPT` followed by the style's negative contents, capped in length
+fn negative_prompt(global: Option<&str>, negative_contents: &str) -> Option<String> {
+    let negative_contents = negative_contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ")
+        .chars()
+        .take(NEGATIVE_PROMPT_LIMIT)
+        .collect::<String>();
+    let parts = [
+        global.map(str::trim).unwrap_or_default(),
+        negative_contents.as_str(),
     ]
     .into_iter()
-    .flatten()
-    {
-        let file_path = Path::new(WALLPAPERS_DIR).join(file_name);
-        if file_path.exists() {
-            fs::remove_file(file_path).await?;
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>();
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+/// How many images to diffuse per generation, set with `GENERATION_CANDIDATES`
+fn generation_candidates() -> u32 {
+    config::var("GENERATION_CANDIDATES")
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(1)
+        .clamp(1, MAX_GENERATION_CANDIDATES)
+}
+
+/// Diffuse several images for one prompt at once, each is its own prediction as models return one image
+/// Failed candidates are skipped as long as one succeeds
+async fn diffuse_candidates(
+    images: &Arc<dyn ImageProvider>,
+    model: &ImageModel,
+    prompt_data: &PromptData,
+    size: (u32, u32),
+    count: u32,
+) -> Result<Vec<DynamicImage>> {
+    let mut tasks = JoinSet::new();
+    for _ in 0..count {
+        let images = images.clone();
+        let model = model.clone();
+        let prompt = prompt_data.prompt.clone();
+        let negative_prompt = prompt_data.negative_prompt.clone();
+        tasks.spawn(async move {
+            images
+                .diffuse(&model, &prompt, negative_prompt.as_deref(), size)
+                .await
+        });
+    }
+
+    let mut images = Vec::new();
+    let mut last_error = None;
+    while let Some(result) = tasks.join_next().await {
+        match result? {
+            Ok((image_url, image)) => {
+                log::info!("Generated image: {}", &image_url);
+                images.push(image);
+            }
+            Err(e) => {
+                log::warn!("Candidate image failed: {:?}", e);
+                last_error = Some(e);
+            }
         }
     }
+    match last_error {
+        Some(e) if images.is_empty() => Err(e),
+        _ => Ok(images),
+    }
+}
 
-    // Save the updated database
-    write_database(&database).await?;
+/// Weighted sum of sharpness relative to the sharpest candidate, contrast, and how far the hue is from recent wallpapers
+fn candidate_score(color_data: &ColorData, max_sharpness: f32, recent_hues: &[f32]) -> f32 {
+    let sharpness = if max_sharpness > 0.0 {
+        color_data.sharpness_score / max_sharpness
+    } else {
+        0.0
+    };
+    // Contrast ratios run from 1 to 21
+    let contrast = ((color_data.contrast_ratio - 1.0) / 20.0).clamp(0.0, 1.0);
+    // Distance around the hue wheel to the nearest recent hue, 0.5 is as far apart as two hues get
+    let hue_diversity = recent_hues
+        .iter()
+        .map(|hue| hue_distance(color_data.hue * 360.0, hue * 360.0) / 360.0)
+        .fold(0.5, f32::min)
+        * 2.0;
+    0.5 * sharpness + 0.3 * contrast + 0.2 * hue_diversity
+}
 
-    Ok(())
+/// Images from the Replicate API, reading `REPLICATE_API_TOKEN` on every request
+#[derive(Default)]
+pub struct Replicate {
+    client: Client,
 }
 
-/// <https://replicate.com/recraft-ai/recraft-v3>
-async fn image_diffusion(
-    client: &Client,
-    api_token: &str,
-    prompt: &str,
-) -> Result<(String, DynamicImage)> {
-    let result_url = replicate_request_prediction(
-        client,
-        api_token,
-        "https://api.replicate.com/v1/models/recraft-ai/recraft-v3/predictions",
-        &json!({
-            "input": {
-                "prompt": prompt,
-                "size": "1536x1024",
-                "style": "digital_illustration",
-            }
-        }),
-    )
-    .await?;
+impl Replicate {
+    fn api_token() -> Result<String> {
+        env::var("REPLICATE_API_TOKEN")
+            .map_err(|_| anyhow!("REPLICATE_API_TOKEN environment variable not set"))
+    }
 
-    let img_data = client.get(&result_url).send().await?.bytes().await?;
-    let img = ImageReader::new(Cursor::new(img_data))
-        .with_guessed_format()?
-        .decode()?;
+    async fn download(&self, url: &str) -> Result<DynamicImage> {
+        let img_data = self.client.get(url).send().await?.bytes().await?;
+        Ok(ImageReader::new(Cursor::new(img_data))
+            .with_guessed_format()?
+            .decode()?)
+    }
+}
+
+#[async_trait]
+impl ImageProvider for Replicate {
+    async fn diffuse(
+        &self,
+        model: &ImageModel,
+        prompt: &str,
+        negative_prompt: Option<&str>,
+        size: (u32, u32),
+    ) -> Result<(String, DynamicImage)> {
+        let input = model.build_input(prompt, negative_prompt, size);
+        let result_url = replicate_request_prediction(
+            &self.client,
+            &Self::api_token()?,
+            &model.predictions_url(),
+            &json!({ "input": input }),
+        )
+        .await?;
+        Ok((result_url.clone(), self.download(&result_url).await?))
+    }
 
-    Ok((result_url, img))
+    /// <https://replicate.com/philz1337x/clarity-upscaler>
+    async fn upscale(&self, image: &DynamicImage, prompt: &str) -> Result<(String, DynamicImage)> {
+        let image_uri = jpeg_data_uri(image)?;
+
+        let result_url = replicate_request_prediction(
+            &self.client,
+            &Self::api_token()?,
+            "",
+            &json!({
+                "version": "dfad41707589d68ecdccd1dfa600d55a208f9310748e44bfe35b4a6291453d5e",
+                "input": {
+                    "image": image_uri,
+                    "prompt": format!("{}, painting, wallpaper, masterpiece, best quality, highres", prompt),
+                    "negative_prompt": "(worst quality, low quality, normal quality:2), realistic, (signature:3, signed, watermark, inscription, writing, text)",
+                    "dynamic": 6,
+                    "handfix": "disabled",
+                    "sharpen": 0,
+                    "sd_model": "juggernaut_reborn.safetensors [338b85bc4f]",
+                    "scheduler": "DPM++ 3M SDE Karras",
+                    "creativity": 0.35,
+                    "resemblance": 0.6,
+                    "scale_factor": 2,
+                    "output_format": "png",
+                    "num_inference_steps": 18,
+                }
+            }),
+        )
+        .await?;
+        Ok((result_url.clone(), self.download(&result_url).await?))
+    }
 }
 
-/// <https://replicate.com/philz1337x/clarity-upscaler>
-async fn upscale_image(
-    client: &Client,
-    api_token: &str,
-    image: &DynamicImage,
-    prompt: &str,
-) -> Result<(String, DynamicImage)> {
+/// Inline an image as a base64 jpeg for APIs that take image urls
+fn jpeg_data_uri(image: &DynamicImage) -> Result<String> {
     let mut bytes = Vec::new();
     let encoder = JpegEncoder::new_with_quality(&mut bytes, 90);
     image.write_with_encoder(encoder)?;
-    let image_uri = format!("data:image/jpeg;base64,{}", STANDARD.encode(&bytes));
-
-    let result_url = replicate_request_prediction(
-        client,
-        api_token,
-        "",
-        &json!({
-            "version": "dfad41707589d68ecdccd1dfa600d55a208f9310748e44bfe35b4a6291453d5e",
-            "input": {
-                "image": image_uri,
-                "prompt": format!("{}, painting, wallpaper, masterpiece, best quality, highres", prompt),
-                "negative_prompt": "(worst quality, low quality, normal quality:2), realistic, (signature:3, signed, watermark, inscription, writing, text)",
-                "dynamic": 6,
-                "handfix": "disabled",
-                "sharpen": 0,
-                "sd_model": "juggernaut_reborn.safetensors [338b85bc4f]",
-                "scheduler": "DPM++ 3M SDE Karras",
-                "creativity": 0.35,
-                "resemblance": 0.6,
-                "scale_factor": 2,
-                "output_format": "png",
-                "num_inference_steps": 18,
-            }
-        }),
-    )
-    .await?;
-
-    let img_data = client.get(&result_url).send().await?.bytes().await?;
-    let img = ImageReader::new(Cursor::new(img_data))
-        .with_guessed_format()?
-        .decode()?;
-
-    Ok((result_url, img))
+    Ok(format!(
+        "data:image/jpeg;base64,{}",
+        STANDARD.encode(&bytes)
+    ))
 }
 
 async fn replicate_request_prediction(
@@ -675,50 +2720,543 @@ async fn replicate_request_prediction(
     model: &str,
     input_json: &serde_json::Value,
 ) -> Result<String> {
-    let url = if model.is_empty() {
-        "https://api.replicate.com/v1/predictions"
-    } else {
-        model
-    };
-    let response = client
-        .post(url)
-        .header("Authorization", format!("Bearer {api_token}"))
-        .header("Content-Type", "application/json")
-        .json(input_json)
-        .send()
-        .await?;
-
-    let response_json = response.json::<serde_json::Value>().await?;
+    let response_json = replicate_create_prediction(client, api_token, model, input_json).await?;
     let status_url = response_json["urls"]["get"]
         .as_str()
         .ok_or_else(|| anyhow!("No valid status URL found"))?
         .to_string();
 
     for _ in 0..TIMEOUT {
-        let status_response = client
-            .get(status_url.clone())
+        let status_json = replicate_get(client, api_token, &status_url).await?;
+
+        match status_json["status"].as_str() {
+            Some("succeeded") => {
+                return prediction_output(&status_json)
+                    .ok_or_else(|| anyhow!("Prediction succeeded without an output URL"));
+            }
+            Some(status @ ("failed" | "canceled")) => {
+                return Err(anyhow!(
+                    "Prediction {status}: {}",
+                    status_json["error"].as_str().unwrap_or("no error given")
+                ));
+            }
+            // Starting or processing
+            _ => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+
+    Err(anyhow!("Prediction timed out after {TIMEOUT} seconds"))
+}
+
+/// Start a prediction without waiting for it, an empty model uses the versioned predictions endpoint
+async fn replicate_create_prediction(
+    client: &Client,
+    api_token: &str,
+    model: &str,
+    input_json: &serde_json::Value,
+) -> Result<serde_json::Value> {
+    let url = if model.is_empty() {
+        "https://api.replicate.com/v1/predictions"
+    } else {
+        model
+    };
+    // Every prediction created is billed, so only retry when Replicate can't have started one
+    let policy = RetryPolicy {
+        idempotent: false,
+        ..RetryPolicy::default()
+    };
+    with_retry(policy, "Replicate prediction", || async {
+        let response = client
+            .post(url)
             .header("Authorization", format!("Bearer {api_token}"))
             .header("Content-Type", "application/json")
+            .json(input_json)
             .send()
             .await?;
+        Ok(check_status(response)
+            .await?
+            .json::<serde_json::Value>()
+            .await?)
+    })
+    .await
+}
 
-        let status_json = status_response.json::<serde_json::Value>().await?;
+async fn replicate_get(
+    client: &Client,
+    api_token: &str,
+    status_url: &str,
+) -> Result<serde_json::Value> {
+    with_retry(RetryPolicy::default(), "Replicate status", || async {
+        let response = client
+            .get(status_url)
+            .header("Authorization", format!("Bearer {api_token}"))
+            .header("Content-Type", "application/json")
+            .send()
+            .await?;
+        Ok(check_status(response)
+            .await?
+            .json::<serde_json::Value>()
+            .await?)
+    })
+    .await
+}
 
-        if status_json["status"] == "succeeded" {
-            if let Some(url) = status_json["output"].as_str() {
-                return Ok(url.to_string());
+/// Boot the default image model with a throwaway prediction when `WARMUP_ON_START` is set
+/// Replicate can take a minute to cold start a model, the prediction is cancelled once it starts running
+pub async fn warmup_model() {
+    if !config::var("WARMUP_ON_START").is_some_and(|value| value.eq_ignore_ascii_case("true")) {
+        return;
+    }
+    let model = models::resolve(None);
+    let result = async {
+        let client = Client::new();
+        let api_token = env::var("REPLICATE_API_TOKEN")
+            .map_err(|_| anyhow!("REPLICATE_API_TOKEN environment variable not set"))?;
+        let input = model.build_input("warmup", None, ResolutionPreset::FHD.diffusion_size());
+        let prediction = replicate_create_prediction(
+            &client,
+            &api_token,
+            &model.predictions_url(),
+            &json!({ "input": input }),
+        )
+        .await?;
+        let status_url = prediction["urls"]["get"]
+            .as_str()
+            .ok_or_else(|| anyhow!("No valid status URL found"))?;
+        let cancel_url = prediction["urls"]["cancel"]
+            .as_str()
+            .ok_or_else(|| anyhow!("No valid cancel URL found"))?;
+
+        // Starting covers the cold boot, anything after means the container is up
+        for _ in 0..TIMEOUT {
+            let status_json = replicate_get(&client, &api_token, status_url).await?;
+            if status_json["status"].as_str() != Some("starting") {
+                break;
             }
-            if let Some(url) = status_json["output"]
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        let response = client
+            .post(cancel_url)
+            .header("Authorization", format!("Bearer {api_token}"))
+            .send()
+            .await?;
+        check_status(response).await?;
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+    match result {
+        Ok(()) => log::info!("Model {} warmed up", model.name),
+        Err(e) => log::warn!("Failed to warm up model {}: {:?}", model.name, e),
+    }
+}
+
+/// The output URL of a finished prediction, models return either one URL or a list
+fn prediction_output(status_json: &serde_json::Value) -> Option<String> {
+    status_json["output"]
+        .as_str()
+        .or_else(|| {
+            status_json["output"]
                 .as_array()
-                .and_then(|arr| arr.first())
-                .and_then(|v| v.as_str())
-            {
-                return Ok(url.to_string());
+                .and_then(|outputs| outputs.first())
+                .and_then(|output| output.as_str())
+        })
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{DatabaseStyle, HistoryConfig, ThumbnailSettings};
+    use chrono::TimeZone;
+
+    fn image_file(width: u32, height: u32) -> ImageFile {
+        ImageFile {
+            file_name: format!("{width}x{height}.webp"),
+            width,
+            height,
+            resolution_preset: String::new(),
+            alt_formats: Vec::new(),
+        }
+    }
+
+    fn wallpaper(width: u32, height: u32) -> WallpaperData {
+        let mut wallpaper = WallpaperData {
+            original_file: image_file(width, height),
+            thumbnail_file: image_file(640, 360),
+            shared_liked_state: LikedState::Liked,
+            ..WallpaperData::sample()
+        };
+        wallpaper.color_data.average_color = (1.0, 0.5, 0.0);
+        wallpaper.color_data.top_20_percent_brightness = 0.7;
+        wallpaper.color_data.color_temperature = ColorTemperature::Warm;
+        wallpaper
+    }
+
+    fn filter(orientation: &str) -> SmartgetFilter {
+        SmartgetQuery {
+            temperature: None,
+            orientation: Some(orientation.to_string()),
+            profile: None,
+            hue: None,
+            hue_tolerance: None,
+            token: None,
+        }
+        .filter()
+        .unwrap()
+    }
+
+    /// Midday, when bright wallpapers are picked
+    fn noon() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn scales_brightness_histogram() {
+        let histogram = brightness_histogram(&[0.0, 0.0, 1.0, 0.5]);
+        assert_eq!(histogram.len(), 256);
+        assert_eq!(histogram[0], u16::MAX);
+        assert_eq!(histogram[255], u16::MAX / 2);
+        assert_eq!(histogram[128], u16::MAX / 2);
+        assert_eq!(histogram.iter().filter(|count| **count > 0).count(), 3);
+    }
+
+    #[test]
+    fn filters_on_orientation() {
+        let landscape = wallpaper(2560, 1440);
+        let portrait = wallpaper(1080, 2400);
+        let wallpapers = [landscape.clone(), portrait.clone()];
+
+        let chosen = smartget_choice(wallpapers.clone(), &filter("portrait"), noon()).unwrap();
+        assert_eq!(chosen.id, portrait.id);
+        let chosen = smartget_choice(wallpapers, &filter("Landscape"), noon()).unwrap();
+        assert_eq!(chosen.id, landscape.id);
+
+        let query = SmartgetQuery {
+            temperature: None,
+            orientation: Some("square".to_string()),
+            profile: None,
+            hue: None,
+            hue_tolerance: None,
+            token: None,
+        };
+        assert!(query.filter().is_none());
+    }
+
+    #[test]
+    fn filters_on_hue_and_widens_when_nothing_matches() {
+        let mut blue = wallpaper(2560, 1440);
+        blue.color_data.hue = 210.0 / 360.0;
+        let red = wallpaper(2560, 1440);
+        let wallpapers = [blue.clone(), red.clone()];
+        let hue_filter = |hue: f32, tolerance: f32| {
+            SmartgetQuery {
+                temperature: None,
+                orientation: None,
+                profile: None,
+                hue: Some(hue),
+                hue_tolerance: Some(tolerance),
+                token: None,
             }
+            .filter()
+        };
+
+        let filter = hue_filter(200.0, 20.0).unwrap();
+        assert!(filter.matches(&blue, noon()));
+        assert!(!filter.matches(&red, noon()));
+        let chosen = smartget_choice(wallpapers.clone(), &filter, noon()).unwrap();
+        assert_eq!(chosen.id, blue.id);
+
+        // Red wraps around the wheel from 350
+        let filter = hue_filter(350.0, 15.0).unwrap();
+        assert!(filter.matches(&red, noon()));
+
+        // Green is 90 from red and 120 from blue, so widening reaches red first
+        let filter = hue_filter(90.0, 10.0).unwrap();
+        assert!(!filter.matches(&red, noon()));
+        let chosen = smartget_choice(wallpapers, &filter, noon()).unwrap();
+        assert_eq!(chosen.id, red.id);
+
+        assert!(hue_filter(90.0, 200.0).is_none());
+    }
+
+    #[test]
+    fn weights_by_rating() {
+        let mut rated = wallpaper(2560, 1440);
+        let unrated = smartget_weight(&rated);
+        rated.rating = Some(1);
+        let low = smartget_weight(&rated);
+        rated.rating = Some(5);
+        let high = smartget_weight(&rated);
+        assert!(low < unrated && unrated < high);
+        assert!(low > 0);
+    }
+
+    #[test]
+    fn scores_candidates() {
+        let mut color_data = wallpaper(2560, 1440).color_data;
+        color_data.sharpness_score = 200.0;
+        color_data.contrast_ratio = 11.0;
+        color_data.hue = 0.5;
+        let novel = candidate_score(&color_data, 200.0, &[0.0]);
+        assert!((novel - 0.5 - 0.15 - 0.2).abs() < 1e-5);
+
+        // A hue close to a recent wallpaper wraps around the wheel
+        color_data.hue = 0.95;
+        let repeated = candidate_score(&color_data, 200.0, &[0.0]);
+        assert!(repeated < novel);
+
+        color_data.hue = 0.5;
+        color_data.sharpness_score = 100.0;
+        assert!(candidate_score(&color_data, 200.0, &[0.0]) < novel);
+    }
+
+    #[test]
+    fn picks_from_the_accounts_own_likes() {
+        let mine = Uuid::new_v4();
+        let theirs = Uuid::new_v4();
+        let mut wallpaper = wallpaper(2560, 1440);
+        wallpaper.shared_liked_state = LikedState::Neutral;
+        wallpaper.liked_states.insert(theirs, LikedState::Loved);
+        wallpaper.liked_states.insert(mine, LikedState::Disliked);
+        let wallpapers = [wallpaper.clone()];
+
+        let for_account = |account| SmartgetFilter {
+            account,
+            ..SmartgetFilter::default()
+        };
+        assert_eq!(
+            smartget_candidates(&wallpapers, &for_account(Some(mine)), noon()),
+            0
+        );
+        assert_eq!(
+            smartget_candidates(&wallpapers, &for_account(Some(theirs)), noon()),
+            1
+        );
+        assert_eq!(
+            smartget_candidates(&wallpapers, &for_account(None), noon()),
+            1
+        );
+
+        // Accounts that never voted see the vote from before likes were per account
+        wallpaper.shared_liked_state = LikedState::Liked;
+        assert!(wallpaper.liked_state(Uuid::new_v4()) == LikedState::Liked);
+        assert!(wallpaper.liked_state(mine) == LikedState::Disliked);
+        assert!(wallpaper.combined_liked_state() == LikedState::Loved);
+    }
+
+    #[test]
+    fn combines_negative_prompts() {
+        assert_eq!(negative_prompt(None, ""), None);
+        assert_eq!(
+            negative_prompt(Some("watermark, blurry"), "people\n\ntext "),
+            Some("watermark, blurry, people, text".to_string())
+        );
+        assert_eq!(
+            negative_prompt(Some(" "), "people"),
+            Some("people".to_string())
+        );
+        let long = negative_prompt(Some("watermark"), &"x".repeat(600)).unwrap();
+        assert_eq!(long.len(), "watermark, ".len() + NEGATIVE_PROMPT_LIMIT);
+    }
+
+    #[test]
+    fn applies_profile_constraints() {
+        let mut dark = wallpaper(1080, 2400);
+        dark.color_data.top_20_percent_brightness = 0.2;
+        dark.rating = Some(5);
+        let mut loved = dark.clone();
+        loved.id = Uuid::new_v4();
+        loved.shared_liked_state = LikedState::Loved;
+        let bright = wallpaper(1080, 2400);
+        let wallpapers = [dark.clone(), loved.clone(), bright];
+
+        let mut profile = FetchProfile {
+            name: "oled".to_string(),
+            orientation: Some(Orientation::Portrait),
+            brightness_range: Some((0.0, 0.3)),
+            min_rating: Some(4),
+            loved_only: false,
+            rest_hours: None,
+        };
+        let default = SmartgetFilter::default();
+        assert_eq!(smartget_candidates(&wallpapers, &default, noon()), 1);
+        let oled = SmartgetFilter::default().with_profile(&profile);
+        assert_eq!(smartget_candidates(&wallpapers, &oled, noon()), 2);
+        profile.loved_only = true;
+        let oled = SmartgetFilter::default().with_profile(&profile);
+        assert_eq!(
+            smartget_choice(wallpapers.clone(), &oled, noon())
+                .unwrap()
+                .id,
+            loved.id
+        );
+
+        // The query orientation wins over the profile's
+        let landscape = filter("landscape").with_profile(&profile);
+        assert_eq!(smartget_candidates(&wallpapers, &landscape, noon()), 0);
+        // Unrated wallpapers count as three stars
+        profile.loved_only = false;
+        profile.min_rating = Some(3);
+        dark.rating = None;
+        let rated = SmartgetFilter::default().with_profile(&profile);
+        assert!(rated.matches(&dark, noon()));
+        profile.min_rating = Some(4);
+        let rated = SmartgetFilter::default().with_profile(&profile);
+        assert!(!rated.matches(&dark, noon()));
+    }
+
+    /// A horizontal ramp from black to the colour
+    fn gradient(color: [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgb8(::image::RgbImage::from_fn(256, 64, |x, _| {
+            ::image::Rgb(color.map(|channel| (u32::from(channel) * x / 255) as u8))
+        }))
+    }
+
+    #[test]
+    fn darkens_with_a_tone_curve() {
+        let original = calculate_color_data(&gradient([255, 255, 255]));
+        let dark = calculate_color_data(&darken_image(&gradient([255, 255, 255]), 0.3));
+        assert!(dark.top_20_percent_brightness <= 0.3 + 1e-2);
+        assert!(dark.top_20_percent_brightness > 0.15);
+        assert!(dark.lightness < original.lightness);
+        // Shadows sink further than a flat multiply would take them
+        assert!(dark.bottom_20_percent_brightness < original.bottom_20_percent_brightness * 0.3);
+        assert!(dark.contrast_ratio > 1.5);
+
+        // Hue is kept while darkening a colour
+        let original = calculate_color_data(&gradient([40, 120, 255]));
+        let dark = calculate_color_data(&darken_image(&gradient([40, 120, 255]), 0.25));
+        assert!(hue_distance(original.hue * 360.0, dark.hue * 360.0) < 5.0);
+        assert!(dark.top_20_percent_brightness <= 0.25 + 1e-2);
+    }
+
+    #[test]
+    fn prefers_loved_dark_variants_at_night() {
+        let midnight = Utc.with_ymd_and_hms(2025, 6, 1, 23, 0, 0).unwrap();
+        let mut loved = wallpaper(2560, 1440);
+        loved.color_data.top_20_percent_brightness = 0.5;
+        loved.shared_liked_state = LikedState::Loved;
+        let mut dark = loved.clone();
+        dark.id = Uuid::new_v4();
+        dark.dark_variant = true;
+        dark.color_data.top_20_percent_brightness = 0.25;
+        let wallpapers = [loved.clone(), dark.clone()];
+
+        for _ in 0..10 {
+            let chosen = smartget_choice(wallpapers.clone(), &filter("landscape"), midnight);
+            assert_eq!(chosen.unwrap().id, dark.id);
+        }
+        // Not when a profile sets its own brightness
+        let mut fixed = filter("landscape");
+        fixed.brightness_range = Some((0.4, 0.6));
+        let chosen = smartget_choice(wallpapers, &fixed, midnight).unwrap();
+        assert_eq!(chosen.id, loved.id);
+    }
+
+    #[test]
+    fn rests_recently_served_wallpapers() {
+        let mut served = wallpaper(2560, 1440);
+        served.last_served = Some(noon() - chrono::Duration::hours(1));
+        let fresh = wallpaper(2560, 1440);
+
+        let chosen = smartget_choice(
+            [served.clone(), fresh.clone()],
+            &filter("landscape"),
+            noon(),
+        )
+        .unwrap();
+        assert_eq!(chosen.id, fresh.id);
+
+        // With nothing else to pick it is still served
+        let chosen = smartget_choice([served.clone()], &filter("landscape"), noon()).unwrap();
+        assert_eq!(chosen.id, served.id);
+    }
+
+    #[test]
+    fn meta_json_has_stable_fields() {
+        let wallpaper = wallpaper(2560, 1440);
+        let meta = smartget_meta_json(
+            &wallpaper,
+            wallpaper.combined_liked_state(),
+            "http://example.com",
+        );
+        assert_eq!(meta["id"], json!(wallpaper.id));
+        assert_eq!(meta["prompt"], "Quiet harbour");
+        assert_eq!(meta["full_prompt"], "A quiet harbour at dusk");
+        assert_eq!(meta["liked_state"], "liked");
+        assert_eq!(meta["colors"]["average"], "#ff7f00");
+        assert_eq!(meta["colors"]["temperature"], "warm");
+        assert_eq!(meta["width"], 2560);
+        assert_eq!(meta["height"], 1440);
+        assert_eq!(
+            meta["image_url"],
+            "http://example.com/wallpapers/2560x1440.webp"
+        );
+    }
+
+    #[tokio::test]
+    async fn removes_every_wallpaper_file() {
+        let dir = std::env::temp_dir().join(format!("wallpapy-remove-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join(generation_context::CONTEXT_DIR)).unwrap();
+        let mut wallpaper = wallpaper(2560, 1440);
+        wallpaper.original_file.alt_formats = vec!["avif".to_string()];
+        wallpaper.upscaled_file = Some(image_file(5120, 2880));
+        wallpaper.reference_file = Some(image_file(1024, 1024));
+        wallpaper.context_file = Some(format!(
+            "{}/{}.txt.zst",
+            generation_context::CONTEXT_DIR,
+            wallpaper.id
+        ));
+        let files = wallpaper.all_files();
+        assert_eq!(files.len(), 6);
+        for file_name in &files {
+            std::fs::write(dir.join(file_name), b"").unwrap();
         }
 
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        remove_wallpaper_files(&dir, &wallpaper).await;
+        let context_dir = dir.join(generation_context::CONTEXT_DIR);
+        assert_eq!(std::fs::read_dir(&context_dir).unwrap().count(), 0);
+        std::fs::remove_dir(&context_dir).unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        // Already missing files are only logged
+        remove_wallpaper_files(&dir, &wallpaper).await;
+        std::fs::remove_dir(&dir).unwrap();
     }
 
-    Err(anyhow!("Operation timed out or failed"))
+    #[test]
+    fn unlinks_removed_wallpapers() {
+        let source = wallpaper(2560, 1440);
+        let mut crop = wallpaper(1440, 2560);
+        crop.parent = Some(source.id);
+        crop.original_id = Some(source.id);
+        let mut variation = wallpaper(2560, 1440);
+        variation.variation_of = Some(source.id);
+        let mut kept = wallpaper(2560, 1440);
+        kept.variations = vec![source.id, variation.id];
+        let mut database = Database {
+            style: DatabaseStyle::default(),
+            history_config: HistoryConfig::default(),
+            prompt_templates: Vec::new(),
+            wallpapers: [&crop, &variation, &kept]
+                .into_iter()
+                .map(|wallpaper| (wallpaper.id, wallpaper.clone()))
+                .collect(),
+            comments: HashMap::new(),
+            pending_prompts: HashMap::new(),
+            fetch_profiles: Vec::new(),
+            users: HashMap::new(),
+            viewer: None,
+            thumbnail_settings: ThumbnailSettings::default(),
+            stale_thumbnails: Vec::new(),
+            presets: HashMap::new(),
+            share_links: Vec::new(),
+        };
+
+        unlink_removed(&mut database, &[source.id]);
+        let crop = &database.wallpapers[&crop.id];
+        assert!(crop.parent.is_none() && crop.original_id.is_none());
+        assert!(database.wallpapers[&variation.id].variation_of.is_none());
+        assert_eq!(database.wallpapers[&kept.id].variations, vec![variation.id]);
+    }
 }
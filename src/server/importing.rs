@@ -0,0 +1,538 @@
+use crate::common::{AuditAction, ImportDirPacket, TokenStringPacket};
+use crate::server::{
+    audit,
+    auth::{token_account, verify_token},
+    error_status, image,
+    net::check_status,
+    AppState,
+};
+use anyhow::{anyhow, bail, Result};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use reqwest::{redirect::Policy, Client, Url};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::fs;
+use uuid::Uuid;
+
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "webp", "gif", "bmp", "tif", "tiff", "avif",
+];
+
+/// Prompt and title of images pasted into the client
+const PASTED_TITLE: &str = "(Imported)";
+
+/// Largest image downloaded for a pasted URL, the same as a direct upload
+const MAX_URL_IMPORT_SIZE: usize = 64 * 1024 * 1024;
+
+/// Redirects followed for a pasted URL, every hop is checked like the URL itself
+const MAX_URL_IMPORT_REDIRECTS: usize = 5;
+
+const URL_IMPORT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const URL_IMPORT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize, // Already imported by an earlier run
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Serialize)]
+pub struct ImportFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// Import every image under a directory, skipping files whose contents were imported before
+pub async fn import_dir(
+    state: &AppState,
+    dir: &Path,
+    caption: bool,
+    liked: bool,
+) -> Result<ImportSummary> {
+    let _lock = state.import_lock.lock().await;
+    let mut imports = read_imports(state).await?;
+    let mut summary = ImportSummary::default();
+
+    for path in image_files(dir).await? {
+        let result: Result<bool> = async {
+            let data = fs::read(&path).await?;
+            let hash = content_hash(&data);
+            if imports.contains_key(&hash) {
+                return Ok(false);
+            }
+            let id =
+                image::store_imported(state, data, &title_from_path(&path), caption, liked).await?;
+            // Record each file as soon as it's stored so a rerun never duplicates it
+            imports.insert(hash, id);
+            write_imports(state, &imports).await?;
+            Ok(true)
+        }
+        .await;
+        match result {
+            Ok(true) => {
+                log::info!("Imported {}", path.display());
+                summary.imported += 1;
+            }
+            Ok(false) => summary.skipped += 1,
+            Err(e) => {
+                log::error!("Failed to import {}: {:?}", path.display(), e);
+                summary.failed.push(ImportFailure {
+                    path: path.display().to_string(),
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Import a directory on the server, only available to admins
+pub async fn import(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: ImportDirPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize import packet: {:?}", e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    match token_account(&state, &packet.token).await {
+        Ok(Some((_, true))) => {}
+        Ok(_) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    match import_dir(
+        &state,
+        Path::new(&packet.path),
+        packet.caption,
+        packet.liked,
+    )
+    .await
+    {
+        Ok(summary) => {
+            let summary_text = format!(
+                "Imported {} from {} ({} skipped, {} failed)",
+                summary.imported,
+                packet.path,
+                summary.skipped,
+                summary.failed.len()
+            );
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Import,
+                None,
+                summary_text,
+            )
+            .await;
+            match serde_json::to_string(&summary) {
+                Ok(json) => (
+                    StatusCode::OK,
+                    [(header::CONTENT_TYPE, "application/json")],
+                    json,
+                )
+                    .into_response(),
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Errored import {:?}", e);
+            error_status(&e).into_response()
+        }
+    }
+}
+
+/// A pasted URL pointed at the server's own machine or network rather than the public internet
+#[derive(Debug)]
+struct BlockedUrl(String);
+
+impl fmt::Display for BlockedUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} isn't a public address", self.0)
+    }
+}
+
+impl std::error::Error for BlockedUrl {}
+
+/// Where a pasted image comes from, a link to download or the image itself inline
+enum PastedImage {
+    Url(Url),
+    Data(Vec<u8>),
+}
+
+impl PastedImage {
+    /// Parse an http(s) image URL or a base64 `data:image/...` URI
+    fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        if let Some(data_uri) = text.strip_prefix("data:image/") {
+            let (_, data) = data_uri
+                .split_once(";base64,")
+                .ok_or_else(|| anyhow!("Image data URI isn't base64"))?;
+            return Ok(Self::Data(STANDARD.decode(data)?));
+        }
+        Ok(Self::Url(web_url(Url::parse(text)?)?))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Url(url) => url.to_string(),
+            Self::Data(data) => format!("a pasted {} byte image", data.len()),
+        }
+    }
+
+    async fn into_data(self) -> Result<Vec<u8>> {
+        let mut url = match self {
+            Self::Url(url) => url,
+            Self::Data(data) => return Ok(data),
+        };
+        // Redirects are followed by hand so every hop gets the same address check
+        let mut redirects = 0;
+        let mut response = loop {
+            let response = public_client(&url).await?.get(url.clone()).send().await?;
+            if !response.status().is_redirection() {
+                break check_status(response).await?;
+            }
+            if redirects == MAX_URL_IMPORT_REDIRECTS {
+                bail!("Stopped after {MAX_URL_IMPORT_REDIRECTS} redirects");
+            }
+            redirects += 1;
+            let location = response
+                .headers()
+                .get(header::LOCATION)
+                .ok_or_else(|| anyhow!("Redirect without a location"))?
+                .to_str()?;
+            url = web_url(url.join(location)?)?;
+        };
+        if response
+            .content_length()
+            .is_some_and(|length| length > MAX_URL_IMPORT_SIZE as u64)
+        {
+            bail!("Image is larger than {MAX_URL_IMPORT_SIZE} bytes");
+        }
+        // The length header can be missing or wrong, so keep counting while downloading
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if data.len() + chunk.len() > MAX_URL_IMPORT_SIZE {
+                bail!("Image is larger than {MAX_URL_IMPORT_SIZE} bytes");
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+}
+
+/// Only http and https URLs can be downloaded
+fn web_url(url: Url) -> Result<Url> {
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!(
+            "Only http and https images can be imported, not {}",
+            url.scheme()
+        );
+    }
+    Ok(url)
+}
+
+/// A client for one hop of a pasted URL, pinned to the addresses its host resolved to after checking
+/// they're all public, so the lookup can't be swapped for a private address before connecting
+async fn public_client(url: &Url) -> Result<Client> {
+    let host = url.host_str().ok_or_else(|| anyhow!("URL has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let literal = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>();
+    let addrs = match literal {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host, port)).await?.collect(),
+    };
+    if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+        return Err(BlockedUrl(host.to_string()).into());
+    }
+
+    let builder = Client::builder()
+        .redirect(Policy::none())
+        .connect_timeout(URL_IMPORT_CONNECT_TIMEOUT)
+        .timeout(URL_IMPORT_TIMEOUT);
+    let builder = if literal.is_ok() {
+        builder
+    } else {
+        builder.resolve_to_addrs(host, &addrs)
+    };
+    Ok(builder.build()?)
+}
+
+/// Whether an address is on the public internet, not loopback, private, link local or otherwise reserved
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b)) // Carrier grade NAT
+                || (a == 198 && (b == 18 || b == 19))) // Benchmarking
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(ip.into());
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00 // Unique local
+                || (first & 0xffc0) == 0xfe80 // Link local
+                || first == 0x2001 && ip.segments()[1] == 0xdb8) // Documentation
+        }
+    }
+}
+
+/// Import an image from a URL or data URI pasted into the client, a conflict if it was imported before
+pub async fn import_url(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenStringPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize import_url packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let pasted = match PastedImage::parse(&packet.string) {
+        Ok(pasted) => pasted,
+        Err(e) => {
+            log::error!("Invalid pasted image {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    let source = pasted.describe();
+    match import_pasted(&state, pasted).await {
+        Ok(Some(id)) => {
+            let summary = format!("Imported {source}");
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Import,
+                Some(id),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Ok(None) => StatusCode::CONFLICT,
+        Err(e) => {
+            log::error!("Errored import_url from {source} {:?}", e);
+            if e.is::<::image::ImageError>() || e.is::<BlockedUrl>() {
+                StatusCode::BAD_REQUEST
+            } else {
+                error_status(&e)
+            }
+        }
+    }
+}
+
+/// Store the image, None when the same image was imported before
+async fn import_pasted(state: &AppState, pasted: PastedImage) -> Result<Option<Uuid>> {
+    let data = pasted.into_data().await?;
+    let _lock = state.import_lock.lock().await;
+    let mut imports = read_imports(state).await?;
+    let hash = content_hash(&data);
+    if imports.contains_key(&hash) {
+        return Ok(None);
+    }
+    let id = image::store_imported(state, data, PASTED_TITLE, false, false).await?;
+    imports.insert(hash, id);
+    write_imports(state, &imports).await?;
+    Ok(Some(id))
+}
+
+/// Every image file under the directory, sorted so runs go in the same order
+async fn image_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                dirs.push(path);
+            } else if is_image(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// A readable title from a file name like `misty_forest-2.jpg`
+fn title_from_path(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().replace(['_', '-'], " "))
+        .unwrap_or_default();
+    let title = stem.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.is_empty() {
+        "Imported image".to_string()
+    } else {
+        title
+    }
+}
+
+/// Hex SHA-256 of the file contents
+fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Hashes of every file imported so far, so an interrupted import picks up where it stopped
+fn imports_file(state: &AppState) -> PathBuf {
+    state.config.data_dir.join("imports.ron")
+}
+
+async fn read_imports(state: &AppState) -> Result<HashMap<String, Uuid>> {
+    let imports_file = imports_file(state);
+    if fs::metadata(&imports_file).await.is_err() {
+        return Ok(HashMap::new());
+    }
+    let data = fs::read_to_string(&imports_file).await?;
+    Ok(ron::from_str(&data)?)
+}
+
+async fn write_imports(state: &AppState, imports: &HashMap<String, Uuid>) -> Result<()> {
+    let data = ron::ser::to_string_pretty(imports, ron::ser::PrettyConfig::new())?;
+    let imports_file = imports_file(state);
+    let temp_file = imports_file.with_extension("ron.tmp");
+    fs::write(&temp_file, data).await?;
+    fs::rename(&temp_file, &imports_file).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_from_file_names() {
+        assert_eq!(
+            title_from_path(Path::new("walls/misty_forest--at-dawn.JPG")),
+            "misty forest at dawn"
+        );
+        assert_eq!(title_from_path(Path::new("walls/__.png")), "Imported image");
+    }
+
+    #[test]
+    fn parses_pasted_images() {
+        assert!(matches!(
+            PastedImage::parse(" https://example.com/forest.jpg\n"),
+            Ok(PastedImage::Url(url)) if url.as_str() == "https://example.com/forest.jpg"
+        ));
+        assert!(matches!(
+            PastedImage::parse("data:image/png;base64,iVBORw0K"),
+            Ok(PastedImage::Data(data)) if data == b"\x89PNG\r\n"
+        ));
+        assert!(PastedImage::parse("file:///etc/passwd").is_err());
+        assert!(PastedImage::parse("data:image/svg+xml,<svg/>").is_err());
+        assert!(PastedImage::parse("not a url").is_err());
+    }
+
+    #[test]
+    fn only_public_addresses_are_allowed() {
+        for ip in ["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_urls_to_private_hosts() {
+        for url in [
+            "http://127.0.0.1:8080/wallpaper.png",
+            "http://[::1]/wallpaper.png",
+            "http://localhost/wallpaper.png",
+            "http://169.254.169.254/latest/meta-data",
+        ] {
+            let error = PastedImage::parse(url)
+                .unwrap()
+                .into_data()
+                .await
+                .unwrap_err();
+            assert!(error.is::<BlockedUrl>(), "{url}: {error:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_images_recursively() {
+        let dir = std::env::temp_dir().join(format!("wallpapy-import-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in ["b.png", "a.JPEG", "notes.txt", "nested/c.webp"] {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+
+        let files = image_files(&dir).await.unwrap();
+        let names = files
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.JPEG", "b.png", "nested/c.webp"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
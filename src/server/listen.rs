@@ -0,0 +1,83 @@
+use crate::server::config;
+use anyhow::{Context, Result};
+use axum::Router;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+/// Serve on the Unix socket at `SOCKET_PATH` when set, otherwise on every interface at `PORT`
+/// The socket is for headless servers behind a local proxy, nothing else is bound alongside it
+pub async fn serve(app: Router) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(path) = config::var("SOCKET_PATH") {
+        // ehttp and the image loaders only speak http over TCP, so the window can't reach a socket
+        if cfg!(feature = "gui") {
+            anyhow::bail!(
+                "SOCKET_PATH is only supported by headless builds, unset it to use the GUI"
+            );
+        }
+        return serve_unix(app, path.as_ref()).await;
+    }
+    #[cfg(not(unix))]
+    if config::var("SOCKET_PATH").is_some() {
+        log::warn!("SOCKET_PATH is only supported on unix, listening on TCP instead");
+    }
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], config::port()));
+    println!("Listening on {addr}");
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind {addr}"))?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM so stopping a container still shuts down cleanly
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for Ctrl+C {:?}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM {:?}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(app: Router, path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left behind by an earlier run would make the bind fail, anything else is left alone
+    if let Ok(metadata) = tokio::fs::symlink_metadata(path).await {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!(
+                "SOCKET_PATH '{}' exists and is not a socket",
+                path.display()
+            );
+        }
+        tokio::fs::remove_file(path).await?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind socket '{}'", path.display()))?;
+    println!("Listening on {}", path.display());
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use crate::common::{LogLevel, LogLine};
+use crate::server::{auth::token_account, AppState};
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::Utc;
+use log::{Log, Metadata, Record, SetLoggerError};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::{collections::VecDeque, future::Future, str::FromStr, sync::LazyLock};
+
+/// Lines kept in memory, older ones are dropped as new ones come in
+const LOG_BUFFER_LINES: usize = 500;
+
+static LOG_BUFFER: LazyLock<Mutex<VecDeque<LogLine>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::with_capacity(LOG_BUFFER_LINES)));
+
+tokio::task_local! {
+    /// Id of the request being handled, prefixed to every line logged while handling it
+    static REQUEST_ID: String;
+}
+
+/// Run a request handler with its id on every line it logs
+pub async fn with_request_id<F: Future>(request_id: String, handler: F) -> F::Output {
+    REQUEST_ID.scope(request_id, handler).await
+}
+
+fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Keeps the most recent lines in memory as well as passing them on to the wrapped logger
+struct BufferedLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> Log for BufferedLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        match current_request_id() {
+            Some(request_id) => self.log_record(
+                &Record::builder()
+                    .metadata(record.metadata().clone())
+                    .args(format_args!("[{request_id}] {}", record.args()))
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.log_record(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+impl<L: Log> BufferedLogger<L> {
+    fn log_record(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            // Format before taking the lock, in case formatting logs something itself
+            let line = LogLine {
+                timestamp: Utc::now(),
+                level: record.level().into(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            };
+            push_line(&mut LOG_BUFFER.lock(), line);
+        }
+        self.inner.log(record);
+    }
+}
+
+/// Install the logger with the buffer in front of it, in place of `SimpleLogger::init`
+pub fn init(logger: simple_logger::SimpleLogger) -> Result<(), SetLoggerError> {
+    log::set_max_level(logger.max_level());
+    log::set_boxed_logger(Box::new(BufferedLogger { inner: logger }))
+}
+
+fn push_line(buffer: &mut VecDeque<LogLine>, line: LogLine) {
+    if buffer.len() >= LOG_BUFFER_LINES {
+        buffer.pop_front();
+    }
+    buffer.push_back(line);
+}
+
+/// Buffered lines at least as severe as the level, oldest first
+fn recent(buffer: &VecDeque<LogLine>, level: LogLevel) -> Vec<LogLine> {
+    buffer
+        .iter()
+        .filter(|line| line.level <= level)
+        .cloned()
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    token: String,
+    level: Option<String>, // Least severe level to include, like warn, defaults to everything
+}
+
+/// The recent log lines as JSON, only available to admins
+pub async fn get(
+    State(state): State<AppState>,
+    Query(query): Query<LogsQuery>,
+) -> impl IntoResponse {
+    match token_account(&state, &query.token).await {
+        Ok(Some((_, true))) => {}
+        Ok(_) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    let level = match query.level.as_deref().map(log::Level::from_str) {
+        Some(Ok(level)) => level.into(),
+        Some(Err(_)) => return StatusCode::BAD_REQUEST.into_response(),
+        None => LogLevel::Trace,
+    };
+
+    let lines = recent(&LOG_BUFFER.lock(), level);
+    match serde_json::to_string(&lines) {
+        Ok(json) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            json,
+        )
+            .into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_recent_lines_by_level() {
+        let mut buffer = VecDeque::new();
+        for i in 0..LOG_BUFFER_LINES + 10 {
+            let level = if i % 10 == 0 {
+                LogLevel::Warn
+            } else {
+                LogLevel::Info
+            };
+            push_line(
+                &mut buffer,
+                LogLine {
+                    timestamp: Utc::now(),
+                    level,
+                    target: "wallpapy".to_string(),
+                    message: format!("Line {i}"),
+                },
+            );
+        }
+
+        assert_eq!(buffer.len(), LOG_BUFFER_LINES);
+        assert_eq!(buffer[0].message, "Line 10");
+        let warnings = recent(&buffer, LogLevel::Warn);
+        assert_eq!(warnings.len(), LOG_BUFFER_LINES / 10);
+        assert!(warnings.iter().all(|line| line.level == LogLevel::Warn));
+        assert_eq!(recent(&buffer, LogLevel::Error).len(), 0);
+    }
+
+    #[tokio::test]
+    async fn request_id_is_only_set_inside_the_handler() {
+        assert_eq!(current_request_id(), None);
+        let inner = with_request_id("abc".to_string(), async { current_request_id() }).await;
+        assert_eq!(inner.as_deref(), Some("abc"));
+        assert_eq!(current_request_id(), None);
+    }
+}
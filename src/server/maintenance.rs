@@ -0,0 +1,531 @@
+use crate::common::{
+    AuditAction, Database, ImageFile, MaintenanceReport, ThumbnailSettings, TokenPacket,
+    WallpaperData,
+};
+use crate::server::{
+    audit, auth::token_account, encoding, error_status, image, thumbnails, AppState,
+};
+use ::image::DynamicImage;
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use std::{collections::HashSet, path::Path, time::Duration};
+use tokio::fs;
+use uuid::Uuid;
+
+/// Wait between stale thumbnail rebuilds, so a settings change doesn't hold up everything else
+const THUMBNAIL_REBUILD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Unreferenced files newer than this are left alone, they may belong to a wallpaper still being stored
+const ORPHAN_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize)]
+pub struct VerifyQuery {
+    fix: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Fix {
+    RegenerateThumbs,
+    RemoveDanglingEntries,
+    DeleteOrphanFiles,
+}
+
+impl Fix {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::RegenerateThumbs => "regenerate-thumbs",
+            Self::RemoveDanglingEntries => "remove-dangling-entries",
+            Self::DeleteOrphanFiles => "delete-orphan-files",
+        }
+    }
+}
+
+/// Cross reference the database against the wallpapers directory, optionally fixing one kind of problem
+pub async fn verify(
+    State(state): State<AppState>,
+    Query(query): Query<VerifyQuery>,
+    packet: Bytes,
+) -> impl IntoResponse {
+    let packet: TokenPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize verify packet: {:?}", e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    let admin = match token_account(&state, &packet.token).await {
+        Ok(Some((_, admin))) => admin,
+        Ok(None) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let fix = match query.fix.as_deref() {
+        None => None,
+        Some("regenerate-thumbs") => Some(Fix::RegenerateThumbs),
+        Some("remove-dangling-entries") => Some(Fix::RemoveDanglingEntries),
+        Some("delete-orphan-files") => Some(Fix::DeleteOrphanFiles),
+        Some(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    // Any account can look, only admins can change anything
+    if fix.is_some() && !admin {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match verify_impl(&state, fix).await {
+        Ok(report) => {
+            if let Some(fix) = fix {
+                let summary = format!("Ran {}, fixed {}", fix.name(), report.fixed);
+                audit(
+                    &state,
+                    &packet.token,
+                    AuditAction::Maintenance,
+                    None,
+                    summary,
+                )
+                .await;
+            }
+            match serde_json::to_vec(&report) {
+                Ok(data) => (
+                    StatusCode::OK,
+                    [(header::CONTENT_TYPE, "application/json")],
+                    data,
+                )
+                    .into_response(),
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Errored verify {:?}", e);
+            error_status(&e).into_response()
+        }
+    }
+}
+
+/// Log any disagreement between the database and the wallpapers directory
+pub async fn startup_check(state: &AppState) {
+    match verify_impl(state, None).await {
+        Ok(report) if report.is_clean() => log::info!("Wallpaper files are consistent"),
+        Ok(report) => log::warn!(
+            "Wallpaper files are inconsistent: {} missing originals, {} missing thumbnails, {} orphan files",
+            report.missing_originals.len(),
+            report.missing_thumbnails.len(),
+            report.orphan_files.len()
+        ),
+        Err(e) => log::error!("Failed to verify wallpaper files {:?}", e),
+    }
+}
+
+async fn verify_impl(state: &AppState, fix: Option<Fix>) -> Result<MaintenanceReport> {
+    let dir = state.config.wallpapers_dir.as_path();
+    // Only fixes write back, a plain check doesn't need to queue behind other writes
+    let _lock = if fix.is_some() {
+        Some(state.lock_database().await?)
+    } else {
+        None
+    };
+    let mut database = state.read_database().await;
+    let files = disk_files(dir).await?;
+    let report = find_problems(&database, &files);
+
+    let fixed = match fix {
+        None => 0,
+        Some(Fix::RegenerateThumbs) => {
+            let fixed = regenerate_thumbnails(
+                &state.config.wallpapers_dir,
+                &mut database,
+                &report.missing_thumbnails,
+                &files,
+            );
+            state.write_database(&database).await?;
+            fixed
+        }
+        Some(Fix::RemoveDanglingEntries) => {
+            let removed = report
+                .missing_originals
+                .iter()
+                .filter_map(|id| database.wallpapers.remove(id))
+                .collect::<Vec<_>>();
+            image::unlink_removed(&mut database, &report.missing_originals);
+            state.write_database(&database).await?;
+            // Clean up whatever files they still have so they don't become orphans
+            for wallpaper in &removed {
+                image::remove_wallpaper_files(dir, wallpaper).await;
+            }
+            report.missing_originals.len()
+        }
+        Some(Fix::DeleteOrphanFiles) => {
+            let mut fixed = 0;
+            for file_name in &report.orphan_files {
+                let path = dir.join(file_name);
+                if recently_modified(&path).await? {
+                    continue;
+                }
+                fs::remove_file(path).await?;
+                fixed += 1;
+            }
+            fixed
+        }
+    };
+
+    // Report what is left after the fix
+    let mut report = if fix.is_some() {
+        find_problems(&database, &disk_files(dir).await?)
+    } else {
+        report
+    };
+    report.fixed = fixed;
+    Ok(report)
+}
+
+fn find_problems(database: &Database, files: &HashSet<String>) -> MaintenanceReport {
+    let mut report = MaintenanceReport::default();
+    let mut referenced = HashSet::new();
+    for wallpaper in database.wallpapers.values() {
+        let full_size_missing = std::iter::once(&wallpaper.original_file)
+            .chain(wallpaper.upscaled_file.as_ref())
+            .any(|file| !files.contains(&file.file_name));
+        if full_size_missing {
+            report.missing_originals.push(wallpaper.id);
+        }
+        if !files.contains(&wallpaper.thumbnail_file.file_name) {
+            report.missing_thumbnails.push(wallpaper.id);
+        }
+        referenced.extend(wallpaper.all_files());
+    }
+    report.orphan_files = files
+        .iter()
+        .filter(|file_name| !referenced.contains(*file_name))
+        .cloned()
+        .collect();
+
+    report.missing_originals.sort_unstable();
+    report.missing_thumbnails.sort_unstable();
+    report.orphan_files.sort_unstable();
+    report
+}
+
+/// Rebuild missing thumbnails from the best full size image still on disk, returning how many were rebuilt
+fn regenerate_thumbnails(
+    dir: &Path,
+    database: &mut Database,
+    ids: &[Uuid],
+    files: &HashSet<String>,
+) -> usize {
+    let version = Utc::now().timestamp_millis();
+    let mut fixed = 0;
+    for id in ids {
+        let Some(wallpaper) = database.wallpapers.get_mut(id) else {
+            continue;
+        };
+        let Some(source) = wallpaper
+            .upscaled_file
+            .iter()
+            .chain(std::iter::once(&wallpaper.original_file))
+            .find(|file| files.contains(&file.file_name))
+            .map(|file| file.file_name.clone())
+        else {
+            continue;
+        };
+
+        let file_name = format!("{}_thumb_v{version}.webp", wallpaper.datetime.to_rfc3339());
+        match write_thumbnail(dir, &source, file_name, &wallpaper.thumbnail_file) {
+            Ok((thumbnail_file, _)) => {
+                wallpaper.thumbnail_file = thumbnail_file;
+                fixed += 1;
+            }
+            Err(e) => log::error!("Failed to regenerate thumbnail for {id}: {:?}", e),
+        }
+    }
+    fixed
+}
+
+/// Downscale a full size image into a new thumbnail file, returning it along with the thumbnail image
+fn write_thumbnail(
+    dir: &Path,
+    source: &str,
+    file_name: String,
+    old_thumbnail: &ImageFile,
+) -> Result<(ImageFile, DynamicImage)> {
+    let image = ::image::open(dir.join(source))?;
+    let thumb_image = thumbnails::resize(&image);
+    std::fs::write(dir.join(&file_name), thumbnails::encode(&thumb_image)?)?;
+    let thumbnail_file = ImageFile {
+        file_name,
+        width: thumb_image.width(),
+        height: thumb_image.height(),
+        resolution_preset: old_thumbnail.resolution_preset.clone(),
+        alt_formats: Vec::new(),
+    };
+    Ok((thumbnail_file, thumb_image))
+}
+
+/// Flag every thumbnail as stale if they were written with other settings, at startup and on config reload
+pub async fn check_thumbnail_settings(state: &AppState) -> Result<()> {
+    let settings = thumbnails::settings();
+    if state.read_database().await.thumbnail_settings == settings {
+        return Ok(());
+    }
+    let stale = state
+        .with_database(|database| {
+            mark_stale_thumbnails(database, settings);
+            Ok(database.stale_thumbnails.len())
+        })
+        .await?;
+    log::info!(
+        "Thumbnail settings changed to {}x{} at quality {}, rebuilding {stale} thumbnails",
+        settings.width,
+        settings.height,
+        settings.quality
+    );
+    state.thumbnails_stale.notify_one();
+    Ok(())
+}
+
+/// Queue every wallpaper's thumbnail for a rebuild, oldest first
+fn mark_stale_thumbnails(database: &mut Database, settings: ThumbnailSettings) {
+    let mut wallpapers = database
+        .wallpapers
+        .values()
+        .map(|wallpaper| (wallpaper.datetime, wallpaper.id))
+        .collect::<Vec<_>>();
+    wallpapers.sort_unstable();
+    database.stale_thumbnails = wallpapers.into_iter().map(|(_, id)| id).collect();
+    database.thumbnail_settings = settings;
+}
+
+/// Rebuild stale thumbnails one at a time every `THUMBNAIL_REBUILD_INTERVAL`, sleeping while there are none
+pub async fn rebuild_stale_thumbnails(state: AppState) {
+    let state = &state;
+    loop {
+        match rebuild_next_thumbnail(state).await {
+            Ok(true) => tokio::time::sleep(THUMBNAIL_REBUILD_INTERVAL).await,
+            Ok(false) => state.thumbnails_stale.notified().await,
+            Err(e) => {
+                log::error!("Failed to rebuild stale thumbnail {:?}", e);
+                tokio::time::sleep(THUMBNAIL_REBUILD_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Rebuild the oldest stale thumbnail, false when there are none left
+async fn rebuild_next_thumbnail(state: &AppState) -> Result<bool> {
+    let database = state.read_database().await;
+    let Some(&id) = database.stale_thumbnails.first() else {
+        return Ok(false);
+    };
+
+    // Encode without holding the lock, the wallpaper may be gone by the time it's done
+    let rebuilt = match database.wallpapers.get(&id) {
+        Some(wallpaper) => match rebuild_thumbnail(state, wallpaper).await {
+            Ok(thumbnail_file) => Some(thumbnail_file),
+            Err(e) => {
+                // Dropped from the queue regardless, a missing original shows up in verify instead
+                log::error!("Failed to rebuild thumbnail for {id}: {:?}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let unused = state
+        .with_database(|database| {
+            database.stale_thumbnails.retain(|stale| *stale != id);
+            Ok(match (rebuilt, database.wallpapers.get_mut(&id)) {
+                (Some(thumbnail_file), Some(wallpaper)) => Some(std::mem::replace(
+                    &mut wallpaper.thumbnail_file,
+                    thumbnail_file,
+                )),
+                (rebuilt, _) => rebuilt,
+            })
+        })
+        .await?;
+
+    // Whichever thumbnail is no longer referenced, the old one or the new one for a removed wallpaper
+    if let Some(unused) = unused {
+        for file_name in std::iter::once(unused.file_name.clone()).chain(unused.alt_file_names()) {
+            if let Err(e) = fs::remove_file(state.config.wallpapers_dir.join(&file_name)).await {
+                log::error!("Failed to remove old thumbnail {file_name}: {:?}", e);
+            }
+        }
+    }
+    Ok(true)
+}
+
+async fn rebuild_thumbnail(state: &AppState, wallpaper: &WallpaperData) -> Result<ImageFile> {
+    let source = wallpaper
+        .upscaled_file
+        .as_ref()
+        .unwrap_or(&wallpaper.original_file)
+        .file_name
+        .clone();
+    let file_name = format!(
+        "{}_thumb_v{}.webp",
+        wallpaper.datetime.to_rfc3339(),
+        Utc::now().timestamp_millis()
+    );
+    let old_thumbnail = wallpaper.thumbnail_file.clone();
+    // Decoding and encoding is slow, keep it off the async runtime
+    let config = state.config.clone();
+    let (mut thumbnail_file, thumb_image) = tokio::task::spawn_blocking(move || {
+        write_thumbnail(&config.wallpapers_dir, &source, file_name, &old_thumbnail)
+    })
+    .await??;
+    encoding::write_alternates(
+        &state.config.wallpapers_dir,
+        &thumb_image,
+        &mut thumbnail_file,
+        true,
+    )
+    .await;
+    Ok(thumbnail_file)
+}
+
+/// Whether a file was written within `ORPHAN_MIN_AGE`
+async fn recently_modified(path: &Path) -> Result<bool> {
+    let modified = fs::metadata(path).await?.modified()?;
+    Ok(modified.elapsed().unwrap_or_default() < ORPHAN_MIN_AGE)
+}
+
+async fn disk_files(dir: &Path) -> Result<HashSet<String>> {
+    let mut files = HashSet::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_file() {
+            files.insert(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{DatabaseStyle, HistoryConfig, ThumbnailSettings};
+    use std::collections::HashMap;
+
+    fn image_file(file_name: &str) -> ImageFile {
+        ImageFile {
+            file_name: file_name.to_string(),
+            width: 0,
+            height: 0,
+            resolution_preset: String::new(),
+            alt_formats: Vec::new(),
+        }
+    }
+
+    fn wallpaper(original: &str, thumbnail: &str) -> WallpaperData {
+        WallpaperData {
+            original_file: image_file(original),
+            thumbnail_file: image_file(thumbnail),
+            ..WallpaperData::sample()
+        }
+    }
+
+    #[test]
+    fn finds_missing_and_orphan_files() {
+        let mut complete = wallpaper("a.webp", "a_thumb.webp");
+        complete.original_file.alt_formats = vec!["avif".to_string()];
+        let no_thumbnail = wallpaper("b.webp", "b_thumb.webp");
+        let mut no_upscaled = wallpaper("c.webp", "c_thumb.webp");
+        no_upscaled.upscaled_file = Some(image_file("c_upscaled.webp"));
+        let database = Database {
+            style: DatabaseStyle::default(),
+            history_config: HistoryConfig::default(),
+            prompt_templates: Vec::new(),
+            wallpapers: [&complete, &no_thumbnail, &no_upscaled]
+                .into_iter()
+                .map(|wallpaper| (wallpaper.id, wallpaper.clone()))
+                .collect::<HashMap<_, _>>(),
+            comments: HashMap::new(),
+            pending_prompts: HashMap::new(),
+            fetch_profiles: Vec::new(),
+            users: HashMap::new(),
+            viewer: None,
+            thumbnail_settings: ThumbnailSettings::default(),
+            stale_thumbnails: Vec::new(),
+            presets: HashMap::new(),
+            share_links: Vec::new(),
+        };
+        let files = [
+            "a.webp",
+            "a.avif",
+            "a_thumb.webp",
+            "b.webp",
+            "c.webp",
+            "c_thumb.webp",
+            "stray.webp",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+
+        let report = find_problems(&database, &files);
+        assert_eq!(report.missing_originals, vec![no_upscaled.id]);
+        assert_eq!(report.missing_thumbnails, vec![no_thumbnail.id]);
+        assert_eq!(report.orphan_files, vec!["stray.webp".to_string()]);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn marks_thumbnails_stale_oldest_first() {
+        let mut newer = wallpaper("a.webp", "a_thumb.webp");
+        let mut older = wallpaper("b.webp", "b_thumb.webp");
+        older.datetime = newer.datetime - chrono::Duration::days(2);
+        newer.datetime -= chrono::Duration::days(1);
+        let mut database = Database {
+            style: DatabaseStyle::default(),
+            history_config: HistoryConfig::default(),
+            prompt_templates: Vec::new(),
+            wallpapers: [&newer, &older]
+                .into_iter()
+                .map(|wallpaper| (wallpaper.id, wallpaper.clone()))
+                .collect::<HashMap<_, _>>(),
+            comments: HashMap::new(),
+            pending_prompts: HashMap::new(),
+            fetch_profiles: Vec::new(),
+            users: HashMap::new(),
+            viewer: None,
+            thumbnail_settings: ThumbnailSettings::default(),
+            stale_thumbnails: Vec::new(),
+            presets: HashMap::new(),
+            share_links: Vec::new(),
+        };
+
+        let settings = ThumbnailSettings {
+            width: 1280,
+            height: 720,
+            quality: 80,
+        };
+        mark_stale_thumbnails(&mut database, settings);
+        assert_eq!(database.stale_thumbnails, vec![older.id, newer.id]);
+        assert_eq!(database.thumbnail_settings, settings);
+    }
+
+    #[tokio::test]
+    async fn keeps_new_files_that_look_orphaned() {
+        let path = std::env::temp_dir().join(format!("wallpapy-orphan-{}.webp", Uuid::new_v4()));
+        std::fs::write(&path, b"webp").unwrap();
+        assert!(recently_modified(&path).await.unwrap());
+        let old = std::time::SystemTime::now() - ORPHAN_MIN_AGE * 2;
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert!(!recently_modified(&path).await.unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,54 +1,210 @@
-use crate::common::{Database, DatabaseStyle};
+use crate::common::{AuditAction, AuditEntry, Database};
 use anyhow::Result;
-use chrono::Duration;
-use std::collections::HashMap;
+use axum::http::StatusCode;
+use chrono::Utc;
+use parking_lot::RwLock;
+use paths::ServerConfig;
+use providers::{ImageProvider, LlmProvider};
+use status::SchedulerStatus;
+use std::{fmt, path::PathBuf, sync::Arc};
+use store::DatabaseStore;
 use tokio::{
     fs::{self, OpenOptions},
     io::AsyncReadExt,
+    sync::{Mutex, MutexGuard, Notify},
 };
+use uuid::Uuid;
 
 mod auth;
+pub mod backups;
+pub mod caching;
+pub mod cli;
+mod clustering;
 mod commenting;
+pub mod config;
+mod encoding;
+mod generation_context;
 mod gpt;
+mod history;
 mod image;
+mod importing;
+pub mod listen;
+pub mod logs;
+mod maintenance;
+mod models;
+mod moodboard;
+mod net;
+mod pages;
+pub mod paths;
+mod profiles;
+mod prompts;
+mod providers;
+mod resolution;
 pub mod routing;
+mod security;
+mod sharing;
+mod stats;
+mod status;
+mod store;
+mod templates;
+mod thumbnails;
+mod time_of_day;
+mod users;
+mod weather;
+mod webhook;
 
-const DATABASE_FILE: &str = "data/database.ron";
+/// Oldest audit entries are dropped past this so the file doesn't grow without bound
+const AUDIT_LOG_LIMIT: usize = 10_000;
 
-async fn read_database() -> Result<Database> {
-    if fs::metadata(DATABASE_FILE).await.is_err() {
-        return Ok(Database {
-            style: DatabaseStyle::default(),
-            wallpapers: HashMap::new(),
-            comments: HashMap::new(),
-        });
+/// Shared by every handler and background task, cheap to clone
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<ServerConfig>,
+    pub store: Arc<DatabaseStore>,
+    /// Writes prompts and captions
+    llm: Arc<dyn LlmProvider>,
+    /// Diffuses and upscales images
+    images: Arc<dyn ImageProvider>,
+    /// Kept up to date by the scheduler loop and every running generation
+    scheduler: Arc<RwLock<SchedulerStatus>>,
+    /// Signs share links
+    share_key: Arc<[u8]>,
+    /// Wakes the rebuild task when thumbnails are flagged as stale
+    thumbnails_stale: Arc<Notify>,
+    /// Only one import walks the manifest at a time
+    import_lock: Arc<Mutex<()>>,
+    /// Held across every read-modify-write of auth.ron so a token check can't undo a logout
+    auth_lock: Arc<Mutex<()>>,
+    /// Held while appending to audit.ron so concurrent entries aren't lost
+    audit_lock: Arc<Mutex<()>>,
+}
+
+impl AppState {
+    /// Read the database file with OpenAI and Replicate as providers
+    /// Call once at startup, after any recovery from backups
+    pub async fn load(config: ServerConfig) -> Result<Self> {
+        Ok(Self {
+            store: Arc::new(DatabaseStore::load(database_file(&config)).await?),
+            llm: Arc::new(gpt::OpenAi::default()),
+            images: Arc::new(image::Replicate::default()),
+            scheduler: Arc::default(),
+            share_key: sharing::load_key(&config).into(),
+            thumbnails_stale: Arc::default(),
+            import_lock: Arc::default(),
+            auth_lock: Arc::default(),
+            audit_lock: Arc::default(),
+            config: Arc::new(config),
+        })
+    }
+
+    async fn read_database(&self) -> Database {
+        self.store.read().await
+    }
+
+    /// Wait for exclusive write access to the database, giving up after `DB_WRITE_TIMEOUT_SECS`
+    async fn lock_database(&self) -> Result<MutexGuard<'_, ()>> {
+        self.store.lock().await
+    }
+
+    /// Replace the database, only while holding `lock_database`
+    async fn write_database(&self, database: &Database) -> Result<()> {
+        self.store.write(database).await
+    }
+
+    /// Read, modify and write the database while holding the write lock
+    async fn with_database<T>(&self, modify: impl FnOnce(&mut Database) -> Result<T>) -> Result<T> {
+        let _lock = self.lock_database().await?;
+        let mut database = self.read_database().await;
+        let value = modify(&mut database)?;
+        self.write_database(&database).await?;
+        Ok(value)
     }
+}
 
-    let mut file = OpenOptions::new().read(true).open(DATABASE_FILE).await?;
+fn database_file(config: &ServerConfig) -> PathBuf {
+    config.data_dir.join("database.ron")
+}
+
+fn audit_file(state: &AppState) -> PathBuf {
+    state.config.data_dir.join("audit.ron")
+}
+
+/// The write lock couldn't be acquired within `DB_WRITE_TIMEOUT_SECS`
+#[derive(Debug)]
+struct DatabaseBusy;
+
+impl fmt::Display for DatabaseBusy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Timed out waiting for the database write lock")
+    }
+}
+
+impl std::error::Error for DatabaseBusy {}
+
+/// Status for a failed request, busy rather than broken when the database lock timed out
+fn error_status(error: &anyhow::Error) -> StatusCode {
+    if error.is::<DatabaseBusy>() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+async fn read_audit_log(state: &AppState) -> Result<Vec<AuditEntry>> {
+    let audit_file = audit_file(state);
+    if fs::metadata(&audit_file).await.is_err() {
+        return Ok(Vec::new());
+    }
+
+    let mut file = OpenOptions::new().read(true).open(&audit_file).await?;
     let mut data = String::new();
     file.read_to_string(&mut data).await?;
-    let database: Database = ron::from_str(&data)?;
-    Ok(database)
+    let audit_log: Vec<AuditEntry> = ron::from_str(&data)?;
+    Ok(audit_log)
 }
 
-async fn write_database(database: &Database) -> Result<()> {
+async fn append_audit_log(state: &AppState, entry: &AuditEntry) -> Result<()> {
+    let _lock = state.audit_lock.lock().await;
+    let mut audit_log = read_audit_log(state).await?;
+    audit_log.push(entry.clone());
+    let excess = audit_log.len().saturating_sub(AUDIT_LOG_LIMIT);
+    audit_log.drain(..excess);
     let pretty = ron::ser::PrettyConfig::new().compact_arrays(true);
-    let data = ron::ser::to_string_pretty(database, pretty)?;
-    fs::write(DATABASE_FILE, data).await?;
+    let data = ron::ser::to_string_pretty(&audit_log, pretty)?;
+    // Write beside the file then rename over it, so a reader never sees it half written
+    let audit_file = audit_file(state);
+    let temp_file = audit_file.with_extension("ron.tmp");
+    fs::write(&temp_file, data).await?;
+    fs::rename(&temp_file, &audit_file).await?;
     Ok(())
 }
 
-fn format_duration(duration: Duration) -> String {
-    let minutes = duration.num_minutes();
-    let hours = duration.num_hours();
-    let days = duration.num_days();
-    let weeks = duration.num_weeks();
-
-    match (weeks, days, hours, minutes) {
-        (w, _, _, _) if w >= 1 => format!("{} week{}", w, if w == 1 { "" } else { "s" }),
-        (_, d, _, _) if d >= 1 => format!("{} day{}", d, if d == 1 { "" } else { "s" }),
-        (_, _, h, _) if h >= 1 => format!("{} hour{}", h, if h == 1 { "" } else { "s" }),
-        (_, _, _, m) if m >= 1 => format!("{} minute{}", m, if m == 1 { "" } else { "s" }),
-        _ => "less than a minute".to_string(),
+/// Record a mutating action by the owner of the token, failures are only logged
+async fn audit(
+    state: &AppState,
+    token: &str,
+    action: AuditAction,
+    target_uuid: Option<Uuid>,
+    summary: String,
+) {
+    let result = async {
+        let Some((actor_uuid, _)) = auth::token_account(state, token).await? else {
+            return Ok(());
+        };
+        append_audit_log(
+            state,
+            &AuditEntry {
+                actor_uuid,
+                action,
+                target_uuid,
+                timestamp: Utc::now(),
+                summary,
+            },
+        )
+        .await
+    }
+    .await;
+    if let Err(e) = result {
+        log::error!("Failed to write audit log {:?}", e);
     }
 }
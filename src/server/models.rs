@@ -0,0 +1,182 @@
+use crate::common::ImageModelInfo;
+use crate::server::config;
+use axum::{http::StatusCode, response::IntoResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Used when no `models` are configured and for older wallpapers that don't record their model
+pub const DEFAULT_MODEL_ID: &str = "recraft-v3";
+
+/// A Replicate model wallpapers can be diffused with, configured in the `models` table
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ImageModel {
+    pub id: String,   // Short name clients select it by
+    pub name: String, // Shown in the model dropdown
+    pub slug: String, // Replicate owner/model
+    /// Input JSON with `{prompt}`, `{negative_prompt}`, `{width}`, `{height}` and `{size}` placeholders
+    /// A field that is only `{negative_prompt}` is left out when there is no negative prompt
+    pub input: Value,
+}
+
+impl ImageModel {
+    fn recraft() -> Self {
+        Self {
+            id: DEFAULT_MODEL_ID.to_string(),
+            name: "Recraft V3".to_string(),
+            slug: "recraft-ai/recraft-v3".to_string(),
+            input: json!({
+                "prompt": "{prompt}",
+                "negative_prompt": "{negative_prompt}",
+                "size": "{size}",
+                "style": "digital_illustration",
+            }),
+        }
+    }
+
+    /// <https://replicate.com/docs/reference/http#models.predictions.create>
+    pub fn predictions_url(&self) -> String {
+        format!(
+            "https://api.replicate.com/v1/models/{}/predictions",
+            self.slug
+        )
+    }
+
+    /// The prediction input with the placeholders filled in
+    pub fn build_input(
+        &self,
+        prompt: &str,
+        negative_prompt: Option<&str>,
+        (width, height): (u32, u32),
+    ) -> Value {
+        let inputs = Inputs {
+            prompt,
+            negative_prompt,
+            width,
+            height,
+        };
+        inputs.fill(&self.input).unwrap_or(Value::Null)
+    }
+}
+
+struct Inputs<'a> {
+    prompt: &'a str,
+    negative_prompt: Option<&'a str>,
+    width: u32,
+    height: u32,
+}
+
+impl Inputs<'_> {
+    /// Fill in a template value, None drops it when it's only an absent negative prompt
+    fn fill(&self, value: &Value) -> Option<Value> {
+        match value {
+            Value::String(text) => match text.as_str() {
+                "{width}" => Some(json!(self.width)),
+                "{height}" => Some(json!(self.height)),
+                "{negative_prompt}" => self.negative_prompt.map(|text| json!(text)),
+                _ => Some(json!(text
+                    .replace("{size}", &format!("{}x{}", self.width, self.height))
+                    .replace("{width}", &self.width.to_string())
+                    .replace("{height}", &self.height.to_string())
+                    .replace(
+                        "{negative_prompt}",
+                        self.negative_prompt.unwrap_or_default()
+                    )
+                    .replace("{prompt}", self.prompt))),
+            },
+            Value::Array(items) => Some(Value::Array(
+                items.iter().filter_map(|item| self.fill(item)).collect(),
+            )),
+            Value::Object(fields) => Some(Value::Object(
+                fields
+                    .iter()
+                    .filter_map(|(key, value)| Some((key.clone(), self.fill(value)?)))
+                    .collect(),
+            )),
+            value => Some(value.clone()),
+        }
+    }
+}
+
+/// Every model in the `models` table, or just recraft when there are none
+pub fn configured() -> Vec<ImageModel> {
+    let models = config::var("MODELS")
+        .and_then(
+            |value| match serde_json::from_str::<Vec<ImageModel>>(&value) {
+                Ok(models) => Some(models),
+                Err(e) => {
+                    log::warn!("Invalid MODELS, using the default model: {e}");
+                    None
+                }
+            },
+        )
+        .unwrap_or_default();
+    if models.is_empty() {
+        vec![ImageModel::recraft()]
+    } else {
+        models
+    }
+}
+
+/// The model with this id, falling back to the first configured one
+pub fn resolve(id: Option<&str>) -> ImageModel {
+    let mut models = configured();
+    let position = id.and_then(|id| {
+        let position = models.iter().position(|model| model.id == id);
+        if position.is_none() {
+            log::warn!("Unknown image model '{id}', using {}", models[0].id);
+        }
+        position
+    });
+    models.swap_remove(position.unwrap_or(0))
+}
+
+/// The configured models for the client to choose from, the first is the default
+pub async fn list() -> impl IntoResponse {
+    let models = configured()
+        .into_iter()
+        .map(|model| ImageModelInfo {
+            id: model.id,
+            name: model.name,
+        })
+        .collect::<Vec<_>>();
+    match bincode::serialize(&models) {
+        Ok(data) => (StatusCode::OK, data).into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_input_template() {
+        let input = ImageModel::recraft().build_input("A harbour", None, (1536, 1024));
+        assert_eq!(
+            input,
+            json!({ "prompt": "A harbour", "size": "1536x1024", "style": "digital_illustration" })
+        );
+
+        let model: ImageModel = toml::from_str(
+            "id = \"flux\"\nname = \"FLUX 1.1 Pro\"\nslug = \"black-forest-labs/flux-1.1-pro\"\ninput = { prompt = \"{prompt}, avoid {negative_prompt}\", width = \"{width}\", height = \"{height}\", safety_tolerance = 2 }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            model.build_input("A harbour", Some("text"), (1820, 1024)),
+            json!({
+                "prompt": "A harbour, avoid text",
+                "width": 1820,
+                "height": 1024,
+                "safety_tolerance": 2,
+            })
+        );
+        assert_eq!(
+            model.predictions_url(),
+            "https://api.replicate.com/v1/models/black-forest-labs/flux-1.1-pro/predictions"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use crate::common::{AuditAction, ColorData, ColorTemperature, MoodboardPacket, TokenPacket};
+use crate::server::{
+    audit,
+    auth::verify_token,
+    image::{calculate_color_data, decode_upload, rgb_to_hsl},
+    AppState,
+};
+use ::image::{imageops::FilterType, DynamicImage};
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use tokio::fs;
+
+/// Longest side the reference is stored at, it only needs to show its palette and texture
+const MOODBOARD_SIZE: u32 = 1024;
+
+/// Pixels with less chroma than this are too grey to count towards the palette
+const MIN_CHROMA: f32 = 0.15;
+
+/// Names of 30 degree hue bins, the first centred on red
+const HUE_NAMES: [&str; 12] = [
+    "red", "orange", "golden", "lime", "green", "emerald", "teal", "azure", "blue", "violet",
+    "magenta", "rose",
+];
+
+/// Set the moodboard to the uploaded image
+pub async fn set(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: MoodboardPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize moodboard packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let image = match decode_upload(packet.data) {
+        Ok(image) => image,
+        Err(e) => {
+            log::error!("Failed to decode moodboard image: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    match set_impl(&state, &image).await {
+        Ok(description) => {
+            log::info!("Moodboard set: {description}");
+            let summary = format!("Set the moodboard to {description}");
+            audit(&state, &packet.token, AuditAction::Moodboard, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored set_moodboard {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Reference image that new prompts lean towards the look of
+fn moodboard_file(state: &AppState) -> PathBuf {
+    state.config.data_dir.join("moodboard.webp")
+}
+
+async fn set_impl(state: &AppState, image: &DynamicImage) -> Result<String> {
+    let image = image.resize(MOODBOARD_SIZE, MOODBOARD_SIZE, FilterType::Lanczos3);
+    // Copied out of the encoder's buffer, which isn't Send and so can't be held across an await
+    let data = webp::Encoder::from_image(&image)
+        .map_err(|e| anyhow::anyhow!("Failed to encode moodboard: {e}"))?
+        .encode(90.0)
+        .to_vec();
+    fs::create_dir_all(&state.config.data_dir).await?;
+    let moodboard_file = moodboard_file(state);
+    let temp_file = moodboard_file.with_extension("webp.tmp");
+    fs::write(&temp_file, data).await?;
+    fs::rename(&temp_file, &moodboard_file).await?;
+    Ok(describe_image(&image))
+}
+
+/// Remove the moodboard so prompts go back to following the style alone
+pub async fn clear(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize clear_moodboard packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let moodboard_file = moodboard_file(&state);
+    if fs::metadata(&moodboard_file).await.is_err() {
+        return StatusCode::OK;
+    }
+    match fs::remove_file(&moodboard_file).await {
+        Ok(()) => {
+            let summary = "Cleared the moodboard".to_string();
+            audit(&state, &packet.token, AuditAction::Moodboard, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored clear_moodboard {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// The current moodboard image, not found when there isn't one
+pub async fn get(State(state): State<AppState>) -> impl IntoResponse {
+    match fs::read(moodboard_file(&state)).await {
+        Ok(data) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "image/webp"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            data,
+        )
+            .into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// When the moodboard was last set, None when there isn't one
+pub async fn updated(state: &AppState) -> Option<DateTime<Utc>> {
+    let modified = fs::metadata(moodboard_file(state))
+        .await
+        .ok()?
+        .modified()
+        .ok()?;
+    Some(modified.into())
+}
+
+/// Describe the moodboard's look for the prompt writer, None when there isn't one
+pub async fn description(state: &AppState) -> Option<String> {
+    let moodboard_file = moodboard_file(state);
+    if fs::metadata(&moodboard_file).await.is_err() {
+        return None;
+    }
+    match ::image::open(&moodboard_file) {
+        Ok(image) => Some(describe_image(&image)),
+        Err(e) => {
+            log::error!("Failed to read moodboard {:?}", e);
+            None
+        }
+    }
+}
+
+fn describe_image(image: &DynamicImage) -> String {
+    let thumb_image = image.resize(640, 640, FilterType::Triangle);
+    describe(
+        &calculate_color_data(&thumb_image),
+        &dominant_hues(&thumb_image),
+    )
+}
+
+/// The most common hues among the colourful pixels, most common first, empty for a mostly grey image
+fn dominant_hues(image: &DynamicImage) -> Vec<&'static str> {
+    let mut counts = [0usize; HUE_NAMES.len()];
+    let mut grey = 0;
+    for pixel in image.to_rgb8().pixels() {
+        let [r, g, b] = pixel.0.map(|c| f32::from(c) / 255.0);
+        if r.max(g).max(b) - r.min(g).min(b) < MIN_CHROMA {
+            grey += 1;
+            continue;
+        }
+        let (hue, _, _) = rgb_to_hsl(r, g, b);
+        counts[(hue.mul_add(HUE_NAMES.len() as f32, 0.5) as usize) % HUE_NAMES.len()] += 1;
+    }
+
+    let colourful = counts.iter().sum::<usize>();
+    if colourful * 3 < grey {
+        return Vec::new();
+    }
+    let mut bins = counts.iter().enumerate().collect::<Vec<_>>();
+    bins.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+    bins.into_iter()
+        .take(2)
+        .filter(|(_, count)| **count * 4 >= colourful)
+        .map(|(bin, _)| HUE_NAMES[bin])
+        .collect()
+}
+
+/// Like "warm golden and rose palette, high contrast, soft painterly texture"
+fn describe(color_data: &ColorData, hues: &[&str]) -> String {
+    let temperature = match color_data.color_temperature {
+        ColorTemperature::Warm => "warm",
+        ColorTemperature::Cool => "cool",
+        ColorTemperature::Neutral => "neutral",
+    };
+    let palette = if hues.is_empty() {
+        "muted greyscale palette".to_string()
+    } else {
+        format!("{temperature} {} palette", hues.join(" and "))
+    };
+    let tone = if color_data.lightness < 0.3 {
+        Some("dark moody tones")
+    } else if color_data.lightness > 0.65 {
+        Some("bright airy tones")
+    } else {
+        None
+    };
+    let contrast = if color_data.contrast_ratio > 6.0 {
+        "high contrast"
+    } else if color_data.contrast_ratio < 2.5 {
+        "low contrast"
+    } else {
+        "balanced contrast"
+    };
+    let texture = if color_data.sharpness_score < 100.0 {
+        "soft painterly texture"
+    } else if color_data.sharpness_score > 800.0 {
+        "crisp detailed texture"
+    } else {
+        "smooth texture"
+    };
+
+    [Some(palette.as_str()), tone, Some(contrast), Some(texture)]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{Rgb, RgbImage};
+
+    #[test]
+    fn describes_reference_aesthetic() {
+        // Golden on the left, rose on the right, with a dark band through the middle
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(90, 60, |x, y| {
+            if (20..40).contains(&y) {
+                Rgb([20, 15, 10])
+            } else if x < 45 {
+                Rgb([230, 200, 40])
+            } else {
+                Rgb([220, 60, 110])
+            }
+        }));
+        let hues = dominant_hues(&image);
+        assert_eq!(hues.len(), 2);
+        assert!(hues.contains(&"golden") && hues.contains(&"rose"));
+
+        let color_data = calculate_color_data(&image);
+        let description = describe(&color_data, &hues);
+        assert!(description.starts_with("warm "), "{description}");
+        assert!(description.contains("high contrast"), "{description}");
+
+        let grey = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb([128, 128, 128])));
+        assert!(dominant_hues(&grey).is_empty());
+        assert!(describe(&calculate_color_data(&grey), &[]).starts_with("muted greyscale palette"));
+    }
+}
@@ -0,0 +1,265 @@
+use anyhow::Result;
+use rand::Rng;
+use reqwest::{Response, StatusCode};
+use std::{fmt, future::Future, time::Duration};
+
+/// How many times to try a request to an external API and how long to wait between tries
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration, // Doubled after every failed attempt
+    pub max_delay: Duration,
+    /// Whether repeating the request is harmless after the server may have acted on it, when it
+    /// isn't only rate limits and failed connections are retried since neither reached the server
+    pub idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            idempotent: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before the next attempt, jittered between half and all of it so retries spread out
+    fn delay(self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// An API answered with an unsuccessful status
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Request failed with status {}: {}",
+            self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Pass successful responses through, turning any other status into a `StatusError`
+pub async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(StatusError { status, body }.into())
+}
+
+/// Rate limits, server errors, timeouts and failed connections are worth another try, anything else isn't
+fn is_retryable(error: &anyhow::Error, idempotent: bool) -> bool {
+    if let Some(error) = error.downcast_ref::<StatusError>() {
+        return retryable_status(error.status, idempotent);
+    }
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return error.is_connect()
+            || (idempotent && error.is_timeout())
+            || error
+                .status()
+                .is_some_and(|status| retryable_status(status, idempotent));
+    }
+    false
+}
+
+fn retryable_status(status: StatusCode, idempotent: bool) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || (idempotent && status.is_server_error())
+}
+
+/// Run a request, retrying transient failures with exponential backoff
+pub async fn with_retry<T, F, Fut>(policy: RetryPolicy, what: &str, mut request: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Err(e) if attempt < policy.max_attempts && is_retryable(&e, policy.idempotent) => {
+                let delay = policy.delay(attempt);
+                log::warn!("{what} failed on attempt {attempt}, retrying in {delay:?}: {e}");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, routing::get, Router};
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    const INSTANT: RetryPolicy = RetryPolicy {
+        max_attempts: 4,
+        base_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+        idempotent: true,
+    };
+
+    fn status_error(status: StatusCode) -> anyhow::Error {
+        StatusError {
+            status,
+            body: String::new(),
+        }
+        .into()
+    }
+
+    /// Serve the given statuses in turn then OK, returning the url and a count of requests received
+    async fn mock_server(statuses: &[u16]) -> (String, Arc<AtomicU32>) {
+        let received = Arc::new(AtomicU32::new(0));
+        let statuses = Arc::new(statuses.to_vec());
+        let app = Router::new().route(
+            "/",
+            get(
+                |State((received, statuses)): State<(Arc<AtomicU32>, Arc<Vec<u16>>)>| async move {
+                    let attempt = received.fetch_add(1, Ordering::SeqCst) as usize;
+                    let status = statuses.get(attempt).copied().unwrap_or(200);
+                    (
+                        StatusCode::from_u16(status).unwrap(),
+                        format!("reply {attempt}"),
+                    )
+                },
+            ),
+        );
+        let app = app.with_state((received.clone(), statuses));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        (format!("http://{addr}"), received)
+    }
+
+    async fn fetch(policy: RetryPolicy, url: &str) -> Result<String> {
+        let client = reqwest::Client::new();
+        with_retry(policy, "test", || async {
+            Ok(check_status(client.get(url).send().await?)
+                .await?
+                .text()
+                .await?)
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        let attempts = AtomicU32::new(0);
+        let result = with_retry(INSTANT, "test", || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(status_error(StatusCode::BAD_GATEWAY)),
+                1 => Err(status_error(StatusCode::TOO_MANY_REQUESTS)),
+                _ => Ok("done"),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn fails_fast_on_client_errors() {
+        for status in [StatusCode::UNAUTHORIZED, StatusCode::BAD_REQUEST] {
+            let attempts = AtomicU32::new(0);
+            let result: Result<()> = with_retry(INSTANT, "test", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(status_error(status))
+            })
+            .await;
+            assert!(result.is_err());
+            assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<()> = with_retry(INSTANT, "test", || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(status_error(StatusCode::SERVICE_UNAVAILABLE))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), INSTANT.max_attempts);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_over_http() {
+        let (url, received) = mock_server(&[503, 429]).await;
+        assert_eq!(fetch(INSTANT, &url).await.unwrap(), "reply 2");
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn fails_fast_on_unauthorized_over_http() {
+        let (url, received) = mock_server(&[401]).await;
+        let error = fetch(INSTANT, &url).await.unwrap_err();
+        let error = error.downcast_ref::<StatusError>().unwrap();
+        assert_eq!(error.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error.body, "reply 0");
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn only_retries_rate_limits_when_not_idempotent() {
+        let policy = RetryPolicy {
+            idempotent: false,
+            ..INSTANT
+        };
+        let (url, received) = mock_server(&[500]).await;
+        assert!(fetch(policy, &url).await.is_err());
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+
+        let (url, received) = mock_server(&[429]).await;
+        assert_eq!(fetch(policy, &url).await.unwrap(), "reply 1");
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_refused_connections() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let error = fetch(INSTANT, &url).await.unwrap_err();
+        assert!(error.downcast_ref::<reqwest::Error>().unwrap().is_connect());
+        assert!(is_retryable(&error, false));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(10),
+            ..RetryPolicy::default()
+        };
+        assert!(policy.delay(1) <= Duration::from_secs(2));
+        assert!(policy.delay(2) >= Duration::from_secs(2));
+        assert!(policy.delay(2) <= Duration::from_secs(4));
+        assert!(policy.delay(8) <= Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,318 @@
+use crate::common::WallpaperData;
+use crate::server::{config, AppState};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use uuid::Uuid;
+
+const GALLERY_PAGE_SIZE: usize = 24;
+const FEED_LENGTH: usize = 30;
+
+#[derive(Deserialize)]
+pub struct GalleryQuery {
+    page: Option<usize>,
+}
+
+/// Shareable html pages are only served when `PUBLIC_PAGES` is set to true
+fn pages_enabled() -> bool {
+    config::var("PUBLIC_PAGES").is_some_and(|value| value.eq_ignore_ascii_case("true"))
+}
+
+pub async fn view(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if !pages_enabled() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let mut database = state.read_database().await;
+    let wallpaper = database.wallpapers.remove(&id);
+    let Some(wallpaper) = wallpaper else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let file = wallpaper
+        .upscaled_file
+        .as_ref()
+        .unwrap_or(&wallpaper.original_file);
+    let image_url = format!("/wallpapers/{}", file.file_name);
+    let title = escape_html(&wallpaper.prompt_data.shortened_prompt);
+    let prompt = escape_html(&wallpaper.prompt_data.prompt);
+    let date = wallpaper.datetime.format("%d/%m/%Y %H:%M UTC");
+
+    // Chat apps need an absolute url to unfurl the preview image
+    let og_image = escape_html(&format!("{}{image_url}", base_url(&headers)));
+
+    let body = format!(
+        r#"<img class="full" src="{image_url}" alt="{title}">
+<h1>{title}</h1>
+<p>{prompt}</p>
+<p class="date">{date}</p>
+<p><a href="/gallery">Back to gallery</a></p>"#
+    );
+    let meta = format!(
+        r#"<meta property="og:type" content="website">
+<meta property="og:title" content="{title}">
+<meta property="og:description" content="{prompt}">
+<meta property="og:image" content="{og_image}">
+<meta property="og:image:width" content="{}">
+<meta property="og:image:height" content="{}">
+<meta name="twitter:card" content="summary_large_image">"#,
+        file.width, file.height
+    );
+
+    Html(render_page(&title, &meta, &body)).into_response()
+}
+
+pub async fn gallery(
+    State(state): State<AppState>,
+    Query(query): Query<GalleryQuery>,
+) -> impl IntoResponse {
+    if !pages_enabled() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let database = state.read_database().await;
+    let mut wallpapers: Vec<WallpaperData> = database.wallpapers.into_values().collect();
+    wallpapers.sort_by_key(|wallpaper| std::cmp::Reverse(wallpaper.datetime));
+
+    let page_count = wallpapers.len().div_ceil(GALLERY_PAGE_SIZE).max(1);
+    let page = query.page.unwrap_or(1).clamp(1, page_count);
+
+    let thumbnails = wallpapers
+        .iter()
+        .skip((page - 1) * GALLERY_PAGE_SIZE)
+        .take(GALLERY_PAGE_SIZE)
+        .map(|wallpaper| {
+            format!(
+                r#"<a href="/view/{}"><img src="/wallpapers/{}" alt="{}" loading="lazy"></a>"#,
+                wallpaper.id,
+                wallpaper.thumbnail_file.file_name,
+                escape_html(&wallpaper.prompt_data.shortened_prompt)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut navigation = Vec::new();
+    if page > 1 {
+        navigation.push(format!(r#"<a href="/gallery?page={}">Newer</a>"#, page - 1));
+    }
+    navigation.push(format!("Page {page} of {page_count}"));
+    if page < page_count {
+        navigation.push(format!(r#"<a href="/gallery?page={}">Older</a>"#, page + 1));
+    }
+
+    let body = format!(
+        r#"<h1>Wallpapy</h1>
+<div class="grid">
+{thumbnails}
+</div>
+<p class="nav">{}</p>"#,
+        navigation.join(" ")
+    );
+
+    Html(render_page("Wallpapy Gallery", "", &body)).into_response()
+}
+
+/// Atom feed of the newest wallpapers for feed readers
+pub async fn feed(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if !pages_enabled() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let database = state.read_database().await;
+    let wallpapers = database.wallpapers.into_values().collect();
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        render_feed(wallpapers, &base_url(&headers)),
+    )
+        .into_response()
+}
+
+/// Absolute url of the server, from `PUBLIC_HOST` or else the request's host header
+fn base_url(headers: &HeaderMap) -> String {
+    let host = config::var("PUBLIC_HOST")
+        .filter(|host| !host.is_empty())
+        .or_else(|| {
+            headers
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .map(str::to_string)
+        })
+        .unwrap_or_default();
+    let host = host.trim_end_matches('/');
+    if host.is_empty() || host.contains("://") {
+        host.to_string()
+    } else {
+        format!("http://{host}")
+    }
+}
+
+fn render_feed(mut wallpapers: Vec<WallpaperData>, base: &str) -> String {
+    // The base can come from the request's host header
+    let base = escape_html(base);
+    wallpapers.sort_by_key(|wallpaper| std::cmp::Reverse(wallpaper.datetime));
+    wallpapers.truncate(FEED_LENGTH);
+    let updated = wallpapers
+        .first()
+        .map_or_else(Utc::now, |wallpaper| wallpaper.datetime)
+        .to_rfc3339();
+
+    let entries = wallpapers
+        .iter()
+        .map(|wallpaper| {
+            let thumbnail = &wallpaper.thumbnail_file.file_name;
+            let date = wallpaper.datetime.to_rfc3339();
+            format!(
+                r#"<entry>
+<title>{}</title>
+<id>urn:uuid:{}</id>
+<link rel="alternate" type="text/html" href="{base}/view/{}"/>
+<link rel="enclosure" type="{}" href="{base}/wallpapers/{}"/>
+<published>{date}</published>
+<updated>{date}</updated>
+<content type="text">{}</content>
+</entry>"#,
+                escape_html(&wallpaper.prompt_data.shortened_prompt),
+                wallpaper.id,
+                wallpaper.id,
+                mime_guess::from_path(thumbnail).first_or_octet_stream(),
+                escape_html(thumbnail),
+                escape_html(&wallpaper.prompt_data.prompt)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>Wallpapy</title>
+<id>{base}/feed.xml</id>
+<link rel="self" href="{base}/feed.xml"/>
+<link rel="alternate" type="text/html" href="{base}/gallery"/>
+<author><name>Wallpapy</name></author>
+<updated>{updated}</updated>
+{entries}
+</feed>"#
+    )
+}
+
+fn render_page(title: &str, meta: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1.0">
+<title>{title}</title>
+{meta}
+<style>
+body {{ background: #191923; color: #ddd; font-family: sans-serif; margin: 0 auto; max-width: 1600px; padding: 16px; }}
+a {{ color: #9ab; }}
+img {{ border-radius: 16px; }}
+.full {{ width: 100%; }}
+.date {{ color: #888; }}
+.grid {{ display: grid; grid-template-columns: repeat(auto-fill, minmax(320px, 1fr)); gap: 8px; }}
+.grid img {{ width: 100%; aspect-ratio: 16 / 9; object-fit: cover; }}
+.nav {{ text-align: center; }}
+</style>
+</head>
+<body>
+{body}
+</body>
+</html>"#
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration};
+    use quick_xml::{events::Event, Reader};
+
+    fn wallpaper(prompt: &str, datetime: DateTime<Utc>) -> WallpaperData {
+        let mut wallpaper = WallpaperData {
+            datetime,
+            ..WallpaperData::sample()
+        };
+        wallpaper.prompt_data.prompt = format!("{prompt}, in watercolour");
+        wallpaper.prompt_data.shortened_prompt = prompt.to_string();
+        wallpaper
+    }
+
+    #[test]
+    fn renders_atom_feed_newest_first() {
+        let now = Utc::now();
+        // Shuffled ages so the feed has to sort them
+        let wallpapers = (0..35)
+            .map(|i| {
+                let age = Duration::hours((i * 8) % 35);
+                wallpaper(&format!("Harbour {i} & <boats>"), now - age)
+            })
+            .collect();
+        let xml = render_feed(wallpapers, "http://walls.example.com");
+        let hostile = render_feed(Vec::new(), r#"http://evil"/><script>"#);
+        assert!(!hostile.contains("<script>"));
+
+        let mut reader = Reader::from_str(&xml);
+        reader.config_mut().trim_text(true);
+        let mut element = Vec::new();
+        let mut titles = Vec::new();
+        let mut published = Vec::new();
+        let mut enclosures = 0;
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(start) => element = start.name().as_ref().to_vec(),
+                Event::Empty(empty) => {
+                    let enclosure = empty.attributes().any(|attribute| {
+                        let attribute = attribute.unwrap();
+                        attribute.key.as_ref() == b"rel" && attribute.value.as_ref() == b"enclosure"
+                    });
+                    if enclosure {
+                        enclosures += 1;
+                    }
+                }
+                Event::Text(text) => {
+                    let text = text.unescape().unwrap().to_string();
+                    match element.as_slice() {
+                        b"title" => titles.push(text),
+                        b"published" => {
+                            published.push(DateTime::parse_from_rfc3339(&text).unwrap())
+                        }
+                        _ => {}
+                    }
+                }
+                Event::End(_) => element.clear(),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        // The feed's own title and then one per entry
+        assert_eq!(titles.len(), FEED_LENGTH + 1);
+        assert_eq!(titles[1], "Harbour 0 & <boats>");
+        assert_eq!(published.len(), FEED_LENGTH);
+        assert_eq!(enclosures, FEED_LENGTH);
+        assert!(published.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(xml.contains(
+            r#"type="image/webp" href="http://walls.example.com/wallpapers/thumb.webp""#
+        ));
+    }
+}
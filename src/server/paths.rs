@@ -0,0 +1,159 @@
+use crate::server::config;
+use anyhow::{bail, Context, Result};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// Lock file in the data dir, held by the server and by commands that change the data
+const LOCK_FILE: &str = "wallpapy.lock";
+
+/// Where the server keeps its files, read once at startup and carried in the app state
+pub struct ServerConfig {
+    /// Holds the database, accounts, audit log and backups, set with `DATA_DIR`
+    pub data_dir: PathBuf,
+    /// Holds the image files, set with `WALLPAPERS_DIR`, defaulting to inside the data dir
+    pub wallpapers_dir: PathBuf,
+}
+
+impl ServerConfig {
+    /// Read the dirs from the config file or environment, call after `config::load`
+    pub fn from_env() -> Self {
+        let data_dir = config::var("DATA_DIR").map_or_else(|| PathBuf::from("data"), PathBuf::from);
+        let wallpapers_dir = config::var("WALLPAPERS_DIR")
+            .map_or_else(|| data_dir.join("wallpapers"), PathBuf::from);
+        Self {
+            data_dir,
+            wallpapers_dir,
+        }
+    }
+
+    /// Keep everything in one dir, with the images in its `wallpapers` subdir
+    #[cfg(test)]
+    pub fn in_dir(data_dir: &Path) -> Self {
+        Self {
+            data_dir: data_dir.to_path_buf(),
+            wallpapers_dir: data_dir.join("wallpapers"),
+        }
+    }
+
+    /// Make sure both dirs exist and are writable, so a bad path fails here rather than in a request
+    pub fn validate(&self) -> Result<()> {
+        for (name, dir) in [
+            ("DATA_DIR", &self.data_dir),
+            ("WALLPAPERS_DIR", &self.wallpapers_dir),
+        ] {
+            ensure_writable_dir(dir)
+                .with_context(|| format!("{name} '{}' is not usable", dir.display()))?;
+            let absolute = std::path::absolute(dir).unwrap_or_else(|_| dir.clone());
+            log::info!("Using {name} {}", absolute.display());
+        }
+        Ok(())
+    }
+}
+
+/// Exclusive hold on the data dir, released when dropped or when the process exits however it exits
+pub struct DataLock {
+    _file: fs::File,
+}
+
+impl DataLock {
+    /// Take the lock without waiting, failing if a server or another command already holds it
+    pub fn acquire(config: &ServerConfig) -> Result<Self> {
+        let path = config.data_dir.join(LOCK_FILE);
+        match open_locked(&path) {
+            Ok(Some(file)) => Ok(Self { _file: file }),
+            Ok(None) => bail!(
+                "'{}' is in use by a running server or command, stop it first",
+                config.data_dir.display()
+            ),
+            Err(e) => Err(e).with_context(|| format!("Failed to lock '{}'", path.display())),
+        }
+    }
+}
+
+/// Open the lock file holding an exclusive advisory lock, None when someone else holds it
+#[cfg(unix)]
+fn open_locked(path: &Path) -> io::Result<Option<fs::File>> {
+    use rustix::{
+        fs::{flock, FlockOperation},
+        io::Errno,
+    };
+
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    match flock(&file, FlockOperation::NonBlockingLockExclusive) {
+        Ok(()) => Ok(Some(file)),
+        Err(Errno::WOULDBLOCK) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Open the lock file without sharing, so nobody else can open it until it's closed
+#[cfg(windows)]
+fn open_locked(path: &Path) -> io::Result<Option<fs::File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    match fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .share_mode(0)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn ensure_writable_dir(dir: &Path) -> Result<()> {
+    if dir.exists() && !dir.is_dir() {
+        bail!("Not a directory");
+    }
+    fs::create_dir_all(dir).context("Failed to create directory")?;
+
+    let probe = dir.join(format!(".write-test-{}", Uuid::new_v4()));
+    fs::write(&probe, b"").context("Directory is not writable")?;
+    fs::remove_file(&probe).context("Failed to remove write test file")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_missing_dir_and_rejects_files() {
+        let root = std::env::temp_dir().join(format!("wallpapy-paths-test-{}", Uuid::new_v4()));
+        let dir = root.join("nested").join("wallpapers");
+        ensure_writable_dir(&dir).unwrap();
+        assert!(dir.is_dir());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        let file = root.join("file.txt");
+        fs::write(&file, "not a dir").unwrap();
+        assert!(ensure_writable_dir(&file).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn data_lock_is_exclusive_until_dropped() {
+        let dir = std::env::temp_dir().join(format!("wallpapy-lock-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = ServerConfig::in_dir(&dir);
+
+        let lock = DataLock::acquire(&config).unwrap();
+        assert!(DataLock::acquire(&config).is_err());
+        drop(lock);
+        DataLock::acquire(&config).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
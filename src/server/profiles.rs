@@ -0,0 +1,126 @@
+use crate::common::{AuditAction, FetchProfilePacket, ProfilePreview, TokenStringPacket};
+use crate::server::{
+    audit,
+    auth::verify_token,
+    error_status,
+    image::{smartget_candidates, SmartgetFilter},
+    AppState,
+};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::Utc;
+
+/// Every profile with how many wallpapers it could pick right now
+pub async fn list(State(state): State<AppState>) -> impl IntoResponse {
+    let database = state.read_database().await;
+    let now = Utc::now();
+    let previews = database
+        .fetch_profiles
+        .iter()
+        .map(|profile| ProfilePreview {
+            name: profile.name.clone(),
+            candidates: smartget_candidates(
+                database.wallpapers.values(),
+                &SmartgetFilter::default().with_profile(profile),
+                now,
+            ),
+        })
+        .collect::<Vec<_>>();
+    match serde_json::to_string(&previews) {
+        Ok(json) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            json,
+        )
+            .into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Add a profile, or replace the one with the same name
+pub async fn save(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: FetchProfilePacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize save_profile packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let mut profile = packet.profile;
+    profile.name = profile.name.trim().to_string();
+    if let Err(e) = profile.validate() {
+        log::error!("Invalid profile: {e}");
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let name = profile.name.clone();
+    let result = state
+        .with_database(|database| {
+            match database
+                .fetch_profiles
+                .iter_mut()
+                .find(|existing| existing.name.eq_ignore_ascii_case(&profile.name))
+            {
+                Some(existing) => *existing = profile,
+                None => database.fetch_profiles.push(profile),
+            }
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => {
+            let summary = format!("Saved profile '{name}'");
+            audit(&state, &packet.token, AuditAction::Profile, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored save_profile {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+pub async fn remove(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenStringPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize remove_profile packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let result = state
+        .with_database(|database| {
+            database
+                .fetch_profiles
+                .retain(|profile| !profile.name.eq_ignore_ascii_case(&packet.string));
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => {
+            let summary = format!("Removed profile '{}'", packet.string);
+            audit(&state, &packet.token, AuditAction::Profile, None, summary).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored remove_profile {:?}", e);
+            error_status(&e)
+        }
+    }
+}
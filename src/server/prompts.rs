@@ -0,0 +1,291 @@
+use crate::common::{AuditAction, GeneratePacket, GenerationMode, PendingPrompt, TokenUuidPacket};
+use crate::server::{
+    audit, auth::verify_token, error_status, gpt, image, resolution::ResolutionPreset,
+    status::GenerationGuard, AppState,
+};
+use anyhow::{anyhow, Result};
+use axum::{body::Bytes, extract::State, http::StatusCode, response::IntoResponse};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+const PENDING_PROMPT_LIFETIME: Duration = Duration::days(7);
+
+/// Write a prompt and hold it for review instead of generating an image straight away
+pub async fn generate(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: GeneratePacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize generate_prompt packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let message = Some(packet.message.trim().to_string()).filter(|message| !message.is_empty());
+    match generate_impl(&state, message, packet.mode, packet.model).await {
+        Ok(pending) => {
+            let summary = format!(
+                "Wrote prompt '{}' for review",
+                pending.prompt_data.shortened_prompt
+            );
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Prompt,
+                Some(pending.id),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored generate_prompt {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+/// Replace a pending prompt with a fresh one written from the same message
+pub async fn reroll(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize reroll_prompt packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    match reroll_impl(&state, packet.uuid).await {
+        Ok(pending) => {
+            let summary = format!(
+                "Rerolled prompt to '{}'",
+                pending.prompt_data.shortened_prompt
+            );
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Prompt,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored reroll_prompt {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+/// Generate the image for a pending prompt, which is kept if the generation fails
+pub async fn accept(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize accept_prompt packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    match accept_impl(&state, packet.uuid).await {
+        Ok((pending, id)) => {
+            image::attribute(&state, id, &packet.token).await;
+            let summary = format!(
+                "Generated wallpaper from reviewed prompt '{}'",
+                pending.prompt_data.shortened_prompt
+            );
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Generate,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored accept_prompt {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+pub async fn discard(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize discard_prompt packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let result = state
+        .with_database(|database| {
+            database
+                .pending_prompts
+                .remove(&packet.uuid)
+                .ok_or_else(|| anyhow!("Prompt not found"))
+        })
+        .await;
+
+    match result {
+        Ok(pending) => {
+            let summary = format!(
+                "Discarded prompt '{}'",
+                pending.prompt_data.shortened_prompt
+            );
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Prompt,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored discard_prompt {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+async fn generate_impl(
+    state: &AppState,
+    message: Option<String>,
+    mode: GenerationMode,
+    model: Option<String>,
+) -> Result<PendingPrompt> {
+    // The LLM is slow, only take the write lock once the prompt is written
+    let new = gpt::generate(state, message.clone(), mode, None).await?;
+    let pending = PendingPrompt {
+        id: Uuid::new_v4(),
+        prompt_data: new.prompt_data,
+        datetime: Utc::now(),
+        message,
+        comment_ids: new.comment_ids,
+        llm_usage: new.usage,
+        llm_provider: new.llm_provider,
+        mode,
+        context: new.context,
+        model,
+        weather_context: new.weather_context,
+        moodboard_influence: new.moodboard_influence,
+    };
+    state
+        .with_database(|database| {
+            database.pending_prompts.insert(pending.id, pending.clone());
+            Ok(())
+        })
+        .await?;
+    Ok(pending)
+}
+
+async fn reroll_impl(state: &AppState, id: Uuid) -> Result<PendingPrompt> {
+    let pending = state
+        .read_database()
+        .await
+        .pending_prompts
+        .remove(&id)
+        .ok_or_else(|| anyhow!("Prompt not found"))?;
+    let new = gpt::generate(state, pending.message, pending.mode, None).await?;
+    state
+        .with_database(|database| {
+            // It may have been accepted or discarded while the new prompt was being written
+            let pending = database
+                .pending_prompts
+                .get_mut(&id)
+                .ok_or_else(|| anyhow!("Prompt not found"))?;
+            pending.prompt_data = new.prompt_data;
+            pending.datetime = Utc::now();
+            pending.comment_ids = new.comment_ids;
+            pending.llm_usage = new.usage;
+            pending.llm_provider = new.llm_provider;
+            pending.context = new.context;
+            pending.weather_context = new.weather_context;
+            pending.moodboard_influence = new.moodboard_influence;
+            Ok(pending.clone())
+        })
+        .await
+}
+
+/// The accepted prompt and the wallpaper generated from it
+async fn accept_impl(state: &AppState, id: Uuid) -> Result<(PendingPrompt, Uuid)> {
+    // Take it out first so a second accept can't generate it twice
+    let pending = state
+        .with_database(|database| {
+            database
+                .pending_prompts
+                .remove(&id)
+                .ok_or_else(|| anyhow!("Prompt not found"))
+        })
+        .await?;
+
+    let _running = GenerationGuard::start(state);
+    let request = image::ImageRequest {
+        prompt_data: pending.prompt_data.clone(),
+        comment_ids: pending.comment_ids.clone(),
+        llm_usage: Some(pending.llm_usage),
+        llm_provider: pending.llm_provider.clone(),
+        context: Some(pending.context.clone()),
+        mode: pending.mode,
+        resolution: ResolutionPreset::from_env(),
+        model: pending.model.clone(),
+        weather_context: pending.weather_context.clone(),
+        moodboard_influence: pending.moodboard_influence,
+    };
+    let result = image::generate_image_impl(state, request).await;
+    match result {
+        Ok(wallpaper_id) => Ok((pending, wallpaper_id)),
+        Err(e) => {
+            let restored = state
+                .with_database(|database| {
+                    database.pending_prompts.insert(id, pending);
+                    Ok(())
+                })
+                .await;
+            if let Err(restore_error) = restored {
+                log::error!("Failed to restore pending prompt {id} {:?}", restore_error);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Drop pending prompts nobody reviewed within `PENDING_PROMPT_LIFETIME`, returning how many went
+pub async fn remove_stale_prompts(state: &AppState) -> Result<usize> {
+    let cutoff = Utc::now() - PENDING_PROMPT_LIFETIME;
+    let is_stale = |pending: &PendingPrompt| pending.datetime < cutoff;
+    if !state
+        .read_database()
+        .await
+        .pending_prompts
+        .values()
+        .any(is_stale)
+    {
+        return Ok(0);
+    }
+    state
+        .with_database(|database| {
+            let before = database.pending_prompts.len();
+            database
+                .pending_prompts
+                .retain(|_, pending| !is_stale(pending));
+            Ok(before - database.pending_prompts.len())
+        })
+        .await
+}
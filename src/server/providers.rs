@@ -0,0 +1,28 @@
+use crate::server::models::ImageModel;
+use ::image::DynamicImage;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+
+/// The language model prompts, captions and tags are written with
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Send a chat completion request, returning the response and the provider/model that answered
+    async fn chat_completion(&self, request_body: Value) -> Result<(Value, String)>;
+}
+
+/// The service images are diffused and upscaled with
+#[async_trait]
+pub trait ImageProvider: Send + Sync {
+    /// Diffuse an image with the input built from the models template, returning its url and the image
+    async fn diffuse(
+        &self,
+        model: &ImageModel,
+        prompt: &str,
+        negative_prompt: Option<&str>,
+        size: (u32, u32),
+    ) -> Result<(String, DynamicImage)>;
+
+    /// Upscale an image guided by its prompt, returning its url and the image
+    async fn upscale(&self, image: &DynamicImage, prompt: &str) -> Result<(String, DynamicImage)>;
+}
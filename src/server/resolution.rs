@@ -0,0 +1,120 @@
+use crate::server::config;
+use std::{fmt, str::FromStr};
+
+/// Sizes supported by the image diffusion model
+const DIFFUSION_SIZES: [(u32, u32); 15] = [
+    (1024, 1024),
+    (1365, 1024),
+    (1024, 1365),
+    (1536, 1024),
+    (1024, 1536),
+    (1820, 1024),
+    (1024, 1820),
+    (1024, 2048),
+    (2048, 1024),
+    (1434, 1024),
+    (1024, 1434),
+    (1024, 1280),
+    (1280, 1024),
+    (1024, 1707),
+    (1707, 1024),
+];
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResolutionPreset {
+    UHD4K,
+    #[default]
+    QHD,
+    FHD,
+    Ultrawide21_9,
+    Portrait9_16,
+    Custom(u32, u32),
+}
+
+impl ResolutionPreset {
+    /// Read the preset from the `WALLPAPER_RESOLUTION` env var, defaulting to QHD
+    pub fn from_env() -> Self {
+        config::var("WALLPAPER_RESOLUTION")
+            .and_then(|value| {
+                let preset = value.parse().ok();
+                if preset.is_none() {
+                    log::warn!("Invalid WALLPAPER_RESOLUTION '{value}', using default");
+                }
+                preset
+            })
+            .unwrap_or_default()
+    }
+
+    /// Strip a leading `[PRESET:name]` from a message, returning the preset and remaining message
+    pub fn strip_prefix(message: &str) -> (Option<Self>, &str) {
+        message
+            .trim_start()
+            .strip_prefix("[PRESET:")
+            .and_then(|rest| rest.split_once(']'))
+            .and_then(|(name, rest)| Some((name.parse().ok()?, rest.trim_start())))
+            .map_or((None, message), |(preset, rest)| (Some(preset), rest))
+    }
+
+    pub const fn dimensions(self) -> (u32, u32) {
+        match self {
+            Self::UHD4K => (3840, 2160),
+            Self::QHD => (2560, 1440),
+            Self::FHD => (1920, 1080),
+            Self::Ultrawide21_9 => (3440, 1440),
+            Self::Portrait9_16 => (1080, 1920),
+            Self::Custom(width, height) => (width, height),
+        }
+    }
+
+    /// The diffusion model size closest to this presets aspect ratio
+    pub fn diffusion_size(self) -> (u32, u32) {
+        let (width, height) = self.dimensions();
+        let aspect = (width as f32 / height as f32).ln();
+        DIFFUSION_SIZES
+            .into_iter()
+            .min_by(|(aw, ah), (bw, bh)| {
+                let a = ((*aw as f32 / *ah as f32).ln() - aspect).abs();
+                let b = ((*bw as f32 / *bh as f32).ln() - aspect).abs();
+                a.total_cmp(&b)
+            })
+            .unwrap_or((1536, 1024))
+    }
+}
+
+impl fmt::Display for ResolutionPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UHD4K => write!(f, "uhd4k"),
+            Self::QHD => write!(f, "qhd"),
+            Self::FHD => write!(f, "fhd"),
+            Self::Ultrawide21_9 => write!(f, "ultrawide"),
+            Self::Portrait9_16 => write!(f, "portrait"),
+            Self::Custom(width, height) => write!(f, "{width}x{height}"),
+        }
+    }
+}
+
+impl FromStr for ResolutionPreset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "uhd4k" | "uhd" | "4k" => Ok(Self::UHD4K),
+            "qhd" | "1440p" => Ok(Self::QHD),
+            "fhd" | "1080p" => Ok(Self::FHD),
+            "ultrawide" | "ultrawide21_9" | "21:9" => Ok(Self::Ultrawide21_9),
+            "portrait" | "portrait9_16" | "9:16" => Ok(Self::Portrait9_16),
+            custom => {
+                let (width, height) = custom
+                    .split_once('x')
+                    .ok_or_else(|| anyhow::anyhow!("Unknown resolution preset '{s}'"))?;
+                let (width, height) = (width.parse()?, height.parse()?);
+                if width == 0 || height == 0 {
+                    return Err(anyhow::anyhow!("Resolution must be non-zero '{s}'"));
+                }
+                Ok(Self::Custom(width, height))
+            }
+        }
+    }
+}
@@ -1,39 +1,205 @@
-use crate::server::{auth::login_server, commenting, format_duration, image, read_database};
+use crate::common::{
+    utils::format_duration, Database, GalleryPage, GallerySort, GenerationMode, LikedState,
+    RetentionPolicy,
+};
+use crate::server::{
+    auth::{change_password, list_accounts, login_server, logout, token_account, verify_token},
+    backups, clustering, commenting, config, generation_context, image, importing, logs,
+    maintenance, models, moodboard, pages, profiles, prompts, read_audit_log,
+    resolution::ResolutionPreset,
+    security, sharing, stats, status, templates, users, webhook, AppState,
+};
 use axum::{
+    extract::{DefaultBodyLimit, Query, Request, State},
     http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
     Router,
 };
 use chrono::{Duration, Utc};
+use serde::Deserialize;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use uuid::Uuid;
 
-const NEW_WALLPAPER_INTERVAL: Duration = Duration::hours(6);
+const MAX_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
+const MAX_PAGE_SIZE: usize = 200;
+const DEFAULT_AUDIT_LIMIT: usize = 50;
+const DEFAULT_ACTIVITY_LIMIT: usize = 100;
 
-pub fn setup_routes(app: Router) -> Router {
-    app.route("/login", post(login_server))
+pub fn setup_routes(app: Router, state: AppState) -> Router {
+    let api = Router::new()
+        .route("/health", get(health))
+        .route("/login", post(login_server))
+        .route("/logout", post(logout))
+        .route("/auth/changepassword", post(change_password))
+        .route("/auth/account", delete(users::delete_own))
         .route("/get", get(get_database))
+        .route("/retention", get(get_retention))
+        .route("/models", get(models::list))
+        .route("/status", get(status::status))
+        .route("/clusters", get(clustering::clusters))
         .route("/latest", get(image::latest))
         .route("/favourites", get(image::favourites))
         .route("/smartget", get(image::smartget))
+        .route("/smartget/meta", get(image::smartget_meta))
+        .route("/profiles", get(profiles::list).post(profiles::save))
+        .route("/profiles/remove", post(profiles::remove))
+        .route("/served/{uuid}", post(image::served))
         .route("/generate", post(image::generate))
+        .route("/generatefromtemplate", post(templates::generate))
+        .route("/promptgenerate", post(prompts::generate))
+        .route("/promptreroll", post(prompts::reroll))
+        .route("/promptaccept", post(prompts::accept))
+        .route("/promptdiscard", post(prompts::discard))
+        .route("/template/add", post(templates::add))
+        .route("/template/remove", post(templates::remove))
         .route("/commentadd", post(commenting::add))
         .route("/commentremove", post(commenting::remove))
+        .route("/commentreact", post(commenting::react))
         .route("/imageliked", post(image::like))
+        .route("/imageliked/batch", post(image::like_batch))
         .route("/imageremove", post(image::remove))
+        .route("/imageremove/batch", post(image::remove_batch))
+        .route("/imagepin", post(image::pin))
+        .route("/imagenote", post(image::note))
+        .route("/imagerate", post(image::rate))
+        .route("/imagecontext/{uuid}", get(generation_context::get))
+        .route("/sharelink", post(sharing::create))
+        .route("/share/{token}", get(sharing::serve))
         .route("/imagerecreate", post(image::recreate))
+        .route("/imagerecreate/edited", post(image::recreate_edited))
+        .route("/generatevariation", post(image::variation))
+        .route("/imagetransform", post(image::transform))
+        .route("/imagecrop", post(image::crop))
+        .route("/imagedarken", post(image::darken))
+        .route(
+            "/imageupload",
+            post(image::upload).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route(
+            "/generatefrom",
+            post(image::generate_from).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route("/importurl", post(importing::import_url))
+        .route("/moodboard", get(moodboard::get))
+        .route(
+            "/moodboard/set",
+            post(moodboard::set).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route("/moodboard/clear", post(moodboard::clear))
         .route("/styles", post(commenting::styles))
+        .route("/presetsave", post(commenting::save_preset))
+        .route("/presetapply", post(commenting::apply_preset))
+        .route("/presetdelete", post(commenting::delete_preset))
+        .route("/historyconfig", post(commenting::history_config))
+        .route("/season", post(commenting::season))
         .route("/queryprompt", post(commenting::query_prompt))
+        .route("/stats/costs", post(stats::costs))
+        .route("/audit", get(get_audit_log))
+        .route("/activity", get(get_activity))
+        .route("/logs", get(logs::get))
+        .route("/admin/backups", get(backups::list))
+        .route("/admin/restore", post(backups::restore))
+        .route("/admin/config", get(config::get).post(config::reload))
+        .route("/admin/users/{uuid}/export", get(users::export))
+        .route("/admin/users/{uuid}/data", delete(users::delete))
+        .route("/admin/import", post(importing::import))
+        .route("/maintenance/verify", post(maintenance::verify))
+        .route("/view/{id}", get(pages::view))
+        .route("/gallery", get(pages::gallery))
+        .route("/feed.xml", get(pages::feed))
+        .with_state(state);
+    app.merge(api)
+        .layer(middleware::from_fn(security::headers))
+        // Tag every request with an X-Request-ID, echoed in the response and the log
+        .layer(middleware::from_fn(log_request))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        // Outermost so preflight requests are answered before anything else runs
+        .layer(security::cors())
+}
+
+/// Log failed requests, with the request id on every line logged while handling it so an error a
+/// client reports can be found
+async fn log_request(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    logs::with_request_id(request_id, async move {
+        let response = next.run(request).await;
+        let status = response.status();
+        if status.is_client_error() || status.is_server_error() {
+            log::warn!("{method} {path} -> {status}");
+        } else {
+            log::debug!("{method} {path} -> {status}");
+        }
+        response
+    })
+    .await
+}
+
+/// For container health checks, the database is held in memory so answering at all is healthy
+pub async fn health() -> impl IntoResponse {
+    StatusCode::OK
 }
 
-pub async fn get_database() -> impl IntoResponse {
-    match read_database().await {
-        Ok(database) => match bincode::serialize(&database) {
-            Ok(data) => (StatusCode::OK, data).into_response(),
+#[derive(Deserialize)]
+pub struct PageQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+    #[serde(default)]
+    sort: GallerySort,
+    token: Option<String>, // Whose likes to include, admins get everyone's
+}
+
+/// The whole database, or just a page of wallpapers when a limit is given
+pub async fn get_database(
+    State(state): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> impl IntoResponse {
+    let viewer = match &query.token {
+        None => None,
+        Some(token) => match token_account(&state, token).await {
+            Ok(Some(account)) => Some(account),
+            Ok(None) => return StatusCode::UNAUTHORIZED.into_response(),
             Err(e) => {
                 log::error!("{:?}", e);
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
         },
+    };
+    let mut database = state.read_database().await;
+    filter_likes(&mut database, viewer);
+    // Anyone holding a token can download the wallpaper, so they never leave the server
+    database.share_links.clear();
+    // Comments only store the author's uuid, send the usernames to show beside them
+    match list_accounts(&state).await {
+        Ok(accounts) => {
+            database.users = accounts
+                .into_iter()
+                .map(|account| (account.uuid, account.username))
+                .collect();
+        }
+        Err(e) => log::error!("Failed to list accounts for comment authors {:?}", e),
+    }
+    let data = match query.limit {
+        Some(limit) => bincode::serialize(&wallpaper_page(
+            database,
+            query.sort,
+            query.offset.unwrap_or(0),
+            limit.min(MAX_PAGE_SIZE),
+        )),
+        None => bincode::serialize(&database),
+    };
+    match data {
+        Ok(data) => (StatusCode::OK, data).into_response(),
         Err(e) => {
             log::error!("{:?}", e);
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -41,31 +207,754 @@ pub async fn get_database() -> impl IntoResponse {
     }
 }
 
-pub async fn start_server() {
-    loop {
-        match read_database().await {
-            Ok(database) => {
-                // Generate a new wallpaper every NEW_WALLPAPER_INTERVAL
-                let cur_time = Utc::now();
-                let latest_time = database
-                    .wallpapers
-                    .iter()
-                    .max_by_key(|(_, wallpaper)| wallpaper.datetime)
-                    .map_or(cur_time, |(_, wallpaper)| wallpaper.datetime);
-                log::info!(
-                    "Time since last wallpaper: {}",
-                    format_duration(cur_time - latest_time)
-                );
-                if cur_time - latest_time > NEW_WALLPAPER_INTERVAL {
-                    if let Err(err) = image::generate_wallpaper_impl(None, None).await {
-                        log::error!("Error generating wallpaper: {:?}", err);
-                    }
+/// Keep only the viewer's own votes, or every account's for an admin
+fn filter_likes(database: &mut Database, viewer: Option<(Uuid, bool)>) {
+    let uuid = viewer.map(|(uuid, _)| uuid);
+    database.viewer = uuid;
+    if matches!(viewer, Some((_, true))) {
+        return;
+    }
+    for wallpaper in database.wallpapers.values_mut() {
+        wallpaper
+            .liked_states
+            .retain(|account, _| Some(*account) == uuid);
+    }
+}
+
+fn wallpaper_page(
+    database: Database,
+    sort: GallerySort,
+    offset: usize,
+    limit: usize,
+) -> GalleryPage {
+    let viewer = database.viewer;
+    let mut wallpapers = database.wallpapers.into_values().collect::<Vec<_>>();
+    match sort {
+        GallerySort::Newest => {
+            wallpapers.sort_by_key(|wallpaper| std::cmp::Reverse(wallpaper.datetime))
+        }
+        GallerySort::Oldest => wallpapers.sort_by_key(|wallpaper| wallpaper.datetime),
+        GallerySort::Liked => wallpapers.sort_by_key(|wallpaper| {
+            let rank = match wallpaper.liked_state_for(viewer) {
+                LikedState::Loved => 0,
+                LikedState::Liked => 1,
+                LikedState::Neutral => 2,
+                LikedState::Disliked => 3,
+            };
+            (rank, std::cmp::Reverse(wallpaper.datetime))
+        }),
+    }
+    let total = wallpapers.len();
+    GalleryPage {
+        wallpapers: wallpapers.into_iter().skip(offset).take(limit).collect(),
+        total,
+    }
+}
+
+pub async fn get_retention() -> impl IntoResponse {
+    match bincode::serialize(&retention_policy()) {
+        Ok(data) => (StatusCode::OK, data).into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    token: String,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+/// Page through the audit log newest first, only available to admins
+pub async fn get_audit_log(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    match token_account(&state, &query.token).await {
+        Ok(Some((_, true))) => {}
+        Ok(_) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    match read_audit_log(&state).await {
+        Ok(audit_log) => {
+            let entries = audit_log
+                .into_iter()
+                .rev()
+                .skip(query.offset.unwrap_or(0))
+                .take(query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT))
+                .collect::<Vec<_>>();
+            match bincode::serialize(&entries) {
+                Ok(data) => (StatusCode::OK, data).into_response(),
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
                 }
             }
-            Err(e) => log::error!("{:?}", e),
+        }
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ActivityQuery {
+    token: String,
+    limit: Option<usize>,
+}
+
+/// The most recent audit entries newest first, readable by every account
+pub async fn get_activity(
+    State(state): State<AppState>,
+    Query(query): Query<ActivityQuery>,
+) -> impl IntoResponse {
+    if !verify_token(&state, &query.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match read_audit_log(&state).await {
+        Ok(audit_log) => {
+            let entries = audit_log
+                .into_iter()
+                .rev()
+                .take(query.limit.unwrap_or(DEFAULT_ACTIVITY_LIMIT))
+                .collect::<Vec<_>>();
+            match bincode::serialize(&entries) {
+                Ok(data) => (StatusCode::OK, data).into_response(),
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// How long after the latest wallpaper to generate another, set with `GENERATION_INTERVAL_HOURS`
+fn generation_interval() -> Duration {
+    let hours = config::var("GENERATION_INTERVAL_HOURS")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(6);
+    Duration::hours(hours)
+}
+
+/// Read the auto-delete periods from `AUTO_DELETE_DISLIKED_DAYS` and `AUTO_DELETE_NEUTRAL_DAYS`
+pub fn retention_policy() -> RetentionPolicy {
+    let days = |name: &str, default: u32| {
+        config::var(name)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+    RetentionPolicy {
+        disliked_days: days("AUTO_DELETE_DISLIKED_DAYS", 7),
+        neutral_days: days("AUTO_DELETE_NEUTRAL_DAYS", 0),
+    }
+}
+
+pub async fn start_server(state: AppState) {
+    let state = &state;
+    maintenance::startup_check(state).await;
+    if let Err(e) = maintenance::check_thumbnail_settings(state).await {
+        log::error!("Failed to check thumbnail settings {:?}", e);
+    }
+    tokio::spawn(maintenance::rebuild_stale_thumbnails(state.clone()));
+    // Runs beside the scheduler so a slow boot never holds up the first generation check
+    tokio::spawn(image::warmup_model());
+
+    loop {
+        state.scheduler.write().interval = generation_interval();
+        let database = state.read_database().await;
+        // Generate a new wallpaper every `GENERATION_INTERVAL_HOURS`
+        let cur_time = Utc::now();
+        let latest_time = database
+            .wallpapers
+            .iter()
+            .max_by_key(|(_, wallpaper)| wallpaper.datetime)
+            .map_or(cur_time, |(_, wallpaper)| wallpaper.datetime);
+        log::info!(
+            "Time since last wallpaper: {}",
+            format_duration(cur_time - latest_time)
+        );
+        if cur_time - latest_time > generation_interval() {
+            match image::generate_wallpaper_impl(
+                state,
+                None,
+                None,
+                GenerationMode::Normal,
+                ResolutionPreset::from_env(),
+                None,
+            )
+            .await
+            {
+                Ok(id) => webhook::notify_generated(state, id).await,
+                Err(err) => log::error!("Error generating wallpaper: {:?}", err),
+            }
+        }
+
+        // Pending prompts are kept for a week, the scheduler never waits on them
+        match prompts::remove_stale_prompts(state).await {
+            Ok(0) => {}
+            Ok(removed) => log::info!("Removed {removed} stale pending prompts"),
+            Err(err) => log::error!("Error removing stale pending prompts: {:?}", err),
+        }
+
+        // Clean up disliked and neutral wallpapers past their retention period
+        if let Err(err) = image::remove_expired_wallpapers(state, retention_policy()).await {
+            log::error!("Error auto-deleting wallpapers: {:?}", err);
         }
 
         // Sleep for 10 minutes
-        tokio::time::sleep(tokio::time::Duration::from_secs(60 * 10)).await;
+        let sleep = Duration::minutes(10);
+        state.scheduler.write().next_check = Some(Utc::now() + sleep);
+        tokio::time::sleep(sleep.to_std().unwrap_or_default()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{
+        AuditAction, AuditEntry, ChangePasswordPacket, CommentData, ImageTransformPacket,
+        ImageUploadPacket, LogLevel, LogLine, LoginPacket, SetStylePacket, StyleVariant,
+        TokenPacket, TokenStringPacket, TokenUuidLikedPacket, TokenUuidPacket,
+    };
+    use crate::common::{GeneratePacket, GenerationMode};
+    use crate::server::{
+        caching,
+        models::ImageModel,
+        paths::ServerConfig,
+        providers::{ImageProvider, LlmProvider},
+    };
+    use ::image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use serde::Serialize;
+    use serde_json::{json, Value};
+    use std::{io::Cursor, path::PathBuf, sync::Arc};
+
+    const FAKE_PROMPT: &str = "A misty pine forest at dawn, soft golden light, digital painting";
+
+    /// Answers each chat completion in the shape its response format asks for
+    struct FakeLlm;
+
+    #[async_trait]
+    impl LlmProvider for FakeLlm {
+        async fn chat_completion(&self, request_body: Value) -> Result<(Value, String)> {
+            let content = match request_body
+                .pointer("/response_format/json_schema/name")
+                .and_then(Value::as_str)
+            {
+                Some("prompt_data") => json!({
+                    "prompt": FAKE_PROMPT,
+                    "shortened_prompt": "A misty pine forest at dawn",
+                })
+                .to_string(),
+                Some("tags") => json!({ "tags": ["Forest", "golden hour"] }).to_string(),
+                _ => "A misty forest".to_string(),
+            };
+            let response = json!({
+                "model": "fake",
+                "choices": [{ "message": { "content": content } }],
+                "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 },
+            });
+            Ok((response, "fake/llm".to_string()))
+        }
+    }
+
+    /// Paints a flat image instead of diffusing one
+    struct FakeImages;
+
+    #[async_trait]
+    impl ImageProvider for FakeImages {
+        async fn diffuse(
+            &self,
+            _model: &ImageModel,
+            _prompt: &str,
+            _negative_prompt: Option<&str>,
+            _size: (u32, u32),
+        ) -> Result<(String, DynamicImage)> {
+            let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 36, Rgb([30, 80, 50])));
+            Ok(("fake://diffused".to_string(), image))
+        }
+
+        async fn upscale(
+            &self,
+            image: &DynamicImage,
+            _prompt: &str,
+        ) -> Result<(String, DynamicImage)> {
+            Ok(("fake://upscaled".to_string(), image.clone()))
+        }
+    }
+
+    /// State on a fresh temp dir with fake providers, so nothing reaches a real API
+    async fn test_state() -> (PathBuf, AppState) {
+        let dir =
+            std::env::temp_dir().join(format!("wallpapy-routes-test-{}", uuid::Uuid::new_v4()));
+        let config = ServerConfig::in_dir(&dir);
+        config.validate().unwrap();
+        let state = AppState {
+            llm: Arc::new(FakeLlm),
+            images: Arc::new(FakeImages),
+            ..AppState::load(config).await.unwrap()
+        };
+        (dir, state)
+    }
+
+    /// Serve the routes on a free local port, returning its base url
+    async fn serve(state: &AppState) -> String {
+        let app = setup_routes(
+            Router::new().nest_service(
+                "/wallpapers",
+                caching::wallpapers_service(&state.config.wallpapers_dir),
+            ),
+            state.clone(),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        format!("http://{addr}")
+    }
+
+    /// Post a packet encoded the way the client does it
+    async fn post(
+        client: &reqwest::Client,
+        base: &str,
+        path: &str,
+        packet: &impl Serialize,
+    ) -> reqwest::Response {
+        client
+            .post(format!("{base}{path}"))
+            .body(bincode::serialize(packet).unwrap())
+            .send()
+            .await
+            .unwrap()
+    }
+
+    async fn get_database(client: &reqwest::Client, base: &str) -> Database {
+        let response = client.get(format!("{base}/get")).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        bincode::deserialize(&response.bytes().await.unwrap()).unwrap()
+    }
+
+    /// The database as an account sees it, with its own votes
+    async fn get_own_database(client: &reqwest::Client, base: &str, token: &str) -> Database {
+        let response = client
+            .get(format!("{base}/get?token={token}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        bincode::deserialize(&response.bytes().await.unwrap()).unwrap()
+    }
+
+    fn comment<'a>(database: &'a Database, text: &str) -> Option<&'a CommentData> {
+        database
+            .comments
+            .values()
+            .find(|comment| comment.comment == text)
+    }
+
+    #[tokio::test]
+    async fn round_trips_client_packets() {
+        let (dir, state) = test_state().await;
+        state.store.spawn_persistence();
+        let base = serve(&state).await;
+        let client = reqwest::Client::new();
+
+        // The first login creates the admin account
+        let login = LoginPacket {
+            username: "admin".to_string(),
+            password: "correct horse".to_string(),
+        };
+        let response = post(&client, &base, "/login", &login).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body = response.text().await.unwrap();
+        let (message, token) = body.split_once('|').unwrap();
+        assert_eq!(message, "Admin Account Created");
+        let token = token.to_string();
+
+        let response = post(&client, &base, "/login", &login).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let wrong = LoginPacket {
+            username: "admin".to_string(),
+            password: "wrong password".to_string(),
+        };
+        let response = post(&client, &base, "/login", &wrong).await;
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        // Mutations need a valid token
+        let comment_packet = TokenStringPacket {
+            token: "not a token".to_string(),
+            string: "More oceans please".to_string(),
+        };
+        let response = post(&client, &base, "/commentadd", &comment_packet).await;
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let response = client
+            .post(format!("{base}/commentadd"))
+            .body("not bincode")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        // Comments
+        let comment_packet = TokenStringPacket {
+            token: token.clone(),
+            ..comment_packet
+        };
+        let response = post(&client, &base, "/commentadd", &comment_packet).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let database = get_database(&client, &base).await;
+        let added = comment(&database, "More oceans please").unwrap();
+        let id = added.id;
+        let author = added.author_uuid.unwrap();
+        assert_eq!(database.users[&author], "admin");
+
+        // Reacting twice takes the thumbs up back
+        let react = TokenUuidPacket {
+            token: token.clone(),
+            uuid: id,
+        };
+        let response = post(&client, &base, "/commentreact", &react).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let database = get_database(&client, &base).await;
+        let reacted = comment(&database, "More oceans please").unwrap();
+        assert_eq!(reacted.reaction_count(), 1);
+        assert_eq!(reacted.reactions.get(&author), Some(&true));
+        let response = post(&client, &base, "/commentreact", &react).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let database = get_database(&client, &base).await;
+        assert_eq!(
+            comment(&database, "More oceans please")
+                .unwrap()
+                .reaction_count(),
+            0
+        );
+        let missing = TokenUuidPacket {
+            token: token.clone(),
+            uuid: uuid::Uuid::new_v4(),
+        };
+        let response = post(&client, &base, "/commentreact", &missing).await;
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        let remove = TokenUuidPacket {
+            token: token.clone(),
+            uuid: id,
+        };
+        let response = post(&client, &base, "/commentremove", &remove).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(comment(&get_database(&client, &base).await, "More oceans please").is_none());
+
+        // Styles
+        let style = SetStylePacket {
+            token: token.clone(),
+            variant: StyleVariant::Style("Ink wash painting".to_string()),
+        };
+        let response = post(&client, &base, "/styles", &style).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            get_database(&client, &base).await.style.style,
+            "Ink wash painting"
+        );
+
+        // Upload an image to have a wallpaper to like without calling out to any APIs
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 36, Rgb([40, 90, 160])))
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        let upload = ImageUploadPacket {
+            token: token.clone(),
+            title: Some("Blue".to_string()),
+            data,
+        };
+        let response = post(&client, &base, "/imageupload", &upload).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let database = get_database(&client, &base).await;
+        let wallpaper = database.wallpapers.values().next().unwrap();
+        assert_eq!(wallpaper.prompt_data.prompt, "Blue");
+        assert_eq!(wallpaper.original_file.width, 64);
+        let thumbnail = client
+            .get(format!(
+                "{base}/wallpapers/{}",
+                wallpaper.thumbnail_file.file_name
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(thumbnail.status(), reqwest::StatusCode::OK);
+
+        // Rotating swaps in newly encoded files and removes the old ones
+        let transform = ImageTransformPacket {
+            token: token.clone(),
+            uuid: wallpaper.id,
+            rotation: Some(90),
+            flip_horizontal: false,
+            flip_vertical: false,
+        };
+        let response = post(&client, &base, "/imagetransform", &transform).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let rotated_database = get_database(&client, &base).await;
+        let rotated = &rotated_database.wallpapers[&wallpaper.id];
+        assert_eq!(rotated.original_file.width, 36);
+        assert_ne!(
+            rotated.original_file.file_name,
+            wallpaper.original_file.file_name
+        );
+        let wallpapers_dir = &state.config.wallpapers_dir;
+        assert!(!wallpapers_dir
+            .join(&wallpaper.original_file.file_name)
+            .exists());
+        assert!(wallpapers_dir
+            .join(&rotated.original_file.file_name)
+            .exists());
+
+        // Liking twice toggles back to neutral
+        let like = TokenUuidLikedPacket {
+            token: token.clone(),
+            uuid: wallpaper.id,
+            liked: LikedState::Loved,
+        };
+        let response = post(&client, &base, "/imageliked", &like).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let database = get_own_database(&client, &base, &token).await;
+        assert_eq!(database.viewer, Some(author));
+        assert!(database.wallpapers[&wallpaper.id].liked_state(author) == LikedState::Loved);
+        // Votes are only sent to the account that made them
+        assert!(get_database(&client, &base).await.wallpapers[&wallpaper.id]
+            .liked_states
+            .is_empty());
+        let response = client
+            .get(format!("{base}/get?token=not-a-token"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let response = post(&client, &base, "/imageliked", &like).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(
+            get_own_database(&client, &base, &token).await.wallpapers[&wallpaper.id]
+                .liked_state(author)
+                == LikedState::Neutral
+        );
+
+        // Every account can see who did what, newest first
+        let response = client
+            .get(format!("{base}/activity?token={token}&limit=2"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let activity: Vec<AuditEntry> =
+            bincode::deserialize(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!(activity.len(), 2);
+        assert!(activity[0].action == AuditAction::Like);
+        assert_eq!(activity[0].summary, "Set to neutral");
+        assert_eq!(activity[0].target_uuid, Some(wallpaper.id));
+        assert_eq!(activity[0].actor_uuid, author);
+        let response = client
+            .get(format!("{base}/activity?token=not-a-token"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        // Admins can page through the full audit log
+        let response = client
+            .get(format!("{base}/audit?token={token}&limit=1&offset=1"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let audit_log: Vec<AuditEntry> =
+            bincode::deserialize(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!(audit_log.len(), 1);
+        assert_eq!(audit_log[0].summary, activity[1].summary);
+
+        // Admins can read the recent server log, filtered by level
+        let response = client
+            .get(format!("{base}/logs?token={token}&level=warn"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let lines: Vec<LogLine> = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+        assert!(lines.iter().all(|line| line.level <= LogLevel::Warn));
+        for (query, status) in [
+            (
+                format!("token={token}&level=loud"),
+                reqwest::StatusCode::BAD_REQUEST,
+            ),
+            (
+                "token=not-a-token".to_string(),
+                reqwest::StatusCode::UNAUTHORIZED,
+            ),
+        ] {
+            let response = client
+                .get(format!("{base}/logs?{query}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        // Concurrent likes on different wallpapers all land, none overwrite another
+        for shade in 0..12u8 {
+            let mut data = Vec::new();
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 18, Rgb([shade * 20, 60, 90])))
+                .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+                .unwrap();
+            let upload = ImageUploadPacket {
+                token: token.clone(),
+                title: Some(format!("Shade {shade}")),
+                data,
+            };
+            let response = post(&client, &base, "/imageupload", &upload).await;
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+        }
+        let shades = get_database(&client, &base)
+            .await
+            .wallpapers
+            .into_values()
+            .filter(|wallpaper| wallpaper.prompt_data.prompt.starts_with("Shade"))
+            .map(|wallpaper| wallpaper.id)
+            .collect::<Vec<_>>();
+        assert_eq!(shades.len(), 12);
+        let likes = shades
+            .iter()
+            .map(|&uuid| {
+                let (client, base) = (client.clone(), base.clone());
+                let like = TokenUuidLikedPacket {
+                    token: token.clone(),
+                    uuid,
+                    liked: LikedState::Liked,
+                };
+                tokio::spawn(async move { post(&client, &base, "/imageliked", &like).await })
+            })
+            .collect::<Vec<_>>();
+        for like in likes {
+            assert_eq!(like.await.unwrap().status(), reqwest::StatusCode::OK);
+        }
+        let database = get_own_database(&client, &base, &token).await;
+        assert!(shades
+            .iter()
+            .all(|id| database.wallpapers[id].liked_state(author) == LikedState::Liked));
+        // And they reach the file once flushed
+        state.store.flush().await.unwrap();
+        let saved: Database =
+            ron::from_str(&std::fs::read_to_string(dir.join("database.ron")).unwrap()).unwrap();
+        assert!(shades
+            .iter()
+            .all(|id| saved.wallpapers[id].liked_state(author) == LikedState::Liked));
+
+        // Changing the password logs out every other session
+        let mut change = ChangePasswordPacket {
+            token: token.clone(),
+            old_password: "wrong password".to_string(),
+            new_password: "battery staple".to_string(),
+        };
+        let response = post(&client, &base, "/auth/changepassword", &change).await;
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+        change.old_password = login.password.clone();
+        let response = post(&client, &base, "/auth/changepassword", &change).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let response = post(&client, &base, "/login", &login).await;
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let login = LoginPacket {
+            username: "admin".to_string(),
+            password: change.new_password,
+        };
+        let response = post(&client, &base, "/login", &login).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let response = post(&client, &base, "/styles", &style).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        // Logging out revokes the token
+        let logout = TokenPacket { token };
+        let response = post(&client, &base, "/logout", &logout).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let response = post(&client, &base, "/styles", &style).await;
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn generates_wallpaper_end_to_end() {
+        let (dir, state) = test_state().await;
+        let base = serve(&state).await;
+        let client = reqwest::Client::new();
+
+        let login = LoginPacket {
+            username: "admin".to_string(),
+            password: "correct horse".to_string(),
+        };
+        let response = post(&client, &base, "/login", &login).await;
+        let body = response.text().await.unwrap();
+        let token = body.split_once('|').unwrap().1.to_string();
+
+        let mut generate = GeneratePacket {
+            token: "not a token".to_string(),
+            message: "something calm".to_string(),
+            mode: GenerationMode::Normal,
+            model: None,
+            comment: None,
+        };
+        let response = post(&client, &base, "/generate", &generate).await;
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert!(get_database(&client, &base).await.wallpapers.is_empty());
+
+        generate.token = token;
+        let response = post(&client, &base, "/generate", &generate).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        // Stored with the prompt, provider and tags the fakes returned
+        let database = get_database(&client, &base).await;
+        assert_eq!(database.wallpapers.len(), 1);
+        let wallpaper = database.wallpapers.values().next().unwrap();
+        assert_eq!(wallpaper.prompt_data.prompt, FAKE_PROMPT);
+        assert_eq!(wallpaper.llm_provider_used, "fake/llm");
+        assert_eq!(wallpaper.tags, ["forest", "golden hour"]);
+        assert!(wallpaper
+            .llm_usage
+            .is_some_and(|usage| usage.total_tokens > 0));
+        assert_eq!(wallpaper.candidates_evaluated, 1);
+
+        // The image and its thumbnail are on disk and served
+        let wallpapers_dir = &state.config.wallpapers_dir;
+        assert!(wallpapers_dir
+            .join(&wallpaper.original_file.file_name)
+            .is_file());
+        assert!(wallpapers_dir
+            .join(&wallpaper.thumbnail_file.file_name)
+            .is_file());
+        let response = client
+            .get(format!(
+                "{base}/wallpapers/{}",
+                wallpaper.thumbnail_file.file_name
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,172 @@
+use crate::server::config;
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{sync::LazyLock, time::Duration};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// The trunk build loads the wasm through an inline module script and styles the page inline,
+/// so those need allowing on top of same origin scripts
+const DEFAULT_CSP: &str = "default-src 'self'; img-src 'self' data: https://replicate.delivery; script-src 'self' 'unsafe-inline' 'wasm-unsafe-eval'; style-src 'self' 'unsafe-inline'";
+const HSTS: &str = "max-age=31536000; includeSubDomains";
+
+/// Replaced with `CSP_OVERRIDE` for setups that host the API and frontend on different origins
+static CONTENT_SECURITY_POLICY: LazyLock<HeaderValue> = LazyLock::new(|| {
+    config::var("CSP_OVERRIDE")
+        .and_then(|csp| {
+            let value = HeaderValue::from_str(&csp).ok();
+            if value.is_none() {
+                log::warn!("Invalid CSP_OVERRIDE '{csp}', using default");
+            }
+            value
+        })
+        .unwrap_or_else(|| HeaderValue::from_static(DEFAULT_CSP))
+});
+
+/// Set `HTTPS_ONLY` when a TLS terminating proxy sits in front of the server
+fn https_only() -> bool {
+    config::var("HTTPS_ONLY").is_some_and(|value| value == "true")
+}
+
+/// Add security headers to every response, redirecting plain http requests when `HTTPS_ONLY` is set
+pub async fn headers(request: Request, next: Next) -> Response {
+    let https_only = https_only();
+    // Health checks come from inside the container over plain http
+    if https_only && request.uri().path() != "/health" {
+        if let Some(location) = https_redirect(request.headers(), request.uri()) {
+            return (
+                StatusCode::PERMANENT_REDIRECT,
+                [(header::LOCATION, location)],
+            )
+                .into_response();
+        }
+    }
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        CONTENT_SECURITY_POLICY.clone(),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    headers.insert(
+        header::REFERRER_POLICY,
+        HeaderValue::from_static("no-referrer"),
+    );
+    if https_only {
+        headers.insert(
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static(HSTS),
+        );
+    }
+    response
+}
+
+/// The https url to send a request to, unless the proxy says it already came in over https
+fn https_redirect(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    let proto = headers
+        .get("x-forwarded-proto")
+        .and_then(|value| value.to_str().ok());
+    if proto.is_some_and(|proto| proto.eq_ignore_ascii_case("https")) {
+        return None;
+    }
+    let host = headers.get(header::HOST)?.to_str().ok()?;
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    Some(format!("https://{host}{path}"))
+}
+
+/// Let frontends on other origins call the API, limited to `CORS_ALLOWED_ORIGINS` when set
+pub fn cors() -> CorsLayer {
+    let origins = config::var("CORS_ALLOWED_ORIGINS").unwrap_or_else(|| "*".to_string());
+    let max_age = config::var("CORS_MAX_AGE_SECS")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(86400);
+    CorsLayer::new()
+        .allow_origin(parse_origins(&origins).map_or_else(AllowOrigin::any, AllowOrigin::list))
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+        .expose_headers([HeaderName::from_static("x-request-id")])
+        .max_age(Duration::from_secs(max_age))
+}
+
+/// The comma separated origins, or none when any origin is allowed
+fn parse_origins(value: &str) -> Option<Vec<HeaderValue>> {
+    if value.trim() == "*" {
+        return None;
+    }
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .filter_map(|origin| {
+                let value = HeaderValue::from_str(origin).ok();
+                if value.is_none() {
+                    log::warn!("Invalid origin '{origin}' in CORS_ALLOWED_ORIGINS");
+                }
+                value
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    header::HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn redirects_plain_http_only() {
+        let uri: Uri = "/smartget?temperature=warm".parse().unwrap();
+        assert_eq!(
+            https_redirect(&request_headers(&[("host", "walls.example.com")]), &uri).as_deref(),
+            Some("https://walls.example.com/smartget?temperature=warm")
+        );
+        assert_eq!(
+            https_redirect(
+                &request_headers(&[("host", "walls.example.com"), ("x-forwarded-proto", "http")]),
+                &uri
+            )
+            .as_deref(),
+            Some("https://walls.example.com/smartget?temperature=warm")
+        );
+        assert!(https_redirect(
+            &request_headers(&[
+                ("host", "walls.example.com"),
+                ("x-forwarded-proto", "https")
+            ]),
+            &uri
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn parses_allowed_origins() {
+        assert!(parse_origins("*").is_none());
+        assert_eq!(
+            parse_origins("https://a.example.com, https://b.example.com,").unwrap(),
+            vec![
+                HeaderValue::from_static("https://a.example.com"),
+                HeaderValue::from_static("https://b.example.com")
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use crate::common::{AuditAction, ShareLink, TokenUuidPacket};
+use crate::server::{
+    audit, auth::verify_token, config, error_status, image::full_size_file, paths::ServerConfig,
+    AppState,
+};
+use anyhow::anyhow;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::{env, fs as std_fs};
+use subtle::ConstantTimeEq;
+use tokio::fs;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Key for signing share links from `SHARE_SECRET`
+/// Without it a random key is generated once and kept in the data dir, so stored links survive a restart
+pub fn load_key(config: &ServerConfig) -> Vec<u8> {
+    if let Ok(secret) = env::var("SHARE_SECRET") {
+        if !secret.is_empty() {
+            return secret.into_bytes();
+        }
+    }
+    let path = config.data_dir.join("share.key");
+    if let Ok(key) = std_fs::read(&path) {
+        if !key.is_empty() {
+            return key;
+        }
+    }
+    let mut key = vec![0; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    if let Err(e) = std_fs::write(&path, &key) {
+        log::error!(
+            "Failed to save share key, links won't survive a restart {:?}",
+            e
+        );
+    }
+    key
+}
+
+/// How long a share link works for, set with `SHARE_LINK_EXPIRY_HOURS`
+fn link_lifetime() -> Duration {
+    let hours = config::var("SHARE_LINK_EXPIRY_HOURS")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(24);
+    Duration::hours(hours)
+}
+
+/// Signature of a wallpaper id and expiry time, which doubles as the link token
+fn sign(key: &[u8], uuid: Uuid, expiry: DateTime<Utc>) -> String {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC takes any key size");
+    mac.update(uuid.as_bytes());
+    mac.update(&expiry.timestamp().to_be_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The wallpaper a token is for, if it's a link we handed out that hasn't expired
+fn resolve(key: &[u8], links: &[ShareLink], token: &str, now: DateTime<Utc>) -> Option<Uuid> {
+    links
+        .iter()
+        .find(|link| {
+            link.expiry > now
+                && bool::from(link.token.as_bytes().ct_eq(token.as_bytes()))
+                && bool::from(
+                    sign(key, link.uuid, link.expiry)
+                        .as_bytes()
+                        .ct_eq(token.as_bytes()),
+                )
+        })
+        .map(|link| link.uuid)
+}
+
+/// Create a link anyone can open to download a wallpaper until it expires, returning its token
+pub async fn create(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize share_link packet: {:?}", e);
+            return (StatusCode::BAD_REQUEST, String::new());
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return (StatusCode::UNAUTHORIZED, String::new());
+    }
+
+    let now = Utc::now();
+    let expiry = now + link_lifetime();
+    let token = sign(&state.share_key, packet.uuid, expiry);
+    let result = state
+        .with_database(|database| {
+            if !database.wallpapers.contains_key(&packet.uuid) {
+                return Ok(false);
+            }
+            database.share_links.retain(|link| link.expiry > now);
+            database.share_links.push(ShareLink {
+                token: token.clone(),
+                uuid: packet.uuid,
+                expiry,
+            });
+            Ok(true)
+        })
+        .await;
+    match result {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, String::new()),
+        Err(e) => {
+            log::error!("Errored share_link {:?}", e);
+            return (error_status(&e), String::new());
+        }
+    }
+    let summary = format!("Shared until {}", expiry.format("%d/%m/%Y %H:%M UTC"));
+    audit(
+        &state,
+        &packet.token,
+        AuditAction::Share,
+        Some(packet.uuid),
+        summary,
+    )
+    .await;
+    (StatusCode::OK, token)
+}
+
+/// Serve the full size image behind a share link, no login needed
+pub async fn serve(State(state): State<AppState>, Path(token): Path<String>) -> impl IntoResponse {
+    let mut database = state.read_database().await;
+    let wallpaper = resolve(&state.share_key, &database.share_links, &token, Utc::now())
+        .ok_or_else(|| anyhow!("Unknown share link"))
+        .map(|uuid| database.wallpapers.remove(&uuid));
+    let Ok(wallpaper) = wallpaper else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(wallpaper) = wallpaper else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let image_path = state
+        .config
+        .wallpapers_dir
+        .join(&full_size_file(&wallpaper).file_name);
+    match fs::read(&image_path).await {
+        Ok(data) => {
+            let mime_type = mime_guess::from_path(&image_path).first_or_octet_stream();
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, mime_type.to_string())],
+                data,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            log::error!("Failed to read image file: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"test key";
+
+    #[test]
+    fn resolves_only_live_signed_links() {
+        let now = Utc::now();
+        let uuid = Uuid::new_v4();
+        let expiry = now + Duration::hours(1);
+        let token = sign(KEY, uuid, expiry);
+        let expired_token = sign(KEY, uuid, now - Duration::hours(1));
+        let links = vec![
+            ShareLink {
+                token: token.clone(),
+                uuid,
+                expiry,
+            },
+            ShareLink {
+                token: expired_token.clone(),
+                uuid,
+                expiry: now - Duration::hours(1),
+            },
+            // Stored under a token that doesn't match its signature
+            ShareLink {
+                token: "forged".to_string(),
+                uuid,
+                expiry,
+            },
+        ];
+
+        assert_eq!(resolve(KEY, &links, &token, now), Some(uuid));
+        assert_eq!(resolve(KEY, &links, &expired_token, now), None);
+        assert_eq!(resolve(KEY, &links, "forged", now), None);
+        assert_ne!(sign(KEY, uuid, expiry), sign(KEY, Uuid::new_v4(), expiry));
+        assert_ne!(sign(KEY, uuid, expiry), sign(b"other key", uuid, expiry));
+    }
+}
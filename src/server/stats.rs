@@ -0,0 +1,92 @@
+use crate::common::{
+    CostStats, Database, LikedState, ModelStats, MonthlyCost, Source, TokenPacket, UserStats,
+};
+use crate::server::{auth::verify_token, models::DEFAULT_MODEL_ID, AppState};
+use axum::{body::Bytes, extract::State, http::StatusCode, response::IntoResponse};
+use chrono::Datelike;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+pub async fn costs(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize costs packet: {:?}", e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let database = state.read_database().await;
+    match bincode::serialize(&cost_stats(&database)) {
+        Ok(data) => (StatusCode::OK, data).into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Sum the LLM usage of every wallpaper, in total and per month, and count what each image model made
+fn cost_stats(database: &Database) -> CostStats {
+    let mut months: BTreeMap<(i32, u32), (u64, f32)> = BTreeMap::new();
+    let mut models: BTreeMap<&str, (u32, u32)> = BTreeMap::new();
+    let mut users: BTreeMap<Option<Uuid>, u32> = BTreeMap::new();
+    for wallpaper in database.wallpapers.values() {
+        if wallpaper.source == Source::Generated {
+            *users.entry(wallpaper.generated_by).or_default() += 1;
+        }
+        // Generated wallpapers from before models were recorded all came from the default
+        let model = wallpaper
+            .model
+            .as_deref()
+            .or_else(|| (wallpaper.source == Source::Generated).then_some(DEFAULT_MODEL_ID));
+        if let Some(model) = model {
+            let counts = models.entry(model).or_default();
+            counts.0 += 1;
+            if wallpaper.combined_liked_state() == LikedState::Loved {
+                counts.1 += 1;
+            }
+        }
+        if let Some(usage) = wallpaper.llm_usage {
+            let month = months
+                .entry((wallpaper.datetime.year(), wallpaper.datetime.month()))
+                .or_default();
+            month.0 += u64::from(usage.total_tokens);
+            month.1 += usage.estimated_cost_usd;
+        }
+    }
+
+    let mut model_stats = models
+        .into_iter()
+        .map(|(model, (generated, loved))| ModelStats {
+            model: model.to_string(),
+            generated,
+            loved,
+        })
+        .collect::<Vec<_>>();
+    model_stats.sort_by_key(|stats| std::cmp::Reverse(stats.generated));
+    let mut user_stats = users
+        .into_iter()
+        .map(|(user, generated)| UserStats { user, generated })
+        .collect::<Vec<_>>();
+    user_stats.sort_by_key(|stats| std::cmp::Reverse(stats.generated));
+
+    CostStats {
+        total_tokens: months.values().map(|(tokens, _)| tokens).sum(),
+        total_cost_usd: months.values().map(|(_, cost)| cost).sum(),
+        months: months
+            .into_iter()
+            .map(|((year, month), (total_tokens, cost_usd))| MonthlyCost {
+                year,
+                month,
+                total_tokens,
+                cost_usd,
+            })
+            .collect(),
+        models: model_stats,
+        users: user_stats,
+    }
+}
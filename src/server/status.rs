@@ -0,0 +1,127 @@
+use crate::common::ServerStatus;
+use crate::server::{moodboard, AppState};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use std::{env, sync::Arc};
+
+#[derive(Default)]
+pub struct SchedulerStatus {
+    pub interval: Duration,
+    pub next_check: Option<DateTime<Utc>>,
+    running: Vec<DateTime<Utc>>, // Start times of the generations in progress
+    last_duration: Option<Duration>,
+}
+
+/// Marks a generation as running until dropped, however it finishes
+pub struct GenerationGuard {
+    scheduler: Arc<RwLock<SchedulerStatus>>,
+    started: DateTime<Utc>,
+}
+
+impl GenerationGuard {
+    pub fn start(state: &AppState) -> Self {
+        let started = Utc::now();
+        state.scheduler.write().running.push(started);
+        Self {
+            scheduler: state.scheduler.clone(),
+            started,
+        }
+    }
+}
+
+impl Drop for GenerationGuard {
+    fn drop(&mut self) {
+        let mut status = self.scheduler.write();
+        if let Some(index) = status
+            .running
+            .iter()
+            .position(|started| *started == self.started)
+        {
+            status.running.remove(index);
+        }
+        status.last_duration = Some(Utc::now() - self.started);
+    }
+}
+
+/// When the scheduler, checking at `next_check` and every interval after, will next generate
+fn next_generation(
+    last_generation: Option<DateTime<Utc>>,
+    interval: Duration,
+    next_check: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    let next_check = next_check?;
+    let due = last_generation.map_or(next_check, |last| last + interval);
+    Some(due.max(next_check))
+}
+
+/// The scheduler state along with the latest wallpaper and prompt queue from the database
+pub async fn status(State(state): State<AppState>) -> impl IntoResponse {
+    let database = state.read_database().await;
+    let last_generation = database
+        .wallpapers
+        .values()
+        .map(|wallpaper| wallpaper.datetime)
+        .max();
+    let moodboard_updated = moodboard::updated(&state).await;
+
+    let status = {
+        let scheduler = state.scheduler.read();
+        ServerStatus {
+            last_generation,
+            next_generation: next_generation(
+                last_generation,
+                scheduler.interval,
+                scheduler.next_check,
+            ),
+            generating: !scheduler.running.is_empty(),
+            generation_started: scheduler.running.iter().min().copied(),
+            last_duration_secs: scheduler
+                .last_duration
+                .map(|duration| duration.num_seconds()),
+            queue_length: database.pending_prompts.len(),
+            api_keys_configured: ["OPENAI_API_KEY", "REPLICATE_API_TOKEN"]
+                .iter()
+                .all(|name| env::var(name).is_ok_and(|value| !value.is_empty())),
+            moodboard_updated,
+        }
+    };
+    match serde_json::to_vec(&status) {
+        Ok(data) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            data,
+        )
+            .into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_generation_waits_for_interval_and_check() {
+        let now = Utc::now();
+        let interval = Duration::hours(6);
+        assert_eq!(next_generation(Some(now), interval, None), None);
+        assert_eq!(
+            next_generation(Some(now - Duration::hours(1)), interval, Some(now)),
+            Some(now + Duration::hours(5))
+        );
+        // Overdue wallpapers wait for the scheduler to wake up
+        assert_eq!(
+            next_generation(Some(now - Duration::hours(7)), interval, Some(now)),
+            Some(now)
+        );
+        assert_eq!(next_generation(None, interval, Some(now)), Some(now));
+    }
+}
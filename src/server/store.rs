@@ -0,0 +1,156 @@
+use crate::common::{Database, DatabaseStyle, HistoryConfig, ThumbnailSettings};
+use crate::server::{backups, config, DatabaseBusy};
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    fs,
+    sync::{Mutex, MutexGuard, Notify, RwLock},
+};
+
+/// How long changes gather before being written, so a burst of requests costs one write
+const PERSIST_DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The database every request works on, read from the file once at startup and persisted in the background
+pub struct DatabaseStore {
+    file: PathBuf,
+    database: RwLock<Database>,
+    /// Held across every read-modify-write so concurrent changes aren't lost
+    write_lock: Mutex<()>,
+    /// Changes not yet written to the file
+    dirty: AtomicBool,
+    /// Wakes the task that writes them
+    changed: Notify,
+    /// Until `spawn_persistence` runs every change is written straight away
+    persistence_running: AtomicBool,
+    /// Held while writing so a flush on shutdown doesn't interleave with the background one
+    persist_lock: Mutex<()>,
+}
+
+impl DatabaseStore {
+    /// Parse the database file, or start empty when there isn't one yet
+    pub async fn load(file: PathBuf) -> Result<Self> {
+        let database = read_file(&file).await?;
+        Ok(Self {
+            file,
+            database: RwLock::new(database),
+            write_lock: Mutex::new(()),
+            dirty: AtomicBool::new(false),
+            changed: Notify::new(),
+            persistence_running: AtomicBool::new(false),
+            persist_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    pub async fn read(&self) -> Database {
+        self.database.read().await.clone()
+    }
+
+    /// Wait for exclusive write access to the database, giving up after `DB_WRITE_TIMEOUT_SECS`
+    pub async fn lock(&self) -> Result<MutexGuard<'_, ()>> {
+        let timeout_secs = config::var("DB_WRITE_TIMEOUT_SECS")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(30);
+        tokio::time::timeout(
+            std::time::Duration::from_secs(timeout_secs),
+            self.write_lock.lock(),
+        )
+        .await
+        .map_err(|_| DatabaseBusy.into())
+    }
+
+    /// Replace the database in memory, the file is written by the persistence task within a second
+    pub async fn write(&self, database: &Database) -> Result<()> {
+        *self.database.write().await = database.clone();
+        self.dirty.store(true, Ordering::SeqCst);
+        if self.persistence_running.load(Ordering::SeqCst) {
+            self.changed.notify_one();
+            Ok(())
+        } else {
+            self.flush().await
+        }
+    }
+
+    /// Parse the file again after it was replaced on disk, dropping anything not yet written
+    pub async fn reload(&self) -> Result<()> {
+        let database = read_file(&self.file).await?;
+        self.dirty.store(false, Ordering::SeqCst);
+        *self.database.write().await = database;
+        Ok(())
+    }
+
+    /// Write the database to the file in the background, at most once per `PERSIST_DEBOUNCE`
+    pub fn spawn_persistence(self: &Arc<Self>) {
+        self.persistence_running.store(true, Ordering::SeqCst);
+        let store = self.clone();
+        tokio::spawn(async move {
+            loop {
+                store.changed.notified().await;
+                tokio::time::sleep(PERSIST_DEBOUNCE).await;
+                if let Err(e) = store.flush().await {
+                    log::error!("Failed to write database {:?}", e);
+                }
+            }
+        });
+    }
+
+    /// Write any changes not yet persisted, called on shutdown so the last second isn't lost
+    pub async fn flush(&self) -> Result<()> {
+        let _lock = self.persist_lock.lock().await;
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let database = self.read().await;
+
+        let result = async {
+            let pretty = ron::ser::PrettyConfig::new().compact_arrays(true);
+            let data = ron::ser::to_string_pretty(&database, pretty)?;
+            if let Err(e) = backups::backup_database(&self.file).await {
+                log::error!("Failed to back up database {:?}", e);
+            }
+
+            // Write beside the database then rename over it, so a crash mid write leaves the old file intact
+            let temp_file = self.file.with_extension("ron.tmp");
+            fs::write(&temp_file, data).await?;
+            fs::rename(&temp_file, &self.file).await?;
+            Ok(())
+        }
+        .await;
+        if result.is_err() {
+            // Still unsaved, so the next change or shutdown tries again
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+}
+
+async fn read_file(file: &PathBuf) -> Result<Database> {
+    if fs::metadata(file).await.is_err() {
+        return Ok(Database {
+            style: DatabaseStyle::default(),
+            history_config: HistoryConfig::default(),
+            prompt_templates: Vec::new(),
+            wallpapers: HashMap::new(),
+            comments: HashMap::new(),
+            pending_prompts: HashMap::new(),
+            fetch_profiles: Vec::new(),
+            users: HashMap::new(),
+            viewer: None,
+            thumbnail_settings: ThumbnailSettings::default(),
+            stale_thumbnails: Vec::new(),
+            presets: HashMap::new(),
+            share_links: Vec::new(),
+        });
+    }
+    Ok(ron::from_str(&fs::read_to_string(file).await?)?)
+}
@@ -0,0 +1,172 @@
+use crate::common::{
+    AuditAction, GenerateFromTemplatePacket, GenerationMode, PromptData, PromptTemplate,
+    TemplateAddPacket, TokenUuidPacket,
+};
+use crate::server::{
+    audit, auth::verify_token, error_status, image, resolution::ResolutionPreset, AppState,
+};
+use anyhow::anyhow;
+use axum::{body::Bytes, extract::State, http::StatusCode, response::IntoResponse};
+use uuid::Uuid;
+
+pub async fn add(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TemplateAddPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize add_template packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let (name, template) = (packet.name.trim(), packet.template.trim());
+    if name.is_empty() || template.is_empty() {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let id = Uuid::new_v4();
+    let result = state
+        .with_database(|database| {
+            database.prompt_templates.push(PromptTemplate {
+                id,
+                name: name.to_string(),
+                template: template.to_string(),
+                slots: PromptTemplate::parse_slots(template),
+            });
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => {
+            let summary = format!("Added template '{name}'");
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::AddTemplate,
+                Some(id),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored add_template {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+pub async fn remove(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenUuidPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize remove_template packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let result = state
+        .with_database(|database| {
+            database
+                .prompt_templates
+                .retain(|template| template.id != packet.uuid);
+            Ok(())
+        })
+        .await;
+
+    match result {
+        Ok(()) => {
+            let summary = "Removed template".to_string();
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::RemoveTemplate,
+                Some(packet.uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Errored remove_template {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+/// Generate a wallpaper straight from a filled in template, skipping the LLM
+pub async fn generate(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: GenerateFromTemplatePacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!(
+                "Failed to deserialize generate_from_template packet: {:?}",
+                e
+            );
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !verify_token(&state, &packet.token).await.unwrap_or(false) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let template = state
+        .read_database()
+        .await
+        .prompt_templates
+        .into_iter()
+        .find(|template| template.id == packet.template_id)
+        .ok_or_else(|| anyhow!("Template not found"));
+    let prompt = match template
+        .and_then(|template| template.fill(&packet.slot_values).map_err(|e| anyhow!(e)))
+    {
+        Ok(prompt) => prompt,
+        Err(e) => {
+            log::error!("Failed to fill template: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    let summary = format!("Generated wallpaper from template '{prompt}'");
+    let prompt_data = PromptData {
+        prompt: prompt.clone(),
+        shortened_prompt: prompt,
+        seasonal_hint: None,
+        time_of_day: None,
+        negative_prompt: None,
+        was_refined: false,
+    };
+    match image::generate_wallpaper_impl(
+        &state,
+        Some(prompt_data),
+        None,
+        GenerationMode::Normal,
+        ResolutionPreset::from_env(),
+        None,
+    )
+    .await
+    {
+        Ok(id) => {
+            image::attribute(&state, id, &packet.token).await;
+            let target = Some(packet.template_id);
+            audit(
+                &state,
+                &packet.token,
+                AuditAction::Generate,
+                target,
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            log::error!("Failed to generate wallpaper from template: {:?}", e);
+            error_status(&e)
+        }
+    }
+}
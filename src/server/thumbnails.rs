@@ -0,0 +1,45 @@
+use crate::common::ThumbnailSettings;
+use crate::server::config;
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, DynamicImage};
+use std::{fmt::Display, ops::RangeInclusive, str::FromStr};
+
+/// Read from `THUMBNAIL_WIDTH`, `THUMBNAIL_HEIGHT` and `THUMBNAIL_QUALITY`, each defaulting separately
+pub fn settings() -> ThumbnailSettings {
+    let default = ThumbnailSettings::default();
+    ThumbnailSettings {
+        width: parse_setting("THUMBNAIL_WIDTH", 64..=3840).unwrap_or(default.width),
+        height: parse_setting("THUMBNAIL_HEIGHT", 64..=2160).unwrap_or(default.height),
+        quality: parse_setting("THUMBNAIL_QUALITY", 1..=100).unwrap_or(default.quality),
+    }
+}
+
+fn parse_setting<T: FromStr + PartialOrd + Display>(
+    name: &str,
+    range: RangeInclusive<T>,
+) -> Option<T> {
+    let value = config::var(name)?;
+    let parsed = value.parse().ok().filter(|parsed| range.contains(parsed));
+    if parsed.is_none() {
+        log::warn!(
+            "Invalid {name} '{value}', expected {} to {}, using default",
+            range.start(),
+            range.end()
+        );
+    }
+    parsed
+}
+
+/// Downscale and crop an image to the thumbnail size
+pub fn resize(image: &DynamicImage) -> DynamicImage {
+    let settings = settings();
+    image.resize_to_fill(settings.width, settings.height, FilterType::Lanczos3)
+}
+
+/// Webp encode a thumbnail at the configured quality
+pub fn encode(thumb_image: &DynamicImage) -> Result<Vec<u8>> {
+    Ok(webp::Encoder::from_image(thumb_image)
+        .map_err(|e| anyhow!("{e}"))?
+        .encode(f32::from(settings().quality))
+        .to_vec())
+}
@@ -0,0 +1,140 @@
+use crate::common::{Database, LikedState, Source, TimeOfDay, WallpaperData};
+
+/// How many of the latest non-disliked wallpapers to look at
+const RECENT_COUNT: usize = 30;
+/// Fewer wallpapers than this isn't enough to call the library skewed
+const MIN_SAMPLES: usize = 6;
+/// Top 20% brightness at or above which a wallpaper counts as a daytime image
+const DAY_BRIGHTNESS: f32 = 0.5;
+/// Share of one bucket above which the next generation targets the other
+const SKEW_RATIO: f32 = 0.65;
+
+/// The bucket a wallpaper falls in, the targeted one if it was generated with a target
+fn bucket(wallpaper: &WallpaperData) -> TimeOfDay {
+    wallpaper.prompt_data.time_of_day.unwrap_or(
+        if wallpaper.color_data.top_20_percent_brightness >= DAY_BRIGHTNESS {
+            TimeOfDay::Day
+        } else {
+            TimeOfDay::Night
+        },
+    )
+}
+
+/// Pick a time of day to aim for when the recent wallpapers lean too far towards the other
+pub fn rebalance_target(database: &Database) -> Option<TimeOfDay> {
+    let mut wallpapers = database
+        .wallpapers
+        .values()
+        .filter(|wallpaper| {
+            wallpaper.source == Source::Generated
+                && wallpaper.combined_liked_state() != LikedState::Disliked
+        })
+        .collect::<Vec<_>>();
+    wallpapers.sort_by_key(|wallpaper| std::cmp::Reverse(wallpaper.datetime));
+    wallpapers.truncate(RECENT_COUNT);
+    if wallpapers.len() < MIN_SAMPLES {
+        return None;
+    }
+
+    let day_share = wallpapers
+        .iter()
+        .filter(|wallpaper| bucket(wallpaper) == TimeOfDay::Day)
+        .count() as f32
+        / wallpapers.len() as f32;
+    if day_share > SKEW_RATIO {
+        Some(TimeOfDay::Night)
+    } else if 1.0 - day_share > SKEW_RATIO {
+        Some(TimeOfDay::Day)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{DatabaseStyle, HistoryConfig, ThumbnailSettings};
+    use chrono::{Duration, Utc};
+    use std::collections::HashMap;
+
+    /// Build a database of wallpapers with the given top 20% brightness, entry i is i hours old
+    fn database(entries: &[(f32, LikedState)]) -> Database {
+        let now = Utc::now();
+        Database {
+            style: DatabaseStyle::default(),
+            history_config: HistoryConfig::default(),
+            prompt_templates: Vec::new(),
+            wallpapers: entries
+                .iter()
+                .enumerate()
+                .map(|(i, (brightness, liked_state))| {
+                    let mut wallpaper = WallpaperData {
+                        datetime: now - Duration::hours(i as i64),
+                        shared_liked_state: *liked_state,
+                        ..WallpaperData::sample()
+                    };
+                    wallpaper.color_data.top_20_percent_brightness = *brightness;
+                    (wallpaper.id, wallpaper)
+                })
+                .collect(),
+            comments: HashMap::new(),
+            pending_prompts: HashMap::new(),
+            fetch_profiles: Vec::new(),
+            users: HashMap::new(),
+            viewer: None,
+            thumbnail_settings: ThumbnailSettings::default(),
+            stale_thumbnails: Vec::new(),
+            presets: HashMap::new(),
+            share_links: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn bright_library_targets_night() {
+        let database = database(&[(0.9, LikedState::Neutral); 8]);
+        assert!(rebalance_target(&database) == Some(TimeOfDay::Night));
+    }
+
+    #[test]
+    fn dark_library_targets_day() {
+        let database = database(&[(0.1, LikedState::Liked); 8]);
+        assert!(rebalance_target(&database) == Some(TimeOfDay::Day));
+    }
+
+    #[test]
+    fn balanced_library_has_no_target() {
+        let database = database(&[
+            (0.9, LikedState::Neutral),
+            (0.1, LikedState::Neutral),
+            (0.8, LikedState::Liked),
+            (0.2, LikedState::Liked),
+            (0.7, LikedState::Loved),
+            (0.3, LikedState::Loved),
+        ]);
+        assert!(rebalance_target(&database).is_none());
+    }
+
+    #[test]
+    fn too_few_wallpapers_has_no_target() {
+        let database = database(&[(0.9, LikedState::Neutral); 3]);
+        assert!(rebalance_target(&database).is_none());
+    }
+
+    #[test]
+    fn ignores_disliked_wallpapers() {
+        let mut entries = vec![(0.9, LikedState::Neutral), (0.1, LikedState::Neutral)];
+        entries.extend([(0.1, LikedState::Disliked); 4]);
+        entries.extend([(0.9, LikedState::Liked), (0.1, LikedState::Liked)]);
+        entries.extend([(0.9, LikedState::Loved), (0.1, LikedState::Loved)]);
+        assert!(rebalance_target(&database(&entries)).is_none());
+    }
+
+    #[test]
+    fn counts_targeted_bucket_over_brightness() {
+        let mut database = database(&[(0.9, LikedState::Neutral); 8]);
+        for wallpaper in database.wallpapers.values_mut().take(4) {
+            wallpaper.prompt_data.time_of_day = Some(TimeOfDay::Night);
+        }
+        assert!(rebalance_target(&database).is_none());
+    }
+}
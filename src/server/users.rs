@@ -0,0 +1,406 @@
+use crate::common::{
+    AuditAction, CommentData, Database, LikedState, TokenStringPacket, WallpaperData,
+};
+use crate::server::{
+    audit,
+    auth::{
+        account_info, check_password, list_accounts, remove_account, token_account, AccountInfo,
+    },
+    config, error_status, image, AppState,
+};
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Bumped whenever the export layout changes
+const EXPORT_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+pub struct UserQuery {
+    token: String,
+}
+
+/// Everything stored about one account, sorted so the same data always exports the same way
+#[derive(Serialize)]
+struct UserExport {
+    version: u32,
+    account: AccountInfo,
+    comments: Vec<CommentData>,
+    votes: Vec<UserVote>,
+}
+
+#[derive(Serialize)]
+struct UserVote {
+    wallpaper_id: Uuid,
+    liked_state: LikedState,
+}
+
+/// Export an account with its comments and votes as JSON, only available to admins
+pub async fn export(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    match token_account(&state, &query.token).await {
+        Ok(Some((_, true))) => {}
+        Ok(_) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    match export_impl(&state, uuid).await {
+        Ok(Some(export)) => match serde_json::to_vec_pretty(&export) {
+            Ok(data) => (
+                StatusCode::OK,
+                [
+                    (header::CONTENT_TYPE, "application/json".to_string()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"wallpapy-user-{uuid}.json\""),
+                    ),
+                ],
+                data,
+            )
+                .into_response(),
+            Err(e) => {
+                log::error!("{:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        },
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            log::error!("Errored export_user {:?}", e);
+            error_status(&e).into_response()
+        }
+    }
+}
+
+/// Delete an account and anonymise the comments and votes it left, only available to admins
+pub async fn delete(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    match token_account(&state, &query.token).await {
+        // Deleting your own account would lock you out mid request
+        Ok(Some((actor, true))) if actor == uuid => return StatusCode::BAD_REQUEST,
+        Ok(Some((_, true))) => {}
+        Ok(_) => return StatusCode::UNAUTHORIZED,
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+
+    match delete_impl(&state, uuid).await {
+        Ok(Some(anonymised)) => {
+            let summary = format!("Deleted account, anonymised {anonymised} records");
+            audit(
+                &state,
+                &query.token,
+                AuditAction::DeleteUserData,
+                Some(uuid),
+                summary,
+            )
+            .await;
+            StatusCode::OK
+        }
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            log::error!("Errored delete_user_data {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+/// Delete your own account once the password is confirmed
+/// Its comments, votes and generated wallpapers pass to another admin, or are removed with `DELETE_USER_CONTENT=true`
+pub async fn delete_own(State(state): State<AppState>, packet: Bytes) -> impl IntoResponse {
+    let packet: TokenStringPacket = match bincode::deserialize(&packet) {
+        Ok(packet) => packet,
+        Err(e) => {
+            log::error!("Failed to deserialize delete_account packet: {:?}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    let uuid = match check_password(&state, &packet.token, &packet.string).await {
+        Ok(Some(uuid)) => uuid,
+        Ok(None) => return StatusCode::UNAUTHORIZED,
+        Err(e) => {
+            log::error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let delete_content =
+        config::var("DELETE_USER_CONTENT").is_some_and(|value| value.eq_ignore_ascii_case("true"));
+
+    let result = async {
+        let accounts = list_accounts(&state).await?;
+        let others = accounts.iter().filter(|account| account.uuid != uuid);
+        let admin = others
+            .clone()
+            .find(|account| account.admin)
+            .map(|account| account.uuid);
+        // The last admin can't leave while other accounts still need one
+        let is_admin = accounts
+            .iter()
+            .any(|account| account.uuid == uuid && account.admin);
+        if is_admin && admin.is_none() && others.count() > 0 {
+            return Ok(false);
+        }
+
+        let (changed, verb, removed) = state
+            .with_database(|database| {
+                Ok(if delete_content {
+                    let (changed, removed) = remove_content(database, uuid);
+                    (changed, "removed", removed)
+                } else if admin.is_some() {
+                    (reassign(database, uuid, admin), "transferred", Vec::new())
+                } else {
+                    (anonymise(database, uuid), "anonymised", Vec::new())
+                })
+            })
+            .await?;
+        for wallpaper in &removed {
+            image::remove_wallpaper_files(&state.config.wallpapers_dir, wallpaper).await;
+        }
+        // Logged before the account goes, while the token still names who did it
+        let summary = format!("Deleted own account, {verb} {changed} records");
+        audit(
+            &state,
+            &packet.token,
+            AuditAction::DeleteUserData,
+            Some(uuid),
+            summary,
+        )
+        .await;
+        remove_account(&state, uuid).await?;
+        Ok(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::CONFLICT,
+        Err(e) => {
+            log::error!("Errored delete_account {:?}", e);
+            error_status(&e)
+        }
+    }
+}
+
+async fn export_impl(state: &AppState, uuid: Uuid) -> Result<Option<UserExport>> {
+    let Some(account) = account_info(state, uuid).await? else {
+        return Ok(None);
+    };
+    let database = state.read_database().await;
+    let (comments, votes) = user_records(&database, uuid);
+    Ok(Some(UserExport {
+        version: EXPORT_VERSION,
+        account,
+        comments,
+        votes,
+    }))
+}
+
+/// Anonymise first so a failed write leaves the account to retry with, returning the records changed
+async fn delete_impl(state: &AppState, uuid: Uuid) -> Result<Option<usize>> {
+    if account_info(state, uuid).await?.is_none() {
+        return Ok(None);
+    }
+    let anonymised = state
+        .with_database(|database| Ok(anonymise(database, uuid)))
+        .await?;
+    remove_account(state, uuid).await?;
+    Ok(Some(anonymised))
+}
+
+/// The comments posted and votes cast by an account, oldest comment and lowest wallpaper id first
+fn user_records(database: &Database, uuid: Uuid) -> (Vec<CommentData>, Vec<UserVote>) {
+    let mut comments = database
+        .comments
+        .values()
+        .filter(|comment| comment.author_uuid == Some(uuid))
+        .cloned()
+        .collect::<Vec<_>>();
+    comments.sort_by_key(|comment| (comment.datetime, comment.id));
+
+    let mut votes = database
+        .wallpapers
+        .values()
+        .filter_map(|wallpaper| {
+            Some(UserVote {
+                wallpaper_id: wallpaper.id,
+                liked_state: *wallpaper.liked_states.get(&uuid)?,
+            })
+        })
+        .collect::<Vec<_>>();
+    votes.sort_by_key(|vote| vote.wallpaper_id);
+    (comments, votes)
+}
+
+/// Clear every reference to an account, keeping the comments and votes themselves
+fn anonymise(database: &mut Database, uuid: Uuid) -> usize {
+    reassign(database, uuid, None)
+}
+
+/// Hand an account's comments, votes and generated wallpapers to another, returning the records changed
+fn reassign(database: &mut Database, uuid: Uuid, owner: Option<Uuid>) -> usize {
+    let mut changed = 0;
+    for comment in database.comments.values_mut() {
+        if comment.author_uuid == Some(uuid) {
+            comment.author_uuid = owner;
+            changed += 1;
+        }
+        // Reactions are keyed by account, so anonymised ones are dropped
+        if let Some(reaction) = comment.reactions.remove(&uuid) {
+            if let Some(owner) = owner {
+                comment.reactions.entry(owner).or_insert(reaction);
+            }
+            changed += 1;
+        }
+    }
+    for wallpaper in database.wallpapers.values_mut() {
+        if wallpaper.last_modified_by == Some(uuid) {
+            wallpaper.last_modified_by = owner;
+            changed += 1;
+        }
+        if wallpaper.generated_by == Some(uuid) {
+            wallpaper.generated_by = owner;
+            changed += 1;
+        }
+        // Votes are keyed by account too, and the new owner's own vote wins
+        if let Some(liked_state) = wallpaper.liked_states.remove(&uuid) {
+            if let Some(owner) = owner {
+                wallpaper.liked_states.entry(owner).or_insert(liked_state);
+            }
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// Remove the comments, votes and generated wallpapers of an account, returning the records changed
+/// and the wallpapers removed, whose files are left for the caller to delete once the database is saved
+fn remove_content(database: &mut Database, uuid: Uuid) -> (usize, Vec<WallpaperData>) {
+    let before = database.comments.len();
+    database
+        .comments
+        .retain(|_, comment| comment.author_uuid != Some(uuid));
+    let ids = database
+        .wallpapers
+        .values()
+        .filter(|wallpaper| wallpaper.generated_by == Some(uuid))
+        .map(|wallpaper| wallpaper.id)
+        .collect::<Vec<_>>();
+    let removed = ids
+        .iter()
+        .filter_map(|id| database.wallpapers.remove(id))
+        .collect::<Vec<_>>();
+    image::unlink_removed(database, &ids);
+    let changed = before - database.comments.len() + removed.len() + anonymise(database, uuid);
+    (changed, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{DatabaseStyle, HistoryConfig, ThumbnailSettings};
+    use chrono::{Duration, Utc};
+    use std::collections::HashMap;
+
+    fn comment(author_uuid: Option<Uuid>, age_days: i64) -> CommentData {
+        CommentData {
+            id: Uuid::new_v4(),
+            datetime: Utc::now() - Duration::days(age_days),
+            comment: String::new(),
+            response: None,
+            author_uuid,
+            used: false,
+            reactions: HashMap::new(),
+            sentiment: None,
+        }
+    }
+
+    #[test]
+    fn exports_and_anonymises_only_that_user() {
+        let user = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let comments = [
+            comment(Some(user), 1),
+            comment(Some(user), 3),
+            comment(Some(other), 2),
+            comment(None, 4),
+        ];
+        let mine = WallpaperData {
+            generated_by: Some(user),
+            ..WallpaperData::sample()
+        };
+        let theirs = WallpaperData {
+            generated_by: Some(other),
+            ..WallpaperData::sample()
+        };
+        let mut database = Database {
+            style: DatabaseStyle::default(),
+            history_config: HistoryConfig::default(),
+            prompt_templates: Vec::new(),
+            wallpapers: [&mine, &theirs]
+                .into_iter()
+                .map(|wallpaper| (wallpaper.id, wallpaper.clone()))
+                .collect(),
+            comments: comments
+                .iter()
+                .map(|comment| (comment.id, comment.clone()))
+                .collect(),
+            pending_prompts: HashMap::new(),
+            fetch_profiles: Vec::new(),
+            users: HashMap::new(),
+            viewer: None,
+            thumbnail_settings: ThumbnailSettings::default(),
+            stale_thumbnails: Vec::new(),
+            presets: HashMap::new(),
+            share_links: Vec::new(),
+        };
+
+        let (exported, votes) = user_records(&database, user);
+        assert_eq!(
+            exported
+                .iter()
+                .map(|comment| comment.id)
+                .collect::<Vec<_>>(),
+            vec![comments[1].id, comments[0].id]
+        );
+        assert!(votes.is_empty());
+
+        assert_eq!(anonymise(&mut database, user), 3);
+        assert!(user_records(&database, user).0.is_empty());
+        assert_eq!(user_records(&database, other).0.len(), 1);
+        assert_eq!(database.comments.len(), 4);
+        assert!(database.wallpapers[&mine.id].generated_by.is_none());
+
+        assert_eq!(reassign(&mut database, other, Some(user)), 2);
+        assert_eq!(user_records(&database, user).0.len(), 1);
+        assert_eq!(database.wallpapers[&theirs.id].generated_by, Some(user));
+        let (changed, removed) = remove_content(&mut database, user);
+        assert_eq!(changed, 2);
+        assert_eq!(
+            removed
+                .iter()
+                .map(|wallpaper| wallpaper.id)
+                .collect::<Vec<_>>(),
+            vec![theirs.id]
+        );
+        assert!(user_records(&database, user).0.is_empty());
+        assert_eq!(database.comments.len(), 3);
+        assert!(database.wallpapers.contains_key(&mine.id));
+        assert!(!database.wallpapers.contains_key(&theirs.id));
+    }
+}
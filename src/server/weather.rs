@@ -0,0 +1,92 @@
+use crate::server::{
+    config,
+    net::{check_status, with_retry, RetryPolicy},
+};
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde_json::Value;
+use std::env;
+
+/// <https://openweathermap.org/current>
+const WEATHER_URL: &str = "https://api.openweathermap.org/data/2.5/weather";
+
+/// The weather at `WEATHER_LOCATION` like "overcast clouds and light rain, 12°C"
+/// None when it isn't configured or the lookup fails, generation carries on without it
+pub async fn current(client: &Client) -> Option<String> {
+    // The API key stays in the environment like the other keys
+    let api_key = env::var("WEATHER_API_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())?;
+    let location = config::var("WEATHER_LOCATION").filter(|location| !location.is_empty())?;
+    match fetch(client, &api_key, &location).await {
+        Ok(weather) => {
+            log::info!("Weather in {location}: {weather}");
+            Some(weather)
+        }
+        Err(e) => {
+            log::error!("Failed to fetch weather for {location}: {:?}", e);
+            None
+        }
+    }
+}
+
+async fn fetch(client: &Client, api_key: &str, location: &str) -> Result<String> {
+    // The url carries the appid, so it's stripped before any error can be logged
+    let text = with_retry(RetryPolicy::default(), "Weather request", || async {
+        let response = client
+            .get(WEATHER_URL)
+            .query(&[("q", location), ("appid", api_key), ("units", "metric")])
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        Ok(check_status(response)
+            .await?
+            .text()
+            .await
+            .map_err(reqwest::Error::without_url)?)
+    })
+    .await?;
+    describe(&serde_json::from_str(&text)?)
+}
+
+/// Conditions and temperature from a current weather response in metric units
+fn describe(response: &Value) -> Result<String> {
+    let conditions = response["weather"]
+        .as_array()
+        .map(|conditions| {
+            conditions
+                .iter()
+                .filter_map(|condition| condition["description"].as_str())
+                .collect::<Vec<_>>()
+                .join(" and ")
+        })
+        .filter(|conditions| !conditions.is_empty())
+        .ok_or_else(|| anyhow!("No weather conditions in response {}", response))?;
+    Ok(match response["main"]["temp"].as_f64() {
+        Some(temperature) => format!("{conditions}, {temperature:.0}°C"),
+        None => conditions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn describes_weather() {
+        let response = json!({
+            "weather": [
+                { "main": "Clouds", "description": "overcast clouds" },
+                { "main": "Rain", "description": "light rain" },
+            ],
+            "main": { "temp": 11.62, "humidity": 87 },
+            "name": "London",
+        });
+        assert_eq!(
+            describe(&response).unwrap(),
+            "overcast clouds and light rain, 12°C"
+        );
+        assert!(describe(&json!({ "cod": "404" })).is_err());
+    }
+}
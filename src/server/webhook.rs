@@ -0,0 +1,187 @@
+use crate::common::{LikedState, WallpaperData};
+use crate::server::{
+    config,
+    net::{check_status, with_retry, RetryPolicy},
+    AppState,
+};
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::env;
+use uuid::Uuid;
+
+const WEBHOOK_ATTEMPTS: u32 = 3;
+const DISCORD_TITLE_LIMIT: usize = 256;
+const DISCORD_DESCRIPTION_LIMIT: usize = 4096;
+
+/// Tell `WEBHOOK_URL` and `DISCORD_WEBHOOK_URL` about a newly generated wallpaper, failures are only logged
+pub async fn notify_generated(state: &AppState, id: Uuid) {
+    let url = config::var("WEBHOOK_URL").filter(|url| !url.is_empty());
+    // Discord webhook urls carry their own token so they stay in the environment
+    let discord_url = env::var("DISCORD_WEBHOOK_URL")
+        .ok()
+        .filter(|url| !url.is_empty());
+    if url.is_none() && discord_url.is_none() {
+        return;
+    }
+    let Some(wallpaper) = state.read_database().await.wallpapers.remove(&id) else {
+        log::error!("Failed to read wallpaper {id} for webhooks: not found");
+        return;
+    };
+
+    if let Some(url) = url {
+        match send_generated(&url, &wallpaper).await {
+            Ok(()) => log::info!("Sent webhook for wallpaper {id}"),
+            Err(e) => log::error!("Failed to send webhook for wallpaper {id}: {:?}", e),
+        }
+    }
+    if let Some(url) = discord_url {
+        let public_host = config::var("PUBLIC_HOST").filter(|host| !host.is_empty());
+        let payload = discord_payload(&wallpaper, public_host.as_deref());
+        match post_json(&url, &payload.to_string(), None).await {
+            Ok(()) => log::info!("Sent Discord webhook for wallpaper {id}"),
+            Err(e) => log::error!("Failed to send Discord webhook for wallpaper {id}: {:?}", e),
+        }
+    }
+}
+
+async fn send_generated(url: &str, wallpaper: &WallpaperData) -> Result<()> {
+    let body = serde_json::to_string(&json!({
+        "event": "wallpaper_generated",
+        "id": wallpaper.id,
+        "shortened_prompt": wallpaper.prompt_data.shortened_prompt,
+        "datetime": wallpaper.datetime,
+    }))?;
+    // Receivers check the body came from us with `WEBHOOK_SECRET`, it's left unsigned without one
+    let signature = env::var("WEBHOOK_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .map(|secret| sign(&secret, body.as_bytes()));
+    post_json(url, &body, signature.as_deref()).await
+}
+
+async fn post_json(url: &str, body: &str, signature: Option<&str>) -> Result<()> {
+    let client = Client::new();
+    let policy = RetryPolicy {
+        max_attempts: WEBHOOK_ATTEMPTS,
+        ..RetryPolicy::default()
+    };
+    with_retry(policy, "Webhook", || async {
+        let mut request = client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        if let Some(signature) = signature {
+            request = request.header("X-Signature", format!("sha256={signature}"));
+        }
+        check_status(request.send().await?).await?;
+        Ok(())
+    })
+    .await
+}
+
+/// A Discord embed with the thumbnail, which needs `PUBLIC_HOST` so Discord can fetch it
+fn discord_payload(wallpaper: &WallpaperData, public_host: Option<&str>) -> Value {
+    let (r, g, b) = wallpaper.color_data.average_color;
+    let color = (u32::from((r * 255.0) as u8) << 16)
+        | (u32::from((g * 255.0) as u8) << 8)
+        | u32::from((b * 255.0) as u8);
+    let liked_state = match wallpaper.combined_liked_state() {
+        LikedState::Loved => "Loved",
+        LikedState::Liked => "Liked",
+        LikedState::Neutral => "Neutral",
+        LikedState::Disliked => "Disliked",
+    };
+    let mut embed = json!({
+        "title": wallpaper
+            .prompt_data
+            .shortened_prompt
+            .chars()
+            .take(DISCORD_TITLE_LIMIT)
+            .collect::<String>(),
+        // Leaves room for the spoiler markers
+        "description": format!(
+            "||{}||",
+            wallpaper
+                .prompt_data
+                .prompt
+                .chars()
+                .take(DISCORD_DESCRIPTION_LIMIT - 4)
+                .collect::<String>()
+        ),
+        "color": color,
+        "fields": [{ "name": "Liked State", "value": liked_state }],
+    });
+    if let Some(host) = public_host {
+        let base = if host.contains("://") {
+            host.trim_end_matches('/').to_string()
+        } else {
+            format!("http://{}", host.trim_end_matches('/'))
+        };
+        embed["image"] = json!({
+            "url": format!("{base}/wallpapers/{}", wallpaper.thumbnail_file.file_name),
+        });
+    }
+    json!({ "embeds": [embed] })
+}
+
+/// Hex HMAC-SHA256 of the body
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ColorTemperature;
+
+    fn wallpaper() -> WallpaperData {
+        let mut wallpaper = WallpaperData::sample();
+        wallpaper.color_data.average_color = (1.0, 0.5, 0.0);
+        wallpaper.color_data.color_temperature = ColorTemperature::Warm;
+        wallpaper
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn builds_discord_embed() {
+        let wallpaper = wallpaper();
+        let payload = discord_payload(&wallpaper, Some("walls.example.com/"));
+        let embed = &payload["embeds"][0];
+        assert_eq!(embed["title"], "Quiet harbour");
+        assert_eq!(embed["description"], "||A quiet harbour at dusk||");
+        assert_eq!(embed["color"], 0x00ff_7f00);
+        assert_eq!(embed["fields"][0]["value"], "Neutral");
+        assert_eq!(
+            embed["image"]["url"],
+            "http://walls.example.com/wallpapers/thumb.webp"
+        );
+
+        let payload = discord_payload(&wallpaper, None);
+        assert!(payload["embeds"][0].get("image").is_none());
+
+        let mut wallpaper = wallpaper;
+        wallpaper.prompt_data.prompt = "harbour ".repeat(1000);
+        let payload = discord_payload(&wallpaper, None);
+        let description = payload["embeds"][0]["description"].as_str().unwrap();
+        assert_eq!(description.chars().count(), DISCORD_DESCRIPTION_LIMIT);
+        assert!(description.ends_with("||"));
+    }
+}